@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures_util::{FutureExt, StreamExt};
+use matrix_sdk::{
+    Client as SdkClient, SessionMeta, SessionTokens,
+    authentication::{
+        matrix::MatrixSession,
+        oauth::{ClientId, OAuthSession, UserSession},
+    },
+    ruma::OwnedUserId,
+    sleep::sleep,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+use uniffi::{Object, export};
+
+use crate::{
+    AccountUnread, AccountUnreadObserver, Client, ClientConfig, FfiError, RT, SessionInfo,
+    SessionKeyProvider, SessionSecret, StoredAccount, errors::IntoFfi, macros::*, platform,
+    safe_call,
+};
+
+/// Unread counts change in bursts during sync; emit once per burst.
+const UNREAD_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Owns the `Client`s for every account under `<base_store_dir>/accounts`.
+#[derive(Object)]
+pub struct AccountManager {
+    base_store_dir: PathBuf,
+    proxy: Option<String>,
+    session_key: Option<Arc<dyn SessionKeyProvider>>,
+    clients: Arc<Mutex<HashMap<String, Arc<Client>>>>,
+    /// Bumped whenever `clients` changes so merged streams re-subscribe.
+    generation: tokio::sync::watch::Sender<u64>,
+    subs_counter: AtomicU64,
+    unread_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
+}
+
+struct SharedKeyProvider(Arc<dyn SessionKeyProvider>);
+
+impl SessionKeyProvider for SharedKeyProvider {
+    fn session_secret(&self, account_key: String) -> Option<SessionSecret> {
+        self.0.session_secret(account_key)
+    }
+}
+
+#[export]
+impl AccountManager {
+    #[uniffi::constructor]
+    pub fn new(
+        base_store_dir: String,
+        proxy: Option<String>,
+        session_key: Option<Box<dyn SessionKeyProvider>>,
+    ) -> Self {
+        platform::init_tracing();
+        let base_store_dir = PathBuf::from(base_store_dir);
+        platform::ensure_dir(&base_store_dir.join("accounts"));
+        platform::purge_trashed_stores(&base_store_dir.join("accounts"));
+        Self {
+            base_store_dir,
+            proxy,
+            session_key: session_key.map(Arc::from),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            generation: tokio::sync::watch::channel(0).0,
+            subs_counter: AtomicU64::new(0),
+            unread_subs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accounts with a session file on disk, whether or not they are open.
+    /// Only reads the files: plaintext ones stay plaintext until
+    /// [`AccountManager::migrate_sessions`] runs or the account is opened.
+    pub fn list_accounts(&self) -> Vec<StoredAccount> {
+        let open: Vec<String> = self.clients.lock().unwrap().keys().cloned().collect();
+        platform::stored_account_ids(&self.accounts_dir())
+            .into_iter()
+            .map(|account_id| {
+                let secret = self
+                    .session_key
+                    .as_ref()
+                    .and_then(|p| p.session_secret(account_id.clone()));
+                let info = platform::peek_session(&self.account_dir(&account_id), secret.as_ref());
+                StoredAccount {
+                    is_open: open.contains(&account_id),
+                    user_id: info.as_ref().map(|i| i.user_id.clone()),
+                    homeserver: info.as_ref().map(|i| i.homeserver.clone()),
+                    is_token_valid: info.as_ref().is_some_and(|i| i.is_token_valid),
+                    account_id,
+                }
+            })
+            .collect()
+    }
+
+    /// Encrypts the session files of stored accounts that are still
+    /// plaintext, under the secret the `SessionKeyProvider` gives for each.
+    /// Does nothing without a provider. Opening an account does the same
+    /// for that account alone.
+    pub fn migrate_sessions(&self) {
+        if self.session_key.is_none() {
+            return;
+        }
+        RT.block_on(async {
+            for account_id in platform::stored_account_ids(&self.accounts_dir()) {
+                self.register_session_secret(&account_id);
+                let _ = platform::load_session(&self.account_dir(&account_id)).await;
+            }
+        })
+    }
+
+    /// The open `Client` for `account_id`, if any.
+    pub fn client(&self, account_id: String) -> Option<Arc<Client>> {
+        self.clients.lock().unwrap().get(&account_id).cloned()
+    }
+
+    /// Opens a stored account, restoring its session against the homeserver
+    /// recorded in the session file. Returns the existing client if already open.
+    pub fn restore_account(
+        &self,
+        account_id: String,
+        store_passphrase: Option<String>,
+    ) -> Result<Arc<Client>, FfiError> {
+        if let Some(client) = self.client(account_id.clone()) {
+            return Ok(client);
+        }
+        validate_account_id(&account_id)?;
+        self.register_session_secret(&account_id);
+        let info = RT
+            .block_on(platform::load_session(&self.account_dir(&account_id)))?
+            .ok_or_else(|| FfiError::Unauthorized(format!("no stored session for {account_id}")))?;
+        self.open(account_id, info.homeserver, store_passphrase)
+    }
+
+    /// Creates (or opens) the store for `account_id`; log in on the returned client.
+    pub fn add_account(
+        &self,
+        account_id: String,
+        homeserver_url: String,
+        store_passphrase: Option<String>,
+    ) -> Result<Arc<Client>, FfiError> {
+        if let Some(client) = self.client(account_id.clone()) {
+            return Ok(client);
+        }
+        validate_account_id(&account_id)?;
+        self.open(account_id, homeserver_url, store_passphrase)
+    }
+
+    /// Shuts the account's client down and drops it from the manager. The
+    /// store and session file stay on disk.
+    pub fn close_account(&self, account_id: String) -> bool {
+        let Some(client) = self.clients.lock().unwrap().remove(&account_id) else {
+            return false;
+        };
+        client.shutdown();
+        self.generation.send_modify(|g| *g += 1);
+        true
+    }
+
+    /// Removes an account. The store dir is renamed aside via
+    /// `trash_store_dir` rather than deleted, so handles still held by the
+    /// host stay valid; it is purged on the next `AccountManager::new`.
+    /// With `sign_out`, a closed account is signed out from its stored
+    /// session, and a failed logout leaves the account in place.
+    pub fn remove_account(&self, account_id: String, sign_out: bool) -> Result<(), FfiError> {
+        validate_account_id(&account_id)?;
+        let dir = self.account_dir(&account_id);
+        if sign_out {
+            let result = match self.client(account_id.clone()) {
+                Some(client) => RT.block_on(client.core.sdk.logout()).ffi(),
+                None => {
+                    self.register_session_secret(&account_id);
+                    match RT.block_on(platform::load_session(&dir))? {
+                        Some(info) if info.is_token_valid => {
+                            RT.block_on(logout_session(info, self.proxy.as_deref()))
+                        }
+                        _ => Ok(()),
+                    }
+                }
+            };
+            match result {
+                // The server already dropped the token, and the device with it.
+                Ok(()) | Err(FfiError::Unauthorized(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let client = self.clients.lock().unwrap().remove(&account_id);
+        if let Some(client) = client {
+            client.shutdown();
+            self.generation.send_modify(|g| *g += 1);
+        }
+        platform::remove_session_file(&dir);
+        platform::set_session_secret(&dir, None);
+        platform::trash_store_dir(&dir);
+        info!("Removed account {account_id}");
+        Ok(())
+    }
+
+    /// Merged unread counts across all open accounts. Accounts opened or
+    /// closed later are picked up automatically. Updates within
+    /// `UNREAD_DEBOUNCE` of each other are emitted once.
+    pub fn observe_unread(&self, observer: Box<dyn AccountUnreadObserver>) -> u64 {
+        let obs: Arc<dyn AccountUnreadObserver> = Arc::from(observer);
+        let clients = self.clients.clone();
+        let mut generation = self.generation.subscribe();
+        let id = self.subs_counter.fetch_add(1, Ordering::Relaxed);
+        let h = spawn_task!(async move {
+            loop {
+                let sdks: Vec<(String, SdkClient)> = clients
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(id, c)| (id.clone(), c.core.sdk.clone()))
+                    .collect();
+                let emit = |sdks: &[(String, SdkClient)]| {
+                    let snapshot: Vec<AccountUnread> = sdks
+                        .iter()
+                        .map(|(id, sdk)| account_unread(id, sdk))
+                        .collect();
+                    safe_call(|| obs.on_update(snapshot));
+                };
+                emit(&sdks);
+
+                let mut updates = futures_util::stream::select_all(sdks.iter().map(|(_, sdk)| {
+                    let mut rx = sdk.subscribe_to_all_room_updates();
+                    Box::pin(async_stream::stream! {
+                        loop {
+                            match rx.recv().await {
+                                Ok(_) | Err(RecvError::Lagged(_)) => yield (),
+                                Err(RecvError::Closed) => break,
+                            }
+                        }
+                    })
+                }));
+
+                loop {
+                    tokio::select! {
+                        changed = generation.changed() => {
+                            if changed.is_err() {
+                                return;
+                            }
+                            break;
+                        }
+                        Some(()) = updates.next() => {
+                            sleep(UNREAD_DEBOUNCE).await;
+                            // One snapshot covers whatever piled up meanwhile.
+                            while let Some(Some(())) = updates.next().now_or_never() {}
+                            emit(&sdks);
+                        }
+                    }
+                }
+            }
+        });
+        self.unread_subs.lock().unwrap().insert(id, h);
+        id
+    }
+
+    pub fn unobserve_unread(&self, sub_id: u64) -> bool {
+        unsub!(self, unread_subs, sub_id)
+    }
+
+    /// Stops every open client and observer.
+    pub fn shutdown(&self) {
+        abort_all_subs!(self; unread_subs);
+        for (_, client) in self.clients.lock().unwrap().drain() {
+            client.shutdown();
+        }
+    }
+}
+
+impl AccountManager {
+    fn accounts_dir(&self) -> PathBuf {
+        self.base_store_dir.join("accounts")
+    }
+
+    fn account_dir(&self, account_id: &str) -> PathBuf {
+        self.accounts_dir().join(account_id)
+    }
+
+    fn register_session_secret(&self, account_id: &str) {
+        if let Some(provider) = &self.session_key {
+            platform::set_session_secret(
+                &self.account_dir(account_id),
+                provider.session_secret(account_id.to_owned()),
+            );
+        }
+    }
+
+    fn open(
+        &self,
+        account_id: String,
+        homeserver_url: String,
+        store_passphrase: Option<String>,
+    ) -> Result<Arc<Client>, FfiError> {
+        let client = Arc::new(Client::with_config(
+            ClientConfig {
+                homeserver_url,
+                base_store_dir: self.base_store_dir.to_string_lossy().into_owned(),
+                account_id: Some(account_id.clone()),
+                proxy: self.proxy.clone(),
+                enable_share_history_on_invite: None,
+                store_passphrase,
+            },
+            self.session_key
+                .clone()
+                .map(|p| Box::new(SharedKeyProvider(p)) as Box<dyn SessionKeyProvider>),
+        )?);
+        self.clients
+            .lock()
+            .unwrap()
+            .insert(account_id, client.clone());
+        self.generation.send_modify(|g| *g += 1);
+        Ok(client)
+    }
+}
+
+impl Drop for AccountManager {
+    fn drop(&mut self) {
+        abort_all_subs!(self; unread_subs);
+    }
+}
+
+fn validate_account_id(account_id: &str) -> Result<(), FfiError> {
+    if account_id.is_empty()
+        || account_id.contains(['/', '\\'])
+        || account_id.starts_with('.')
+        || account_id.contains(".trash.")
+    {
+        return Err(FfiError::InvalidId(format!(
+            "invalid account id: {account_id}"
+        )));
+    }
+    Ok(())
+}
+
+/// Logs a stored session out through a throwaway in-memory client, so a
+/// closed account's store (and its passphrase) is not needed.
+async fn logout_session(info: SessionInfo, proxy: Option<&str>) -> Result<(), FfiError> {
+    let mut builder = SdkClient::builder().homeserver_url(&info.homeserver);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    let sdk = builder.build().await.ffi()?;
+    let meta = SessionMeta {
+        user_id: info.user_id.parse::<OwnedUserId>().ffi()?,
+        device_id: info.device_id.into(),
+    };
+    let tokens = SessionTokens {
+        access_token: info.access_token,
+        refresh_token: info.refresh_token,
+    };
+    match info.client_id {
+        Some(client_id) if info.auth_api == "oauth" => {
+            sdk.restore_session(OAuthSession {
+                client_id: ClientId::new(client_id),
+                user: UserSession { meta, tokens },
+            })
+            .await
+        }
+        _ => sdk.restore_session(MatrixSession { meta, tokens }).await,
+    }
+    .ffi()?;
+    sdk.logout().await.ffi()
+}
+
+fn account_unread(account_id: &str, sdk: &SdkClient) -> AccountUnread {
+    let mut out = AccountUnread {
+        account_id: account_id.to_owned(),
+        user_id: sdk.user_id().map(|u| u.to_string()),
+        unread_rooms: 0,
+        messages: 0,
+        notifications: 0,
+        mentions: 0,
+    };
+    for room in sdk.joined_rooms() {
+        let messages = room.num_unread_messages();
+        let notifications = room.num_unread_notifications();
+        if messages > 0 || notifications > 0 || room.is_marked_unread() {
+            out.unread_rooms += 1;
+        }
+        out.messages += messages;
+        out.notifications += notifications;
+        out.mentions += room.num_unread_mentions();
+    }
+    out
+}
@@ -10,8 +10,8 @@ use matrix_sdk::{
     Client as SdkClient, EncryptionState, Room, RoomDisplayName, RoomMemberships, RoomState,
     notification_settings::RoomNotificationMode,
     ruma::{
-        EventId, OwnedEventId, OwnedRoomAliasId, OwnedRoomId, OwnedRoomOrAliasId, OwnedServerName,
-        OwnedUserId, RoomVersionId, SpaceChildOrder, UInt, UserId,
+        EventId, OwnedDeviceId, OwnedEventId, OwnedRoomAliasId, OwnedRoomId, OwnedRoomOrAliasId,
+        OwnedServerName, OwnedUserId, RoomVersionId, SpaceChildOrder, UInt, UserId,
         api::client::{
             directory::get_public_rooms_filtered,
             presence::{get_presence::v3 as get_presence_v3, set_presence::v3 as set_presence_v3},
@@ -53,22 +53,24 @@ use matrix_sdk::{
 };
 use matrix_sdk_ui::{
     eyeball_im::{Vector, VectorDiff},
-    room_list_service::RoomListItem,
+    room_list_service::{RoomListItem, filters},
     sync_service::SyncService,
     timeline::{RoomExt as _, Timeline},
+    unable_to_decrypt_hook::UtdHookManager,
 };
 use serde_json;
-use tracing::warn;
+use tracing::{info, warn};
 
 use crate::{
     ActionAvailability, ActionPresentation, AttachmentInfo, AttachmentKind, DirectoryUser,
-    FfiError, FfiPushRuleKind, FfiRoomNotificationMode, KnockRequestSummary, MemberActionState, MemberSummary, MessageActionState, MessageEvent,
-    OwnReceipt, PasswordLoginKind, PollDefinition, PredecessorRoomInfo, Presence, PresenceInfo,
-    PublicRoom, PublicRoomsPage, ReactionSummary, RoomActionState, RoomDirectoryVisibility,
-    RoomHistoryVisibility, RoomJoinRule, RoomListEntry, RoomListMembership, RoomPowerLevelChanges,
-    RoomPowerLevels, RoomPreview, RoomPreviewMembership, RoomSummary, RoomTags, RoomUpgradeLinks,
-    SearchHit, SearchPage, SeenByEntry, SendState, SendUpdate, SpaceChildInfo, SpaceHierarchyPage,
-    SpaceInfo, SuccessorRoomInfo, ThreadPage, ThreadSummary, UnreadStats,
+    FfiError, FfiPushRuleKind, FfiRoomNotificationMode, KnockRequestSummary, MemberActionState,
+    MemberSummary, MessageActionState, MessageEvent, OwnReceipt, PasswordLoginKind, PollDefinition,
+    PredecessorRoomInfo, Presence, PresenceInfo, PublicRoom, PublicRoomsPage, ReactionSummary,
+    RoomActionState, RoomDirectoryVisibility, RoomHistoryVisibility, RoomJoinRule, RoomListDiff,
+    RoomListEntry, RoomListFilter, RoomListMembership, RoomPowerLevelChanges, RoomPowerLevels,
+    RoomPreview, RoomPreviewMembership, RoomSummary, RoomTags, RoomUpgradeLinks, SearchFilter,
+    SearchPage, SeenByEntry, SendState, SendUpdate, SpaceChildInfo, SpaceHierarchyPage, SpaceInfo,
+    SpaceTree, SuccessorRoomInfo, ThreadPage, ThreadSummary, UnreadStats,
     VerificationInboxObserver, build_unstable_poll_content, latest_room_event_for,
     map_event_id_via_timeline, map_timeline_event, paginate_backwards_visible,
     timeline_event_filter,
@@ -76,9 +78,12 @@ use crate::{
 
 const REACTION_NOTIFY_RULE_ID: &str = "org.mlm.mages.reaction.notify";
 use crate::{
-    RoomProfile,
-    errors::{IntoFfi, OptionFfi, ffi_err},
-    safe_call,
+    DeviceDeletion, IdentityResetListener, IdentityResetStep, RoomProfile, ThreePid,
+    ThreePidMedium, UiaaHandler, UiaaResponse, UiaaStage, UtdRoomStats, dehydration,
+    errors::{IntoFfi, OptionFfi},
+    safe_call, space_tree,
+    uiaa::{self, UiaaPurpose},
+    utd::{OpenTimelines, UtdReporter},
 };
 
 #[cfg(not(target_family = "wasm"))]
@@ -108,8 +113,13 @@ pub fn room_list_membership(room: &matrix_sdk::Room) -> RoomListMembership {
 #[derive(Clone)]
 pub struct TimelineManager {
     pub(crate) client: SdkClient,
-    timelines: Arc<Mutex<HashMap<OwnedRoomId, Arc<Timeline>>>>,
+    timelines: Arc<OpenTimelines>,
     members_fetched: Arc<Mutex<HashSet<OwnedRoomId>>>,
+    pub(crate) utd: Arc<UtdReporter>,
+    /// Shared by every timeline so UTDs are deduplicated client-wide. Built
+    /// with the first timeline, once logged in, so it can reload the UTDs
+    /// already reported in earlier runs.
+    utd_hook: Arc<tokio::sync::OnceCell<Arc<UtdHookManager>>>,
 }
 
 impl TimelineManager {
@@ -118,6 +128,8 @@ impl TimelineManager {
             client,
             timelines: Arc::new(Mutex::new(HashMap::new())),
             members_fetched: Arc::new(Mutex::new(HashSet::new())),
+            utd: Arc::new(UtdReporter::new()),
+            utd_hook: Arc::new(tokio::sync::OnceCell::new()),
         }
     }
 
@@ -147,9 +159,24 @@ impl TimelineManager {
         }
 
         let room = self.client.get_room(room_id)?;
+        let utd_hook = self
+            .utd_hook
+            .get_or_init(|| async {
+                let mut hook = UtdHookManager::new(
+                    self.utd.client_hook(Arc::downgrade(&self.timelines)),
+                    self.client.clone(),
+                );
+                if let Err(e) = hook.reload_from_store().await {
+                    warn!("Could not reload reported UTDs: {e}");
+                }
+                Arc::new(hook)
+            })
+            .await
+            .clone();
         let tl = Arc::new(
             room.timeline_builder()
                 .event_filter(timeline_event_filter)
+                .with_unable_to_decrypt_hook(utd_hook)
                 .build()
                 .await
                 .ok()?,
@@ -207,13 +234,13 @@ impl CoreClient {
 
     pub(crate) fn require_room(&self, room_id: &str) -> Result<Room, FfiError> {
         let rid = Self::parse_rid(room_id)?;
-        self.sdk.get_room(&rid).or_ffi("room not found")
+        self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)
     }
 
     pub(crate) async fn require_timeline(&self, room_id: &str) -> Result<Arc<Timeline>, FfiError> {
         self.timeline(room_id)
             .await
-            .or_ffi("timeline not available")
+            .ok_or(FfiError::TimelineUnavailable)
     }
 
     pub fn user_id_str(&self) -> String {
@@ -355,6 +382,450 @@ impl CoreClient {
         Ok(())
     }
 
+    /// Registers a new account, walking `handler` through the server's UIAA
+    /// stages. On success the SDK session is set, as after a login.
+    pub async fn register(
+        &self,
+        username: Option<String>,
+        password: String,
+        device_display_name: Option<String>,
+        handler: &dyn UiaaHandler,
+    ) -> Result<(), FfiError> {
+        use matrix_sdk::ruma::api::client::account::register::v3::Request as RegisterRequest;
+
+        let username = username
+            .map(|u| u.trim().to_owned())
+            .filter(|u| !u.is_empty());
+        let device_display_name = device_display_name
+            .map(|n| n.trim().to_owned())
+            .filter(|n| !n.is_empty());
+
+        uiaa::drive(&self.sdk, UiaaPurpose::Registration, handler, |auth| {
+            let mut req = RegisterRequest::new();
+            req.username = username.clone();
+            req.password = Some(password.clone());
+            req.initial_device_display_name = device_display_name.clone();
+            req.refresh_token = true;
+            req.auth = auth;
+            let sdk = self.sdk.clone();
+            async move { sdk.matrix_auth().register(req).await }
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Password and 3PID endpoints only exist for `matrix` auth sessions;
+    /// OAuth users manage them through `account_management_url`.
+    fn require_matrix_auth(&self) -> Result<(), FfiError> {
+        if self.sdk.oauth().full_session().is_some() {
+            return Err(FfiError::Forbidden(
+                "account is managed by the OAuth provider; use account_management_url".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn change_password(
+        &self,
+        new_password: String,
+        logout_devices: bool,
+        handler: &dyn UiaaHandler,
+    ) -> Result<(), FfiError> {
+        use matrix_sdk::ruma::api::client::account::change_password::v3::Request;
+
+        self.require_matrix_auth()?;
+        uiaa::drive(&self.sdk, UiaaPurpose::Account, handler, |auth| {
+            let mut req = Request::new(new_password.clone());
+            req.logout_devices = logout_devices;
+            req.auth = auth;
+            let sdk = self.sdk.clone();
+            async move { sdk.send(req).await }
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_3pids(&self) -> Result<Vec<ThreePid>, FfiError> {
+        use matrix_sdk::ruma::api::client::account::get_3pids::v3::Request;
+        use matrix_sdk::ruma::thirdparty::Medium;
+
+        let resp = self.sdk.send(Request::new()).await?;
+        Ok(resp
+            .threepids
+            .into_iter()
+            .filter_map(|t| {
+                let medium = match t.medium {
+                    Medium::Email => ThreePidMedium::Email,
+                    Medium::Msisdn => ThreePidMedium::Msisdn,
+                    _ => return None,
+                };
+                Some(ThreePid {
+                    medium,
+                    address: t.address,
+                    validated_at_ms: u64::from(t.validated_at.0),
+                    added_at_ms: u64::from(t.added_at.0),
+                })
+            })
+            .collect())
+    }
+
+    /// Sends a validation email / SMS for `address`, waits for the host to
+    /// confirm it through `handler`, then binds it (with UIAA if asked).
+    /// `country` is the 2-letter code for phone numbers.
+    pub async fn add_3pid(
+        &self,
+        medium: ThreePidMedium,
+        address: String,
+        country: Option<String>,
+        handler: &dyn UiaaHandler,
+    ) -> Result<(), FfiError> {
+        use matrix_sdk::ruma::ClientSecret;
+        use matrix_sdk::ruma::api::client::account::{
+            add_3pid, request_3pid_management_token_via_email as via_email,
+            request_3pid_management_token_via_msisdn as via_msisdn,
+        };
+
+        self.require_matrix_auth()?;
+        let address = address.trim().to_owned();
+        if address.is_empty() {
+            return Err(FfiError::Msg("address is required".into()));
+        }
+        let client_secret = ClientSecret::new();
+        let send_attempt = UInt::from(1u32);
+
+        let sid = match medium {
+            ThreePidMedium::Email => {
+                let req = via_email::v3::Request::new(
+                    client_secret.clone(),
+                    address.clone(),
+                    send_attempt,
+                );
+                let sid = self.sdk.send(req).await?.sid;
+                match handler.on_stage(
+                    UiaaStage::EmailIdentity {
+                        sent_to: Some(address.clone()),
+                    },
+                    None,
+                ) {
+                    UiaaResponse::Continue => {}
+                    UiaaResponse::Cancel => return Err(FfiError::Cancelled),
+                    _ => return Err(FfiError::Msg("expected Continue after email sent".into())),
+                }
+                sid
+            }
+            ThreePidMedium::Msisdn => {
+                let country = country.unwrap_or_default().trim().to_uppercase();
+                if country.len() != 2 {
+                    return Err(FfiError::Msg(
+                        "phone numbers require a 2-letter country code".into(),
+                    ));
+                }
+                let req = via_msisdn::v3::Request::new(
+                    client_secret.clone(),
+                    country,
+                    address.clone(),
+                    send_attempt,
+                );
+                let resp = self.sdk.send(req).await?;
+                let submit_url = resp.submit_url.ok_or_else(|| {
+                    FfiError::Msg("homeserver delegates SMS validation; not supported".into())
+                })?;
+                let code = match handler.on_stage(
+                    UiaaStage::MsisdnCode {
+                        sent_to: address.clone(),
+                    },
+                    None,
+                ) {
+                    UiaaResponse::Code { code } => code,
+                    UiaaResponse::Cancel => return Err(FfiError::Cancelled),
+                    _ => return Err(FfiError::Msg("expected Code for SMS validation".into())),
+                };
+                let body = serde_json::json!({
+                    "client_secret": client_secret.as_str(),
+                    "sid": resp.sid.as_str(),
+                    "token": code.trim(),
+                });
+                // The SDK's client, so the configured proxy and TLS apply.
+                let ok = self
+                    .sdk
+                    .http_client()
+                    .post(submit_url)
+                    .json(&body)
+                    .send()
+                    .await
+                    .ffi()?
+                    .json::<serde_json::Value>()
+                    .await
+                    .ffi()?
+                    .get("success")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                if !ok {
+                    return Err(FfiError::Msg("SMS validation code was rejected".into()));
+                }
+                resp.sid
+            }
+        };
+
+        uiaa::drive(&self.sdk, UiaaPurpose::Account, handler, |auth| {
+            let mut req = add_3pid::v3::Request::new(client_secret.clone(), sid.clone());
+            req.auth = auth;
+            let sdk = self.sdk.clone();
+            async move { sdk.send(req).await }
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Returns whether the identity server unbind succeeded (false when
+    /// there was nothing to unbind).
+    pub async fn remove_3pid(
+        &self,
+        medium: ThreePidMedium,
+        address: String,
+    ) -> Result<bool, FfiError> {
+        use matrix_sdk::ruma::api::client::account::ThirdPartyIdRemovalStatus;
+        use matrix_sdk::ruma::api::client::account::delete_3pid::v3::Request;
+        use matrix_sdk::ruma::thirdparty::Medium;
+
+        self.require_matrix_auth()?;
+        let medium = match medium {
+            ThreePidMedium::Email => Medium::Email,
+            ThreePidMedium::Msisdn => Medium::Msisdn,
+        };
+        let resp = self.sdk.send(Request::new(medium, address)).await?;
+        Ok(matches!(
+            resp.id_server_unbind_result,
+            ThirdPartyIdRemovalStatus::Success
+        ))
+    }
+
+    pub async fn deactivate_account(
+        &self,
+        erase: bool,
+        handler: &dyn UiaaHandler,
+    ) -> Result<(), FfiError> {
+        use matrix_sdk::ruma::api::client::account::deactivate::v3::Request;
+
+        self.require_matrix_auth()?;
+        uiaa::drive(&self.sdk, UiaaPurpose::Account, handler, |auth| {
+            let mut req = Request::new();
+            req.erase = erase;
+            req.auth = auth;
+            let sdk = self.sdk.clone();
+            async move { sdk.send(req).await }
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn rename_device(
+        &self,
+        device_id: String,
+        display_name: String,
+    ) -> Result<(), FfiError> {
+        self.sdk
+            .rename_device(&OwnedDeviceId::from(device_id), &display_name)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes other sessions of this account. Matrix-auth accounts go through
+    /// UIAA; OAuth accounts get per-device end-session URLs instead.
+    pub async fn delete_devices(
+        &self,
+        device_ids: Vec<String>,
+        handler: &dyn UiaaHandler,
+    ) -> Result<DeviceDeletion, FfiError> {
+        if let Some(own) = self.sdk.device_id()
+            && device_ids.iter().any(|d| d == own.as_str())
+        {
+            return Err(FfiError::Forbidden(
+                "cannot delete the current session; use logout".into(),
+            ));
+        }
+        if device_ids.is_empty() {
+            return Ok(DeviceDeletion {
+                deleted: Vec::new(),
+                end_session_urls: HashMap::new(),
+            });
+        }
+
+        if self.sdk.oauth().full_session().is_some() {
+            let metadata = self.sdk.oauth().cached_server_metadata().await.ffi()?;
+            let base = metadata
+                .account_management_uri
+                .ok_or_else(|| FfiError::Msg("provider has no account management URL".into()))?;
+            let end_session_urls = device_ids
+                .into_iter()
+                .map(|device_id| {
+                    let mut url = base.clone();
+                    url.query_pairs_mut()
+                        .append_pair("action", "org.matrix.session_end")
+                        .append_pair("device_id", &device_id);
+                    (device_id, url.to_string())
+                })
+                .collect();
+            return Ok(DeviceDeletion {
+                deleted: Vec::new(),
+                end_session_urls,
+            });
+        }
+
+        let ids: Vec<OwnedDeviceId> = device_ids.iter().map(|d| d.as_str().into()).collect();
+        uiaa::drive(&self.sdk, UiaaPurpose::Account, handler, |auth| {
+            let sdk = self.sdk.clone();
+            let ids = ids.clone();
+            async move { sdk.delete_devices(&ids, auth).await }
+        })
+        .await?;
+        info!("Deleted {} device(s)", device_ids.len());
+        Ok(DeviceDeletion {
+            deleted: device_ids,
+            end_session_urls: HashMap::new(),
+        })
+    }
+
+    /// Deletes every session except this one.
+    pub async fn sign_out_other_sessions(
+        &self,
+        handler: &dyn UiaaHandler,
+    ) -> Result<DeviceDeletion, FfiError> {
+        let own = self.sdk.device_id().map(ToOwned::to_owned);
+        // The dehydrated device is not a session; it goes with the setting.
+        let dehydrated: HashSet<OwnedDeviceId> = match self.sdk.user_id() {
+            Some(me) => self
+                .sdk
+                .encryption()
+                .get_user_devices(me)
+                .await
+                .ffi()?
+                .devices()
+                .filter(|d| d.is_dehydrated())
+                .map(|d| d.device_id().to_owned())
+                .collect(),
+            None => HashSet::new(),
+        };
+        let others = self
+            .sdk
+            .devices()
+            .await?
+            .devices
+            .into_iter()
+            .filter(|d| Some(&d.device_id) != own.as_ref() && !dehydrated.contains(&d.device_id))
+            .map(|d| d.device_id.to_string())
+            .collect();
+        self.delete_devices(others, handler).await
+    }
+
+    /// Bootstraps fresh cross-signing keys, e.g. after the user lost access
+    /// to the old ones. Other users will see our identity change.
+    pub async fn reset_identity(
+        &self,
+        handler: &dyn UiaaHandler,
+        listener: &dyn IdentityResetListener,
+    ) -> Result<(), FfiError> {
+        use matrix_sdk::encryption::CrossSigningResetAuthType;
+
+        let step = |s: IdentityResetStep| safe_call(|| listener.on_step(s));
+        step(IdentityResetStep::Starting);
+        let Some(handle) = self.sdk.encryption().recovery().reset_identity().await? else {
+            step(IdentityResetStep::Done);
+            return Ok(());
+        };
+
+        let result = match handle.auth_type() {
+            CrossSigningResetAuthType::Uiaa(_) => {
+                step(IdentityResetStep::AwaitingAuthentication);
+                uiaa::drive(&self.sdk, UiaaPurpose::Account, handler, |auth| {
+                    handle.reset(auth)
+                })
+                .await
+            }
+            CrossSigningResetAuthType::OAuth(info) => {
+                step(IdentityResetStep::OAuthApproval {
+                    url: info.approval_url.to_string(),
+                });
+                handle.reset(None).await.map_err(Into::into)
+            }
+        };
+        if let Err(e) = result {
+            handle.cancel().await;
+            return Err(e);
+        }
+        info!("Identity reset");
+        step(IdentityResetStep::Done);
+        Ok(())
+    }
+
+    /// Accepts a changed identity of `user_id` (clears a pin violation).
+    pub async fn pin_user_identity(&self, user_id: String) -> Result<(), FfiError> {
+        self.user_identity(&user_id).await?.pin().await?;
+        Ok(())
+    }
+
+    /// Drops our verification of `user_id` after their identity changed.
+    pub async fn withdraw_user_verification(&self, user_id: String) -> Result<(), FfiError> {
+        self.user_identity(&user_id)
+            .await?
+            .withdraw_verification()
+            .await?;
+        Ok(())
+    }
+
+    async fn user_identity(
+        &self,
+        user_id: &str,
+    ) -> Result<matrix_sdk::encryption::identities::UserIdentity, FfiError> {
+        let uid = UserId::parse(user_id).ffi()?;
+        self.sdk
+            .encryption()
+            .get_user_identity(&uid)
+            .await
+            .ffi()?
+            .or_ffi("user identity not found")
+    }
+
+    /// Whether a dehydrated device is kept on the server for this account.
+    pub async fn dehydrated_device_enabled(&self) -> bool {
+        dehydration::is_enabled(&self.sdk).await
+    }
+
+    /// Pass the recovery key when enabling so other devices can rehydrate too.
+    pub async fn set_dehydrated_device_enabled(
+        &self,
+        enabled: bool,
+        recovery_key: Option<String>,
+    ) -> Result<(), FfiError> {
+        dehydration::set_enabled(&self.sdk, enabled, recovery_key.as_deref()).await
+    }
+
+    /// Fetches the given megolm sessions from key backup (when enabled) and
+    /// asks the room's timeline to decrypt the matching events again.
+    pub async fn retry_decryption(
+        &self,
+        room_id: String,
+        session_ids: Vec<String>,
+    ) -> Result<(), FfiError> {
+        let rid = Self::parse_rid(&room_id)?;
+        let backups = self.sdk.encryption().backups();
+        if backups.are_enabled().await {
+            for session_id in &session_ids {
+                if let Err(e) = backups.download_room_key(&rid, session_id).await {
+                    warn!("Backup download of {session_id} in {rid} failed: {e}");
+                }
+            }
+        }
+        if let Some(tl) = self.timeline(&room_id).await {
+            tl.retry_decryption(session_ids).await;
+        }
+        Ok(())
+    }
+
+    pub async fn utd_stats(&self) -> Vec<UtdRoomStats> {
+        self.timeline_mgr.utd.snapshot()
+    }
+
     pub async fn finish_authenticated_setup_common(&self) {
         self.sdk
             .encryption()
@@ -403,7 +874,7 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let content = if let Some(fmt) = formatted_body {
             RoomMessageEventContent::text_html(body, fmt)
         } else {
@@ -437,9 +908,9 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
-        let reply_to =
-            EventId::parse(&in_reply_to).map_err(|_| FfiError::Msg("invalid event id".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
+        let reply_to = EventId::parse(&in_reply_to)
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         let content = if let Some(fmt) = formatted_body {
             MsgNoRel::text_html(body, fmt)
         } else {
@@ -459,9 +930,9 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let eid = EventId::parse(&target_event_id)
-            .map_err(|_| FfiError::Msg("invalid event id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         let item = tl
             .item_by_event_id(&eid)
             .await
@@ -480,11 +951,9 @@ impl CoreClient {
         event_id: String,
         reason: Option<String>,
     ) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
-        let eid =
-            EventId::parse(&event_id).map_err(|_| FfiError::Msg("invalid event id".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
+        let eid = EventId::parse(&event_id)
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         room.redact(&eid, reason.as_deref(), None)
             .await
             .ffi()
@@ -500,9 +969,9 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
-        let eid =
-            EventId::parse(&event_id).map_err(|_| FfiError::Msg("invalid event id".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
+        let eid = EventId::parse(&event_id)
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         let item = tl
             .item_by_event_id(&eid)
             .await
@@ -525,9 +994,9 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let root = OwnedEventId::try_from(root_event_id)
-            .map_err(|_| FfiError::Msg("invalid event id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         let mut content = if let Some(fmt) = formatted_body {
             RoomMessageEventContent::text_html(body, fmt)
         } else {
@@ -560,7 +1029,7 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let receipt_type = if send_public_receipt {
             ReceiptType::Read
         } else {
@@ -576,13 +1045,10 @@ impl CoreClient {
         send_public_receipt: bool,
     ) -> Result<(), FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let eid =
-            EventId::parse(&event_id).map_err(|_| FfiError::Msg("invalid event id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let eid = EventId::parse(&event_id)
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let receipt_type = if send_public_receipt {
             ReceiptType::Read
         } else {
@@ -600,13 +1066,10 @@ impl CoreClient {
         send_public_receipt: bool,
     ) -> Result<(), FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
         let eid = OwnedEventId::try_from(event_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid event id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let receipts = if send_public_receipt {
             matrix_sdk::room::Receipts::new()
                 .public_read_receipt(eid.clone())
@@ -627,7 +1090,7 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
 
         let receipt_type = if send_public_receipt {
             ReceiptType::Read
@@ -639,19 +1102,14 @@ impl CoreClient {
     }
 
     pub async fn set_mark_unread(&self, room_id: String, unread: bool) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.set_unread_flag(unread).await.ffi()
     }
 
     pub async fn is_marked_unread(&self, room_id: String) -> Result<Option<bool>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(Some(room.is_marked_unread()))
     }
 
@@ -686,17 +1144,17 @@ impl CoreClient {
         user_id: String,
     ) -> Result<(), FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let eid =
-            EventId::parse(&event_id).map_err(|_| FfiError::Msg("invalid event id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let eid = EventId::parse(&event_id)
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         let uid = user_id
             .parse::<OwnedUserId>()
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         let tl = self
             .timeline_mgr
             .timeline_for(&rid)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let latest = tl
             .latest_user_read_receipt_timeline_event_id(&uid)
             .await
@@ -728,12 +1186,12 @@ impl CoreClient {
 
     pub async fn paginate_backwards(&self, room_id: String, count: u16) -> Result<bool, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
         let tl = self
             .timeline_mgr
             .timeline_for(&rid)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let me = self.user_id_str();
         Ok(paginate_backwards_visible(&tl, &rid, &me, count as usize).await)
     }
@@ -742,7 +1200,7 @@ impl CoreClient {
         let tl = self
             .timeline(&room_id)
             .await
-            .ok_or_else(|| FfiError::Msg("timeline not found".into()))?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         tl.paginate_forwards(count).await.ffi()
     }
 
@@ -771,9 +1229,7 @@ impl CoreClient {
     }
 
     pub async fn set_typing(&self, room_id: String, typing: bool) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.typing_notice(typing).await.ffi()
     }
 
@@ -820,7 +1276,7 @@ impl CoreClient {
 
     pub async fn room_profile(&self, room_id: String) -> Result<Option<RoomProfile>, FfiError> {
         let rid = Self::parse_rid(&room_id)?;
-        let room = self.sdk.get_room(&rid).or_ffi("room not found")?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(Some(self.build_room_profile(&room).await?))
     }
 
@@ -977,7 +1433,7 @@ impl CoreClient {
         let me = self
             .sdk
             .user_id()
-            .ok_or_else(|| FfiError::Msg("not logged in".into()))?;
+            .ok_or_else(|| FfiError::Unauthorized("not logged in".into()))?;
 
         let is_dm = room.is_direct().await.unwrap_or(false);
 
@@ -990,7 +1446,7 @@ impl CoreClient {
             .unwrap_or(0);
 
         let target_user_id = matrix_sdk::ruma::OwnedUserId::try_from(&*user_id)
-            .map_err(|_| FfiError::Msg("invalid target user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid target user id".into()))?;
         let target_level: i64 = power_levels
             .users
             .get(target_user_id.as_ref() as &matrix_sdk::ruma::UserId)
@@ -1031,7 +1487,7 @@ impl CoreClient {
             let target_member = room
                 .get_member(
                     <&matrix_sdk::ruma::UserId>::try_from(user_id)
-                        .map_err(|_| FfiError::Msg("invalid user id".into()))?,
+                        .map_err(|_| FfiError::InvalidId("invalid user id".into()))?,
                 )
                 .await
                 .ffi()?;
@@ -1147,7 +1603,7 @@ impl CoreClient {
         let me = self
             .sdk
             .user_id()
-            .ok_or_else(|| FfiError::Msg("not logged in".into()))?;
+            .ok_or_else(|| FfiError::Unauthorized("not logged in".into()))?;
 
         let power_levels = room.power_levels().await.ffi()?;
         let my_level: i64 = power_levels
@@ -1158,7 +1614,7 @@ impl CoreClient {
             .unwrap_or(0);
 
         let sender_id = matrix_sdk::ruma::OwnedUserId::try_from(&*sender_user_id)
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         let is_me = me.as_str() == sender_user_id;
 
         let sender_level: i64 = power_levels
@@ -1309,11 +1765,8 @@ impl CoreClient {
         room_id: String,
     ) -> Result<Option<UnreadStats>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(Some(UnreadStats {
             messages: room.num_unread_messages(),
             notifications: room.num_unread_notifications(),
@@ -1323,11 +1776,8 @@ impl CoreClient {
 
     pub async fn room_tags(&self, room_id: String) -> Result<Option<RoomTags>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(Some(RoomTags {
             is_favourite: room.is_favourite(),
             is_low_priority: room.is_low_priority(),
@@ -1335,16 +1785,12 @@ impl CoreClient {
     }
 
     pub async fn set_room_favourite(&self, room_id: String, fav: bool) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.set_is_favourite(fav, None).await.ffi()
     }
 
     pub async fn set_room_low_priority(&self, room_id: String, low: bool) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.set_is_low_priority(low, None).await.ffi()
     }
 
@@ -1353,11 +1799,8 @@ impl CoreClient {
         room_id: String,
     ) -> Result<Option<FfiRoomNotificationMode>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let mode = room
             .notification_mode()
             .await
@@ -1589,11 +2032,8 @@ impl CoreClient {
         event_ids: Vec<String>,
     ) -> Result<(), FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let parsed: Vec<_> = event_ids
             .iter()
             .filter_map(|id| EventId::parse(id).ok())
@@ -1605,9 +2045,7 @@ impl CoreClient {
     }
 
     pub async fn set_room_name(&self, room_id: String, name: String) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.send_state_event(RoomNameEventContent::new(name))
             .await
             .ffi()
@@ -1615,9 +2053,7 @@ impl CoreClient {
     }
 
     pub async fn set_room_topic(&self, room_id: String, topic: String) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.send_state_event(RoomTopicEventContent::new(topic))
             .await
             .ffi()
@@ -1626,15 +2062,12 @@ impl CoreClient {
 
     pub async fn dm_peer_user_id(&self, room_id: String) -> Result<Option<String>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let me = self
             .sdk
             .user_id()
-            .ok_or_else(|| FfiError::Msg("not logged in".into()))?;
+            .ok_or_else(|| FfiError::Unauthorized("not logged in".into()))?;
         let members = room.members(RoomMemberships::ACTIVE).await.ffi()?;
         Ok(members
             .into_iter()
@@ -1663,11 +2096,9 @@ impl CoreClient {
         user_id: String,
         reason: Option<String>,
     ) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         let uid = OwnedUserId::try_from(user_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         room.ban_user(uid.as_ref(), reason.as_deref()).await.ffi()
     }
 
@@ -1677,11 +2108,9 @@ impl CoreClient {
         user_id: String,
         reason: Option<String>,
     ) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         let uid = OwnedUserId::try_from(user_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         room.unban_user(uid.as_ref(), reason.as_deref()).await.ffi()
     }
 
@@ -1691,26 +2120,22 @@ impl CoreClient {
         user_id: String,
         reason: Option<String>,
     ) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         let uid = OwnedUserId::try_from(user_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         room.kick_user(uid.as_ref(), reason.as_deref()).await.ffi()
     }
 
     pub async fn invite_user(&self, room_id: String, user_id: String) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         let uid = OwnedUserId::try_from(user_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         room.invite_user_by_id(uid.as_ref()).await.ffi()
     }
 
     pub async fn accept_invite(&self, room_id: String) -> Result<(), FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
         self.sdk.join_room_by_id(&rid).await.ffi().map(|_| ())
     }
 
@@ -1725,7 +2150,7 @@ impl CoreClient {
     ) -> Result<(), FfiError> {
         let room = self.require_room(&room_id)?;
         let event_id = OwnedEventId::try_from(notification_event_id.as_str())
-            .map_err(|_| ffi_err!("invalid event id"))?;
+            .map_err(|_| FfiError::InvalidId("invalid event id".into()))?;
         let content = room.make_decline_call_event(&event_id).await.ffi()?;
         room.send(content).await.ffi().map(|_| ())
     }
@@ -1752,9 +2177,7 @@ impl CoreClient {
     }
 
     pub async fn enable_room_encryption(&self, room_id: String) -> Result<(), FfiError> {
-        let room = self
-            .room(&room_id)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.room(&room_id).ok_or(FfiError::RoomNotFound)?;
         room.enable_encryption().await.ffi()
     }
 
@@ -1835,12 +2258,9 @@ impl CoreClient {
     ) -> Result<(), FfiError> {
         use matrix_sdk::ruma::Int;
         let Ok(rid) = OwnedRoomId::try_from(room_id) else {
-            return Err(FfiError::Msg("bad room id".into()));
+            return Err(FfiError::InvalidId("bad room id".into()));
         };
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let uid = UserId::parse(&user_id).map_err(|e| FfiError::Msg(e.to_string()))?;
         let level =
             Int::new(power_level).ok_or_else(|| FfiError::Msg("invalid power level".into()))?;
@@ -1949,10 +2369,10 @@ impl CoreClient {
         body: Option<String>,
     ) -> Result<(), FfiError> {
         let Ok(rid) = OwnedRoomId::try_from(room_id) else {
-            return Err(FfiError::Msg("invalid room id".into()));
+            return Err(FfiError::InvalidId("invalid room id".into()));
         };
         let Some(room) = self.sdk.get_room(&rid) else {
-            return Err(FfiError::Msg("room not found".into()));
+            return Err(FfiError::RoomNotFound);
         };
         let default_caption = match att.kind {
             AttachmentKind::Image => "Image",
@@ -1977,6 +2397,7 @@ impl CoreClient {
                 info.size = att.size_bytes.and_then(UInt::new);
                 info.width = att.width.map(UInt::from);
                 info.height = att.height.map(UInt::from);
+                info.blurhash = att.blurhash.clone();
                 let mut img = ImageMessageEventContent::new(caption, media_source);
                 img.info = Some(Box::new(info));
                 MessageType::Image(img)
@@ -1987,6 +2408,7 @@ impl CoreClient {
                 info.size = att.size_bytes.and_then(UInt::new);
                 info.width = att.width.map(UInt::from);
                 info.height = att.height.map(UInt::from);
+                info.blurhash = att.blurhash.clone();
                 info.duration = att.duration_ms.map(Duration::from_millis);
                 let mut vid = VideoMessageEventContent::new(caption, media_source);
                 vid.info = Some(Box::new(info));
@@ -2124,7 +2546,7 @@ impl CoreClient {
     pub async fn is_user_ignored(&self, user_id: String) -> Result<bool, FfiError> {
         let uid = user_id
             .parse::<OwnedUserId>()
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         Ok(self.sdk.is_user_ignored(uid.as_ref()).await)
     }
 
@@ -2284,7 +2706,7 @@ impl CoreClient {
 
     pub async fn knock(&self, id_or_alias: String) -> Result<(), FfiError> {
         let target = OwnedRoomOrAliasId::try_from(id_or_alias.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id or alias".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id or alias".into()))?;
         self.sdk.knock(target, None, vec![]).await.ffi().map(|_| ())
     }
 
@@ -2294,7 +2716,7 @@ impl CoreClient {
         }
         if id_or_alias.starts_with('#') {
             let alias = OwnedRoomAliasId::try_from(id_or_alias.as_str())
-                .map_err(|_| FfiError::Msg("invalid room alias".into()))?;
+                .map_err(|_| FfiError::InvalidId("invalid room alias".into()))?;
             let resp = self.sdk.resolve_room_alias(&alias).await.ffi()?;
             return Ok(Some(resp.room_id.to_string()));
         }
@@ -2584,11 +3006,8 @@ impl CoreClient {
         room_id: String,
     ) -> Result<Option<SuccessorRoomInfo>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(room.successor_room().map(Into::into))
     }
 
@@ -2597,11 +3016,8 @@ impl CoreClient {
         room_id: String,
     ) -> Result<Option<PredecessorRoomInfo>, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(room.predecessor_room().map(Into::into))
     }
 
@@ -2651,7 +3067,10 @@ impl CoreClient {
         state: Presence,
         status_msg: Option<String>,
     ) -> Result<(), FfiError> {
-        let me = self.sdk.user_id().or_ffi("No logged-in user")?;
+        let me = self
+            .sdk
+            .user_id()
+            .ok_or_else(|| FfiError::Unauthorized("no logged-in user".into()))?;
         let presence = match state {
             Presence::Online => PresenceState::Online,
             Presence::Offline => PresenceState::Offline,
@@ -2684,11 +3103,8 @@ impl CoreClient {
 
     pub async fn is_space(&self, room_id: String) -> Result<bool, FfiError> {
         let rid = OwnedRoomId::try_from(room_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid room id".into()))?;
-        let room = self
-            .sdk
-            .get_room(&rid)
-            .ok_or_else(|| FfiError::Msg("room not found".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid room id".into()))?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         Ok(room.is_space())
     }
 
@@ -2714,6 +3130,67 @@ impl CoreClient {
         out
     }
 
+    /// Builds the room list filter for `f`. Space membership is read once
+    /// here, so a filter has to be rebuilt to pick up new children;
+    /// `observe_room_list` does so on every space link change.
+    pub async fn room_list_filter(&self, f: &RoomListFilter) -> filters::BoxedFilterFn {
+        let mut parts: Vec<filters::BoxedFilterFn> = vec![Box::new(filters::new_filter_non_left())];
+        if f.unread_only {
+            parts.push(Box::new(|room: &RoomListItem| {
+                room.read_receipts().num_unread > 0 || room.is_marked_unread()
+            }));
+        }
+        if f.dms_only {
+            parts.push(Box::new(filters::new_filter_category(
+                filters::RoomCategory::People,
+            )));
+        }
+        if f.groups_only {
+            parts.push(Box::new(filters::new_filter_category(
+                filters::RoomCategory::Group,
+            )));
+        }
+        if f.favourites {
+            parts.push(Box::new(filters::new_filter_favourite()));
+        }
+        if f.low_priority {
+            parts.push(Box::new(filters::new_filter_low_priority()));
+        }
+        if f.invites {
+            parts.push(Box::new(filters::new_filter_invite()));
+        }
+        if f.mentions_only {
+            parts.push(Box::new(|room: &RoomListItem| {
+                room.num_unread_mentions() > 0
+            }));
+        }
+        if let Some(space_id) = f.space_id.as_deref() {
+            let space = Self::parse_rid(space_id)
+                .ok()
+                .and_then(|rid| self.sdk.get_room(&rid));
+            let children = match space {
+                Some(space) if f.include_subspaces => {
+                    space_tree::descendant_rooms(&self.sdk, space.room_id()).await
+                }
+                Some(space) => space_tree::child_ids(&space).await,
+                None => HashSet::new(),
+            };
+            parts.push(Box::new(move |room: &RoomListItem| {
+                children.contains(room.room_id())
+            }));
+        }
+        let query = f.name_query.as_deref().map(str::trim);
+        if let Some(query) = query.filter(|q| !q.is_empty()) {
+            parts.push(Box::new(filters::new_filter_fuzzy_match_room_name(query)));
+        }
+        Box::new(filters::new_filter_all(parts))
+    }
+
+    pub async fn space_tree(&self) -> SpaceTree {
+        let graph = space_tree::SpaceGraph::load(&self.sdk).await;
+        space_tree::build(&self.sdk, &graph).await
+    }
+
     pub async fn space_add_child(
         &self,
         space_id: String,
@@ -2723,7 +3200,10 @@ impl CoreClient {
     ) -> Result<(), FfiError> {
         let rid_space = Self::parse_rid(&space_id)?;
         let rid_child = Self::parse_rid(&child_room_id)?;
-        let room = self.sdk.get_room(&rid_space).or_ffi("space not found")?;
+        let room = self
+            .sdk
+            .get_room(&rid_space)
+            .ok_or(FfiError::RoomNotFound)?;
         let via: Vec<_> = rid_child
             .server_name()
             .map(|s| s.to_owned())
@@ -2749,7 +3229,10 @@ impl CoreClient {
         child_room_id: String,
     ) -> Result<(), FfiError> {
         let rid_space = Self::parse_rid(&space_id)?;
-        let room = self.sdk.get_room(&rid_space).or_ffi("space not found")?;
+        let room = self
+            .sdk
+            .get_room(&rid_space)
+            .ok_or(FfiError::RoomNotFound)?;
         room.send_state_event_raw(
             "m.space.child",
             child_room_id.as_str(),
@@ -2809,11 +3292,9 @@ impl CoreClient {
         space_id: String,
         user_id: String,
     ) -> Result<(), FfiError> {
-        let room = self
-            .room(&space_id)
-            .ok_or_else(|| FfiError::Msg("space not found".into()))?;
+        let room = self.room(&space_id).ok_or(FfiError::RoomNotFound)?;
         let uid = OwnedUserId::try_from(user_id.as_str())
-            .map_err(|_| FfiError::Msg("invalid user id".into()))?;
+            .map_err(|_| FfiError::InvalidId("invalid user id".into()))?;
         room.invite_user_by_id(&uid).await.ffi()
     }
 
@@ -2904,12 +3385,12 @@ impl CoreClient {
         use matrix_sdk::ruma::api::client::profile::{AvatarUrl, DisplayName};
         let rid = Self::parse_rid(&room_id)?;
         let eid = Self::parse_eid(&event_id)?;
-        let room = self.sdk.get_room(&rid).or_ffi("room not found")?;
+        let room = self.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
         let tl = self
             .timeline_mgr
             .timeline_for(&rid)
             .await
-            .or_ffi("timeline not available")?;
+            .ok_or(FfiError::TimelineUnavailable)?;
         let _ = tl.fetch_members().await;
 
         let me = self.sdk.user_id().map(|u| u.to_string());
@@ -3144,40 +3625,31 @@ impl CoreClient {
             return Ok(SearchPage {
                 hits: vec![],
                 next_offset: None,
+                next_cursor: None,
             });
         };
-        let event_ids = room
-            .search(query.trim(), limit_usize, offset.map(|o| o as usize))
-            .await
-            .ffi()?;
-        let mut hits = Vec::new();
-        for eid in event_ids.iter() {
-            if let Some(mev) =
-                map_event_id_via_timeline(&self.timeline_mgr, &self.sdk, &rid, eid).await
-            {
-                hits.push(SearchHit {
-                    room_id: rid.to_string(),
-                    event_id: mev.event_id.clone(),
-                    sender: mev.sender.clone(),
-                    body: mev.body.clone(),
-                    timestamp_ms: mev.timestamp_ms,
-                });
-            } else {
-                hits.push(SearchHit {
-                    room_id: rid.to_string(),
-                    event_id: eid.to_string(),
-                    sender: "".into(),
-                    body: "".into(),
-                    timestamp_ms: 0,
-                });
-            }
+        crate::search::search_room(&room, query.trim(), limit_usize, offset).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn search_all_rooms(
+        &self,
+        query: String,
+        filter: SearchFilter,
+        limit: u32,
+        cursor: Option<String>,
+    ) -> Result<SearchPage, FfiError> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(SearchPage {
+                hits: vec![],
+                next_offset: None,
+                next_cursor: None,
+            });
         }
-        let next_offset = if hits.len() == limit_usize {
-            Some(offset.unwrap_or(0).saturating_add(hits.len() as u32))
-        } else {
-            None
-        };
-        Ok(SearchPage { hits, next_offset })
+        let limit_usize = (limit.max(1)).min(200) as usize;
+        crate::search::search_all_rooms(&self.sdk, query, &filter, limit_usize, cursor.as_deref())
+            .await
     }
 
     pub async fn typing_stream(
@@ -3255,111 +3727,143 @@ impl CoreClient {
         }
     }
 
-    pub fn apply_vector_diff<T: Clone>(items: &mut Vector<T>, diffs: Vec<VectorDiff<T>>) -> bool {
-        let mut changed = false;
-        for diff in diffs {
-            match diff {
-                VectorDiff::Reset { values } => {
-                    *items = values;
-                    changed = true;
-                }
-                VectorDiff::Clear => {
-                    items.clear();
-                    changed = true;
-                }
-                VectorDiff::PushFront { value } => {
-                    items.insert(0, value);
-                    changed = true;
-                }
-                VectorDiff::PushBack { value } => {
-                    items.push_back(value);
-                    changed = true;
-                }
-                VectorDiff::PopFront => {
-                    if !items.is_empty() {
-                        items.remove(0);
-                        changed = true;
-                    }
-                }
-                VectorDiff::PopBack => {
-                    items.pop_back();
-                    changed = true;
-                }
-                VectorDiff::Insert { index, value } => {
-                    if index <= items.len() {
-                        items.insert(index, value);
-                        changed = true;
-                    }
-                }
-                VectorDiff::Set { index, value } => {
-                    if index < items.len() {
-                        items[index] = value;
-                        changed = true;
-                    }
-                }
-                VectorDiff::Remove { index } => {
-                    if index < items.len() {
-                        items.remove(index);
-                        changed = true;
-                    }
-                }
-                VectorDiff::Truncate { length } => {
-                    items.truncate(length);
-                    changed = true;
-                }
-                VectorDiff::Append { values } => {
-                    items.append(values);
-                    changed = true;
-                }
-            }
-        }
-        changed
-    }
-
     pub async fn build_room_list_snapshot(
         &self,
         items: &Vector<RoomListItem>,
     ) -> Vec<RoomListEntry> {
         let mut snapshot = Vec::with_capacity(items.len());
         for item in items.iter() {
-            let room = &**item;
-            let last_ts = room.recency_stamp().map_or(0, |s| s.into());
-            let is_dm = room.is_direct().await.unwrap_or(false);
-            let mut avatar_url = room.avatar_url().map(|mxc| mxc.to_string());
-            if avatar_url.is_none() && is_dm {
-                avatar_url = Self::dm_peer_avatar_url(room, self.sdk.user_id()).await;
-            }
-            let latest_event = latest_room_event_for(room, &self.timeline_mgr).await;
-            let membership = room_list_membership(room);
-            snapshot.push(RoomListEntry {
-                room_id: room.room_id().to_string(),
-                name: item
-                    .cached_display_name()
-                    .clone()
-                    .unwrap_or(RoomDisplayName::Named(room.room_id().to_string()))
-                    .to_string(),
-                last_ts,
-                notifications: room.num_unread_notifications(),
-                messages: room.num_unread_messages(),
-                mentions: room.num_unread_mentions(),
-                marked_unread: room.is_marked_unread(),
-                is_favourite: room.is_favourite(),
-                is_low_priority: room.is_low_priority(),
-                is_invited: matches!(room.state(), RoomState::Invited),
-                membership,
-                avatar_url,
-                is_dm,
-                is_encrypted: matches!(
-                    room.encryption_state(),
-                    matrix_sdk::EncryptionState::Encrypted
-                ),
-                member_count: room.joined_members_count().min(u32::MAX as u64) as u32,
-                topic: room.topic(),
-                latest_event,
-            });
+            snapshot.push(self.build_room_list_entry(item).await);
         }
         snapshot
     }
+
+    pub async fn build_room_list_entry(&self, item: &RoomListItem) -> RoomListEntry {
+        let room = &**item;
+        let last_ts = room.recency_stamp().map_or(0, |s| s.into());
+        let is_dm = room.is_direct().await.unwrap_or(false);
+        let mut avatar_url = room.avatar_url().map(|mxc| mxc.to_string());
+        if avatar_url.is_none() && is_dm {
+            avatar_url = Self::dm_peer_avatar_url(room, self.sdk.user_id()).await;
+        }
+        let latest_event = latest_room_event_for(room, &self.timeline_mgr).await;
+        let membership = room_list_membership(room);
+        RoomListEntry {
+            room_id: room.room_id().to_string(),
+            name: item
+                .cached_display_name()
+                .clone()
+                .unwrap_or(RoomDisplayName::Named(room.room_id().to_string()))
+                .to_string(),
+            last_ts,
+            notifications: room.num_unread_notifications(),
+            messages: room.num_unread_messages(),
+            mentions: room.num_unread_mentions(),
+            marked_unread: room.is_marked_unread(),
+            is_favourite: room.is_favourite(),
+            is_low_priority: room.is_low_priority(),
+            is_invited: matches!(room.state(), RoomState::Invited),
+            membership,
+            avatar_url,
+            is_dm,
+            is_encrypted: matches!(
+                room.encryption_state(),
+                matrix_sdk::EncryptionState::Encrypted
+            ),
+            member_count: room.joined_members_count().min(u32::MAX as u64) as u32,
+            topic: room.topic(),
+            latest_event,
+        }
+    }
+
+    /// Applies `diff` to `entries` and maps it to the positional form sent to
+    /// observers. Only the rooms the diff touches are rebuilt. Returns `None`
+    /// for out-of-range indices, which leave `entries` untouched.
+    pub async fn apply_room_list_diff(
+        &self,
+        entries: &mut Vec<RoomListEntry>,
+        diff: VectorDiff<RoomListItem>,
+    ) -> Option<RoomListDiff> {
+        match diff {
+            VectorDiff::Append { values } => {
+                let mut mapped = Vec::with_capacity(values.len());
+                for item in values.iter() {
+                    mapped.push(self.build_room_list_entry(item).await);
+                }
+                entries.extend(mapped.iter().cloned());
+                Some(RoomListDiff::Append { values: mapped })
+            }
+            VectorDiff::PushFront { value } => {
+                let value = self.build_room_list_entry(&value).await;
+                entries.insert(0, value.clone());
+                Some(RoomListDiff::Insert { index: 0, value })
+            }
+            VectorDiff::PushBack { value } => {
+                let value = self.build_room_list_entry(&value).await;
+                let index = entries.len() as u32;
+                entries.push(value.clone());
+                Some(RoomListDiff::Insert { index, value })
+            }
+            VectorDiff::Insert { index, value } => {
+                if index > entries.len() {
+                    return None;
+                }
+                let value = self.build_room_list_entry(&value).await;
+                entries.insert(index, value.clone());
+                Some(RoomListDiff::Insert {
+                    index: index as u32,
+                    value,
+                })
+            }
+            VectorDiff::Set { index, value } => {
+                let slot = entries.get_mut(index)?;
+                let value = self.build_room_list_entry(&value).await;
+                *slot = value.clone();
+                Some(RoomListDiff::Set {
+                    index: index as u32,
+                    value,
+                })
+            }
+            VectorDiff::PopFront => {
+                if entries.is_empty() {
+                    return None;
+                }
+                entries.remove(0);
+                Some(RoomListDiff::Remove { index: 0 })
+            }
+            VectorDiff::PopBack => {
+                entries.pop()?;
+                Some(RoomListDiff::Remove {
+                    index: entries.len() as u32,
+                })
+            }
+            VectorDiff::Remove { index } => {
+                if index >= entries.len() {
+                    return None;
+                }
+                entries.remove(index);
+                Some(RoomListDiff::Remove {
+                    index: index as u32,
+                })
+            }
+            VectorDiff::Truncate { length } => {
+                entries.truncate(length);
+                Some(RoomListDiff::Truncate {
+                    length: length as u32,
+                })
+            }
+            VectorDiff::Clear => {
+                entries.clear();
+                Some(RoomListDiff::Clear)
+            }
+            VectorDiff::Reset { values } => {
+                *entries = self.build_room_list_snapshot(&values).await;
+                Some(RoomListDiff::Reset {
+                    values: entries.clone(),
+                })
+            }
+        }
+    }
 }
 
 pub(crate) fn map_send_queue_update(
@@ -0,0 +1,241 @@
+use matrix_sdk::{
+    Client as SdkClient,
+    crypto::{DecryptionSettings, TrustRequirement, store::types::DehydratedDeviceKey},
+    ruma::{
+        api::client::{
+            config::{get_global_account_data, set_global_account_data},
+            dehydrated_device::{
+                delete_dehydrated_device, get_dehydrated_device, get_events, put_dehydrated_device,
+            },
+            error::ErrorKind,
+        },
+        events::{GlobalAccountDataEventType, secret::request::SecretName},
+        serde::Raw,
+    },
+};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{FfiError, errors::IntoFfi, ffi_err};
+
+/// Global account data event holding the setting, so every session of the
+/// account sees it, including ones logged in after it was turned on.
+const SETTING_EVENT: &str = "org.mages.dehydrated_device";
+/// Secret-storage name the pickle key is shared under, per MSC3814.
+const SECRET_NAME: &str = "org.matrix.msc3814";
+const DISPLAY_NAME: &str = "Mages (offline device)";
+
+/// The account-wide setting. Accounts without one (the device was parked by
+/// another client) count as enabled while a device is parked on the server.
+pub(crate) async fn is_enabled(sdk: &SdkClient) -> bool {
+    match read_setting(sdk).await {
+        Some(enabled) => enabled,
+        None => matches!(parked_device(sdk).await, Ok(Some(_))),
+    }
+}
+
+/// Turns the setting on or off. Enabling parks a device right away (and
+/// shares its key through secret storage when `recovery_key` is given);
+/// disabling deletes it from the server and forgets the key.
+pub(crate) async fn set_enabled(
+    sdk: &SdkClient,
+    enabled: bool,
+    recovery_key: Option<&str>,
+) -> Result<(), FfiError> {
+    write_setting(sdk, enabled).await?;
+    if enabled {
+        refresh(sdk, recovery_key).await?;
+    } else {
+        remove(sdk).await?;
+    }
+    Ok(())
+}
+
+/// MSC3814: the parked (dehydrated) device keeps receiving room keys while no
+/// Mages instance is online. Rehydrates it if there is one, taking over
+/// those keys, then parks a fresh one. Returns how many keys were recovered.
+///
+/// With `recovery_key` the pickle key is first taken from secret storage.
+/// Without it, and without a key stored locally, a new key is only minted
+/// when the server has no parked device: replacing one would lose its keys.
+pub(crate) async fn refresh(sdk: &SdkClient, recovery_key: Option<&str>) -> Result<u64, FfiError> {
+    if let Some(key) = recovery_key {
+        sync_secret_storage(sdk, key).await?;
+    }
+    let dehydrated = sdk.encryption().dehydrated_devices();
+    let parked = parked_device(sdk).await?;
+    let pickle_key = match dehydrated.get_dehydrated_device_pickle_key().await.ffi()? {
+        Some(key) => key,
+        None if parked.is_some() => {
+            return Err(ffi_err!(
+                "the dehydrated device's key is in secret storage; recover with the recovery key"
+            ));
+        }
+        None => {
+            let key = DehydratedDeviceKey::new().ffi()?;
+            dehydrated
+                .save_dehydrated_device_pickle_key(&key)
+                .await
+                .ffi()?;
+            key
+        }
+    };
+
+    let recovered = match parked {
+        Some(resp) => match dehydrated
+            .rehydrate(&pickle_key, &resp.device_id, resp.device_data)
+            .await
+        {
+            Ok(device) => {
+                let settings = DecryptionSettings {
+                    sender_device_trust_requirement: TrustRequirement::Untrusted,
+                };
+                let mut recovered = 0u64;
+                let mut next_batch = None;
+                loop {
+                    let mut req = get_events::unstable::Request::new(resp.device_id.clone());
+                    req.next_batch = next_batch.take();
+                    let page = sdk.send(req).await?;
+                    if page.events.is_empty() {
+                        break;
+                    }
+                    recovered += device
+                        .receive_events(page.events, &settings)
+                        .await
+                        .ffi()?
+                        .len() as u64;
+                    match page.next_batch {
+                        Some(nb) => next_batch = Some(nb),
+                        None => break,
+                    }
+                }
+                info!("Rehydrated {}: {recovered} room key(s)", resp.device_id);
+                recovered
+            }
+            Err(e) => {
+                // Parked under another key or unreadable; it will never
+                // rehydrate, so it must not keep the slot.
+                warn!("Could not rehydrate {}, replacing it: {e}", resp.device_id);
+                if let Err(e) = sdk
+                    .send(delete_dehydrated_device::unstable::Request::new())
+                    .await
+                {
+                    warn!("Deleting the stale dehydrated device failed: {e}");
+                }
+                0
+            }
+        },
+        None => 0,
+    };
+
+    park_new_device(sdk, &pickle_key).await?;
+    Ok(recovered)
+}
+
+/// Deletes the parked device from the server and drops the local key.
+pub(crate) async fn remove(sdk: &SdkClient) -> Result<(), FfiError> {
+    match sdk
+        .send(delete_dehydrated_device::unstable::Request::new())
+        .await
+    {
+        Ok(resp) => info!("Deleted dehydrated device {}", resp.device_id),
+        Err(e) if e.client_api_error_kind() == Some(&ErrorKind::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+    sdk.encryption()
+        .dehydrated_devices()
+        .delete_dehydrated_device_pickle_key()
+        .await
+        .ffi()
+}
+
+/// Makes the local pickle key and secret storage agree: a key found in
+/// secret storage wins (another device parked with it), otherwise ours is
+/// uploaded so other devices can rehydrate.
+pub(crate) async fn sync_secret_storage(
+    sdk: &SdkClient,
+    recovery_key: &str,
+) -> Result<(), FfiError> {
+    let store = sdk
+        .encryption()
+        .secret_storage()
+        .open_secret_store(recovery_key)
+        .await
+        .ffi()?;
+    let dehydrated = sdk.encryption().dehydrated_devices();
+    let name = SecretName::from(SECRET_NAME);
+
+    if let Some(secret) = store.get_secret(name.clone()).await.ffi()? {
+        let key = DehydratedDeviceKey::from_base64(&secret).ffi()?;
+        dehydrated
+            .save_dehydrated_device_pickle_key(&key)
+            .await
+            .ffi()?;
+        return Ok(());
+    }
+    let key = match dehydrated.get_dehydrated_device_pickle_key().await.ffi()? {
+        Some(key) => key,
+        None => {
+            let key = DehydratedDeviceKey::new().ffi()?;
+            dehydrated
+                .save_dehydrated_device_pickle_key(&key)
+                .await
+                .ffi()?;
+            key
+        }
+    };
+    store.put_secret(name, &key.to_base64()).await.ffi()?;
+    Ok(())
+}
+
+async fn parked_device(
+    sdk: &SdkClient,
+) -> Result<Option<get_dehydrated_device::unstable::Response>, FfiError> {
+    match sdk
+        .send(get_dehydrated_device::unstable::Request::new())
+        .await
+    {
+        Ok(resp) => Ok(Some(resp)),
+        Err(e) if e.client_api_error_kind() == Some(&ErrorKind::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `None` when the account has no setting yet or the server is unreachable.
+async fn read_setting(sdk: &SdkClient) -> Option<bool> {
+    let req = get_global_account_data::v3::Request::new(
+        sdk.user_id()?.to_owned(),
+        GlobalAccountDataEventType::from(SETTING_EVENT),
+    );
+    let resp = sdk.send(req).await.ok()?;
+    let content: Value = serde_json::from_str(resp.account_data.json().get()).ok()?;
+    content.get("enabled")?.as_bool()
+}
+
+async fn write_setting(sdk: &SdkClient, enabled: bool) -> Result<(), FfiError> {
+    let user_id = sdk
+        .user_id()
+        .ok_or_else(|| FfiError::Unauthorized("no logged-in user".into()))?;
+    let content = serde_json::value::to_raw_value(&json!({ "enabled": enabled })).ffi()?;
+    let req = set_global_account_data::v3::Request::new_raw(
+        user_id.to_owned(),
+        GlobalAccountDataEventType::from(SETTING_EVENT),
+        Raw::from_json(content),
+    );
+    sdk.send(req).await?;
+    Ok(())
+}
+
+async fn park_new_device(
+    sdk: &SdkClient,
+    pickle_key: &DehydratedDeviceKey,
+) -> Result<(), FfiError> {
+    let device = sdk.encryption().dehydrated_devices().create().await.ffi()?;
+    let req: put_dehydrated_device::unstable::Request = device
+        .keys_for_upload(DISPLAY_NAME.to_owned(), pickle_key)
+        .await
+        .ffi()?;
+    let resp = sdk.send(req).await?;
+    info!("Parked dehydrated device {}", resp.device_id);
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use matrix_sdk::{
+    Client as SdkClient,
+    crypto::{AttachmentDecryptor, MediaEncryptionInfo},
+    reqwest::{Response, StatusCode, header},
+    ruma::{MxcUri, OwnedMxcUri, events::room::EncryptedFile},
+};
+use once_cell::sync::Lazy;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::{
+    DownloadResult, FfiError, MediaDownloadObserver, ProgressObserver,
+    errors::{IntoFfi, classify_reqwest, classify_status},
+    safe_call, upload,
+};
+
+/// Result of the download that last held a `.part` file, for callers that
+/// queued behind it.
+type Slot = Arc<tokio::sync::Mutex<Option<DownloadResult>>>;
+
+/// Downloads running per `.part` file, so two requests for the same media
+/// and destination never write to it at once.
+static IN_FLIGHT: Lazy<Mutex<HashMap<PathBuf, Slot>>> = Lazy::new(Default::default);
+
+/// Holds a slot in [`IN_FLIGHT`]; the last holder removes it, also when the
+/// download is cancelled by dropping its future.
+pub(crate) struct InFlight {
+    part: PathBuf,
+    slot: Slot,
+}
+
+impl InFlight {
+    pub(crate) fn join(part: &Path) -> Self {
+        let slot = IN_FLIGHT
+            .lock()
+            .unwrap()
+            .entry(part.to_owned())
+            .or_default()
+            .clone();
+        Self {
+            part: part.to_owned(),
+            slot,
+        }
+    }
+}
+
+/// Whether a download is writing to (or queued for) this `.part` file.
+pub(crate) fn in_flight(part: &Path) -> bool {
+    IN_FLIGHT.lock().unwrap().contains_key(part)
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut map = IN_FLIGHT.lock().unwrap();
+        // Clones are only made under this lock: two means the map and us.
+        let last = map
+            .get(&self.part)
+            .is_some_and(|s| Arc::ptr_eq(s, &self.slot) && Arc::strong_count(s) == 2);
+        if last {
+            map.remove(&self.part);
+        }
+    }
+}
+
+pub(crate) enum DownloadSource {
+    Plain(OwnedMxcUri),
+    Encrypted(Box<EncryptedFile>),
+    /// Server-side thumbnail of plain media.
+    Thumbnail {
+        uri: OwnedMxcUri,
+        width: u32,
+        height: u32,
+        crop: bool,
+    },
+}
+
+impl DownloadSource {
+    /// `encrypted_json` is the serialized `EncryptedFile`, as in [`crate::EncFile`].
+    pub(crate) fn new(mxc_uri: String, encrypted_json: Option<&str>) -> Result<Self, FfiError> {
+        Ok(match encrypted_json {
+            Some(json) => Self::Encrypted(Box::new(serde_json::from_str(json).ffi()?)),
+            None => Self::Plain(mxc_uri.into()),
+        })
+    }
+
+    fn uri(&self) -> &MxcUri {
+        match self {
+            Self::Plain(uri) | Self::Thumbnail { uri, .. } => uri,
+            Self::Encrypted(file) => &file.url,
+        }
+    }
+
+    fn path(&self, authenticated: bool) -> Result<String, FfiError> {
+        let (server, media_id) = self.uri().parts().ffi()?;
+        let prefix = if authenticated {
+            "_matrix/client/v1/media"
+        } else {
+            "_matrix/media/v3"
+        };
+        Ok(match self {
+            Self::Thumbnail {
+                width,
+                height,
+                crop,
+                ..
+            } => {
+                let method = if *crop { "crop" } else { "scale" };
+                format!(
+                    "{prefix}/thumbnail/{server}/{media_id}\
+                     ?width={width}&height={height}&method={method}"
+                )
+            }
+            _ => format!("{prefix}/download/{server}/{media_id}"),
+        })
+    }
+}
+
+/// Streams the media into `dest` one chunk at a time. Bytes go to a `.part`
+/// file next to `dest` first, which survives a cancelled or failed attempt:
+/// the next call for the same media resumes it with a range request.
+/// Encrypted media is decrypted from the finished `.part` file, which also
+/// checks its hash.
+///
+/// A second call for the same media and `dest` while one is running waits
+/// for it and returns its result (without progress of its own); if the first
+/// fails or is cancelled, the next in line resumes from the `.part` file.
+///
+/// This bypasses the SDK's media cache: nothing is read from or stored in
+/// it, `dest` is the only copy.
+pub(crate) async fn download_to_file(
+    sdk: &SdkClient,
+    source: DownloadSource,
+    dest: &Path,
+    progress: Option<Arc<dyn ProgressObserver>>,
+) -> Result<DownloadResult, FfiError> {
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await.ffi()?;
+    }
+    let part = part_path(dest, source.uri());
+    let in_flight = InFlight::join(&part);
+    let mut done = in_flight.slot.lock().await;
+    if let Some(result) = done.as_ref() {
+        return Ok(result.clone());
+    }
+    let result = fetch(sdk, source, dest, &part, progress).await?;
+    *done = Some(result.clone());
+    Ok(result)
+}
+
+async fn fetch(
+    sdk: &SdkClient,
+    source: DownloadSource,
+    dest: &Path,
+    part: &Path,
+    progress: Option<Arc<dyn ProgressObserver>>,
+) -> Result<DownloadResult, FfiError> {
+    // Thumbnails are rendered per request, so their bytes need not line up.
+    let mut offset = match source {
+        DownloadSource::Thumbnail { .. } => 0,
+        _ => tokio::fs::metadata(part).await.map_or(0, |m| m.len()),
+    };
+
+    let mut resp = loop {
+        let resp = get(sdk, &source, offset).await?;
+        let status = resp.status();
+        // Appending any other range would corrupt the file.
+        let misplaced = status == StatusCode::PARTIAL_CONTENT && range_start(&resp) != Some(offset);
+        if offset > 0 && (status == StatusCode::RANGE_NOT_SATISFIABLE || misplaced) {
+            // The leftover is not a prefix of this media; start over.
+            offset = 0;
+            continue;
+        }
+        if misplaced || !status.is_success() {
+            return Err(classify_status(
+                status,
+                resp.text().await.unwrap_or_default(),
+            ));
+        }
+        break resp;
+    };
+    let resumed = resp.status() == StatusCode::PARTIAL_CONTENT;
+    if !resumed {
+        offset = 0;
+    }
+    let total = resp.content_length().map(|len| len + offset);
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)
+        .await
+        .ffi()?;
+    let mut received = offset;
+    if let Some(p) = progress.as_ref() {
+        safe_call(|| p.on_progress(received, total));
+    }
+    while let Some(chunk) = resp.chunk().await.map_err(classify_reqwest)? {
+        file.write_all(&chunk).await.ffi()?;
+        received += chunk.len() as u64;
+        if let Some(p) = progress.as_ref() {
+            safe_call(|| p.on_progress(received, total));
+        }
+    }
+    file.flush().await.ffi()?;
+    drop(file);
+
+    let bytes = match source {
+        DownloadSource::Encrypted(file) => decrypt(part.to_owned(), dest.to_owned(), *file).await?,
+        _ => {
+            tokio::fs::rename(part, dest).await.ffi()?;
+            received
+        }
+    };
+    if resumed {
+        info!("Resumed download of {} at {offset} bytes", dest.display());
+    }
+    Ok(DownloadResult {
+        path: dest.to_string_lossy().into_owned(),
+        bytes,
+    })
+}
+
+/// Prefers authenticated media (`/_matrix/client/v1/media`), falling back to
+/// the legacy endpoints on servers that do not know it. An expired access
+/// token is refreshed once, as for uploads.
+async fn get(sdk: &SdkClient, source: &DownloadSource, offset: u64) -> Result<Response, FfiError> {
+    let mut authenticated = true;
+    let mut refreshed = false;
+    loop {
+        let url = sdk.homeserver().join(&source.path(authenticated)?).ffi()?;
+        let mut req = sdk.http_client().get(url);
+        if let Some(token) = sdk.access_token() {
+            req = req.bearer_auth(token);
+        }
+        if offset > 0 {
+            req = req.header(header::RANGE, format!("bytes={offset}-"));
+        }
+        let resp = req.send().await.map_err(classify_reqwest)?;
+        let status = resp.status();
+        if status.is_success() || status == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(resp);
+        }
+        if status == StatusCode::UNAUTHORIZED && !refreshed && upload::refresh_token(sdk).await {
+            refreshed = true;
+            continue;
+        }
+        let body = resp.text().await.unwrap_or_default();
+        let unknown_endpoint =
+            status == StatusCode::METHOD_NOT_ALLOWED || body.contains("M_UNRECOGNIZED");
+        if authenticated && unknown_endpoint {
+            authenticated = false;
+            continue;
+        }
+        return Err(classify_status(status, body));
+    }
+}
+
+/// First byte of a `Content-Range: bytes <first>-<last>/<size>` response.
+fn range_start(resp: &Response) -> Option<u64> {
+    let value = resp.headers().get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (first, _) = value.strip_prefix("bytes ")?.split_once('-')?;
+    first.trim().parse().ok()
+}
+
+async fn decrypt(part: PathBuf, dest: PathBuf, file: EncryptedFile) -> Result<u64, FfiError> {
+    tokio::task::spawn_blocking(move || {
+        let run = || -> Result<u64, FfiError> {
+            let mut reader = std::io::BufReader::new(std::fs::File::open(&part).ffi()?);
+            let mut decryptor =
+                AttachmentDecryptor::new(&mut reader, MediaEncryptionInfo::from(file)).ffi()?;
+            let mut out = std::io::BufWriter::new(std::fs::File::create(&dest).ffi()?);
+            let bytes = std::io::copy(&mut decryptor, &mut out).ffi()?;
+            out.flush().ffi()?;
+            Ok(bytes)
+        };
+        let res = run();
+        // Done, or the ciphertext failed its hash; either way a retry has to
+        // start afresh.
+        let _ = std::fs::remove_file(&part);
+        if res.is_err() {
+            let _ = std::fs::remove_file(&dest);
+        }
+        res
+    })
+    .await
+    .ffi()?
+}
+
+/// Keyed by the media as well as `dest`, so a leftover from other media
+/// saved under the same name is never resumed.
+pub(crate) fn part_path(dest: &Path, uri: &MxcUri) -> PathBuf {
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let key = blake3::hash(uri.as_bytes()).to_hex();
+    dest.with_file_name(format!("{name}.{}.part", &key[..8]))
+}
+
+/// Routes download progress to a [`MediaDownloadObserver`].
+pub(crate) struct ObserverProgress(pub Arc<dyn MediaDownloadObserver>);
+
+impl ProgressObserver for ObserverProgress {
+    fn on_progress(&self, sent: u64, total: Option<u64>) {
+        self.0.on_progress(sent, total);
+    }
+}
@@ -1,13 +1,22 @@
+use std::any::Any;
+use std::time::{Duration, SystemTime};
+
+use matrix_sdk::HttpError;
+use matrix_sdk::ruma::IdParseError;
+use matrix_sdk::ruma::api::client::error::{ErrorKind, RetryAfter};
+
 use crate::FfiError;
 
-/// Convert `Result<T, E: Display>` to `Result<T, FfiError>` via `.ffi()`
+/// Convert `Result<T, E: Display>` to `Result<T, FfiError>` via `.ffi()`.
+/// SDK, HTTP and id-parse errors are classified into typed variants; anything
+/// else falls back to `FfiError::Msg`.
 pub(crate) trait IntoFfi<T> {
     fn ffi(self) -> Result<T, FfiError>;
 }
 
-impl<T, E: std::fmt::Display> IntoFfi<T> for Result<T, E> {
+impl<T, E: std::fmt::Display + 'static> IntoFfi<T> for Result<T, E> {
     fn ffi(self) -> Result<T, FfiError> {
-        self.map_err(|e| FfiError::Msg(e.to_string()))
+        self.map_err(|e| classify_any(&e).unwrap_or_else(|| FfiError::Msg(e.to_string())))
     }
 }
 
@@ -29,3 +38,89 @@ macro_rules! ffi_err {
     };
 }
 pub(crate) use ffi_err;
+
+fn classify_any(e: &dyn Any) -> Option<FfiError> {
+    if let Some(e) = e.downcast_ref::<matrix_sdk::Error>() {
+        return classify_sdk(e);
+    }
+    if let Some(e) = e.downcast_ref::<HttpError>() {
+        return classify_http(e);
+    }
+    if let Some(e) = e.downcast_ref::<IdParseError>() {
+        return Some(FfiError::InvalidId(format!("invalid id: {e}")));
+    }
+    None
+}
+
+pub(crate) fn classify_sdk(e: &matrix_sdk::Error) -> Option<FfiError> {
+    use matrix_sdk::Error as E;
+    match e {
+        E::Http(http) => classify_http(http),
+        E::AuthenticationRequired => Some(FfiError::Unauthorized(e.to_string())),
+        E::OlmError(_)
+        | E::MegolmError(_)
+        | E::DecryptorError(_)
+        | E::CryptoStoreError(_)
+        | E::NoOlmMachine => Some(FfiError::CryptoError(e.to_string())),
+        _ => None,
+    }
+}
+
+pub(crate) fn classify_http(e: &HttpError) -> Option<FfiError> {
+    #[cfg(not(target_family = "wasm"))]
+    if let HttpError::Reqwest(re) = e
+        && (re.is_connect() || re.is_timeout())
+    {
+        return Some(FfiError::NetworkUnreachable(re.to_string()));
+    }
+
+    let kind = e.client_api_error_kind()?;
+    Some(match kind {
+        ErrorKind::UnknownToken { soft_logout: true } => FfiError::SoftLogout,
+        ErrorKind::UnknownToken { .. } | ErrorKind::MissingToken => {
+            FfiError::Unauthorized(e.to_string())
+        }
+        ErrorKind::LimitExceeded { retry_after } => FfiError::RateLimited {
+            retry_after_ms: retry_after.as_ref().map(retry_after_ms),
+        },
+        ErrorKind::Forbidden { .. } => FfiError::Forbidden(e.to_string()),
+        _ => return None,
+    })
+}
+
+/// For requests made with the raw HTTP client (streamed media), which bypass
+/// the SDK's error parsing.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn classify_reqwest(e: matrix_sdk::reqwest::Error) -> FfiError {
+    if e.is_connect() || e.is_timeout() {
+        FfiError::NetworkUnreachable(e.to_string())
+    } else {
+        FfiError::Msg(format!("media request failed: {e}"))
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn classify_status(status: matrix_sdk::reqwest::StatusCode, body: String) -> FfiError {
+    use matrix_sdk::reqwest::StatusCode;
+    match status {
+        StatusCode::UNAUTHORIZED => FfiError::Unauthorized(body),
+        StatusCode::FORBIDDEN => FfiError::Forbidden(body),
+        StatusCode::TOO_MANY_REQUESTS => FfiError::RateLimited {
+            retry_after_ms: None,
+        },
+        StatusCode::PAYLOAD_TOO_LARGE => {
+            FfiError::Msg("file is too large for the homeserver".into())
+        }
+        _ => FfiError::Msg(format!("media request failed ({status}): {body}")),
+    }
+}
+
+fn retry_after_ms(r: &RetryAfter) -> u64 {
+    let d = match r {
+        RetryAfter::Delay(d) => *d,
+        RetryAfter::DateTime(at) => at
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    };
+    d.as_millis() as u64
+}
@@ -0,0 +1,252 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use image::{
+    DynamicImage, GenericImageView, ImageFormat, codecs::jpeg::JpegEncoder, imageops::FilterType,
+    metadata::Orientation,
+};
+use mime::Mime;
+use tracing::info;
+
+use crate::{
+    FfiError, ImageUploadOptions, ImageUploadQuality,
+    errors::{IntoFfi, OptionFfi},
+    ffi_err, media_info,
+};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Enough of the file for `image::guess_format` to tell the formats apart.
+const SNIFF_BYTES: u64 = 16;
+/// Largest file read into memory to be prepared.
+pub(crate) const MAX_PREPARE_BYTES: u64 = 64 * 1024 * 1024;
+/// Re-encoding only to make the picture upright, or because the container
+/// could not be stripped, should not visibly cost quality.
+const REENCODE_QUALITY: u8 = 95;
+
+/// The image as it will be uploaded, when it differs from the file.
+pub(crate) struct Prepared {
+    pub bytes: Vec<u8>,
+    pub mime: Mime,
+    pub saved_bytes: u64,
+}
+
+/// Applies `options` to a JPEG, PNG or WebP file, going by its contents
+/// rather than the declared type. Stripping rewrites the container without
+/// the metadata and leaves the pixels alone, unless the EXIF orientation has
+/// to be baked in first or the container cannot be parsed. `Optimised`
+/// downscales and re-encodes. Ok(None) means the file goes up unchanged,
+/// which never happens to one of those formats when stripping was asked for:
+/// then the upload fails instead, also for files over `MAX_PREPARE_BYTES`.
+/// Other formats always go up unchanged, and only their first bytes are read.
+pub(crate) fn prepare(
+    path: &Path,
+    options: &ImageUploadOptions,
+) -> Result<Option<Prepared>, FfiError> {
+    if !options.strip_metadata && options.quality == ImageUploadQuality::Original {
+        return Ok(None);
+    }
+    let mut file = File::open(path).ffi()?;
+    let mut original = Vec::new();
+    file.by_ref()
+        .take(SNIFF_BYTES)
+        .read_to_end(&mut original)
+        .ffi()?;
+    let format = match image::guess_format(&original) {
+        Ok(f @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => f,
+        _ => return Ok(None),
+    };
+    let size = file.metadata().ffi()?.len();
+    if size > MAX_PREPARE_BYTES {
+        return if options.strip_metadata {
+            Err(ffi_err!("image is too large to remove its metadata"))
+        } else {
+            Ok(None)
+        };
+    }
+    original.reserve(size as usize);
+    file.read_to_end(&mut original).ffi()?;
+
+    let optimised = match options.quality {
+        ImageUploadQuality::Optimised => optimise(&original, format, options),
+        ImageUploadQuality::Original => None,
+    };
+    let (bytes, mime) = match optimised {
+        Some(v) => v,
+        None if options.strip_metadata => strip_or_reencode(&original, format)?,
+        None => return Ok(None),
+    };
+    let saved_bytes = (original.len() as u64).saturating_sub(bytes.len() as u64);
+    info!("Prepared image for upload, {saved_bytes} bytes saved");
+    Ok(Some(Prepared {
+        bytes,
+        mime,
+        saved_bytes,
+    }))
+}
+
+/// Strips the container or, where that is not enough or not possible,
+/// decodes and re-encodes, which drops all metadata too.
+fn strip_or_reencode(original: &[u8], format: ImageFormat) -> Result<(Vec<u8>, Mime), FfiError> {
+    // Dropping EXIF would drop the rotation with it.
+    if !rotated(original)
+        && let Some(stripped) = strip(original, format)
+    {
+        return Ok((stripped, mime_for(format)));
+    }
+    media_info::decode_oriented(original)
+        .and_then(|img| encode(&img, REENCODE_QUALITY))
+        .or_ffi("could not remove the metadata from the image")
+}
+
+/// Downscales to `max_dimension` and re-encodes, which drops all metadata.
+/// Keeps the stripped original when that is smaller and already fits.
+fn optimise(
+    original: &[u8],
+    format: ImageFormat,
+    options: &ImageUploadOptions,
+) -> Option<(Vec<u8>, Mime)> {
+    let img = media_info::decode_oriented(original)?;
+    let (w, h) = img.dimensions();
+    let max = options.max_dimension.max(1);
+    let downscaled = w > max || h > max;
+    let img = if downscaled {
+        img.resize(max, max, FilterType::Lanczos3)
+    } else {
+        img
+    };
+
+    let (bytes, new_mime) = encode(&img, options.jpeg_quality.clamp(1, 100))?;
+    if !downscaled
+        && !rotated(original)
+        && let Some(stripped) = strip(original, format).filter(|s| s.len() <= bytes.len())
+    {
+        return Some((stripped, mime_for(format)));
+    }
+    Some((bytes, new_mime))
+}
+
+fn mime_for(format: ImageFormat) -> Mime {
+    format.to_mime_type().parse().unwrap_or(mime::IMAGE_STAR)
+}
+
+/// Images with transparency become PNG, everything else JPEG.
+fn encode(img: &DynamicImage, jpeg_quality: u8) -> Option<(Vec<u8>, Mime)> {
+    let mut out = Vec::new();
+    if img.color().has_alpha() {
+        img.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+            .ok()?;
+        Some((out, mime::IMAGE_PNG))
+    } else {
+        JpegEncoder::new_with_quality(&mut out, jpeg_quality)
+            .encode_image(&img.to_rgb8())
+            .ok()?;
+        Some((out, mime::IMAGE_JPEG))
+    }
+}
+
+fn strip(bytes: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => strip_jpeg(bytes),
+        ImageFormat::Png => strip_png(bytes),
+        ImageFormat::WebP => strip_webp(bytes),
+        _ => None,
+    }
+}
+
+/// Whether the EXIF asks for anything but the stored orientation.
+fn rotated(bytes: &[u8]) -> bool {
+    media_info::decode_orientation(bytes).is_some_and(|o| o != Orientation::NoTransforms)
+}
+
+/// Drops APP1 (EXIF, XMP), APP13 (IPTC) and comment segments; JFIF, ICC
+/// profile and Adobe segments stay so colours render the same.
+pub(crate) fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+    let mut pos = 2;
+    loop {
+        if bytes.get(pos) != Some(&0xFF) {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        // Start of scan: the rest is entropy-coded data.
+        if marker == 0xDA {
+            out.extend_from_slice(&bytes[pos..]);
+            return Some(out);
+        }
+        let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > bytes.len() {
+            return None;
+        }
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+/// Drops `eXIf`, text chunks (which carry XMP) and `tIME`.
+pub(crate) fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < bytes.len() {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = bytes.get(pos + 4..pos + 8)?;
+        // length, type, data, CRC
+        let end = pos.checked_add(12)?.checked_add(len)?;
+        if end > bytes.len() {
+            return None;
+        }
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"iTXt" | b"zTXt" | b"tIME") {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+    Some(out)
+}
+
+/// Drops the `EXIF` and `XMP ` chunks and clears their flags in `VP8X`.
+pub(crate) fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..12]);
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let kind = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().ok()?) as usize;
+        // Chunks are padded to an even size.
+        let end = pos.checked_add(8)?.checked_add(len)?.checked_add(len & 1)?;
+        if end > bytes.len() {
+            return None;
+        }
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            // Too short to hold the flags byte.
+            b"VP8X" if len < 1 => return None,
+            b"VP8X" => {
+                let start = out.len();
+                out.extend_from_slice(&bytes[pos..end]);
+                // Flags byte: bit 3 EXIF, bit 2 XMP.
+                out[start + 8] &= !0b0000_1100;
+            }
+            _ => out.extend_from_slice(&bytes[pos..end]),
+        }
+        pos = end;
+    }
+    let riff_len = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Some(out)
+}
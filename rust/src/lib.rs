@@ -6,6 +6,8 @@ use matrix_sdk::live_locations_observer::LiveLocationShare;
 use matrix_sdk::ruma::events::rtc::notification::CallIntent;
 use matrix_sdk::utils::UrlOrQuery;
 
+#[cfg(not(target_family = "wasm"))]
+use matrix_sdk::authentication::oauth::qrcode::{CheckCodeSender, QrCodeData};
 use matrix_sdk::authentication::oauth::registration::language_tags::LanguageTag;
 use matrix_sdk::authentication::oauth::registration::{
     ApplicationType, ClientMetadata, Localized, OAuthGrantType,
@@ -46,20 +48,47 @@ use tokio::runtime::Runtime;
 use tracing::{info, warn};
 use uniffi::{Object, export, setup_scaffolding};
 
+#[cfg(not(target_family = "wasm"))]
+mod account_manager;
 mod core;
+mod dehydration;
+#[cfg(not(target_family = "wasm"))]
+mod download;
 mod errors;
+#[cfg(not(target_family = "wasm"))]
+mod image_prep;
 mod macros;
+mod media_cache;
+#[cfg(not(target_family = "wasm"))]
+mod media_info;
 mod platform;
+#[cfg(not(target_family = "wasm"))]
+mod qr_login;
+#[cfg(not(target_family = "wasm"))]
+mod room_keys;
+mod room_list_sort;
+#[cfg(not(target_family = "wasm"))]
+mod search;
+mod space_tree;
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests;
 mod types;
+mod uiaa;
+#[cfg(not(target_family = "wasm"))]
+mod upload;
+mod utd;
 mod verification_flow;
 #[cfg(target_family = "wasm")]
 mod wasm_bridge;
 
+#[cfg(not(target_family = "wasm"))]
+pub use account_manager::AccountManager;
 pub use core::{CoreClient, TimelineManager};
 pub use types::*;
 
 use errors::{IntoFfi, OptionFfi, ffi_err};
 use macros::*;
+use room_list_sort::SortedRoomList;
 
 use matrix_sdk::{
     Client as SdkClient, Room, SessionMeta, SessionTokens,
@@ -80,7 +109,10 @@ use matrix_sdk::{
     },
 };
 use matrix_sdk::{
-    encryption::{EncryptionSettings, verification::Verification},
+    encryption::{
+        EncryptionSettings,
+        verification::{QrVerificationData, Verification},
+    },
     ruma,
 };
 use matrix_sdk_ui::{
@@ -88,7 +120,6 @@ use matrix_sdk_ui::{
     notification_client::{
         NotificationClient, NotificationEvent, NotificationProcessSetup, NotificationStatus,
     },
-    room_list_service::filters,
     sync_service::State,
     timeline::{
         EventSendState, EventTimelineItem, MsgLikeContent, MsgLikeKind, RoomExt as _, Timeline,
@@ -127,8 +158,18 @@ static RT: Lazy<Runtime> = Lazy::new(|| {
         .expect("tokio runtime")
 });
 
+/// How long room list changes settle before `room_list_cache.json` is rewritten.
+const ROOM_LIST_CACHE_DEBOUNCE: Duration = Duration::from_secs(2);
+/// Unread counts change in bursts during sync; rebuild the space tree once per burst.
+const SPACE_TREE_DEBOUNCE: Duration = Duration::from_millis(300);
+
 delegate_unit_result! {
     send_queue_set_enabled(enabled: bool);
+    rename_device(device_id: String, display_name: String);
+    pin_user_identity(user_id: String);
+    withdraw_user_verification(user_id: String);
+    set_dehydrated_device_enabled(enabled: bool, recovery_key: Option<String>);
+    retry_decryption(room_id: String, session_ids: Vec<String>);
     set_typing(room_id: String, typing: bool);
     send_message(room_id: String, body: String, formatted_body: Option<String>);
     reply(room_id: String, in_reply_to: String, body: String, formatted_body: Option<String>);
@@ -206,6 +247,8 @@ delegate_result! { bool; is_user_ignored(user_id: String); is_space(room_id: Str
 }
 
 delegate_result! { Vec<MemberSummary>; list_members(room_id: String); }
+delegate_result! { Vec<ThreePid>; list_3pids(); }
+delegate_result! { bool; remove_3pid(medium: ThreePidMedium, address: String); }
 delegate_result! { Vec<RoomProfile>; list_invited(); }
 delegate_result! { Vec<String>; ignored_users(); }
 delegate_result! { Vec<DirectoryUser>; search_users(search_term: String, limit: u64); }
@@ -241,7 +284,10 @@ delegate_plain! { OwnReceipt; own_last_read(room_id: String); }
 delegate_plain! { HashMap<String, Vec<ReactionSummary>>; reactions_batch(room_id: String, event_ids: Vec<String>); }
 delegate_plain! { Vec<ReactionSummary>; reactions_for_event(room_id: String, event_id: String); }
 delegate_plain! { Vec<SpaceInfo>; my_spaces(); }
+delegate_plain! { SpaceTree; space_tree(); }
 delegate_plain! { Vec<RoomSummary>; rooms(); }
+delegate_plain! { bool; dehydrated_device_enabled(); }
+delegate_plain! { Vec<UtdRoomStats>; utd_stats(); }
 
 #[derive(Object)]
 pub struct Client {
@@ -268,10 +314,288 @@ pub struct Client {
     beacon_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
     recovery_state_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
     backup_state_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
+    identity_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
+    utd_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
+    space_tree_subs: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
+    /// Running verification flows; each task removes its own entry when the
+    /// flow ends.
+    verification_tasks: Arc<Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>>,
     pub app_in_foreground: Arc<AtomicUsize>,
     widget_handles: Mutex<HashMap<u64, WidgetDriverHandle>>,
     widget_driver_tasks: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
     widget_recv_tasks: Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>,
+    media_cache: media_cache::MediaCache,
+    /// Cancel senders of background uploads and downloads; each task
+    /// removes its own entry when it ends.
+    transfer_cancels: Arc<Mutex<HashMap<u64, tokio::sync::oneshot::Sender<()>>>>,
+    /// Check-code sender of the QR login in progress on this (new) device.
+    #[cfg(not(target_family = "wasm"))]
+    qr_check_code: Arc<Mutex<Option<CheckCodeSender>>>,
+}
+
+/// Awaitable view of a [`Client`] for Kotlin coroutines / Swift async.
+///
+/// Each call is spawned onto `RT` and the foreign side only awaits the join
+/// handle, so no caller thread is parked for the network round trip.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Object)]
+pub struct AsyncClient {
+    client: Arc<Client>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+async fn on_rt<T: Send + 'static>(fut: impl Future<Output = T> + Send + 'static) -> T {
+    match RT.spawn(fut).await {
+        Ok(v) => v,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[export]
+impl Client {
+    pub fn async_client(self: Arc<Self>) -> Arc<AsyncClient> {
+        Arc::new(AsyncClient { client: self })
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[export]
+impl AsyncClient {
+    pub async fn start_live_location(
+        &self,
+        room_id: String,
+        duration_ms: u64,
+        description: Option<String>,
+    ) -> Result<String, FfiError> {
+        let core = self.client.core.clone();
+        on_rt(async move {
+            core.start_live_location(room_id, duration_ms, description)
+                .await
+        })
+        .await
+    }
+
+    pub async fn change_password(
+        &self,
+        new_password: String,
+        logout_devices: bool,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        let core = self.client.core.clone();
+        on_rt(async move {
+            core.change_password(new_password, logout_devices, &*handler)
+                .await
+        })
+        .await
+    }
+
+    pub async fn add_3pid(
+        &self,
+        medium: ThreePidMedium,
+        address: String,
+        country: Option<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        let core = self.client.core.clone();
+        on_rt(async move { core.add_3pid(medium, address, country, &*handler).await }).await
+    }
+
+    pub async fn delete_devices(
+        &self,
+        device_ids: Vec<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<DeviceDeletion, FfiError> {
+        let core = self.client.core.clone();
+        on_rt(async move { core.delete_devices(device_ids, &*handler).await }).await
+    }
+
+    pub async fn sign_out_other_sessions(
+        &self,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<DeviceDeletion, FfiError> {
+        let core = self.client.core.clone();
+        on_rt(async move { core.sign_out_other_sessions(&*handler).await }).await
+    }
+
+    pub async fn reset_identity(
+        &self,
+        handler: Box<dyn UiaaHandler>,
+        listener: Box<dyn IdentityResetListener>,
+    ) -> Result<(), FfiError> {
+        let core = self.client.core.clone();
+        on_rt(async move { core.reset_identity(&*handler, &*listener).await }).await
+    }
+
+    /// Reads cached state, so it never waits on the network.
+    pub async fn recovery_state(&self) -> RecoveryState {
+        self.client.recovery_state()
+    }
+
+    /// Reads cached state, so it never waits on the network.
+    pub async fn backup_state(&self) -> BackupState {
+        self.client.backup_state()
+    }
+}
+
+async_core! {
+    room_profile(room_id: String) -> Result<Option<RoomProfile>, FfiError>;
+    send_poll_start(room_id: String, def: PollDefinition) -> Result<String, FfiError>;
+    create_room(
+        name: Option<String>, topic: Option<String>, invitees: Vec<String>, is_public: bool,
+        room_alias: Option<String>,
+    ) -> Result<String, FfiError>;
+    create_space(
+        name: String, topic: Option<String>, is_public: bool, invitees: Vec<String>,
+    ) -> Result<String, FfiError>;
+    room_preview(id_or_alias: String) -> Result<RoomPreview, FfiError>;
+    space_hierarchy(
+        space_id: String, from: Option<String>, limit: u32, max_depth: Option<u32>,
+        suggested_only: bool,
+    ) -> Result<SpaceHierarchyPage, FfiError>;
+    thread_replies(
+        room_id: String, root_event_id: String, from: Option<String>, limit: u32,
+        direction_forward: bool,
+    ) -> Result<ThreadPage, FfiError>;
+    thread_summary(
+        room_id: String, root_event_id: String, per_page: u32, max_pages: u32,
+    ) -> Result<ThreadSummary, FfiError>;
+    get_presence(user_id: String) -> Result<PresenceInfo, FfiError>;
+    publish_room_alias(room_id: String, alias: String) -> Result<bool, FfiError>;
+    unpublish_room_alias(room_id: String, alias: String) -> Result<bool, FfiError>;
+    room_upgrade_links(room_id: String) -> Option<RoomUpgradeLinks>;
+    join_by_id_or_alias(id_or_alias: String) -> Result<(), FfiError>;
+    search_room(
+        room_id: String, query: String, limit: u32, offset: Option<u32>,
+    ) -> Result<SearchPage, FfiError>;
+    search_all_rooms(
+        query: String, filter: SearchFilter, limit: u32, cursor: Option<String>,
+    ) -> Result<SearchPage, FfiError>;
+    backup_exists_on_server(fetch: bool) -> bool;
+    set_key_backup_enabled(enabled: bool) -> bool;
+}
+
+async_client! {
+    login_password => login_password_async(
+        username: String, password: String,
+    ) -> Result<String, FfiError>;
+    homeserver_login_details => homeserver_login_details_async() -> HomeserverLoginDetails;
+    login_oauth => login_oauth_async(redirect_uri: String) -> Result<String, FfiError>;
+    complete_oauth_login => complete_oauth_login_async(
+        callback_url: String,
+    ) -> Result<String, FfiError>;
+    login_sso_loopback => login_sso_loopback_async(
+        opener: Box<dyn UrlOpener>, device_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    login_oauth_loopback => login_oauth_loopback_async(
+        opener: Box<dyn UrlOpener>, device_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    start_oauth_login => start_oauth_login_async(redirect_uri: String) -> Result<String, FfiError>;
+    finish_oauth_login => finish_oauth_login_async(
+        callback_data: String, device_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    start_sso_login => start_sso_login_async(
+        redirect_uri: String, idp_id: Option<String>,
+    ) -> Result<String, FfiError>;
+    finish_sso_login => finish_sso_login_async(
+        callback_url: String, device_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    logout => logout_async() -> bool;
+    login => login_async(
+        username: String, password: String, device_display_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    login_email => login_email_async(
+        email: String, password: String, device_display_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    login_phone => login_phone_async(
+        country: String, phone: String, password: String, device_display_name: Option<String>,
+    ) -> Result<(), FfiError>;
+    register => register_async(
+        username: Option<String>, password: String, device_display_name: Option<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError>;
+    deactivate_account => deactivate_account_async(
+        erase: bool, handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError>;
+    export_room_keys => export_room_keys_async(
+        path: String, passphrase: String, room_filter: Option<Vec<String>>,
+    ) -> Result<u64, FfiError>;
+    import_room_keys => import_room_keys_async(
+        path: String, passphrase: String, progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<RoomKeyImportSummary, FfiError>;
+    login_with_qr_code => login_with_qr_code_async(
+        device_name: Option<String>, listener: Box<dyn QrLoginListener>,
+    ) -> Result<(), FfiError>;
+    qr_login_submit_check_code => qr_login_submit_check_code_async(
+        code: u8,
+    ) -> Result<(), FfiError>;
+    grant_login_with_qr_code => grant_login_with_qr_code_async(
+        qr_data: Vec<u8>, listener: Box<dyn QrLoginListener>,
+    ) -> Result<(), FfiError>;
+    send_existing_attachment => send_existing_attachment_async(
+        room_id: String, att: AttachmentInfo, body: Option<String>,
+        progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<(), FfiError>;
+    download_media => download_media_async(
+        mxc_uri: String, dest_path: String, encrypted_json: Option<String>,
+    ) -> Result<DownloadResult, FfiError>;
+    download_thumbnail => download_thumbnail_async(
+        mxc_uri: String, dest_path: String, width: u32, height: u32,
+    ) -> Result<DownloadResult, FfiError>;
+    fetch_notification => fetch_notification_async(
+        room_id: String, event_id: String,
+    ) -> Result<Option<RenderedNotification>, FfiError>;
+    fetch_notifications_since => fetch_notifications_since_async(
+        since_ts_ms: u64, max_rooms: u32, max_events: u32,
+    ) -> Result<Vec<RenderedNotification>, FfiError>;
+    recover_with_key => recover_with_key_async(recovery_key: String) -> Result<(), FfiError>;
+    disable_recovery => disable_recovery_async() -> Result<(), FfiError>;
+    reset_recovery_key => reset_recovery_key_async() -> Result<String, FfiError>;
+    devices => devices_async() -> Result<Vec<DeviceSummary>, FfiError>;
+    register_unifiedpush => register_unifiedpush_async(
+        app_id: String, pushkey: String, gateway_url: String, device_display_name: String,
+        lang: String, profile_tag: Option<String>,
+    ) -> bool;
+    unregister_unifiedpush => unregister_unifiedpush_async(app_id: String, pushkey: String) -> bool;
+    cross_signing_status => cross_signing_status_async() -> Result<HashMap<String, bool>, FfiError>;
+    is_user_verified => is_user_verified_async(user_id: String) -> bool;
+    retry_by_txn => retry_by_txn_async(room_id: String, txn_id: String) -> bool;
+    send_attachment_from_path => send_attachment_from_path_async(
+        room_id: String, path: String, mime: String, filename: Option<String>,
+        caption: Option<String>, formatted_caption: Option<String>,
+        reply_to_event_id: Option<String>, progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<AttachmentUploadResult, FfiError>;
+    send_sticker_from_path => send_sticker_from_path_async(
+        room_id: String, path: String, mime: String, body: String, filename: Option<String>,
+        progress: Option<Box<dyn ProgressObserver>>,
+    ) -> bool;
+    download_sticker_to_cache => download_sticker_to_cache_async(
+        info: StickerInfo, filename_hint: Option<String>,
+    ) -> Result<DownloadResult, FfiError>;
+    download_attachment_to_cache_file => download_attachment_to_cache_file_async(
+        att: AttachmentInfo, filename_hint: Option<String>,
+    ) -> Result<DownloadResult, FfiError>;
+    thumbnail_to_cache => thumbnail_to_cache_async(
+        att: AttachmentInfo, width: u32, height: u32, use_crop: bool,
+    ) -> Result<String, FfiError>;
+    mxc_thumbnail_to_cache => mxc_thumbnail_to_cache_async(
+        mxc_uri: String, width: u32, height: u32, crop: bool,
+    ) -> Result<String, FfiError>;
+    cancel_verification => cancel_verification_async(flow_id: String) -> bool;
+    confirm_sas => confirm_sas_async(flow_id: String) -> bool;
+    accept_verification_request => accept_verification_request_async(
+        flow_id: String, other_user_id: Option<String>,
+    ) -> bool;
+    accept_sas => accept_sas_async(flow_id: String, other_user_id: Option<String>) -> bool;
+    scan_verification_qr => scan_verification_qr_async(
+        flow_id: String, other_user_id: Option<String>, qr_data: Vec<u8>,
+    ) -> Result<(), FfiError>;
+    confirm_qr_verification => confirm_qr_verification_async(
+        flow_id: String, other_user_id: Option<String>,
+    ) -> bool;
+    start_sas_verification => start_sas_verification_async(
+        flow_id: String, other_user_id: Option<String>,
+    ) -> bool;
 }
 
 fn mages_client_metadata(redirect_uri: &Url) -> Raw<ClientMetadata> {
@@ -316,6 +640,27 @@ fn mages_client_metadata(redirect_uri: &Url) -> Raw<ClientMetadata> {
     Raw::new(&metadata).expect("Couldn't serialize client metadata")
 }
 
+/// Metadata for MSC4108 QR login, which signs the new device in through the
+/// device authorization grant instead of a redirect.
+#[cfg(not(target_family = "wasm"))]
+fn mages_qr_client_metadata() -> Raw<ClientMetadata> {
+    let client_uri = Localized::new(
+        Url::parse("https://github.com/mlm-games/mages").expect("valid URL"),
+        [],
+    );
+    let metadata = ClientMetadata {
+        client_name: Some(Localized::new("Mages".to_owned(), [])),
+        policy_uri: Some(client_uri.clone()),
+        tos_uri: Some(client_uri.clone()),
+        ..ClientMetadata::new(
+            ApplicationType::Native,
+            vec![OAuthGrantType::DeviceCode],
+            client_uri,
+        )
+    };
+    Raw::new(&metadata).expect("Couldn't serialize client metadata")
+}
+
 fn now_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -362,9 +707,68 @@ impl Client {
         account_id: Option<String>,
         proxy: Option<String>,
         enable_share_history_on_invite: Option<bool>,
+    ) -> Result<Self, FfiError> {
+        Self::with_config(
+            ClientConfig {
+                homeserver_url,
+                base_store_dir,
+                account_id,
+                proxy,
+                enable_share_history_on_invite,
+                store_passphrase: None,
+            },
+            None,
+        )
+    }
+
+    /// Like [`Client::new`], but the persisted session file is encrypted with
+    /// the secret `session_key` returns for this account. Existing plaintext
+    /// session files are re-written encrypted on first load. An encrypted
+    /// session file that the secret does not open, or a missing secret, fails
+    /// with [`FfiError::WrongPassphrase`].
+    #[uniffi::constructor]
+    pub fn new_with_session_key(
+        homeserver_url: String,
+        base_store_dir: String,
+        account_id: Option<String>,
+        proxy: Option<String>,
+        enable_share_history_on_invite: Option<bool>,
+        session_key: Option<Box<dyn SessionKeyProvider>>,
+    ) -> Result<Self, FfiError> {
+        Self::with_config(
+            ClientConfig {
+                homeserver_url,
+                base_store_dir,
+                account_id,
+                proxy,
+                enable_share_history_on_invite,
+                store_passphrase: None,
+            },
+            session_key,
+        )
+    }
+
+    /// Builds a client from a [`ClientConfig`]. With `store_passphrase` set,
+    /// a passphrase that does not match the existing stores fails with
+    /// [`FfiError::WrongPassphrase`] rather than a generic build error.
+    #[uniffi::constructor]
+    pub fn with_config(
+        config: ClientConfig,
+        session_key: Option<Box<dyn SessionKeyProvider>>,
     ) -> Result<Self, FfiError> {
         platform::init_tracing();
 
+        let ClientConfig {
+            homeserver_url,
+            base_store_dir,
+            account_id,
+            proxy,
+            enable_share_history_on_invite,
+            store_passphrase,
+        } = config;
+        #[cfg(target_arch = "wasm32")]
+        let _ = &store_passphrase;
+
         let raw = homeserver_url.trim();
         let (server_name_or_url, is_url) = if let Ok(url) = Url::parse(raw) {
             (strip_matrix_path(url).to_string(), true)
@@ -383,6 +787,15 @@ impl Client {
         #[cfg(not(target_family = "wasm"))]
         let _ = std::fs::create_dir_all(&store_dir_path);
 
+        let session_secret = session_key.and_then(|provider| {
+            let account_key = store_dir_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            provider.session_secret(account_key)
+        });
+        platform::set_session_secret(&store_dir_path, session_secret);
+
         // NOTE: for previous unsafe wipes
         #[cfg(not(target_family = "wasm"))]
         if let Some(parent) = store_dir_path.parent() {
@@ -402,6 +815,13 @@ impl Client {
             }
         }
 
+        // Before anything opens the stores or the index key.
+        platform::recover_rekey(&store_dir_path)?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let idx = platform::search_index_config(&store_dir_path, store_passphrase.as_deref())?
+            .expect("native builds require search index config");
+
         let inner = RT
             .block_on(async {
                 #[cfg(target_arch = "wasm32")]
@@ -431,8 +851,6 @@ impl Client {
 
                 #[cfg(not(target_arch = "wasm32"))]
                 let client = {
-                    let idx = platform::search_index_config(&store_dir_path)
-                        .expect("native builds require search index config");
                     let mut builder = if is_url {
                         SdkClient::builder().homeserver_url(server_name_or_url.clone())
                     } else {
@@ -440,7 +858,7 @@ impl Client {
                             .server_name_or_homeserver_url(server_name_or_url.clone())
                     }
                     .request_config(RequestConfig::new().timeout(Duration::from_secs(30)))
-                    .sqlite_store(&store_dir_path, None)
+                    .sqlite_store(&store_dir_path, store_passphrase.as_deref())
                     .search_index_store(SearchIndexStoreKind::EncryptedDirectory(idx.dir, idx.key))
                     .with_encryption_settings(EncryptionSettings {
                         auto_enable_cross_signing: true,
@@ -465,9 +883,20 @@ impl Client {
 
                 client
             })
-            .map_err(|e| FfiError::Msg(format!("failed to build client: {e}")))?;
+            .map_err(|e| {
+                #[cfg(not(target_arch = "wasm32"))]
+                if matches!(e, matrix_sdk::ClientBuildError::SqliteStore(_))
+                    && let Some(passphrase) = store_passphrase.as_deref()
+                    && platform::store_passphrase_rejected(&store_dir_path, passphrase)
+                {
+                    warn!("failed to open encrypted stores: {e}");
+                    return FfiError::WrongPassphrase;
+                }
+                FfiError::Msg(format!("failed to build client: {e}"))
+            })?;
 
         let core = Arc::new(CoreClient::new(inner.clone()));
+        let media_cache = media_cache::MediaCache::open(cache_dir(&store_dir_path));
         let (send_tx, mut send_rx) = tokio::sync::mpsc::unbounded_channel::<SendUpdate>();
 
         let this = Self {
@@ -494,9 +923,17 @@ impl Client {
             beacon_subs: Mutex::new(HashMap::new()),
             recovery_state_subs: Mutex::new(HashMap::new()),
             backup_state_subs: Mutex::new(HashMap::new()),
+            identity_subs: Mutex::new(HashMap::new()),
+            utd_subs: Mutex::new(HashMap::new()),
+            space_tree_subs: Mutex::new(HashMap::new()),
+            verification_tasks: Arc::new(Mutex::new(HashMap::new())),
             widget_handles: Mutex::new(HashMap::new()),
             widget_driver_tasks: Mutex::new(HashMap::new()),
             widget_recv_tasks: Mutex::new(HashMap::new()),
+            media_cache,
+            transfer_cancels: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(not(target_family = "wasm"))]
+            qr_check_code: Arc::new(Mutex::new(None)),
             app_in_foreground: Arc::new(AtomicUsize::new(0)),
         };
 
@@ -546,10 +983,13 @@ impl Client {
             this.guards.lock().unwrap().push(h);
         }
 
-        // Restore session
+        // Restore session. A sealed session file the secret cannot open is
+        // an error, not a logged-out account.
+        #[cfg(not(target_family = "wasm"))]
+        let stored = RT.block_on(platform::load_session(&this.store_dir))?;
         #[cfg(not(target_family = "wasm"))]
         RT.block_on(async {
-            if let Some(info) = platform::load_session(&this.store_dir).await {
+            if let Some(info) = stored {
                 info!("Loading session for {} (auth: {})", info.user_id, info.auth_api);
                 if let Ok(user_id) = info.user_id.parse::<OwnedUserId>() {
                     let meta = SessionMeta {
@@ -619,7 +1059,7 @@ impl Client {
                             let is_auth_error = error_str.contains("AuthenticationRequired")
                                 || error_str.contains("Invalid access token")
                                 || error_str.contains("UnknownToken");
-                            if let Some(mut session_info) = platform::load_session(&this.store_dir).await {
+                            if let Ok(Some(mut session_info)) = platform::load_session(&this.store_dir).await {
                                 if is_auth_error {
                                     session_info.is_token_valid = false;
                                     let _ = platform::persist_session(&this.store_dir, &session_info).await;
@@ -694,6 +1134,16 @@ impl Client {
             .await;
 
         Self::persist_current_session(self).await;
+
+        let sdk = self.core.sdk.clone();
+        let h = spawn_task!(async move {
+            if dehydration::is_enabled(&sdk).await
+                && let Err(e) = dehydration::refresh(&sdk, None).await
+            {
+                warn!("Dehydrated device refresh failed after login: {e}");
+            }
+        });
+        self.guards.lock().unwrap().push(h);
     }
 
     pub fn room_profile(&self, room_id: String) -> Result<Option<RoomProfile>, FfiError> {
@@ -813,6 +1263,23 @@ impl Client {
         RT.block_on(self.core.search_room(room_id, query, limit, offset))
     }
 
+    /// Searches every joined room (or `filter.room_ids`) and ranks the hits
+    /// together. Pass the returned `next_cursor` back for the next page.
+    pub fn search_all_rooms(
+        &self,
+        query: String,
+        filter: SearchFilter,
+        limit: u32,
+        cursor: Option<String>,
+    ) -> Result<SearchPage, FfiError> {
+        #[cfg(target_family = "wasm")]
+        return Err(FfiError::Msg(
+            "search_all_rooms: not supported on web".into(),
+        ));
+        #[cfg(not(target_family = "wasm"))]
+        RT.block_on(self.core.search_all_rooms(query, filter, limit, cursor))
+    }
+
     pub fn whoami(&self) -> Option<String> {
         self.core.whoami()
     }
@@ -820,6 +1287,16 @@ impl Client {
         self.core.is_logged_in()
     }
 
+    /// Re-wraps the store encryption under `new_passphrase`. Takes effect on
+    /// the next [`Client::with_config`]; this client keeps running unchanged.
+    pub fn rekey_stores(
+        &self,
+        old_passphrase: String,
+        new_passphrase: String,
+    ) -> Result<(), FfiError> {
+        platform::rekey_stores(&self.store_dir, &old_passphrase, &new_passphrase)
+    }
+
     fn next_sub_id(&self) -> u64 {
         self.subs_counter
             .fetch_add(1, Ordering::Relaxed)
@@ -1078,6 +1555,11 @@ impl Client {
     }
 
     pub fn observe_room_list(&self, observer: Box<dyn RoomListObserver>) -> u64 {
+        use matrix_sdk::ruma::events::{
+            SyncStateEvent,
+            space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
+        };
+
         let obs: Arc<dyn RoomListObserver> = Arc::from(observer);
         let core = self.core.clone();
         let store_dir = self.store_dir.clone();
@@ -1099,31 +1581,54 @@ impl Client {
             };
             let (stream, controller) = all.entries_with_dynamic_adapters(50);
             tokio::pin!(stream);
-            controller.set_filter(Box::new(filters::new_filter_non_left()));
-            use matrix_sdk_ui::room_list_service::RoomListItem;
-            let mut items = Vector::<RoomListItem>::new();
+            let mut filter = RoomListFilter::default();
+            controller.set_filter(core.room_list_filter(&filter).await);
+            // A space filter holds the space's rooms as of when it was built,
+            // so it is rebuilt whenever space links change or a space is
+            // joined or left.
+            let child_handler = core
+                .sdk
+                .observe_events::<SyncStateEvent<SpaceChildEventContent>, Room>();
+            let parent_handler = core
+                .sdk
+                .observe_events::<SyncStateEvent<SpaceParentEventContent>, Room>();
+            let mut space_children = child_handler.subscribe();
+            let mut space_parents = parent_handler.subscribe();
+            let mut spaces = space_tree::joined_space_ids(&core.sdk);
+            let mut entries = Vec::<RoomListEntry>::new();
+            let mut sorted = SortedRoomList::new(platform::load_room_list_sort(&store_dir).await);
+            // Only the first snapshot goes through `on_reset`; later resets
+            // (re-sort, resync) are diffs like any other.
+            let mut initial = true;
+            let mut emit = move |diff: RoomListDiff| match diff {
+                RoomListDiff::Reset { values } if initial => {
+                    initial = false;
+                    safe_call(|| obs.on_reset(values))
+                }
+                diff => safe_call(|| obs.on_diff(diff)),
+            };
+            let mut flush_at: Option<tokio::time::Instant> = None;
             loop {
                 tokio::select! {
                     Some(cmd) = cmd_rx.recv() => {
                         match cmd {
                             RoomListCmd::SetUnreadOnly(unread_only) => {
-                                if unread_only {
-                                    let unread_filter: filters::BoxedFilterFn = Box::new(
-                                        |room: &RoomListItem| -> bool {
-                                            let receipts = room.read_receipts();
-                                            receipts.num_unread > 0 || room.is_marked_unread()
-                                        },
-                                    );
-                                    controller.set_filter(Box::new(filters::new_filter_all(vec![
-                                        Box::new(filters::new_filter_non_left()),
-                                        unread_filter,
-                                    ])));
-                                } else {
-                                    controller.set_filter(Box::new(filters::new_filter_non_left()));
-                                }
+                                filter.unread_only = unread_only;
+                                controller.set_filter(core.room_list_filter(&filter).await);
+                            }
+                            RoomListCmd::SetFilter(next) => {
+                                filter = next;
+                                controller.set_filter(core.room_list_filter(&filter).await);
+                            }
+                            RoomListCmd::SetSort(sort) => {
+                                let _ = platform::write_room_list_sort(&store_dir, &sort).await;
+                                emit(sorted.set_sort(sort));
+                                flush_at.get_or_insert_with(|| {
+                                    tokio::time::Instant::now() + ROOM_LIST_CACHE_DEBOUNCE
+                                });
                             }
                             RoomListCmd::UpdateVisibleRange((range, threshold)) => {
-                                let total_items = items.len();
+                                let total_items = entries.len();
                                 if let Some(&position) = range.last() {
                                     let threshold_idx = total_items.saturating_sub(threshold);
                                     if total_items > 0 && position as usize >= threshold_idx {
@@ -1133,17 +1638,44 @@ impl Client {
                             }
                         }
                     }
+                    Some(_) = space_children.next(), if filter.space_id.is_some() => {
+                        controller.set_filter(core.room_list_filter(&filter).await);
+                    }
+                    Some(_) = space_parents.next(), if filter.space_id.is_some() => {
+                        controller.set_filter(core.room_list_filter(&filter).await);
+                    }
                     Some(diffs) = stream.next() => {
-                        if CoreClient::apply_vector_diff(&mut items, diffs) {
-                            let snapshot = core.build_room_list_snapshot(&items).await;
-                            let _ = platform::write_room_list_cache(&store_dir, &snapshot).await;
-                            let obs_clone = obs.clone();
-                            safe_call(move || obs_clone.on_reset(snapshot));
+                        for diff in diffs {
+                            let Some(diff) = core.apply_room_list_diff(&mut entries, diff).await
+                            else {
+                                continue;
+                            };
+                            sorted.apply(diff).into_iter().for_each(&mut emit);
+                            flush_at.get_or_insert_with(|| {
+                                tokio::time::Instant::now() + ROOM_LIST_CACHE_DEBOUNCE
+                            });
+                        }
+                        // Joining or leaving a space sends no link event.
+                        let joined = space_tree::joined_space_ids(&core.sdk);
+                        if joined != spaces {
+                            spaces = joined;
+                            if filter.space_id.is_some() {
+                                controller.set_filter(core.room_list_filter(&filter).await);
+                            }
                         }
                     }
+                    _ = tokio::time::sleep_until(
+                        flush_at.unwrap_or_else(tokio::time::Instant::now)
+                    ), if flush_at.is_some() => {
+                        flush_at = None;
+                        let _ = platform::write_room_list_cache(&store_dir, sorted.entries()).await;
+                    }
                     else => break,
                 }
             }
+            if flush_at.is_some() {
+                let _ = platform::write_room_list_cache(&store_dir, sorted.entries()).await;
+            }
         });
         self.room_list_subs.lock().unwrap().insert(id, h);
         id
@@ -1162,6 +1694,31 @@ impl Client {
         }
     }
 
+    /// Replaces the filter of the room list observed under `token`. The
+    /// observer gets a fresh `on_reset` with the matching rooms.
+    pub fn room_list_set_filter(&self, token: u64, filter: RoomListFilter) -> bool {
+        if let Some(tx) = self.room_list_cmds.lock().unwrap().get(&token).cloned() {
+            tx.send(RoomListCmd::SetFilter(filter)).is_ok()
+        } else {
+            false
+        }
+    }
+
+    /// Changes the order of the room list observed under `token` and
+    /// remembers it for later sessions (see `room_list_sort`).
+    pub fn room_list_set_sort(&self, token: u64, sort: RoomListSort) -> bool {
+        if let Some(tx) = self.room_list_cmds.lock().unwrap().get(&token).cloned() {
+            tx.send(RoomListCmd::SetSort(sort)).is_ok()
+        } else {
+            false
+        }
+    }
+
+    /// The persisted room list order, applied when `observe_room_list` starts.
+    pub fn room_list_sort(&self) -> RoomListSort {
+        RT.block_on(platform::load_room_list_sort(&self.store_dir))
+    }
+
     pub fn room_list_update_visible_range(
         &self,
         token: u64,
@@ -1398,6 +1955,142 @@ impl Client {
         unsub!(self, backup_state_subs, sub_id)
     }
 
+    /// Identity changes of `room_id`'s members that need attention, so the UI
+    /// can warn "X's identity has changed" and offer pin or withdraw.
+    pub fn observe_identity_changes(
+        &self,
+        room_id: String,
+        observer: Box<dyn IdentityChangeObserver>,
+    ) -> u64 {
+        let Ok(rid) = OwnedRoomId::try_from(room_id) else {
+            return 0;
+        };
+        let obs: Arc<dyn IdentityChangeObserver> = Arc::from(observer);
+        let sdk = self.core.sdk.clone();
+        sub_manager!(self, identity_subs, async move {
+            use matrix_sdk::encryption::identities::room_identity_state::IdentityState as SdkIdentityState;
+
+            let Some(room) = sdk.get_room(&rid) else {
+                return;
+            };
+            let stream = match room.subscribe_to_identity_status_changes().await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("identity status stream for {rid} failed: {e:?}");
+                    return;
+                }
+            };
+            tokio::pin!(stream);
+            while let Some(changes) = stream.next().await {
+                let mut out = Vec::with_capacity(changes.len());
+                for c in changes {
+                    let state = match c.changed_to {
+                        SdkIdentityState::Verified => IdentityState::Verified,
+                        SdkIdentityState::Pinned => IdentityState::Pinned,
+                        SdkIdentityState::PinViolation => IdentityState::PinViolation,
+                        SdkIdentityState::VerificationViolation => {
+                            IdentityState::VerificationViolation
+                        }
+                    };
+                    let display_name = match room.get_member_no_sync(&c.user_id).await {
+                        Ok(Some(m)) => m.display_name().map(str::to_owned),
+                        _ => None,
+                    };
+                    out.push(IdentityChange {
+                        user_id: c.user_id.to_string(),
+                        display_name,
+                        state,
+                    });
+                }
+                safe_call(|| obs.on_changes(out));
+            }
+        })
+    }
+
+    pub fn unobserve_identity_changes(&self, sub_id: u64) -> bool {
+        unsub!(self, identity_subs, sub_id)
+    }
+
+    /// Per-room UTD tallies, re-emitted whenever a timeline reports one.
+    pub fn observe_utd_reports(&self, observer: Box<dyn UtdReportObserver>) -> u64 {
+        let obs: Arc<dyn UtdReportObserver> = Arc::from(observer);
+        let reporter = self.core.timeline_mgr.utd.clone();
+        sub_manager!(self, utd_subs, async move {
+            let mut generation = reporter.subscribe();
+            loop {
+                let snapshot = reporter.snapshot();
+                safe_call(|| obs.on_update(snapshot));
+                if generation.changed().await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+
+    pub fn unobserve_utd_reports(&self, sub_id: u64) -> bool {
+        unsub!(self, utd_subs, sub_id)
+    }
+
+    /// Emits the space tree now and again whenever space links change
+    /// (`m.space.child` / `m.space.parent`) or room unread counts move. The
+    /// link graph is only reloaded for the former.
+    pub fn observe_space_tree(&self, observer: Box<dyn SpaceTreeObserver>) -> u64 {
+        use futures_util::FutureExt;
+        use matrix_sdk::ruma::events::{
+            SyncStateEvent,
+            space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
+        };
+        use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+        let obs: Arc<dyn SpaceTreeObserver> = Arc::from(observer);
+        let sdk = self.core.sdk.clone();
+        sub_manager!(self, space_tree_subs, async move {
+            let child_handler =
+                sdk.observe_events::<SyncStateEvent<SpaceChildEventContent>, Room>();
+            let parent_handler =
+                sdk.observe_events::<SyncStateEvent<SpaceParentEventContent>, Room>();
+            let mut child_sub = child_handler.subscribe();
+            let mut parent_sub = parent_handler.subscribe();
+            let mut room_updates = sdk.room_info_notable_update_receiver();
+            let mut graph = space_tree::SpaceGraph::load(&sdk).await;
+            let mut last: Option<SpaceTree> = None;
+            loop {
+                let tree = space_tree::build(&sdk, &graph).await;
+                if last.as_ref() != Some(&tree) {
+                    let t = tree.clone();
+                    safe_call(|| obs.on_update(t));
+                    last = Some(tree);
+                }
+                let mut links_changed = tokio::select! {
+                    Some(_) = child_sub.next() => true,
+                    Some(_) = parent_sub.next() => true,
+                    res = room_updates.recv() => {
+                        if matches!(res, Err(RecvError::Closed)) {
+                            return;
+                        }
+                        false
+                    }
+                };
+                sleep(SPACE_TREE_DEBOUNCE).await;
+                // The rebuild covers whatever piled up meanwhile.
+                while let Ok(_) | Err(TryRecvError::Lagged(_)) = room_updates.try_recv() {}
+                while let Some(Some(_)) = child_sub.next().now_or_never() {
+                    links_changed = true;
+                }
+                while let Some(Some(_)) = parent_sub.next().now_or_never() {
+                    links_changed = true;
+                }
+                if links_changed || !graph.covers(&sdk) {
+                    graph = space_tree::SpaceGraph::load(&sdk).await;
+                }
+            }
+        })
+    }
+
+    pub fn unobserve_space_tree(&self, sub_id: u64) -> bool {
+        unsub!(self, space_tree_subs, sub_id)
+    }
+
     pub fn start_verification_inbox(&self, observer: Box<dyn VerificationInboxObserver>) -> u64 {
         let core = self.core.clone();
         let obs: Arc<dyn VerificationInboxObserver> = Arc::from(observer);
@@ -1583,66 +2276,78 @@ impl Client {
     }
 
     pub fn login_password(&self, username: String, password: String) -> Result<String, FfiError> {
-        RT.block_on(async {
-            self.core
-                .sdk
-                .matrix_auth()
-                .login_username(&username, &password)
-                .send()
-                .await
-                .map_err(|e| FfiError::Msg(format!("login failed: {e}")))?;
-            self.finish_login_setup().await;
-            Ok(self.core.user_id_str())
-        })
+        RT.block_on(self.login_password_async(username, password))
+    }
+
+    async fn login_password_async(
+        &self,
+        username: String,
+        password: String,
+    ) -> Result<String, FfiError> {
+        self.core
+            .sdk
+            .matrix_auth()
+            .login_username(&username, &password)
+            .send()
+            .await
+            .map_err(|e| FfiError::Msg(format!("login failed: {e}")))?;
+        self.finish_login_setup().await;
+        Ok(self.core.user_id_str())
     }
 
     pub fn homeserver_login_details(&self) -> HomeserverLoginDetails {
-        RT.block_on(async {
-            let (supports_sso, supports_password) =
-                match self.core.sdk.matrix_auth().get_login_types().await {
-                    Ok(resp) => {
-                        use matrix_sdk::ruma::api::client::session::get_login_types::v3::LoginType;
-                        (
-                            resp.flows.iter().any(|f| matches!(f, LoginType::Sso(_))),
-                            resp.flows
-                                .iter()
-                                .any(|f| matches!(f, LoginType::Password(_))),
-                        )
-                    }
-                    Err(e) => {
-                        warn!("get_login_types failed: {e:?}");
-                        (false, false)
-                    }
-                };
+        RT.block_on(self.homeserver_login_details_async())
+    }
 
-            let supports_oauth = match self.core.sdk.oauth().server_metadata().await {
-                Ok(_) => true,
+    async fn homeserver_login_details_async(&self) -> HomeserverLoginDetails {
+        let (supports_sso, supports_password) =
+            match self.core.sdk.matrix_auth().get_login_types().await {
+                Ok(resp) => {
+                    use matrix_sdk::ruma::api::client::session::get_login_types::v3::LoginType;
+                    (
+                        resp.flows.iter().any(|f| matches!(f, LoginType::Sso(_))),
+                        resp.flows
+                            .iter()
+                            .any(|f| matches!(f, LoginType::Password(_))),
+                    )
+                }
                 Err(e) => {
-                    if !e.is_not_supported() {
-                        warn!("OAuth discovery failed, treating as unsupported: {e:?}");
-                    }
-                    false
+                    warn!("get_login_types failed: {e:?}");
+                    (false, false)
                 }
             };
 
-            let homeserver_url = self.core.sdk.homeserver().to_string();
-
-            HomeserverLoginDetails {
-                homeserver_url,
-                supports_oauth,
-                supports_sso,
-                supports_password,
+        let supports_oauth = match self.core.sdk.oauth().server_metadata().await {
+            Ok(_) => true,
+            Err(e) => {
+                if !e.is_not_supported() {
+                    warn!("OAuth discovery failed, treating as unsupported: {e:?}");
+                }
+                false
             }
-        })
+        };
+
+        let homeserver_url = self.core.sdk.homeserver().to_string();
+
+        HomeserverLoginDetails {
+            homeserver_url,
+            supports_oauth,
+            supports_sso,
+            supports_password,
+        }
     }
 
     pub fn login_oauth(&self, redirect_uri: String) -> Result<String, FfiError> {
+        RT.block_on(self.login_oauth_async(redirect_uri))
+    }
+
+    async fn login_oauth_async(&self, redirect_uri: String) -> Result<String, FfiError> {
         #[cfg(target_family = "wasm")]
         return Err(FfiError::Msg(
             "login_oauth: use async version on web".into(),
         ));
         #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
+        {
             let redirect = Url::parse(&redirect_uri).ffi()?;
             let metadata = mages_client_metadata(&redirect);
             let auth_data = self
@@ -1654,16 +2359,20 @@ impl Client {
                 .await
                 .map_err(|e| FfiError::Msg(format!("oauth: {e}")))?;
             Ok(auth_data.url.to_string())
-        })
+        }
     }
 
     pub fn complete_oauth_login(&self, callback_url: String) -> Result<String, FfiError> {
+        RT.block_on(self.complete_oauth_login_async(callback_url))
+    }
+
+    async fn complete_oauth_login_async(&self, callback_url: String) -> Result<String, FfiError> {
         #[cfg(target_family = "wasm")]
         return Err(FfiError::Msg(
             "complete_oauth_login: use async version on web".into(),
         ));
         #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
+        {
             let url = Url::parse(&callback_url).ffi()?;
             self.core
                 .sdk
@@ -1673,20 +2382,28 @@ impl Client {
                 .map_err(|e| FfiError::Msg(format!("oauth finish failed: {e}")))?;
             self.finish_login_setup().await;
             Ok(self.core.user_id_str())
-        })
+        }
     }
 
     pub fn login_sso_loopback(
         &self,
         opener: Box<dyn UrlOpener>,
         device_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.login_sso_loopback_async(opener, device_name))
+    }
+
+    async fn login_sso_loopback_async(
+        &self,
+        opener: Box<dyn UrlOpener>,
+        device_name: Option<String>,
     ) -> Result<(), FfiError> {
         #[cfg(target_family = "wasm")]
         return Err(FfiError::Msg(
             "login_sso_loopback: not supported on wasm".into(),
         ));
         #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
+        {
             self.core
                 .sdk
                 .matrix_auth()
@@ -1703,20 +2420,28 @@ impl Client {
                 .ffi()?;
             self.finish_login_setup().await;
             Ok(())
-        })
+        }
     }
 
     pub fn login_oauth_loopback(
         &self,
         opener: Box<dyn UrlOpener>,
         device_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.login_oauth_loopback_async(opener, device_name))
+    }
+
+    async fn login_oauth_loopback_async(
+        &self,
+        opener: Box<dyn UrlOpener>,
+        device_name: Option<String>,
     ) -> Result<(), FfiError> {
         #[cfg(target_family = "wasm")]
         return Err(FfiError::Msg(
             "login_oauth_loopback: not supported on wasm".into(),
         ));
         #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
+        {
             let oauth = self.core.sdk.oauth();
             let (redirect_uri, server_handle) = LocalServerBuilder::new()
                 .ip_address(LocalServerIpAddress::Localhostv4)
@@ -1745,38 +2470,202 @@ impl Client {
             }
             self.finish_login_setup().await;
             Ok(())
-        })
+        }
     }
 
-    pub fn start_oauth_login(&self, redirect_uri: String) -> Result<String, FfiError> {
-        RT.block_on(async {
-            let oauth = self.core.sdk.oauth();
-            let redirect_uri = Url::parse(&redirect_uri).ffi()?;
-            let registration_data = mages_client_metadata(&redirect_uri).into();
-            let auth_data = oauth
-                .login(redirect_uri, None, Some(registration_data), None)
-                .build()
-                .await
-                .ffi()?;
-            Ok(auth_data.url.to_string())
-        })
+    /// New-device side of MSC4108: shows a QR code (via `QrReady`) and waits
+    /// for a logged-in device to scan it. Blocks until the login finishes;
+    /// the check code shown on the other device is passed back through
+    /// [`Client::qr_login_submit_check_code`] from another thread.
+    pub fn login_with_qr_code(
+        &self,
+        device_name: Option<String>,
+        listener: Box<dyn QrLoginListener>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.login_with_qr_code_async(device_name, listener))
     }
 
-    pub fn finish_oauth_login(
+    async fn login_with_qr_code_async(
         &self,
-        callback_data: String,
         device_name: Option<String>,
+        listener: Box<dyn QrLoginListener>,
     ) -> Result<(), FfiError> {
-        RT.block_on(async {
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = (device_name, listener);
+            return Err(FfiError::Msg(
+                "login_with_qr_code: not supported on wasm".into(),
+            ));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            use crate::qr_login::{QrLoginEvent, emit, map_login_progress};
+
+            let lis: Arc<dyn QrLoginListener> = Arc::from(listener);
+            let registration_data = mages_qr_client_metadata().into();
             let oauth = self.core.sdk.oauth();
-            let callback = Url::parse(&callback_data)
-                .map(UrlOrQuery::Url)
-                .unwrap_or_else(|_| UrlOrQuery::Query(callback_data));
-            oauth.finish_login(callback).await.ffi()?;
+            let login = oauth
+                .login_with_qr_code(Some(&registration_data))
+                .generate();
+
+            let mut progress = login.subscribe_to_progress();
+            let check_code = self.qr_check_code.clone();
+            let lis2 = lis.clone();
+            let watcher = spawn_task!(async move {
+                while let Some(p) = progress.next().await {
+                    if let Some(ev) = map_login_progress(p, &check_code) {
+                        safe_call(|| emit(&*lis2, &ev));
+                    }
+                }
+            });
+
+            let result = login.await;
+            watcher.abort();
+            self.qr_check_code.lock().unwrap().take();
+            if let Err(e) = result {
+                let message = format!("QR login failed: {e}");
+                safe_call(|| {
+                    emit(
+                        &*lis,
+                        &QrLoginEvent::Error {
+                            message: message.clone(),
+                        },
+                    )
+                });
+                return Err(FfiError::Msg(message));
+            }
+
             Self::maybe_update_device_name(self, device_name).await;
             self.finish_login_setup().await;
+            self.cross_sign_own_device().await;
+            safe_call(|| emit(&*lis, &QrLoginEvent::Done));
             Ok(())
-        })
+        }
+    }
+
+    /// Submits the 2-digit code shown on the scanning device, after
+    /// `QrScanned` was emitted by [`Client::login_with_qr_code`].
+    pub fn qr_login_submit_check_code(&self, code: u8) -> Result<(), FfiError> {
+        RT.block_on(self.qr_login_submit_check_code_async(code))
+    }
+
+    async fn qr_login_submit_check_code_async(&self, code: u8) -> Result<(), FfiError> {
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = code;
+            return Err(FfiError::Msg(
+                "qr_login_submit_check_code: not supported on wasm".into(),
+            ));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let sender = self
+                .qr_check_code
+                .lock()
+                .unwrap()
+                .take()
+                .or_ffi("No QR login is waiting for a check code")?;
+            sender.send(code).await.ffi()
+        }
+    }
+
+    /// Existing-device side of MSC4108: approves the new device whose QR code
+    /// was scanned. Emits `CheckCode` for the user to type on the new device
+    /// and `WaitingForAuth` with the provider URL to confirm the sign-in.
+    pub fn grant_login_with_qr_code(
+        &self,
+        qr_data: Vec<u8>,
+        listener: Box<dyn QrLoginListener>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.grant_login_with_qr_code_async(qr_data, listener))
+    }
+
+    async fn grant_login_with_qr_code_async(
+        &self,
+        qr_data: Vec<u8>,
+        listener: Box<dyn QrLoginListener>,
+    ) -> Result<(), FfiError> {
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = (qr_data, listener);
+            return Err(FfiError::Msg(
+                "grant_login_with_qr_code: not supported on wasm".into(),
+            ));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            use crate::qr_login::{QrLoginEvent, emit, map_grant_progress};
+
+            let data = QrCodeData::from_bytes(&qr_data).ffi()?;
+            let lis: Arc<dyn QrLoginListener> = Arc::from(listener);
+            let oauth = self.core.sdk.oauth();
+            let grant = oauth.grant_login_with_qr_code().scan(&data);
+
+            let mut progress = grant.subscribe_to_progress();
+            let lis2 = lis.clone();
+            let watcher = spawn_task!(async move {
+                while let Some(p) = progress.next().await {
+                    if let Some(ev) = map_grant_progress(p) {
+                        safe_call(|| emit(&*lis2, &ev));
+                    }
+                }
+            });
+
+            let result = grant.await;
+            watcher.abort();
+            if let Err(e) = result {
+                let message = format!("QR login grant failed: {e}");
+                safe_call(|| {
+                    emit(
+                        &*lis,
+                        &QrLoginEvent::Error {
+                            message: message.clone(),
+                        },
+                    )
+                });
+                return Err(FfiError::Msg(message));
+            }
+            Ok(())
+        }
+    }
+
+    pub fn start_oauth_login(&self, redirect_uri: String) -> Result<String, FfiError> {
+        RT.block_on(self.start_oauth_login_async(redirect_uri))
+    }
+
+    async fn start_oauth_login_async(&self, redirect_uri: String) -> Result<String, FfiError> {
+        let oauth = self.core.sdk.oauth();
+        let redirect_uri = Url::parse(&redirect_uri).ffi()?;
+        let registration_data = mages_client_metadata(&redirect_uri).into();
+        let auth_data = oauth
+            .login(redirect_uri, None, Some(registration_data), None)
+            .build()
+            .await
+            .ffi()?;
+        Ok(auth_data.url.to_string())
+    }
+
+    pub fn finish_oauth_login(
+        &self,
+        callback_data: String,
+        device_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.finish_oauth_login_async(callback_data, device_name))
+    }
+
+    async fn finish_oauth_login_async(
+        &self,
+        callback_data: String,
+        device_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        let oauth = self.core.sdk.oauth();
+        let callback = Url::parse(&callback_data)
+            .map(UrlOrQuery::Url)
+            .unwrap_or_else(|_| UrlOrQuery::Query(callback_data));
+        oauth.finish_login(callback).await.ffi()?;
+        Self::maybe_update_device_name(self, device_name).await;
+        self.finish_login_setup().await;
+        Ok(())
     }
 
     pub fn start_sso_login(
@@ -1784,14 +2673,20 @@ impl Client {
         redirect_uri: String,
         idp_id: Option<String>,
     ) -> Result<String, FfiError> {
-        RT.block_on(async {
-            let auth = self.core.sdk.matrix_auth();
-            let url = auth
-                .get_sso_login_url(&redirect_uri, idp_id.as_deref())
-                .await
-                .ffi()?;
-            Ok(url)
-        })
+        RT.block_on(self.start_sso_login_async(redirect_uri, idp_id))
+    }
+
+    async fn start_sso_login_async(
+        &self,
+        redirect_uri: String,
+        idp_id: Option<String>,
+    ) -> Result<String, FfiError> {
+        let auth = self.core.sdk.matrix_auth();
+        let url = auth
+            .get_sso_login_url(&redirect_uri, idp_id.as_deref())
+            .await
+            .ffi()?;
+        Ok(url)
     }
 
     pub fn finish_sso_login(
@@ -1799,24 +2694,34 @@ impl Client {
         callback_url: String,
         device_name: Option<String>,
     ) -> Result<(), FfiError> {
-        RT.block_on(async {
-            let auth = self.core.sdk.matrix_auth();
-            let callback_url = Url::parse(&callback_url).ffi()?;
-            let mut builder = auth
-                .login_with_sso_callback(UrlOrQuery::Url(callback_url))
-                .ffi()?;
-            if let Some(name) = device_name.as_deref() {
-                builder = builder.initial_device_display_name(name);
-            }
-            let _response = builder.await.ffi()?;
-            self.finish_login_setup().await;
-            Ok(())
-        })
+        RT.block_on(self.finish_sso_login_async(callback_url, device_name))
+    }
+
+    async fn finish_sso_login_async(
+        &self,
+        callback_url: String,
+        device_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        let auth = self.core.sdk.matrix_auth();
+        let callback_url = Url::parse(&callback_url).ffi()?;
+        let mut builder = auth
+            .login_with_sso_callback(UrlOrQuery::Url(callback_url))
+            .ffi()?;
+        if let Some(name) = device_name.as_deref() {
+            builder = builder.initial_device_display_name(name);
+        }
+        let _response = builder.await.ffi()?;
+        self.finish_login_setup().await;
+        Ok(())
     }
 
     pub fn logout(&self) -> bool {
+        RT.block_on(self.logout_async())
+    }
+
+    async fn logout_async(&self) -> bool {
         self.shutdown();
-        let _ = RT.block_on(async { self.core.sdk.logout().await });
+        let _ = self.core.sdk.logout().await;
         platform::remove_session_file(&self.store_dir);
         platform::reset_store_dir(&self.store_dir);
         true
@@ -1828,19 +2733,96 @@ impl Client {
         password: String,
         device_display_name: Option<String>,
     ) -> Result<(), FfiError> {
-        RT.block_on(async {
+        RT.block_on(self.login_async(username, password, device_display_name))
+    }
+
+    async fn login_async(
+        &self,
+        username: String,
+        password: String,
+        device_display_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        self.core
+            .login_password(
+                PasswordLoginKind::Username,
+                username,
+                password,
+                None,
+                device_display_name,
+            )
+            .await?;
+        self.finish_authenticated_setup(true).await;
+        Ok(())
+    }
+
+    /// Creates an account with a password. Every UIAA stage the server asks
+    /// for (terms, email, registration token, captcha...) goes to `handler`.
+    pub fn register(
+        &self,
+        username: Option<String>,
+        password: String,
+        device_display_name: Option<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.register_async(username, password, device_display_name, handler))
+    }
+
+    async fn register_async(
+        &self,
+        username: Option<String>,
+        password: String,
+        device_display_name: Option<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        self.core
+            .register(username, password, device_display_name, &*handler)
+            .await?;
+        self.finish_authenticated_setup(true).await;
+        Ok(())
+    }
+
+    pub fn change_password(
+        &self,
+        new_password: String,
+        logout_devices: bool,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(
             self.core
-                .login_password(
-                    PasswordLoginKind::Username,
-                    username,
-                    password,
-                    None,
-                    device_display_name,
-                )
-                .await?;
-            self.finish_authenticated_setup(true).await;
-            Ok(())
-        })
+                .change_password(new_password, logout_devices, &*handler),
+        )
+    }
+
+    pub fn add_3pid(
+        &self,
+        medium: ThreePidMedium,
+        address: String,
+        country: Option<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.core.add_3pid(medium, address, country, &*handler))
+    }
+
+    /// Deactivates the account on the server, then tears down local state
+    /// the same way `logout` does. `erase` asks the server to forget messages.
+    pub fn deactivate_account(
+        &self,
+        erase: bool,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.deactivate_account_async(erase, handler))
+    }
+
+    async fn deactivate_account_async(
+        &self,
+        erase: bool,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<(), FfiError> {
+        self.core.deactivate_account(erase, &*handler).await?;
+        self.shutdown();
+        platform::remove_session_file(&self.store_dir);
+        platform::reset_store_dir(&self.store_dir);
+        Ok(())
     }
 
     pub fn login_email(
@@ -1849,19 +2831,26 @@ impl Client {
         password: String,
         device_display_name: Option<String>,
     ) -> Result<(), FfiError> {
-        RT.block_on(async {
-            self.core
-                .login_password(
-                    PasswordLoginKind::Email,
-                    email,
-                    password,
-                    None,
-                    device_display_name,
-                )
-                .await?;
-            self.finish_authenticated_setup(true).await;
-            Ok(())
-        })
+        RT.block_on(self.login_email_async(email, password, device_display_name))
+    }
+
+    async fn login_email_async(
+        &self,
+        email: String,
+        password: String,
+        device_display_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        self.core
+            .login_password(
+                PasswordLoginKind::Email,
+                email,
+                password,
+                None,
+                device_display_name,
+            )
+            .await?;
+        self.finish_authenticated_setup(true).await;
+        Ok(())
     }
 
     pub fn login_phone(
@@ -1871,19 +2860,27 @@ impl Client {
         password: String,
         device_display_name: Option<String>,
     ) -> Result<(), FfiError> {
-        RT.block_on(async {
-            self.core
-                .login_password(
-                    PasswordLoginKind::Phone,
-                    phone,
-                    password,
-                    Some(country),
-                    device_display_name,
-                )
-                .await?;
-            self.finish_authenticated_setup(true).await;
-            Ok(())
-        })
+        RT.block_on(self.login_phone_async(country, phone, password, device_display_name))
+    }
+
+    async fn login_phone_async(
+        &self,
+        country: String,
+        phone: String,
+        password: String,
+        device_display_name: Option<String>,
+    ) -> Result<(), FfiError> {
+        self.core
+            .login_password(
+                PasswordLoginKind::Phone,
+                phone,
+                password,
+                Some(country),
+                device_display_name,
+            )
+            .await?;
+        self.finish_authenticated_setup(true).await;
+        Ok(())
     }
 
     async fn finish_authenticated_setup(&self, persist_session: bool) {
@@ -1901,24 +2898,47 @@ impl Client {
         att: AttachmentInfo,
         body: Option<String>,
         progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.send_existing_attachment_async(room_id, att, body, progress))
+    }
+
+    async fn send_existing_attachment_async(
+        &self,
+        room_id: String,
+        att: AttachmentInfo,
+        body: Option<String>,
+        progress: Option<Box<dyn ProgressObserver>>,
     ) -> Result<(), FfiError> {
         if let Some(p) = progress.as_ref() {
             p.on_progress(0, None);
         }
-        let result = RT.block_on(self.core.send_existing_attachment(room_id, att, body));
+        let result = self.core.send_existing_attachment(room_id, att, body).await;
         if let Some(p) = progress {
             p.on_progress(1, Some(1));
         }
         result
     }
 
+    /// Streams to `dest_path`; an interrupted download of the same media to
+    /// the same path resumes where it stopped. Outside the web build this
+    /// does not go through the SDK media cache: `dest_path` is the only copy.
     pub fn download_media(
         &self,
         mxc_uri: String,
         dest_path: String,
         encrypted_json: Option<String>,
     ) -> Result<DownloadResult, FfiError> {
-        RT.block_on(async {
+        RT.block_on(self.download_media_async(mxc_uri, dest_path, encrypted_json))
+    }
+
+    async fn download_media_async(
+        &self,
+        mxc_uri: String,
+        dest_path: String,
+        encrypted_json: Option<String>,
+    ) -> Result<DownloadResult, FfiError> {
+        #[cfg(target_family = "wasm")]
+        {
             let source = if let Some(json) = encrypted_json {
                 let enc_file: matrix_sdk::ruma::events::room::EncryptedFile =
                     serde_json::from_str(&json).ffi()?;
@@ -1937,17 +2957,79 @@ impl Client {
                 .get_media_content(&request, true)
                 .await
                 .ffi()?;
-            #[cfg(not(target_family = "wasm"))]
-            {
-                std::fs::write(&dest_path, &data).ffi()?;
-            }
-            Ok(DownloadResult {
+            return Ok(DownloadResult {
                 path: dest_path,
                 bytes: data.len() as u64,
-            })
-        })
+            });
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let source = download::DownloadSource::new(mxc_uri, encrypted_json.as_deref())?;
+            let dest = PathBuf::from(dest_path);
+            download::download_to_file(&self.core.sdk, source, &dest, None).await
+        }
+    }
+
+    /// Downloads in the background and returns a handle for
+    /// [`Client::cancel_media_download`]. Exactly one of `on_complete`,
+    /// `on_error` or `on_cancelled` ends it.
+    pub fn start_media_download(
+        &self,
+        mxc_uri: String,
+        dest_path: String,
+        encrypted_json: Option<String>,
+        observer: Box<dyn MediaDownloadObserver>,
+    ) -> u64 {
+        let obs: Arc<dyn MediaDownloadObserver> = Arc::from(observer);
+        let id = self.next_sub_id();
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = (mxc_uri, dest_path, encrypted_json);
+            safe_call(|| obs.on_error("media download: not supported on web".into()));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let source = match download::DownloadSource::new(mxc_uri, encrypted_json.as_deref()) {
+                Ok(s) => s,
+                Err(e) => {
+                    safe_call(|| obs.on_error(e.to_string()));
+                    return id;
+                }
+            };
+            let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel::<()>();
+            self.transfer_cancels.lock().unwrap().insert(id, cancel_tx);
+            let cancels = self.transfer_cancels.clone();
+            let sdk = self.core.sdk.clone();
+            spawn_task!(async move {
+                let dest = PathBuf::from(dest_path);
+                let progress: Arc<dyn ProgressObserver> =
+                    Arc::new(download::ObserverProgress(obs.clone()));
+                tokio::select! {
+                    res = download::download_to_file(&sdk, source, &dest, Some(progress)) => {
+                        match res {
+                            Ok(result) => safe_call(|| obs.on_complete(result)),
+                            Err(e) => safe_call(|| obs.on_error(e.to_string())),
+                        }
+                    }
+                    _ = cancel_rx => safe_call(|| obs.on_cancelled()),
+                }
+                cancels.lock().unwrap().remove(&id);
+            });
+        }
+        id
+    }
+
+    /// Stops a download from [`Client::start_media_download`], keeping the
+    /// partial file for a later resume. Returns false once it has finished.
+    pub fn cancel_media_download(&self, download_id: u64) -> bool {
+        match self.transfer_cancels.lock().unwrap().remove(&download_id) {
+            Some(tx) => tx.send(()).is_ok(),
+            None => false,
+        }
     }
 
+    /// Server-side thumbnail saved to `dest_path`. As with
+    /// [`Client::download_media`], only web reads it through the SDK media cache.
     pub fn download_thumbnail(
         &self,
         mxc_uri: String,
@@ -1955,7 +3037,18 @@ impl Client {
         width: u32,
         height: u32,
     ) -> Result<DownloadResult, FfiError> {
-        RT.block_on(async {
+        RT.block_on(self.download_thumbnail_async(mxc_uri, dest_path, width, height))
+    }
+
+    async fn download_thumbnail_async(
+        &self,
+        mxc_uri: String,
+        dest_path: String,
+        width: u32,
+        height: u32,
+    ) -> Result<DownloadResult, FfiError> {
+        #[cfg(target_family = "wasm")]
+        {
             let uri = matrix_sdk::ruma::OwnedMxcUri::from(mxc_uri);
             let settings = matrix_sdk::media::MediaThumbnailSettings::with_method(
                 matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method::Scale,
@@ -1973,15 +3066,22 @@ impl Client {
                 .get_media_content(&request, true)
                 .await
                 .ffi()?;
-            #[cfg(not(target_family = "wasm"))]
-            {
-                std::fs::write(&dest_path, &data).ffi()?;
-            }
-            Ok(DownloadResult {
+            return Ok(DownloadResult {
                 path: dest_path,
                 bytes: data.len() as u64,
-            })
-        })
+            });
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let source = download::DownloadSource::Thumbnail {
+                uri: mxc_uri.into(),
+                width,
+                height,
+                crop: false,
+            };
+            let dest = PathBuf::from(dest_path);
+            download::download_to_file(&self.core.sdk, source, &dest, None).await
+        }
     }
 
     pub fn fetch_notification(
@@ -1989,35 +3089,41 @@ impl Client {
         room_id: String,
         event_id: String,
     ) -> Result<Option<RenderedNotification>, FfiError> {
-        RT.block_on(async {
-            let rid = OwnedRoomId::try_from(room_id).ffi()?;
-            let eid = matrix_sdk::ruma::OwnedEventId::try_from(event_id).ffi()?;
+        RT.block_on(self.fetch_notification_async(room_id, event_id))
+    }
 
-            self.core.ensure_sync_service().await;
-            let process_setup = {
-                let g = self.core.sync_service.lock().unwrap();
-                if let Some(sync) = g.as_ref().cloned() {
-                    NotificationProcessSetup::SingleProcess { sync_service: sync }
-                } else {
-                    NotificationProcessSetup::MultipleProcesses
-                }
-            };
+    async fn fetch_notification_async(
+        &self,
+        room_id: String,
+        event_id: String,
+    ) -> Result<Option<RenderedNotification>, FfiError> {
+        let rid = OwnedRoomId::try_from(room_id).ffi()?;
+        let eid = matrix_sdk::ruma::OwnedEventId::try_from(event_id).ffi()?;
 
-            let nc = match NotificationClient::new(self.core.sdk.clone(), process_setup).await {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!("NotificationClient::new failed: {e:?}");
-                    return Ok(None);
-                }
-            };
-            let item = nc.get_notification(&rid, &eid).await.ffi()?;
-            match item {
-                NotificationStatus::Event(notif) => {
-                    Ok(map_notification_item_to_rendered(&rid, &eid, &notif))
-                }
-                _ => Ok(None),
+        self.core.ensure_sync_service().await;
+        let process_setup = {
+            let g = self.core.sync_service.lock().unwrap();
+            if let Some(sync) = g.as_ref().cloned() {
+                NotificationProcessSetup::SingleProcess { sync_service: sync }
+            } else {
+                NotificationProcessSetup::MultipleProcesses
             }
-        })
+        };
+
+        let nc = match NotificationClient::new(self.core.sdk.clone(), process_setup).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("NotificationClient::new failed: {e:?}");
+                return Ok(None);
+            }
+        };
+        let item = nc.get_notification(&rid, &eid).await.ffi()?;
+        match item {
+            NotificationStatus::Event(notif) => {
+                Ok(map_notification_item_to_rendered(&rid, &eid, &notif))
+            }
+            _ => Ok(None),
+        }
     }
 
     pub fn enable_recovery(&self, observer: Box<dyn RecoveryObserver>) -> bool {
@@ -2029,6 +3135,11 @@ impl Client {
             match sdk.encryption().recovery().enable().await {
                 Ok(key) => {
                     platform::build_and_persist_session(&sdk, &store).await;
+                    if dehydration::is_enabled(&sdk).await
+                        && let Err(e) = dehydration::sync_secret_storage(&sdk, &key).await
+                    {
+                        warn!("Sharing the dehydrated device key failed: {e}");
+                    }
                     obs.on_done(key);
                 }
                 Err(e) => obs.on_error(format!("Recovery setup failed: {e}")),
@@ -2039,102 +3150,228 @@ impl Client {
     }
 
     pub fn recover_with_key(&self, recovery_key: String) -> Result<(), FfiError> {
-        RT.block_on(async {
-            self.core
-                .sdk
-                .encryption()
-                .recovery()
-                .recover(&recovery_key)
-                .await
-                .ffi()
-        })
+        RT.block_on(self.recover_with_key_async(recovery_key))
+    }
+
+    async fn recover_with_key_async(&self, recovery_key: String) -> Result<(), FfiError> {
+        let sdk = &self.core.sdk;
+        sdk.encryption()
+            .recovery()
+            .recover(&recovery_key)
+            .await
+            .ffi()?;
+        // A new session cannot rehydrate at login: the pickle key is only in
+        // secret storage, which recovery opens. Keys sent to the parked
+        // device while no session was online are picked up here.
+        if dehydration::is_enabled(sdk).await {
+            if let Err(e) = dehydration::refresh(sdk, Some(&recovery_key)).await {
+                warn!("Dehydrated device refresh failed after recovery: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes our megolm sessions to `path` as a passphrase-encrypted key
+    /// file, optionally only for `room_filter`. Returns the number exported.
+    pub fn export_room_keys(
+        &self,
+        path: String,
+        passphrase: String,
+        room_filter: Option<Vec<String>>,
+    ) -> Result<u64, FfiError> {
+        RT.block_on(self.export_room_keys_async(path, passphrase, room_filter))
+    }
+
+    async fn export_room_keys_async(
+        &self,
+        path: String,
+        passphrase: String,
+        room_filter: Option<Vec<String>>,
+    ) -> Result<u64, FfiError> {
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = (path, passphrase, room_filter);
+            return Err(FfiError::Msg(
+                "export_room_keys: not supported on wasm".into(),
+            ));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        room_keys::export(&self.core.sdk, path, passphrase, room_filter).await
+    }
+
+    /// Imports a key file written by `export_room_keys` or another client.
+    /// A wrong passphrase fails with [`FfiError::WrongPassphrase`].
+    pub fn import_room_keys(
+        &self,
+        path: String,
+        passphrase: String,
+        progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<RoomKeyImportSummary, FfiError> {
+        RT.block_on(self.import_room_keys_async(path, passphrase, progress))
+    }
+
+    async fn import_room_keys_async(
+        &self,
+        path: String,
+        passphrase: String,
+        progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<RoomKeyImportSummary, FfiError> {
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = (path, passphrase, progress);
+            return Err(FfiError::Msg(
+                "import_room_keys: not supported on wasm".into(),
+            ));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        room_keys::import(&self.core.sdk, path, passphrase, progress.as_deref()).await
     }
 
     pub fn disable_recovery(&self) -> Result<(), FfiError> {
-        RT.block_on(async {
-            self.core
-                .sdk
-                .encryption()
-                .recovery()
-                .disable()
-                .await
-                .ffi()?;
-            Self::persist_current_session(self).await;
-            Ok(())
-        })
+        RT.block_on(self.disable_recovery_async())
+    }
+
+    async fn disable_recovery_async(&self) -> Result<(), FfiError> {
+        self.core
+            .sdk
+            .encryption()
+            .recovery()
+            .disable()
+            .await
+            .ffi()?;
+        Self::persist_current_session(self).await;
+        Ok(())
     }
 
     pub fn recovery_state(&self) -> RecoveryState {
-        RT.block_on(async {
-            match self.core.sdk.encryption().recovery().state() {
-                matrix_sdk::encryption::recovery::RecoveryState::Disabled => {
-                    RecoveryState::Disabled
-                }
-                matrix_sdk::encryption::recovery::RecoveryState::Enabled => RecoveryState::Enabled,
-                matrix_sdk::encryption::recovery::RecoveryState::Incomplete => {
-                    RecoveryState::Incomplete
-                }
-                _ => RecoveryState::Unknown,
+        match self.core.sdk.encryption().recovery().state() {
+            matrix_sdk::encryption::recovery::RecoveryState::Disabled => RecoveryState::Disabled,
+            matrix_sdk::encryption::recovery::RecoveryState::Enabled => RecoveryState::Enabled,
+            matrix_sdk::encryption::recovery::RecoveryState::Incomplete => {
+                RecoveryState::Incomplete
             }
-        })
+            _ => RecoveryState::Unknown,
+        }
     }
 
     pub fn backup_state(&self) -> BackupState {
-        RT.block_on(async {
-            match self.core.sdk.encryption().backups().state() {
-                matrix_sdk::encryption::backups::BackupState::Unknown => BackupState::Unknown,
-                matrix_sdk::encryption::backups::BackupState::Creating => BackupState::Creating,
-                matrix_sdk::encryption::backups::BackupState::Enabling => BackupState::Enabling,
-                matrix_sdk::encryption::backups::BackupState::Resuming => BackupState::Resuming,
-                matrix_sdk::encryption::backups::BackupState::Enabled => BackupState::Enabled,
-                matrix_sdk::encryption::backups::BackupState::Downloading => {
-                    BackupState::Downloading
-                }
-                matrix_sdk::encryption::backups::BackupState::Disabling => BackupState::Disabling,
-                _ => BackupState::Unknown,
-            }
-        })
+        match self.core.sdk.encryption().backups().state() {
+            matrix_sdk::encryption::backups::BackupState::Unknown => BackupState::Unknown,
+            matrix_sdk::encryption::backups::BackupState::Creating => BackupState::Creating,
+            matrix_sdk::encryption::backups::BackupState::Enabling => BackupState::Enabling,
+            matrix_sdk::encryption::backups::BackupState::Resuming => BackupState::Resuming,
+            matrix_sdk::encryption::backups::BackupState::Enabled => BackupState::Enabled,
+            matrix_sdk::encryption::backups::BackupState::Downloading => BackupState::Downloading,
+            matrix_sdk::encryption::backups::BackupState::Disabling => BackupState::Disabling,
+            _ => BackupState::Unknown,
+        }
     }
 
     pub fn reset_recovery_key(&self) -> Result<String, FfiError> {
-        RT.block_on(async {
-            self.core
-                .sdk
-                .encryption()
-                .recovery()
-                .reset_key()
-                .await
-                .ffi()
-        })
+        RT.block_on(self.reset_recovery_key_async())
+    }
+
+    async fn reset_recovery_key_async(&self) -> Result<String, FfiError> {
+        self.core
+            .sdk
+            .encryption()
+            .recovery()
+            .reset_key()
+            .await
+            .ffi()
     }
 
+    /// Our devices: crypto devices merged with the server's device list,
+    /// which carries last-seen info and includes sessions without keys.
     pub fn devices(&self) -> Result<Vec<DeviceSummary>, FfiError> {
-        RT.block_on(async {
-            let encryption = self.core.sdk.encryption();
-            let own_device_id = self
-                .core
-                .sdk
-                .session_meta()
-                .map(|m| m.device_id.to_string())
-                .unwrap_or_default();
-            let devices = encryption
-                .get_user_devices(self.core.sdk.user_id().or_ffi("no user")?)
-                .await
-                .ffi()?;
-            Ok(devices
-                .devices()
-                .map(|d| DeviceSummary {
+        RT.block_on(self.devices_async())
+    }
+
+    async fn devices_async(&self) -> Result<Vec<DeviceSummary>, FfiError> {
+        let encryption = self.core.sdk.encryption();
+        let own_device_id = self
+            .core
+            .sdk
+            .session_meta()
+            .map(|m| m.device_id.to_string())
+            .unwrap_or_default();
+        let devices = encryption
+            .get_user_devices(
+                self.core
+                    .sdk
+                    .user_id()
+                    .ok_or_else(|| FfiError::Unauthorized("no logged-in user".into()))?,
+            )
+            .await
+            .ffi()?;
+        let mut server: HashMap<String, _> = match self.core.sdk.devices().await {
+            Ok(resp) => resp
+                .devices
+                .into_iter()
+                .map(|d| (d.device_id.to_string(), d))
+                .collect(),
+            Err(e) => {
+                warn!("devices(): server device list unavailable: {e}");
+                HashMap::new()
+            }
+        };
+        let mut out: Vec<DeviceSummary> = devices
+            .devices()
+            .map(|d| {
+                let remote = server.remove(d.device_id().as_str());
+                DeviceSummary {
                     device_id: d.device_id().to_string(),
-                    display_name: d
-                        .display_name()
-                        .unwrap_or(d.device_id().as_str())
-                        .to_string(),
+                    display_name: remote
+                        .as_ref()
+                        .and_then(|r| r.display_name.clone())
+                        .unwrap_or_else(|| {
+                            d.display_name()
+                                .unwrap_or(d.device_id().as_str())
+                                .to_string()
+                        }),
                     ed25519: d.ed25519_key().map(|k| k.to_base64()).unwrap_or_default(),
                     is_own: d.device_id().to_string() == own_device_id,
                     verified: d.is_verified(),
-                })
-                .collect())
-        })
+                    is_dehydrated: d.is_dehydrated(),
+                    last_seen_ip: remote.as_ref().and_then(|r| r.last_seen_ip.clone()),
+                    last_seen_ts: remote
+                        .as_ref()
+                        .and_then(|r| r.last_seen_ts)
+                        .map(|ts| ts.get().into()),
+                }
+            })
+            .collect();
+        out.extend(server.into_values().map(|r| {
+            DeviceSummary {
+                display_name: r
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| r.device_id.to_string()),
+                ed25519: String::new(),
+                is_own: r.device_id.as_str() == own_device_id,
+                verified: false,
+                is_dehydrated: false,
+                last_seen_ip: r.last_seen_ip,
+                last_seen_ts: r.last_seen_ts.map(|ts| ts.get().into()),
+                device_id: r.device_id.to_string(),
+            }
+        }));
+        Ok(out)
+    }
+
+    pub fn delete_devices(
+        &self,
+        device_ids: Vec<String>,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<DeviceDeletion, FfiError> {
+        RT.block_on(self.core.delete_devices(device_ids, &*handler))
+    }
+
+    pub fn sign_out_other_sessions(
+        &self,
+        handler: Box<dyn UiaaHandler>,
+    ) -> Result<DeviceDeletion, FfiError> {
+        RT.block_on(self.core.sign_out_other_sessions(&*handler))
     }
 
     pub fn device_id(&self) -> Option<String> {
@@ -2164,7 +3401,7 @@ impl Client {
         let (widget_settings, widget_url, widget_base_url, resolved_parent) = RT.block_on(async {
             let rid = OwnedRoomId::try_from(room_id.as_str()).ffi()?;
             let Some(room) = inner.get_room(&rid) else {
-                return Err(ffi_err!("room not found"));
+                return Err(FfiError::RoomNotFound);
             };
             let element_call_url = element_call_url.ok_or_else(|| {
                 ffi_err!("element_call_url is required - platform must provide embedded URL or explicit fallback")
@@ -2292,11 +3529,30 @@ impl Client {
         device_display_name: String,
         lang: String,
         profile_tag: Option<String>,
+    ) -> bool {
+        RT.block_on(self.register_unifiedpush_async(
+            app_id,
+            pushkey,
+            gateway_url,
+            device_display_name,
+            lang,
+            profile_tag,
+        ))
+    }
+
+    async fn register_unifiedpush_async(
+        &self,
+        app_id: String,
+        pushkey: String,
+        gateway_url: String,
+        device_display_name: String,
+        lang: String,
+        profile_tag: Option<String>,
     ) -> bool {
         #[cfg(target_family = "wasm")]
         return false;
         #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
+        {
             use matrix_sdk::ruma::push::PushFormat;
             let client_secret = self
                 .core
@@ -2326,21 +3582,25 @@ impl Client {
             };
             let pusher: Pusher = init.into();
             self.core.sdk.pusher().set(pusher, true).await.is_ok()
-        })
+        }
     }
 
     pub fn unregister_unifiedpush(&self, app_id: String, pushkey: String) -> bool {
+        RT.block_on(self.unregister_unifiedpush_async(app_id, pushkey))
+    }
+
+    async fn unregister_unifiedpush_async(&self, app_id: String, pushkey: String) -> bool {
         #[cfg(target_family = "wasm")]
         return false;
         #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
+        {
             self.core
                 .sdk
                 .pusher()
                 .delete(PusherIds::new(app_id.into(), pushkey.into()))
                 .await
                 .is_ok()
-        })
+        }
     }
 
     pub fn homeserver_url(&self) -> String {
@@ -2361,33 +3621,44 @@ impl Client {
     }
 
     pub fn cross_signing_status(&self) -> Result<HashMap<String, bool>, FfiError> {
-        RT.block_on(async {
-            let status = self
-                .core
-                .sdk
-                .encryption()
-                .cross_signing_status()
-                .await
-                .or_ffi("cross-signing unavailable")?;
-            let mut map = HashMap::new();
-            map.insert("has_master".into(), status.has_master);
-            map.insert("has_self_signing".into(), status.has_self_signing);
-            map.insert("has_user_signing".into(), status.has_user_signing);
-            Ok(map)
-        })
+        RT.block_on(self.cross_signing_status_async())
+    }
+
+    async fn cross_signing_status_async(&self) -> Result<HashMap<String, bool>, FfiError> {
+        let status = self
+            .core
+            .sdk
+            .encryption()
+            .cross_signing_status()
+            .await
+            .or_ffi("cross-signing unavailable")?;
+        let mut map = HashMap::new();
+        map.insert("has_master".into(), status.has_master);
+        map.insert("has_self_signing".into(), status.has_self_signing);
+        map.insert("has_user_signing".into(), status.has_user_signing);
+        Ok(map)
+    }
+
+    pub fn reset_identity(
+        &self,
+        handler: Box<dyn UiaaHandler>,
+        listener: Box<dyn IdentityResetListener>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.core.reset_identity(&*handler, &*listener))
     }
 
     pub fn is_user_verified(&self, user_id: String) -> bool {
-        RT.block_on(async {
-            let Ok(uid) = user_id.parse::<OwnedUserId>() else {
-                return false;
-            };
-            let Ok(Some(identity)) = self.core.sdk.encryption().get_user_identity(&uid).await
-            else {
-                return false;
-            };
-            identity.is_verified()
-        })
+        RT.block_on(self.is_user_verified_async(user_id))
+    }
+
+    async fn is_user_verified_async(&self, user_id: String) -> bool {
+        let Ok(uid) = user_id.parse::<OwnedUserId>() else {
+            return false;
+        };
+        let Ok(Some(identity)) = self.core.sdk.encryption().get_user_identity(&uid).await else {
+            return false;
+        };
+        identity.is_verified()
     }
 
     pub fn setup_recovery(&self, observer: Box<dyn RecoveryObserver>) -> bool {
@@ -2403,21 +3674,25 @@ impl Client {
     }
 
     pub fn retry_by_txn(&self, _room_id: String, txn_id: String) -> bool {
-        RT.block_on(async {
-            if let Some(handle) = self
-                .send_handles_by_txn
-                .lock()
-                .unwrap()
-                .get(&txn_id)
-                .cloned()
-            {
-                handle.unwedge().await.is_ok()
-            } else {
-                false
-            }
-        })
+        RT.block_on(self.retry_by_txn_async(_room_id, txn_id))
+    }
+
+    async fn retry_by_txn_async(&self, _room_id: String, txn_id: String) -> bool {
+        let handle = self
+            .send_handles_by_txn
+            .lock()
+            .unwrap()
+            .get(&txn_id)
+            .cloned();
+        match handle {
+            Some(handle) => handle.unwedge().await.is_ok(),
+            None => false,
+        }
     }
 
+    /// Streams the file from disk; `progress` follows the bytes on the wire.
+    /// For videos, `thumbnail_path` is an image the host extracted (a poster
+    /// frame): it is sent as the thumbnail and gives the blurhash.
     pub fn send_attachment_from_path(
         &self,
         room_id: String,
@@ -2427,53 +3702,138 @@ impl Client {
         caption: Option<String>,
         formatted_caption: Option<String>,
         reply_to_event_id: Option<String>,
+        thumbnail_path: Option<String>,
         progress: Option<Box<dyn ProgressObserver>>,
-    ) -> bool {
-        RT.block_on(async {
-            let Ok(rid) = OwnedRoomId::try_from(room_id) else {
-                return false;
-            };
-            let Some(room) = self.core.sdk.get_room(&rid) else {
-                return false;
-            };
-            let data = match std::fs::read(&path) {
-                Ok(d) => d,
-                Err(_) => return false,
-            };
-            let mime_type: Mime = mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
-            let fname = filename.unwrap_or_else(|| {
-                std::path::Path::new(&path)
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or("file".into())
-            });
-            let mut config = matrix_sdk::attachment::AttachmentConfig::new();
-            if let Some(c) = caption {
-                let text_content = if let Some(fc) = formatted_caption {
-                    matrix_sdk::ruma::events::room::message::TextMessageEventContent::html(c, fc)
-                } else {
-                    matrix_sdk::ruma::events::room::message::TextMessageEventContent::plain(c)
-                };
-                config = config.caption(Some(text_content));
-            }
-            if let Some(reply_id) = reply_to_event_id {
-                if let Ok(eid) = matrix_sdk::ruma::EventId::parse(&reply_id) {
-                    config = config.reply(Some(matrix_sdk::room::reply::Reply {
-                        event_id: eid.to_owned(),
-                        enforce_thread: matrix_sdk::room::reply::EnforceThread::Unthreaded,
-                        add_mentions: matrix_sdk::ruma::events::room::message::AddMentions::Yes,
-                    }));
-                }
-            }
-            if let Some(p) = progress.as_ref() {
-                p.on_progress(0, Some(data.len() as u64));
-            }
-            let result = room.send_attachment(&fname, &mime_type, data, config).await;
-            if let Some(p) = progress {
-                p.on_progress(1, Some(1));
-            }
-            result.is_ok()
-        })
+    ) -> Result<AttachmentUploadResult, FfiError> {
+        RT.block_on(self.send_attachment_from_path_async(
+            room_id,
+            path,
+            mime,
+            filename,
+            caption,
+            formatted_caption,
+            reply_to_event_id,
+            thumbnail_path,
+            progress,
+        ))
+    }
+
+    async fn send_attachment_from_path_async(
+        &self,
+        room_id: String,
+        path: String,
+        mime: String,
+        filename: Option<String>,
+        caption: Option<String>,
+        formatted_caption: Option<String>,
+        reply_to_event_id: Option<String>,
+        thumbnail_path: Option<String>,
+        progress: Option<Box<dyn ProgressObserver>>,
+    ) -> Result<AttachmentUploadResult, FfiError> {
+        #[cfg(target_family = "wasm")]
+        return Err(FfiError::Msg(
+            "send_attachment_from_path: not supported on web".into(),
+        ));
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let rid = OwnedRoomId::try_from(room_id).ffi()?;
+            let room = self.core.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound)?;
+            let mut upload = upload::AttachmentUpload::new(
+                path,
+                mime,
+                filename,
+                caption,
+                formatted_caption,
+                reply_to_event_id,
+            )?;
+            let progress: Option<Arc<dyn ProgressObserver>> = progress.map(Arc::from);
+            upload.thumbnail = thumbnail_path.map(PathBuf::from);
+            upload.image_options = platform::load_image_upload_options(&self.store_dir).await;
+            upload::send_attachment(&self.core.sdk, &room, upload, progress).await
+        }
+    }
+
+    /// Starts the upload in the background and returns a handle for
+    /// [`Client::cancel_attachment_upload`]. Exactly one of `on_complete`,
+    /// `on_error` or `on_cancelled` ends it. `thumbnail_path` is as for
+    /// [`Client::send_attachment_from_path`].
+    pub fn start_attachment_upload(
+        &self,
+        room_id: String,
+        path: String,
+        mime: String,
+        filename: Option<String>,
+        caption: Option<String>,
+        formatted_caption: Option<String>,
+        reply_to_event_id: Option<String>,
+        thumbnail_path: Option<String>,
+        observer: Box<dyn AttachmentUploadObserver>,
+    ) -> u64 {
+        let obs: Arc<dyn AttachmentUploadObserver> = Arc::from(observer);
+        let id = self.next_sub_id();
+        #[cfg(target_family = "wasm")]
+        {
+            let _ = (room_id, path, mime, filename, caption, formatted_caption);
+            let _ = (reply_to_event_id, thumbnail_path);
+            safe_call(|| obs.on_error("attachment upload: not supported on web".into()));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let prepared = OwnedRoomId::try_from(room_id)
+                .ffi()
+                .and_then(|rid| self.core.sdk.get_room(&rid).ok_or(FfiError::RoomNotFound))
+                .and_then(|room| {
+                    let mut upload = upload::AttachmentUpload::new(
+                        path,
+                        mime,
+                        filename,
+                        caption,
+                        formatted_caption,
+                        reply_to_event_id,
+                    )?;
+                    upload.thumbnail = thumbnail_path.map(PathBuf::from);
+                    Ok((room, upload))
+                });
+            let (room, mut upload) = match prepared {
+                Ok(p) => p,
+                Err(e) => {
+                    safe_call(|| obs.on_error(e.to_string()));
+                    return id;
+                }
+            };
+            let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel::<()>();
+            self.transfer_cancels.lock().unwrap().insert(id, cancel_tx);
+            let cancels = self.transfer_cancels.clone();
+            let sdk = self.core.sdk.clone();
+            let store_dir = self.store_dir.clone();
+            spawn_task!(async move {
+                upload.image_options = platform::load_image_upload_options(&store_dir).await;
+                let progress: Arc<dyn ProgressObserver> =
+                    Arc::new(upload::ObserverProgress(obs.clone()));
+                // Dropping the upload future closes the body stream, which
+                // also stops the file reader.
+                tokio::select! {
+                    res = upload::send_attachment(&sdk, &room, upload, Some(progress)) => {
+                        match res {
+                            Ok(result) => safe_call(|| obs.on_complete(result)),
+                            Err(e) => safe_call(|| obs.on_error(e.to_string())),
+                        }
+                    }
+                    _ = cancel_rx => safe_call(|| obs.on_cancelled()),
+                }
+                cancels.lock().unwrap().remove(&id);
+            });
+        }
+        id
+    }
+
+    /// Aborts an upload from [`Client::start_attachment_upload`]. Returns
+    /// false once it has already finished.
+    pub fn cancel_attachment_upload(&self, upload_id: u64) -> bool {
+        match self.transfer_cancels.lock().unwrap().remove(&upload_id) {
+            Some(tx) => tx.send(()).is_ok(),
+            None => false,
+        }
     }
 
     pub fn send_sticker_from_path(
@@ -2485,62 +3845,82 @@ impl Client {
         filename: Option<String>,
         progress: Option<Box<dyn ProgressObserver>>,
     ) -> bool {
-        RT.block_on(async {
-            let Ok(rid) = OwnedRoomId::try_from(room_id) else {
-                return false;
-            };
-            let Some(room) = self.core.sdk.get_room(&rid) else {
-                return false;
-            };
-            let data = match std::fs::read(&path) {
-                Ok(d) => d,
-                Err(_) => return false,
-            };
-            let mime_type: Mime = mime.parse().unwrap_or(mime::IMAGE_PNG);
-            if !mime.starts_with("image/") {
-                return false;
-            }
-            let data_len = data.len();
+        RT.block_on(
+            self.send_sticker_from_path_async(room_id, path, mime, body, filename, progress),
+        )
+    }
 
-            if let Some(p) = progress.as_ref() {
-                p.on_progress(0, Some(data_len as u64));
-            }
+    async fn send_sticker_from_path_async(
+        &self,
+        room_id: String,
+        path: String,
+        mime: String,
+        body: String,
+        filename: Option<String>,
+        progress: Option<Box<dyn ProgressObserver>>,
+    ) -> bool {
+        let Ok(rid) = OwnedRoomId::try_from(room_id) else {
+            return false;
+        };
+        let Some(room) = self.core.sdk.get_room(&rid) else {
+            return false;
+        };
+        let data = match std::fs::read(&path) {
+            Ok(d) => d,
+            Err(_) => return false,
+        };
+        let mime_type: Mime = mime.parse().unwrap_or(mime::IMAGE_PNG);
+        if !mime.starts_with("image/") {
+            return false;
+        }
+        let data_len = data.len();
 
-            let upload = self.core.sdk.media().upload(&mime_type, data, None).await;
+        if let Some(p) = progress.as_ref() {
+            p.on_progress(0, Some(data_len as u64));
+        }
 
-            let Ok(response) = upload else {
-                return false;
-            };
+        let upload = self.core.sdk.media().upload(&mime_type, data, None).await;
 
-            if let Some(p) = progress.as_ref() {
-                p.on_progress(data_len as u64 / 2, Some(data_len as u64));
-            }
+        let Ok(response) = upload else {
+            return false;
+        };
 
-            use matrix_sdk::ruma::assign;
-            use matrix_sdk::ruma::events::room::ImageInfo;
-            use matrix_sdk::ruma::events::sticker::StickerEventContent;
+        if let Some(p) = progress.as_ref() {
+            p.on_progress(data_len as u64 / 2, Some(data_len as u64));
+        }
 
-            let info = assign!(ImageInfo::new(), {
-                mimetype: Some(mime_type.to_string()),
-                size: matrix_sdk::ruma::UInt::new(data_len as u64),
-            });
+        use matrix_sdk::ruma::assign;
+        use matrix_sdk::ruma::events::room::ImageInfo;
+        use matrix_sdk::ruma::events::sticker::StickerEventContent;
 
-            let content = StickerEventContent::new(body, info, response.content_uri);
+        let info = assign!(ImageInfo::new(), {
+            mimetype: Some(mime_type.to_string()),
+            size: matrix_sdk::ruma::UInt::new(data_len as u64),
+        });
 
-            let result = room.send(content).await;
+        let content = StickerEventContent::new(body, info, response.content_uri);
 
-            if let Some(p) = progress {
-                p.on_progress(data_len as u64, Some(data_len as u64));
-            }
+        let result = room.send(content).await;
 
-            result.is_ok()
-        })
+        if let Some(p) = progress {
+            p.on_progress(data_len as u64, Some(data_len as u64));
+        }
+
+        result.is_ok()
     }
 
     pub fn download_sticker_to_cache(
         &self,
         info: StickerInfo,
         filename_hint: Option<String>,
+    ) -> Result<DownloadResult, FfiError> {
+        RT.block_on(self.download_sticker_to_cache_async(info, filename_hint))
+    }
+
+    async fn download_sticker_to_cache_async(
+        &self,
+        info: StickerInfo,
+        filename_hint: Option<String>,
     ) -> Result<DownloadResult, FfiError> {
         #[cfg(target_family = "wasm")]
         return Err(FfiError::Msg("file downloads not supported on web".into()));
@@ -2554,33 +3934,23 @@ impl Client {
                 .map(sanitize_filename)
                 .filter(|s| !s.is_empty())
                 .unwrap_or_else(|| "sticker.bin".into());
-            let out = dir.join(format!("dl_{}_{}", now_ms(), hint));
-
-            RT.block_on(async {
-                let source = if let Some(enc) = info.encrypted.as_ref() {
-                    let ef: matrix_sdk::ruma::events::room::EncryptedFile =
-                        serde_json::from_str(&enc.json).ffi()?;
-                    matrix_sdk::ruma::events::room::MediaSource::Encrypted(Box::new(ef))
-                } else {
-                    matrix_sdk::ruma::events::room::MediaSource::Plain(info.mxc_uri.clone().into())
-                };
-                let req = MediaRequestParameters {
-                    source,
-                    format: MediaFormat::File,
-                };
-                let data = self
-                    .core
-                    .sdk
-                    .media()
-                    .get_media_content(&req, true)
-                    .await
-                    .ffi()?;
-                std::fs::write(&out, &data).ffi()?;
-                Ok(DownloadResult {
+            // Stable per media, so a repeated request resumes instead of
+            // starting a second copy.
+            let key = blake3::hash(info.mxc_uri.as_bytes()).to_hex();
+            let out = dir.join(format!("dl_{}_{}", &key[..16], hint));
+            if let Some(bytes) = self.media_cache.get(&out) {
+                return Ok(DownloadResult {
                     path: out.to_string_lossy().to_string(),
-                    bytes: data.len() as u64,
-                })
-            })
+                    bytes,
+                });
+            }
+            let source = download::DownloadSource::new(
+                info.mxc_uri,
+                info.encrypted.as_ref().map(|enc| enc.json.as_str()),
+            )?;
+            let result = download::download_to_file(&self.core.sdk, source, &out, None).await?;
+            self.media_cache.insert(&out, None, MediaCacheKind::Sticker);
+            Ok(result)
         }
     }
 
@@ -2588,6 +3958,14 @@ impl Client {
         &self,
         att: AttachmentInfo,
         filename_hint: Option<String>,
+    ) -> Result<DownloadResult, FfiError> {
+        RT.block_on(self.download_attachment_to_cache_file_async(att, filename_hint))
+    }
+
+    async fn download_attachment_to_cache_file_async(
+        &self,
+        att: AttachmentInfo,
+        filename_hint: Option<String>,
     ) -> Result<DownloadResult, FfiError> {
         #[cfg(target_family = "wasm")]
         return Err(FfiError::Msg("file downloads not supported on web".into()));
@@ -2601,33 +3979,24 @@ impl Client {
                 .map(sanitize_filename)
                 .filter(|s| !s.is_empty())
                 .unwrap_or_else(|| "file.bin".into());
-            let out = dir.join(format!("dl_{}_{}", now_ms(), hint));
-
-            RT.block_on(async {
-                let source = if let Some(enc) = att.encrypted.as_ref() {
-                    let ef: matrix_sdk::ruma::events::room::EncryptedFile =
-                        serde_json::from_str(&enc.json).ffi()?;
-                    MediaSource::Encrypted(Box::new(ef))
-                } else {
-                    MediaSource::Plain(att.mxc_uri.clone().into())
-                };
-                let req = MediaRequestParameters {
-                    source,
-                    format: MediaFormat::File,
-                };
-                let data = self
-                    .core
-                    .sdk
-                    .media()
-                    .get_media_content(&req, true)
-                    .await
-                    .ffi()?;
-                std::fs::write(&out, &data).ffi()?;
-                Ok(DownloadResult {
+            // Stable per media, so a repeated request resumes instead of
+            // starting a second copy.
+            let key = blake3::hash(att.mxc_uri.as_bytes()).to_hex();
+            let out = dir.join(format!("dl_{}_{}", &key[..16], hint));
+            if let Some(bytes) = self.media_cache.get(&out) {
+                return Ok(DownloadResult {
                     path: out.to_string_lossy().to_string(),
-                    bytes: data.len() as u64,
-                })
-            })
+                    bytes,
+                });
+            }
+            let source = download::DownloadSource::new(
+                att.mxc_uri,
+                att.encrypted.as_ref().map(|enc| enc.json.as_str()),
+            )?;
+            let result = download::download_to_file(&self.core.sdk, source, &out, None).await?;
+            self.media_cache
+                .insert(&out, att.room_id.as_deref(), att.kind.into());
+            Ok(result)
         }
     }
 
@@ -2637,63 +4006,70 @@ impl Client {
         max_rooms: u32,
         max_events: u32,
     ) -> Result<Vec<RenderedNotification>, FfiError> {
-        RT.block_on(async {
-            self.core.ensure_sync_service().await;
-            let process_setup = {
-                let g = self.core.sync_service.lock().unwrap();
-                if let Some(sync) = g.as_ref().cloned() {
-                    NotificationProcessSetup::SingleProcess { sync_service: sync }
-                } else {
-                    NotificationProcessSetup::MultipleProcesses
-                }
+        RT.block_on(self.fetch_notifications_since_async(since_ts_ms, max_rooms, max_events))
+    }
+
+    async fn fetch_notifications_since_async(
+        &self,
+        since_ts_ms: u64,
+        max_rooms: u32,
+        max_events: u32,
+    ) -> Result<Vec<RenderedNotification>, FfiError> {
+        self.core.ensure_sync_service().await;
+        let process_setup = {
+            let g = self.core.sync_service.lock().unwrap();
+            if let Some(sync) = g.as_ref().cloned() {
+                NotificationProcessSetup::SingleProcess { sync_service: sync }
+            } else {
+                NotificationProcessSetup::MultipleProcesses
+            }
+        };
+        let nc = match NotificationClient::new(self.core.sdk.clone(), process_setup).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("NotificationClient::new failed: {e:?}");
+                return Ok(vec![]);
+            }
+        };
+        let mut out = Vec::new();
+        for room in self
+            .core
+            .sdk
+            .joined_rooms()
+            .into_iter()
+            .take(max_rooms as usize)
+        {
+            let rid = room.room_id().to_owned();
+            let Ok(tl) = room.timeline().await else {
+                continue;
             };
-            let nc = match NotificationClient::new(self.core.sdk.clone(), process_setup).await {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!("NotificationClient::new failed: {e:?}");
-                    return Ok(vec![]);
+            let (items, _stream) = tl.subscribe().await;
+            for it in items.iter().rev() {
+                let Some(ev) = it.as_event() else { continue };
+                let ts: u64 = ev.timestamp().0.into();
+                if ts <= since_ts_ms {
+                    break;
                 }
-            };
-            let mut out = Vec::new();
-            for room in self
-                .core
-                .sdk
-                .joined_rooms()
-                .into_iter()
-                .take(max_rooms as usize)
-            {
-                let rid = room.room_id().to_owned();
-                let Ok(tl) = room.timeline().await else {
+                let Some(eid_ref) = ev.event_id() else {
                     continue;
                 };
-                let (items, _stream) = tl.subscribe().await;
-                for it in items.iter().rev() {
-                    let Some(ev) = it.as_event() else { continue };
-                    let ts: u64 = ev.timestamp().0.into();
-                    if ts <= since_ts_ms {
-                        break;
-                    }
-                    let Some(eid_ref) = ev.event_id() else {
-                        continue;
-                    };
-                    let status = match nc.get_notification(&rid, eid_ref).await {
-                        Ok(s) => s,
-                        Err(_) => continue,
-                    };
-                    let NotificationStatus::Event(item) = status else {
-                        continue;
-                    };
-                    let eid = eid_ref.to_owned();
-                    if let Some(rendered) = map_notification_item_to_rendered(&rid, &eid, &item) {
-                        out.push(rendered);
-                        if out.len() as u32 >= max_events {
-                            return Ok(out);
-                        }
+                let status = match nc.get_notification(&rid, eid_ref).await {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                let NotificationStatus::Event(item) = status else {
+                    continue;
+                };
+                let eid = eid_ref.to_owned();
+                if let Some(rendered) = map_notification_item_to_rendered(&rid, &eid, &item) {
+                    out.push(rendered);
+                    if out.len() as u32 >= max_events {
+                        return Ok(out);
                     }
                 }
             }
-            Ok(out)
-        })
+        }
+        Ok(out)
     }
 
     pub fn thumbnail_to_cache(
@@ -2702,6 +4078,16 @@ impl Client {
         width: u32,
         height: u32,
         use_crop: bool,
+    ) -> Result<String, FfiError> {
+        RT.block_on(self.thumbnail_to_cache_async(att, width, height, use_crop))
+    }
+
+    async fn thumbnail_to_cache_async(
+        &self,
+        att: AttachmentInfo,
+        width: u32,
+        height: u32,
+        use_crop: bool,
     ) -> Result<String, FfiError> {
         use matrix_sdk::media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings};
         use ruma::events::room::MediaSource;
@@ -2772,131 +4158,476 @@ impl Client {
 
         #[cfg(not(target_family = "wasm"))]
         {
+            // Evicted thumbnails are simply fetched again.
+            if self.media_cache.get(&out).is_some() {
+                return Ok(out.to_string_lossy().to_string());
+            }
             if let Some(parent) = out.parent() {
                 std::fs::create_dir_all(parent)?;
             }
 
-            let bytes = RT
-                .block_on(async { self.core.sdk.media().get_media_content(&req, true).await })
-                .or_else(|_e| {
-                    // Fallback only when we asked for a server-side thumb of a plain mxc
-                    if matches!(req.format, MediaFormat::Thumbnail(_)) {
-                        let req_full = MediaRequestParameters {
-                            source: req.source.clone(),
-                            format: MediaFormat::File,
-                        };
-                        RT.block_on(async {
-                            self.core
-                                .sdk
-                                .media()
-                                .get_media_content(&req_full, true)
-                                .await
-                        })
-                    } else {
-                        Err(_e)
-                    }
-                })
-                .map_err(|e| FfiError::Msg(format!("thumbnail fetch: {e}")))?;
+            let media = self.core.sdk.media();
+            let mut fetched = media.get_media_content(&req, true).await;
+            // Fallback only when we asked for a server-side thumb of a plain mxc
+            if fetched.is_err() && matches!(req.format, MediaFormat::Thumbnail(_)) {
+                let req_full = MediaRequestParameters {
+                    source: req.source.clone(),
+                    format: MediaFormat::File,
+                };
+                fetched = media.get_media_content(&req_full, true).await;
+            }
+            let bytes = fetched.map_err(|e| FfiError::Msg(format!("thumbnail fetch: {e}")))?;
+
+            std::fs::write(&out, &bytes)?;
+            self.media_cache
+                .insert(&out, att.room_id.as_deref(), MediaCacheKind::Thumbnail);
+            Ok(out.to_string_lossy().to_string())
+        }
+        #[cfg(target_family = "wasm")]
+        Err(FfiError::Msg(
+            "thumbnail_to_cache: not supported on wasm".into(),
+        ))
+    }
+
+    pub fn mxc_thumbnail_to_cache(
+        &self,
+        mxc_uri: String,
+        width: u32,
+        height: u32,
+        crop: bool,
+    ) -> Result<String, FfiError> {
+        RT.block_on(self.mxc_thumbnail_to_cache_async(mxc_uri, width, height, crop))
+    }
+
+    async fn mxc_thumbnail_to_cache_async(
+        &self,
+        mxc_uri: String,
+        width: u32,
+        height: u32,
+        crop: bool,
+    ) -> Result<String, FfiError> {
+        #[cfg(target_family = "wasm")]
+        return Err(FfiError::Msg(
+            "mxc_thumbnail_to_cache: not supported on wasm".into(),
+        ));
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let dir = cache_dir(&self.store_dir);
+            platform::ensure_dir(&dir);
+            let key = blake3::hash(format!("{mxc_uri}|{width}x{height}|{crop}").as_bytes())
+                .to_hex()
+                .to_string();
+            let cached = ["png", "jpg", "gif", "img"]
+                .iter()
+                .map(|ext| dir.join(format!("mxc_thumb_{key}.{ext}")))
+                .find(|p| self.media_cache.get(p).is_some());
+            if let Some(out) = cached {
+                return Ok(out.to_string_lossy().to_string());
+            }
+            let method = if crop {
+                matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method::Crop
+            } else {
+                matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method::Scale
+            };
+            let settings = matrix_sdk::media::MediaThumbnailSettings::with_method(
+                method,
+                UInt::from(width.max(1)),
+                UInt::from(height.max(1)),
+            );
+            let req = MediaRequestParameters {
+                source: MediaSource::Plain(mxc_uri.clone().into()),
+                format: MediaFormat::Thumbnail(settings),
+            };
+            let bytes = self
+                .core
+                .sdk
+                .media()
+                .get_media_content(&req, true)
+                .await
+                .ffi()?;
+            let ext = if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+                "png"
+            } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+                "jpg"
+            } else if bytes.starts_with(b"GIF8") {
+                "gif"
+            } else {
+                "img"
+            };
+            let out = dir.join(format!("mxc_thumb_{key}.{ext}"));
+            std::fs::write(&out, bytes).ffi()?;
+            self.media_cache
+                .insert(&out, None, MediaCacheKind::Thumbnail);
+            Ok(out.to_string_lossy().to_string())
+        }
+    }
+
+    pub fn media_cache_overview(&self) -> MediaCacheOverview {
+        self.media_cache.overview()
+    }
+
+    /// Removes every unpinned file from the media cache.
+    pub fn clear_media_cache(&self) -> Result<(), FfiError> {
+        self.media_cache.clear(MediaClearScope::All);
+        Ok(())
+    }
+
+    /// Like [`Client::clear_media_cache`], limited to `scope`. Returns the
+    /// bytes freed.
+    pub fn clear_media_cache_scoped(&self, scope: MediaClearScope) -> u64 {
+        self.media_cache.clear(scope)
+    }
+
+    /// Applies the cache policy: age-based expiry, then LRU eviction down to
+    /// the size limit. Pinned files are left alone.
+    pub fn run_media_cache_maintenance(&self) -> Result<(), FfiError> {
+        self.media_cache.maintain();
+        Ok(())
+    }
+
+    pub fn media_cache_policy(&self) -> MediaCachePolicy {
+        self.media_cache.policy()
+    }
+
+    /// Persists `policy` and evicts right away if the cache is over it.
+    pub fn set_media_cache_policy(&self, policy: MediaCachePolicy) {
+        self.media_cache.set_policy(policy);
+    }
+
+    /// How images are prepared before `send_attachment_from_path` and
+    /// `start_attachment_upload` send them. Kept across sessions.
+    pub fn image_upload_options(&self) -> ImageUploadOptions {
+        RT.block_on(platform::load_image_upload_options(&self.store_dir))
+    }
+
+    pub fn set_image_upload_options(&self, options: ImageUploadOptions) -> Result<(), FfiError> {
+        RT.block_on(platform::write_image_upload_options(
+            &self.store_dir,
+            &options,
+        ))
+        .ffi()
+    }
+
+    /// Pins (or unpins) a file returned by the cache helpers, e.g. media the
+    /// user saved. Returns false if `path` is not in the cache.
+    pub fn set_media_pinned(&self, path: String, pinned: bool) -> bool {
+        self.media_cache
+            .set_pinned(std::path::Path::new(&path), pinned)
+    }
+
+    pub fn start_device_verification(
+        &self,
+        device_id: String,
+        listener: Box<dyn VerifEventListener>,
+    ) -> String {
+        self.request_device_verification(device_id, listener, false)
+    }
+
+    /// Like `start_device_verification`, but shows a QR code (`QrReady`) when
+    /// the other device can scan, falling back to SAS otherwise.
+    pub fn start_device_verification_qr(
+        &self,
+        device_id: String,
+        listener: Box<dyn VerifEventListener>,
+    ) -> String {
+        self.request_device_verification(device_id, listener, true)
+    }
+
+    pub fn start_user_verification(
+        &self,
+        user_id: String,
+        listener: Box<dyn VerifEventListener>,
+    ) -> String {
+        self.request_user_verification(user_id, listener, false)
+    }
+
+    pub fn start_user_verification_qr(
+        &self,
+        user_id: String,
+        listener: Box<dyn VerifEventListener>,
+    ) -> String {
+        self.request_user_verification(user_id, listener, true)
+    }
+
+    pub fn cancel_verification(&self, flow_id: String) -> bool {
+        RT.block_on(self.cancel_verification_async(flow_id))
+    }
+
+    async fn cancel_verification_async(&self, flow_id: String) -> bool {
+        let me = match self.core.sdk.user_id() {
+            Some(u) => u,
+            None => return false,
+        };
+
+        if let Some(v) = self
+            .core
+            .sdk
+            .encryption()
+            .get_verification(me, &flow_id)
+            .await
+        {
+            match v {
+                Verification::SasV1(sas) => sas.cancel().await.is_ok(),
+                Verification::QrV1(qr) => qr.cancel().await.is_ok(),
+                _ => false,
+            }
+        } else if let Some(req) = self
+            .core
+            .sdk
+            .encryption()
+            .get_verification_request(me, &flow_id)
+            .await
+        {
+            req.cancel().await.is_ok()
+        } else {
+            false
+        }
+    }
+
+    pub fn confirm_sas(&self, flow_id: String) -> bool {
+        RT.block_on(self.confirm_sas_async(flow_id))
+    }
+
+    async fn confirm_sas_async(&self, flow_id: String) -> bool {
+        let me = match self.core.sdk.user_id() {
+            Some(u) => u,
+            None => return false,
+        };
+
+        if let Some(Verification::SasV1(sas)) = self
+            .core
+            .sdk
+            .encryption()
+            .get_verification(me, &flow_id)
+            .await
+        {
+            sas.confirm().await.is_ok()
+        } else {
+            false
+        }
+    }
+
+    pub fn accept_verification_request(
+        &self,
+        flow_id: String,
+        other_user_id: Option<String>,
+    ) -> bool {
+        RT.block_on(self.accept_verification_request_async(flow_id, other_user_id))
+    }
+
+    async fn accept_verification_request_async(
+        &self,
+        flow_id: String,
+        other_user_id: Option<String>,
+    ) -> bool {
+        let uid = match other_user_id {
+            Some(u) => match u.parse::<OwnedUserId>() {
+                Ok(uid) => uid,
+                Err(_) => return false,
+            },
+            None => match self.core.sdk.user_id() {
+                Some(u) => u.to_owned(),
+                None => return false,
+            },
+        };
+
+        for _ in 0..30 {
+            if let Some(req) = self
+                .core
+                .sdk
+                .encryption()
+                .get_verification_request(&uid, &flow_id)
+                .await
+            {
+                return req.accept().await.is_ok();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        false
+    }
+
+    pub fn accept_sas(&self, flow_id: String, other_user_id: Option<String>) -> bool {
+        RT.block_on(self.accept_sas_async(flow_id, other_user_id))
+    }
+
+    async fn accept_sas_async(&self, flow_id: String, other_user_id: Option<String>) -> bool {
+        let uid = match other_user_id {
+            Some(u) => match u.parse::<OwnedUserId>() {
+                Ok(uid) => uid,
+                Err(_) => return false,
+            },
+            None => match self.core.sdk.user_id() {
+                Some(u) => u.to_owned(),
+                None => return false,
+            },
+        };
+
+        for _ in 0..30 {
+            if let Some(verification) = self
+                .core
+                .sdk
+                .encryption()
+                .get_verification(&uid, &flow_id)
+                .await
+            {
+                if let Some(sas) = verification.sas() {
+                    return sas.accept().await.is_ok();
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        false
+    }
+
+    pub fn accept_and_observe_verification(
+        &self,
+        flow_id: String,
+        other_user_id: String,
+        listener: Box<dyn VerifEventListener>,
+    ) -> bool {
+        self.observe_incoming_verification(flow_id, other_user_id, listener, false)
+    }
+
+    /// Accepts an incoming request and also shows our QR code (`QrReady`)
+    /// for the other side to scan; SAS still works if they start it.
+    pub fn accept_and_observe_verification_qr(
+        &self,
+        flow_id: String,
+        other_user_id: String,
+        listener: Box<dyn VerifEventListener>,
+    ) -> bool {
+        self.observe_incoming_verification(flow_id, other_user_id, listener, true)
+    }
+
+    /// Scans the other device's QR code for the request `flow_id`. Progress
+    /// arrives on the listener already observing that flow (`QrReciprocated`).
+    pub fn scan_verification_qr(
+        &self,
+        flow_id: String,
+        other_user_id: Option<String>,
+        qr_data: Vec<u8>,
+    ) -> Result<(), FfiError> {
+        RT.block_on(self.scan_verification_qr_async(flow_id, other_user_id, qr_data))
+    }
+
+    async fn scan_verification_qr_async(
+        &self,
+        flow_id: String,
+        other_user_id: Option<String>,
+        qr_data: Vec<u8>,
+    ) -> Result<(), FfiError> {
+        let uid = self.verification_user(other_user_id)?;
+        let data = QrVerificationData::from_bytes(qr_data).ffi()?;
+        let request = self
+            .core
+            .sdk
+            .encryption()
+            .get_verification_request(&uid, &flow_id)
+            .await
+            .ok_or_else(|| FfiError::CryptoError("verification request not found".into()))?;
+        request
+            .scan_qr_code(data)
+            .await
+            .ffi()?
+            .or_ffi("QR code does not belong to this verification")?;
+        Ok(())
+    }
+
+    /// Confirms that the other device reported success after scanning our
+    /// QR code (`QrScanned`).
+    pub fn confirm_qr_verification(&self, flow_id: String, other_user_id: Option<String>) -> bool {
+        RT.block_on(self.confirm_qr_verification_async(flow_id, other_user_id))
+    }
 
-            std::fs::write(&out, &bytes)?;
-            Ok(out.to_string_lossy().to_string())
+    async fn confirm_qr_verification_async(
+        &self,
+        flow_id: String,
+        other_user_id: Option<String>,
+    ) -> bool {
+        let Ok(uid) = self.verification_user(other_user_id) else {
+            return false;
+        };
+        if let Some(Verification::QrV1(qr)) = self
+            .core
+            .sdk
+            .encryption()
+            .get_verification(&uid, &flow_id)
+            .await
+        {
+            qr.confirm().await.is_ok()
+        } else {
+            false
         }
-        #[cfg(target_family = "wasm")]
-        Err(FfiError::Msg(
-            "thumbnail_to_cache: not supported on wasm".into(),
-        ))
     }
 
-    pub fn mxc_thumbnail_to_cache(
-        &self,
-        mxc_uri: String,
-        width: u32,
-        height: u32,
-        crop: bool,
-    ) -> Result<String, FfiError> {
-        #[cfg(target_family = "wasm")]
-        return Err(FfiError::Msg(
-            "mxc_thumbnail_to_cache: not supported on wasm".into(),
-        ));
-        #[cfg(not(target_family = "wasm"))]
-        RT.block_on(async {
-            let dir = cache_dir(&self.store_dir);
-            platform::ensure_dir(&dir);
-            let method = if crop {
-                matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method::Crop
-            } else {
-                matrix_sdk::ruma::api::client::media::get_content_thumbnail::v3::Method::Scale
-            };
-            let settings = matrix_sdk::media::MediaThumbnailSettings::with_method(
-                method,
-                UInt::from(width.max(1)),
-                UInt::from(height.max(1)),
-            );
-            let req = MediaRequestParameters {
-                source: MediaSource::Plain(mxc_uri.clone().into()),
-                format: MediaFormat::Thumbnail(settings),
-            };
-            let bytes = self
-                .core
-                .sdk
-                .media()
-                .get_media_content(&req, true)
-                .await
-                .ffi()?;
-            let ext = if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
-                "png"
-            } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
-                "jpg"
-            } else if bytes.starts_with(b"GIF8") {
-                "gif"
-            } else {
-                "img"
-            };
-            let key = blake3::hash(format!("{mxc_uri}|{width}x{height}|{crop}").as_bytes())
-                .to_hex()
-                .to_string();
-            let out = dir.join(format!("mxc_thumb_{key}.{ext}"));
-            std::fs::write(&out, bytes).ffi()?;
-            Ok(out.to_string_lossy().to_string())
-        })
+    /// Switches a flow showing a QR code to emoji comparison, e.g. when the
+    /// other device has no camera.
+    pub fn start_sas_verification(&self, flow_id: String, other_user_id: Option<String>) -> bool {
+        RT.block_on(self.start_sas_verification_async(flow_id, other_user_id))
     }
 
-    pub fn media_cache_overview(&self) -> MediaCacheOverview {
-        let dir = cache_dir(&self.store_dir);
-        let total_bytes = if dir.exists() {
-            std::fs::read_dir(&dir)
-                .map(|entries| {
-                    entries
-                        .flatten()
-                        .filter_map(|e| e.metadata().ok())
-                        .filter(|m| m.is_file())
-                        .map(|m| m.len())
-                        .sum()
-                })
-                .unwrap_or(0)
-        } else {
-            0
+    async fn start_sas_verification_async(
+        &self,
+        flow_id: String,
+        other_user_id: Option<String>,
+    ) -> bool {
+        let Ok(uid) = self.verification_user(other_user_id) else {
+            return false;
         };
-        MediaCacheOverview { total_bytes }
+        match self
+            .core
+            .sdk
+            .encryption()
+            .get_verification_request(&uid, &flow_id)
+            .await
+        {
+            Some(req) => matches!(req.start_sas().await, Ok(Some(_))),
+            None => false,
+        }
     }
 
-    pub fn clear_media_cache(&self) -> Result<(), FfiError> {
-        let dir = cache_dir(&self.store_dir);
-        if dir.exists() {
-            std::fs::remove_dir_all(&dir).ffi()?;
-            std::fs::create_dir_all(&dir).ffi()?;
+    pub fn shutdown(&self) {
+        self.shutdown_inner();
+    }
+}
+
+impl Client {
+    fn shutdown_inner(&self) {
+        for h in self.guards.lock().unwrap().drain(..) {
+            h.abort();
         }
-        Ok(())
+        abort_all_subs!(self;
+            timeline_subs, typing_subs, connection_subs, inbox_subs,
+            receipts_subs, room_list_subs, call_subs, live_location_subs,
+            beacon_subs, recovery_state_subs, backup_state_subs, identity_subs,
+            utd_subs, space_tree_subs, verification_tasks, widget_driver_tasks,
+            widget_recv_tasks
+        );
+        // Dropped senders read as a cancel on the task side.
+        self.transfer_cancels.lock().unwrap().clear();
+        self.media_cache.flush();
     }
 
-    pub fn run_media_cache_maintenance(&self) -> Result<(), FfiError> {
-        self.clear_media_cache()
+    /// Keeps a verification flow's task until the flow ends. The map stays
+    /// locked while `spawn` runs, so a flow that ends at once cannot remove
+    /// its entry before it is added.
+    fn track_verification(
+        &self,
+        spawn: impl FnOnce(
+            u64,
+            Arc<Mutex<HashMap<u64, tokio::task::JoinHandle<()>>>>,
+        ) -> tokio::task::JoinHandle<()>,
+    ) {
+        let id = self.next_sub_id();
+        let mut tasks = self.verification_tasks.lock().unwrap();
+        tasks.insert(id, spawn(id, self.verification_tasks.clone()));
     }
 
-    pub fn start_device_verification(
+    fn request_device_verification(
         &self,
         device_id: String,
         listener: Box<dyn VerifEventListener>,
+        show_qr: bool,
     ) -> String {
         let lis: Arc<dyn VerifEventListener> = Arc::from(listener);
         let emit_err = |lis: &Arc<dyn VerifEventListener>, msg: String| {
@@ -2936,32 +4667,21 @@ impl Client {
         };
         let flow_id = request.flow_id().to_owned();
         let req = request;
-        let lis2 = lis.clone();
-
-        let h = spawn_task!(async move {
-            let stream = crate::verification_flow::drive_verification_request(req, true).await;
-            futures_util::pin_mut!(stream);
-            while let Some(event) = stream.next().await {
-                let json = serde_json::to_string(&event).unwrap_or_default();
-                lis2.on_event(json);
-                if matches!(event, crate::verification_flow::VerifEvent::Done)
-                    || matches!(
-                        event,
-                        crate::verification_flow::VerifEvent::Cancelled { .. }
-                    )
-                {
-                    break;
-                }
-            }
+        self.track_verification(|id, tasks| {
+            spawn_task!(async move {
+                let events =
+                    verification_flow::drive_verification_request(req, true, show_qr).await;
+                verification_flow::forward_verification(events, &*lis, id, &tasks).await;
+            })
         });
-        self.guards.lock().unwrap().push(h);
         flow_id
     }
 
-    pub fn start_user_verification(
+    fn request_user_verification(
         &self,
         user_id: String,
         listener: Box<dyn VerifEventListener>,
+        show_qr: bool,
     ) -> String {
         let lis: Arc<dyn VerifEventListener> = Arc::from(listener);
         let emit_err = |lis: &Arc<dyn VerifEventListener>, msg: String| {
@@ -2996,150 +4716,22 @@ impl Client {
         };
         let flow_id = request.flow_id().to_owned();
         let req = request;
-        let lis2 = lis.clone();
-
-        let h = spawn_task!(async move {
-            let stream = crate::verification_flow::drive_verification_request(req, true).await;
-            futures_util::pin_mut!(stream);
-            while let Some(event) = stream.next().await {
-                let json = serde_json::to_string(&event).unwrap_or_default();
-                lis2.on_event(json);
-                if matches!(event, crate::verification_flow::VerifEvent::Done)
-                    || matches!(
-                        event,
-                        crate::verification_flow::VerifEvent::Cancelled { .. }
-                    )
-                {
-                    break;
-                }
-            }
+        self.track_verification(|id, tasks| {
+            spawn_task!(async move {
+                let events =
+                    verification_flow::drive_verification_request(req, true, show_qr).await;
+                verification_flow::forward_verification(events, &*lis, id, &tasks).await;
+            })
         });
-        self.guards.lock().unwrap().push(h);
         flow_id
     }
 
-    pub fn cancel_verification(&self, flow_id: String) -> bool {
-        let me = match self.core.sdk.user_id() {
-            Some(u) => u,
-            None => return false,
-        };
-
-        RT.block_on(async {
-            if let Some(v) = self
-                .core
-                .sdk
-                .encryption()
-                .get_verification(me, &flow_id)
-                .await
-            {
-                match v {
-                    Verification::SasV1(sas) => sas.cancel().await.is_ok(),
-                    _ => false,
-                }
-            } else if let Some(req) = self
-                .core
-                .sdk
-                .encryption()
-                .get_verification_request(me, &flow_id)
-                .await
-            {
-                req.cancel().await.is_ok()
-            } else {
-                false
-            }
-        })
-    }
-
-    pub fn confirm_sas(&self, flow_id: String) -> bool {
-        let me = match self.core.sdk.user_id() {
-            Some(u) => u,
-            None => return false,
-        };
-
-        RT.block_on(async {
-            if let Some(Verification::SasV1(sas)) = self
-                .core
-                .sdk
-                .encryption()
-                .get_verification(me, &flow_id)
-                .await
-            {
-                sas.confirm().await.is_ok()
-            } else {
-                false
-            }
-        })
-    }
-
-    pub fn accept_verification_request(
-        &self,
-        flow_id: String,
-        other_user_id: Option<String>,
-    ) -> bool {
-        let uid = match other_user_id {
-            Some(u) => match u.parse::<OwnedUserId>() {
-                Ok(uid) => uid,
-                Err(_) => return false,
-            },
-            None => match self.core.sdk.user_id() {
-                Some(u) => u.to_owned(),
-                None => return false,
-            },
-        };
-
-        RT.block_on(async {
-            for _ in 0..30 {
-                if let Some(req) = self
-                    .core
-                    .sdk
-                    .encryption()
-                    .get_verification_request(&uid, &flow_id)
-                    .await
-                {
-                    return req.accept().await.is_ok();
-                }
-                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-            }
-            false
-        })
-    }
-
-    pub fn accept_sas(&self, flow_id: String, other_user_id: Option<String>) -> bool {
-        let uid = match other_user_id {
-            Some(u) => match u.parse::<OwnedUserId>() {
-                Ok(uid) => uid,
-                Err(_) => return false,
-            },
-            None => match self.core.sdk.user_id() {
-                Some(u) => u.to_owned(),
-                None => return false,
-            },
-        };
-
-        RT.block_on(async {
-            for _ in 0..30 {
-                if let Some(verification) = self
-                    .core
-                    .sdk
-                    .encryption()
-                    .get_verification(&uid, &flow_id)
-                    .await
-                {
-                    if let Some(sas) = verification.sas() {
-                        return sas.accept().await.is_ok();
-                    }
-                }
-                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-            }
-            false
-        })
-    }
-
-    pub fn accept_and_observe_verification(
+    fn observe_incoming_verification(
         &self,
         flow_id: String,
         other_user_id: String,
         listener: Box<dyn VerifEventListener>,
+        show_qr: bool,
     ) -> bool {
         let lis: Arc<dyn VerifEventListener> = Arc::from(listener);
 
@@ -3158,43 +4750,25 @@ impl Client {
             None => return false,
         };
 
-        let lis2 = lis.clone();
-        let h = spawn_task!(async move {
-            let stream = crate::verification_flow::drive_incoming_verification(request).await;
-            futures_util::pin_mut!(stream);
-            while let Some(event) = stream.next().await {
-                let json = serde_json::to_string(&event).unwrap_or_default();
-                lis2.on_event(json);
-                if matches!(event, crate::verification_flow::VerifEvent::Done)
-                    || matches!(
-                        event,
-                        crate::verification_flow::VerifEvent::Cancelled { .. }
-                    )
-                {
-                    break;
-                }
-            }
+        self.track_verification(|id, tasks| {
+            spawn_task!(async move {
+                let events = verification_flow::drive_incoming_verification(request, show_qr).await;
+                verification_flow::forward_verification(events, &*lis, id, &tasks).await;
+            })
         });
-        self.guards.lock().unwrap().push(h);
         true
     }
 
-    pub fn shutdown(&self) {
-        self.shutdown_inner();
-    }
-}
-
-impl Client {
-    fn shutdown_inner(&self) {
-        for h in self.guards.lock().unwrap().drain(..) {
-            h.abort();
+    fn verification_user(&self, other_user_id: Option<String>) -> Result<OwnedUserId, FfiError> {
+        match other_user_id {
+            Some(u) => u.parse::<OwnedUserId>().ffi(),
+            None => self
+                .core
+                .sdk
+                .user_id()
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| FfiError::Unauthorized("no logged-in user".into())),
         }
-        abort_all_subs!(self;
-            timeline_subs, typing_subs, connection_subs, inbox_subs,
-            receipts_subs, room_list_subs, call_subs, live_location_subs,
-            beacon_subs, recovery_state_subs, backup_state_subs,
-            widget_driver_tasks, widget_recv_tasks
-        );
     }
 
     async fn maybe_update_device_name(client: &Client, device_name: Option<String>) {
@@ -3208,6 +4782,22 @@ impl Client {
         }
     }
 
+    /// Signs our own device with the self-signing key received during QR
+    /// login, if the secrets bundle did not already leave it cross-signed.
+    #[cfg(not(target_family = "wasm"))]
+    async fn cross_sign_own_device(&self) {
+        let enc = self.core.sdk.encryption();
+        match enc.get_own_device().await {
+            Ok(Some(device)) if !device.is_cross_signed_by_owner() => {
+                if let Err(e) = device.verify().await {
+                    warn!("Failed to cross-sign own device after QR login: {e:?}");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to load own device after QR login: {e:?}"),
+        }
+    }
+
     async fn persist_current_session(client: &Client) {
         platform::build_and_persist_session(&client.core.sdk, &client.store_dir).await;
     }
@@ -3336,6 +4926,7 @@ fn map_timeline_event(
     let is_redacted = content.is_redacted();
     let mut state_event_type: Option<String> = None;
     let mut live_location: Option<LiveLocationEvent> = None;
+    let mut utd: Option<UtdInfo> = None;
 
     match content {
         TimelineItemContent::MsgLike(ml) => {
@@ -3355,7 +4946,10 @@ fn map_timeline_event(
 
             match &ml.kind {
                 MsgLikeKind::Message(msg) => {
-                    attachment = extract_attachment(msg);
+                    attachment = extract_attachment(msg).map(|mut a| {
+                        a.room_id = Some(room_id.to_owned());
+                        a
+                    });
                     is_edited = msg.is_edited();
                     let raw = msg.body();
                     body = if reply_to_event_id.is_some() {
@@ -3464,6 +5058,11 @@ fn map_timeline_event(
                         body = format!("{} started sharing live location", ev.sender());
                     }
                 }
+                MsgLikeKind::UnableToDecrypt(enc) => {
+                    body = render_msg_like(ev, ml);
+                    event_type = EventType::Undecryptable;
+                    utd = Some(utd::utd_info(enc));
+                }
                 _ => {
                     body = render_msg_like(ev, ml);
                 }
@@ -3535,6 +5134,7 @@ fn map_timeline_event(
         state_event_type,
         live_location,
         raw_json,
+        utd,
     })
 }
 
@@ -3571,6 +5171,7 @@ fn extract_attachment(msg: &matrix_sdk_ui::timeline::Message) -> Option<Attachme
             // main image source
             let (mxc_uri, encrypted) = split_source(&c.source);
             let file_name = c.filename.clone();
+            let blurhash = c.info.as_ref().and_then(|info| info.blurhash.clone());
 
             // metadata + thumbnail
             let (w, h, size, mime, thumb_mxc, thumb_enc) = c
@@ -3601,12 +5202,15 @@ fn extract_attachment(msg: &matrix_sdk_ui::timeline::Message) -> Option<Attachme
                 thumbnail_mxc_uri: thumb_mxc,
                 encrypted,
                 thumbnail_encrypted: thumb_enc,
+                room_id: None,
+                blurhash,
             })
         }
 
         MT::Video(c) => {
             let (mxc_uri, encrypted) = split_source(&c.source);
             let file_name = c.filename.clone();
+            let blurhash = c.info.as_ref().and_then(|info| info.blurhash.clone());
 
             let (w, h, size, mime, dur, thumb_mxc, thumb_enc) = c
                 .info
@@ -3637,6 +5241,8 @@ fn extract_attachment(msg: &matrix_sdk_ui::timeline::Message) -> Option<Attachme
                 thumbnail_mxc_uri: thumb_mxc.or_else(|| Some(mxc_uri.clone())),
                 encrypted,
                 thumbnail_encrypted: thumb_enc,
+                room_id: None,
+                blurhash,
             })
         }
 
@@ -3670,6 +5276,8 @@ fn extract_attachment(msg: &matrix_sdk_ui::timeline::Message) -> Option<Attachme
                 thumbnail_mxc_uri: thumb_mxc,
                 encrypted,
                 thumbnail_encrypted: thumb_enc,
+                room_id: None,
+                blurhash: None,
             })
         }
 
@@ -3701,6 +5309,8 @@ fn extract_attachment(msg: &matrix_sdk_ui::timeline::Message) -> Option<Attachme
                 thumbnail_mxc_uri: None,
                 encrypted,
                 thumbnail_encrypted: None,
+                room_id: None,
+                blurhash: None,
             })
         }
 
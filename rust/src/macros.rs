@@ -24,6 +24,16 @@ macro_rules! delegate_unit_result {
                 }
             )+
         }
+        #[cfg(not(target_family = "wasm"))]
+        #[uniffi::export]
+        impl AsyncClient {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> Result<(), FfiError> {
+                    let core = self.client.core.clone();
+                    on_rt(async move { core.$name($($arg),*).await }).await
+                }
+            )+
+        }
     };
 }
 pub(crate) use delegate_unit_result;
@@ -38,6 +48,16 @@ macro_rules! delegate_result {
                 }
             )+
         }
+        #[cfg(not(target_family = "wasm"))]
+        #[uniffi::export]
+        impl AsyncClient {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> Result<$ret, FfiError> {
+                    let core = self.client.core.clone();
+                    on_rt(async move { core.$name($($arg),*).await }).await
+                }
+            )+
+        }
     };
 }
 pub(crate) use delegate_result;
@@ -52,6 +72,16 @@ macro_rules! delegate_option {
                 }
             )+
         }
+        #[cfg(not(target_family = "wasm"))]
+        #[uniffi::export]
+        impl AsyncClient {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> Result<Option<$ret>, FfiError> {
+                    let core = self.client.core.clone();
+                    on_rt(async move { core.$name($($arg),*).await }).await
+                }
+            )+
+        }
     };
 }
 pub(crate) use delegate_option;
@@ -66,10 +96,58 @@ macro_rules! delegate_plain {
                 }
             )+
         }
+        #[cfg(not(target_family = "wasm"))]
+        #[uniffi::export]
+        impl AsyncClient {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> $ret {
+                    let core = self.client.core.clone();
+                    on_rt(async move { core.$name($($arg),*).await }).await
+                }
+            )+
+        }
     };
 }
 pub(crate) use delegate_plain;
 
+/// Awaitable `AsyncClient` wrappers for hand-written `Client` methods that
+/// only await a `CoreClient` method of the same name.
+macro_rules! async_core {
+    ($($name:ident($($arg:ident : $ty:ty),* $(,)?) -> $ret:ty);+ $(;)?) => {
+        #[cfg(not(target_family = "wasm"))]
+        #[uniffi::export]
+        impl AsyncClient {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> $ret {
+                    let core = self.client.core.clone();
+                    on_rt(async move { core.$name($($arg),*).await }).await
+                }
+            )+
+        }
+    };
+}
+pub(crate) use async_core;
+
+/// Awaitable `AsyncClient` wrappers for hand-written `Client` methods whose
+/// bodies need more than `CoreClient` (store dir, subscriptions, session file).
+/// Each such method blocks on an async twin, which the wrapper awaits on `RT`
+/// instead.
+macro_rules! async_client {
+    ($($name:ident => $twin:ident($($arg:ident : $ty:ty),* $(,)?) -> $ret:ty);+ $(;)?) => {
+        #[cfg(not(target_family = "wasm"))]
+        #[uniffi::export]
+        impl AsyncClient {
+            $(
+                pub async fn $name(&self, $($arg: $ty),*) -> $ret {
+                    let client = self.client.clone();
+                    on_rt(async move { client.$twin($($arg),*).await }).await
+                }
+            )+
+        }
+    };
+}
+pub(crate) use async_client;
+
 macro_rules! sub_manager {
     ($self:expr, $subs:ident, $spawn:expr) => {{
         let id = $self.next_sub_id();
@@ -0,0 +1,371 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+#[cfg(not(target_family = "wasm"))]
+use crate::download::in_flight;
+use crate::{
+    AttachmentKind, MediaCacheKind, MediaCacheKindUsage, MediaCacheOverview, MediaCachePolicy,
+    MediaCacheRoomUsage, MediaClearScope, now_ms,
+};
+
+/// Web has no resumable downloads.
+#[cfg(target_family = "wasm")]
+fn in_flight(_part: &Path) -> bool {
+    false
+}
+
+/// Lives inside the cache directory, so it is skipped when scanning.
+const INDEX_FILE: &str = "index.json";
+/// Written next to [`INDEX_FILE`] and renamed over it; skipped likewise.
+const INDEX_TMP: &str = "index.tmp";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    room_id: Option<String>,
+    kind: MediaCacheKind,
+    bytes: u64,
+    last_access_ms: u64,
+    #[serde(default)]
+    pinned: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    policy: MediaCachePolicy,
+    /// Keyed by file name within the cache directory.
+    #[serde(default)]
+    entries: HashMap<String, Entry>,
+}
+
+/// Bookkeeping for the files the media helpers write to `media_cache/`:
+/// which room and kind each belongs to, when it was last handed out, and
+/// whether the user pinned it. Files are only ever evicted, never
+/// rewritten, so a caller that finds its file gone simply fetches it again.
+pub(crate) struct MediaCache {
+    dir: PathBuf,
+    index: Mutex<Index>,
+}
+
+impl MediaCache {
+    pub(crate) fn open(dir: PathBuf) -> Self {
+        let index = std::fs::read_to_string(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|txt| serde_json::from_str(&txt).ok())
+            .unwrap_or_default();
+        Self {
+            dir,
+            index: Mutex::new(index),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> MediaCachePolicy {
+        self.index.lock().unwrap().policy.clone()
+    }
+
+    /// Applies the new limits right away.
+    pub(crate) fn set_policy(&self, policy: MediaCachePolicy) {
+        self.index.lock().unwrap().policy = policy;
+        self.maintain();
+    }
+
+    /// Size of a cached file, if it is still there. Counts as an access.
+    pub(crate) fn get(&self, path: &Path) -> Option<u64> {
+        let bytes = std::fs::metadata(path).ok().filter(|m| m.is_file())?.len();
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = file_name(path).and_then(|n| index.entries.get_mut(&n)) {
+            entry.last_access_ms = now_ms();
+        }
+        Some(bytes)
+    }
+
+    /// Registers a file just written to the cache, then trims the cache if
+    /// it has grown past `max_bytes`.
+    pub(crate) fn insert(&self, path: &Path, room_id: Option<&str>, kind: MediaCacheKind) {
+        let Some(name) = file_name(path) else {
+            return;
+        };
+        let bytes = std::fs::metadata(path).map_or(0, |m| m.len());
+        let over = {
+            let mut index = self.index.lock().unwrap();
+            let pinned = index.entries.get(&name).is_some_and(|e| e.pinned);
+            index.entries.insert(
+                name,
+                Entry {
+                    room_id: room_id.map(str::to_owned),
+                    kind,
+                    bytes,
+                    last_access_ms: now_ms(),
+                    pinned,
+                },
+            );
+            let total: u64 = index.entries.values().map(|e| e.bytes).sum();
+            let over = index.policy.max_bytes.is_some_and(|max| total > max);
+            if !over {
+                self.save(&index);
+            }
+            over
+        };
+        // The full pass rescans the directory, so only pay for it when needed.
+        if over {
+            self.maintain();
+        }
+    }
+
+    /// Pinned files survive eviction and clearing. Returns false when the
+    /// file is not in the cache.
+    pub(crate) fn set_pinned(&self, path: &Path, pinned: bool) -> bool {
+        let mut index = self.index.lock().unwrap();
+        self.reconcile(&mut index);
+        let Some(entry) = file_name(path).and_then(|n| index.entries.get_mut(&n)) else {
+            return false;
+        };
+        entry.pinned = pinned;
+        self.save(&index);
+        true
+    }
+
+    pub(crate) fn overview(&self) -> MediaCacheOverview {
+        let mut index = self.index.lock().unwrap();
+        self.reconcile(&mut index);
+
+        let mut by_room: HashMap<Option<String>, MediaCacheRoomUsage> = HashMap::new();
+        let mut by_kind: HashMap<MediaCacheKind, MediaCacheKindUsage> = HashMap::new();
+        let mut total_bytes = 0;
+        let mut pinned_bytes = 0;
+        for entry in index.entries.values() {
+            total_bytes += entry.bytes;
+            if entry.pinned {
+                pinned_bytes += entry.bytes;
+            }
+            let room =
+                by_room
+                    .entry(entry.room_id.clone())
+                    .or_insert_with(|| MediaCacheRoomUsage {
+                        room_id: entry.room_id.clone(),
+                        bytes: 0,
+                        files: 0,
+                    });
+            room.bytes += entry.bytes;
+            room.files += 1;
+            let kind = by_kind
+                .entry(entry.kind)
+                .or_insert_with(|| MediaCacheKindUsage {
+                    kind: entry.kind,
+                    bytes: 0,
+                    files: 0,
+                });
+            kind.bytes += entry.bytes;
+            kind.files += 1;
+        }
+
+        let mut by_room: Vec<_> = by_room.into_values().collect();
+        by_room.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        let mut by_kind: Vec<_> = by_kind.into_values().collect();
+        by_kind.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        MediaCacheOverview {
+            total_bytes,
+            pinned_bytes,
+            file_count: index.entries.len() as u32,
+            by_room,
+            by_kind,
+        }
+    }
+
+    /// Removes unpinned files in `scope`; `Temporary` covers downloads
+    /// handed out for opening or sharing and interrupted partial files, but
+    /// keeps thumbnails. Partial files of running downloads are left alone.
+    /// Returns the bytes freed.
+    pub(crate) fn clear(&self, scope: MediaClearScope) -> u64 {
+        let mut index = self.index.lock().unwrap();
+        self.reconcile(&mut index);
+        let doomed: Vec<String> = index
+            .entries
+            .iter()
+            .filter(|(name, e)| !e.pinned && !self.in_use(name, e))
+            .filter(|(_, e)| match scope {
+                MediaClearScope::All => true,
+                MediaClearScope::Temporary => e.kind != MediaCacheKind::Thumbnail,
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        let freed = self.remove(&mut index, &doomed);
+        self.save(&index);
+        freed
+    }
+
+    /// Drops unpinned files not accessed within `max_age_ms`, then the least
+    /// recently used ones until the cache fits in `max_bytes`. Partial files
+    /// of running downloads count towards the size but are not evicted.
+    /// Returns the bytes freed.
+    pub(crate) fn maintain(&self) -> u64 {
+        let mut index = self.index.lock().unwrap();
+        self.reconcile(&mut index);
+        let policy = index.policy.clone();
+
+        let mut doomed = Vec::new();
+        let mut lru: Vec<(&String, &Entry)> = index
+            .entries
+            .iter()
+            .filter(|(name, e)| !e.pinned && !self.in_use(name, e))
+            .collect();
+        lru.sort_by_key(|(_, e)| e.last_access_ms);
+        if let Some(max_age) = policy.max_age_ms {
+            let cutoff = now_ms().saturating_sub(max_age);
+            doomed.extend(
+                lru.iter()
+                    .take_while(|(_, e)| e.last_access_ms < cutoff)
+                    .map(|(name, _)| (*name).clone()),
+            );
+        }
+        if let Some(max_bytes) = policy.max_bytes {
+            let mut total: u64 = index.entries.values().map(|e| e.bytes).sum();
+            total -= lru[..doomed.len()]
+                .iter()
+                .map(|(_, e)| e.bytes)
+                .sum::<u64>();
+            for (name, entry) in &lru[doomed.len()..] {
+                if total <= max_bytes {
+                    break;
+                }
+                total -= entry.bytes;
+                doomed.push((*name).clone());
+            }
+        }
+
+        let freed = self.remove(&mut index, &doomed);
+        if freed > 0 {
+            info!(
+                "Evicted {} media cache file(s), {freed} bytes",
+                doomed.len()
+            );
+        }
+        self.save(&index);
+        freed
+    }
+
+    /// A `.part` file a download is still writing to.
+    fn in_use(&self, name: &str, entry: &Entry) -> bool {
+        entry.kind == MediaCacheKind::Partial && in_flight(&self.dir.join(name))
+    }
+
+    /// Writes the index out, e.g. to keep access times across restarts.
+    pub(crate) fn flush(&self) {
+        self.save(&self.index.lock().unwrap());
+    }
+
+    fn remove(&self, index: &mut Index, names: &[String]) -> u64 {
+        let mut freed = 0;
+        for name in names {
+            match std::fs::remove_file(self.dir.join(name)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    warn!("could not evict {name}: {e}");
+                    continue;
+                }
+            }
+            if let Some(entry) = index.entries.remove(name) {
+                freed += entry.bytes;
+            }
+        }
+        freed
+    }
+
+    /// Brings the index in line with the directory: forgets files that are
+    /// gone and adopts files written without going through [`Self::insert`]
+    /// (older versions, interrupted downloads).
+    fn reconcile(&self, index: &mut Index) {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            index.entries.clear();
+            return;
+        };
+        let mut seen = HashMap::new();
+        for entry in dir.flatten() {
+            let Ok(meta) = entry.metadata() else { continue };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !meta.is_file() || name == INDEX_FILE || name == INDEX_TMP {
+                continue;
+            }
+            seen.insert(name, meta);
+        }
+        index.entries.retain(|name, _| seen.contains_key(name));
+        for (name, meta) in seen {
+            let bytes = meta.len();
+            match index.entries.get_mut(&name) {
+                // Partial downloads grow between scans.
+                Some(entry) => entry.bytes = bytes,
+                None => {
+                    let last_access_ms = meta
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map_or_else(now_ms, |d| d.as_millis() as u64);
+                    let kind = kind_from_name(&name);
+                    index.entries.insert(
+                        name,
+                        Entry {
+                            room_id: None,
+                            kind,
+                            bytes,
+                            last_access_ms,
+                            pinned: false,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn save(&self, index: &Index) {
+        let payload = match serde_json::to_string(index) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("media cache index: {e}");
+                return;
+            }
+        };
+        let path = self.dir.join(INDEX_FILE);
+        let tmp = self.dir.join(INDEX_TMP);
+        let res = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&tmp, payload))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            warn!("could not write media cache index: {e}");
+        }
+    }
+}
+
+impl From<AttachmentKind> for MediaCacheKind {
+    fn from(kind: AttachmentKind) -> Self {
+        match kind {
+            AttachmentKind::Image => Self::Image,
+            AttachmentKind::Video => Self::Video,
+            AttachmentKind::Audio => Self::Audio,
+            AttachmentKind::File => Self::File,
+        }
+    }
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name().map(|n| n.to_string_lossy().into_owned())
+}
+
+/// Best guess for files found on disk without an index entry, from the
+/// prefixes the media helpers use.
+fn kind_from_name(name: &str) -> MediaCacheKind {
+    if name.ends_with(".part") {
+        MediaCacheKind::Partial
+    } else if name.starts_with("thumb_") || name.starts_with("mxc_thumb_") {
+        MediaCacheKind::Thumbnail
+    } else {
+        MediaCacheKind::File
+    }
+}
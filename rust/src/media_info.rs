@@ -0,0 +1,243 @@
+use std::{
+    fs::File,
+    io::{BufRead, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use image::{
+    DynamicImage, GenericImageView, ImageDecoder, ImageReader, codecs::jpeg::JpegEncoder,
+    metadata::Orientation,
+};
+
+/// Longest thumbnail edges, as other Matrix clients generate them.
+const THUMB_MAX_WIDTH: u32 = 800;
+const THUMB_MAX_HEIGHT: u32 = 600;
+const THUMB_QUALITY: u8 = 80;
+/// Blurhash is computed on a copy this small; more detail is wasted on it.
+const BLURHASH_SOURCE_EDGE: u32 = 64;
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Always JPEG.
+pub(crate) struct Thumbnail {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub(crate) struct ImageMeta {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: Option<String>,
+    /// Only for images larger than the thumbnail size.
+    pub thumbnail: Option<Thumbnail>,
+}
+
+pub(crate) struct VideoMeta {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_ms: Option<u64>,
+}
+
+/// Decodes the image for its dimensions, a JPEG thumbnail and a blurhash.
+/// None for formats the decoder does not know.
+pub(crate) fn probe_image(path: &Path) -> Option<ImageMeta> {
+    decode(ImageReader::open(path).ok()?).map(image_meta)
+}
+
+/// [`probe_image`] for an image already in memory.
+pub(crate) fn probe_image_bytes(bytes: &[u8]) -> Option<ImageMeta> {
+    decode_oriented(bytes).map(image_meta)
+}
+
+fn image_meta(img: DynamicImage) -> ImageMeta {
+    let (width, height) = img.dimensions();
+    let thumbnail = (width > THUMB_MAX_WIDTH || height > THUMB_MAX_HEIGHT)
+        .then(|| thumbnail(&img))
+        .flatten();
+    ImageMeta {
+        width,
+        height,
+        blurhash: blurhash(&img),
+        thumbnail,
+    }
+}
+
+/// Decodes with the EXIF orientation applied, so the pixels are upright the
+/// way viewers would show them.
+pub(crate) fn decode_oriented(bytes: &[u8]) -> Option<DynamicImage> {
+    decode(ImageReader::new(Cursor::new(bytes)))
+}
+
+/// The EXIF orientation, if the image has one.
+pub(crate) fn decode_orientation(bytes: &[u8]) -> Option<Orientation> {
+    ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?
+        .orientation()
+        .ok()
+}
+
+fn decode<R: BufRead + Seek>(reader: ImageReader<R>) -> Option<DynamicImage> {
+    let mut decoder = reader.with_guessed_format().ok()?.into_decoder().ok()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder).ok()?;
+    img.apply_orientation(orientation);
+    Some(img)
+}
+
+fn thumbnail(img: &DynamicImage) -> Option<Thumbnail> {
+    // JPEG has no alpha channel. `thumbnail` would also scale small images up.
+    let (width, height) = img.dimensions();
+    let small = if width > THUMB_MAX_WIDTH || height > THUMB_MAX_HEIGHT {
+        img.thumbnail(THUMB_MAX_WIDTH, THUMB_MAX_HEIGHT).to_rgb8()
+    } else {
+        img.to_rgb8()
+    };
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, THUMB_QUALITY)
+        .encode_image(&small)
+        .ok()?;
+    Some(Thumbnail {
+        width: small.width(),
+        height: small.height(),
+        bytes,
+    })
+}
+
+fn blurhash(img: &DynamicImage) -> Option<String> {
+    let small = img
+        .thumbnail(BLURHASH_SOURCE_EDGE, BLURHASH_SOURCE_EDGE)
+        .to_rgba8();
+    let (x, y) = BLURHASH_COMPONENTS;
+    blurhash::encode(x, y, small.width(), small.height(), small.as_raw()).ok()
+}
+
+/// A video's poster frame, extracted by the host: the JPEG thumbnail sent
+/// with the video (whatever the frame's size) and the blurhash.
+pub(crate) fn probe_poster(path: &Path) -> Option<(Thumbnail, Option<String>)> {
+    let img = decode(ImageReader::open(path).ok()?)?;
+    Some((thumbnail(&img)?, blurhash(&img)))
+}
+
+/// Dimensions (as displayed, after the track's rotation) and duration from
+/// an MP4/QuickTime header. Frames are not decoded, as this crate links no
+/// video decoder: the thumbnail and blurhash come from a poster frame the
+/// host supplies, see [`probe_poster`].
+pub(crate) fn probe_mp4(path: &Path) -> Option<VideoMeta> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let moov = boxes(&mut file, 0, len)
+        .into_iter()
+        .find(|b| &b.kind == b"moov")?;
+    let children = boxes(&mut file, moov.start, moov.end);
+
+    let duration_ms = children
+        .iter()
+        .find(|b| &b.kind == b"mvhd")
+        .and_then(|mvhd| read_mvhd(&mut file, mvhd));
+    // The first track with a picture size is the video track.
+    let (width, height) = children
+        .iter()
+        .filter(|b| &b.kind == b"trak")
+        .filter_map(|trak| {
+            let tkhd = boxes(&mut file, trak.start, trak.end)
+                .into_iter()
+                .find(|b| &b.kind == b"tkhd")?;
+            read_tkhd_size(&mut file, &tkhd)
+        })
+        .find(|&(w, h)| w > 0 && h > 0)
+        .map_or((None, None), |(w, h)| (Some(w), Some(h)));
+    Some(VideoMeta {
+        width,
+        height,
+        duration_ms,
+    })
+}
+
+/// An ISO-BMFF box; `start..end` is its payload.
+pub(crate) struct Mp4Box {
+    pub kind: [u8; 4],
+    pub start: u64,
+    pub end: u64,
+}
+
+/// The boxes in `pos..end`, stopping at the first one that does not fit.
+pub(crate) fn boxes(file: &mut File, mut pos: u64, end: u64) -> Vec<Mp4Box> {
+    let mut out = Vec::new();
+    while pos.checked_add(8).is_some_and(|p| p <= end) {
+        let Some(header) = read_at::<8>(file, pos) else {
+            break;
+        };
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (header_len, size) = match size {
+            0 => (8, end - pos),
+            1 => match read_at::<8>(file, pos + 8) {
+                Some(large) => (16, u64::from_be_bytes(large)),
+                None => break,
+            },
+            n => (8, n),
+        };
+        // A corrupt size must not wrap past `end`.
+        let Some(box_end) = pos.checked_add(size).filter(|&e| e <= end) else {
+            break;
+        };
+        if size < header_len {
+            break;
+        }
+        out.push(Mp4Box {
+            kind,
+            start: pos + header_len,
+            end: box_end,
+        });
+        pos = box_end;
+    }
+    out
+}
+
+/// Movie duration in milliseconds.
+pub(crate) fn read_mvhd(file: &mut File, mvhd: &Mp4Box) -> Option<u64> {
+    let version = read_at::<1>(file, mvhd.start)?[0];
+    let (timescale, duration) = if version == 1 {
+        // version/flags, creation and modification times (8 bytes each)
+        let timescale = u32::from_be_bytes(read_at::<4>(file, mvhd.start + 20)?);
+        (
+            timescale,
+            u64::from_be_bytes(read_at::<8>(file, mvhd.start + 24)?),
+        )
+    } else {
+        let timescale = u32::from_be_bytes(read_at::<4>(file, mvhd.start + 12)?);
+        let duration = u32::from_be_bytes(read_at::<4>(file, mvhd.start + 16)?);
+        (timescale, duration as u64)
+    };
+    (timescale > 0).then(|| duration.saturating_mul(1000) / timescale as u64)
+}
+
+/// Width and height close every `tkhd`, as 16.16 fixed point, right after
+/// the 3x3 display matrix. A matrix that turns the picture by 90 or 270
+/// degrees (a = d = 0) swaps them, as players do when showing it.
+pub(crate) fn read_tkhd_size(file: &mut File, tkhd: &Mp4Box) -> Option<(u32, u32)> {
+    if tkhd.end < tkhd.start + 8 {
+        return None;
+    }
+    let size = read_at::<8>(file, tkhd.end - 8)?;
+    let w = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) >> 16;
+    let h = u32::from_be_bytes([size[4], size[5], size[6], size[7]]) >> 16;
+    let rotated = tkhd.end >= tkhd.start + 8 + 36
+        && read_at::<36>(file, tkhd.end - 8 - 36).is_some_and(|m| {
+            let entry = |i: usize| i32::from_be_bytes([m[i], m[i + 1], m[i + 2], m[i + 3]]);
+            // a, b, u / c, d, v / x, y, w
+            let (a, b, c, d) = (entry(0), entry(4), entry(12), entry(16));
+            a == 0 && d == 0 && b != 0 && c != 0
+        });
+    Some(if rotated { (h, w) } else { (w, h) })
+}
+
+fn read_at<const N: usize>(file: &mut File, pos: u64) -> Option<[u8; N]> {
+    let mut buf = [0u8; N];
+    file.seek(SeekFrom::Start(pos)).ok()?;
+    file.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
@@ -1,8 +1,18 @@
-use crate::{RoomListEntry, SessionInfo};
+use crate::{
+    FfiError, ImageUploadOptions, RoomListEntry, RoomListSort, SessionInfo, SessionSecret,
+};
 use once_cell::sync::Lazy;
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 
+#[cfg(not(target_family = "wasm"))]
+use matrix_sdk_store_encryption::StoreCipher;
+#[cfg(not(target_family = "wasm"))]
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
 #[cfg(not(target_family = "wasm"))]
 use tracing_subscriber::{EnvFilter, fmt};
 
@@ -83,16 +93,31 @@ pub(crate) fn trash_store_dir(path: &Path) {
     }
 }
 
-pub(crate) async fn load_session(store_dir: &Path) -> Option<SessionInfo> {
+/// Reads the stored session, migrating legacy and plaintext files. A sealed
+/// file that the registered secret cannot open (or no secret at all) is
+/// `WrongPassphrase` rather than "no session", so the host does not log in
+/// over it.
+pub(crate) async fn load_session(store_dir: &Path) -> Result<Option<SessionInfo>, FfiError> {
     #[cfg(not(target_family = "wasm"))]
     {
         let new_path = session_file(store_dir);
 
         if let Ok(txt) = tokio::fs::read_to_string(&new_path).await {
-            match serde_json::from_str::<SessionInfo>(&txt) {
-                Ok(info) => return Some(info),
+            match open_session(store_dir, &txt) {
+                Ok((info, plaintext)) => {
+                    if plaintext && has_session_secret(store_dir) {
+                        match persist_session(store_dir, &info).await {
+                            Ok(()) => info!("Encrypted plaintext session file at {:?}", new_path),
+                            Err(e) => {
+                                warn!("Failed to encrypt session file at {:?}: {e}", new_path)
+                            }
+                        }
+                    }
+                    return Ok(Some(info));
+                }
+                Err(FfiError::WrongPassphrase) => return Err(FfiError::WrongPassphrase),
                 Err(e) => {
-                    warn!("Failed to parse session file at {:?}: {e}", new_path);
+                    warn!("Failed to open session file at {:?}: {e}", new_path);
                 }
             }
         }
@@ -136,7 +161,11 @@ pub(crate) async fn load_session(store_dir: &Path) -> Option<SessionInfo> {
                         }
                     }
 
-                    return Some(info);
+                    if has_session_secret(store_dir) {
+                        let _ = persist_session(store_dir, &info).await;
+                    }
+
+                    return Ok(Some(info));
                 }
                 Err(e) => {
                     warn!("Failed to parse legacy session file at {:?}: {e}", old_path);
@@ -144,12 +173,46 @@ pub(crate) async fn load_session(store_dir: &Path) -> Option<SessionInfo> {
             }
         }
 
-        None
+        Ok(None)
     }
 
     #[cfg(target_family = "wasm")]
     {
         let _ = store_dir;
+        Ok(None)
+    }
+}
+
+/// Reads the session file without side effects: nothing is migrated,
+/// re-encrypted or cached. An encrypted file needs `secret`, unless the
+/// store is open and its cipher already unlocked.
+pub(crate) fn peek_session(
+    store_dir: &Path,
+    secret: Option<&SessionSecret>,
+) -> Option<SessionInfo> {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let txt = std::fs::read_to_string(session_file(store_dir)).ok()?;
+        if let Ok(info) = serde_json::from_str::<SessionInfo>(&txt) {
+            return Some(info);
+        }
+        let file: SealedFile = serde_json::from_str(&txt).ok()?;
+        let unlocked = SESSION_CRYPTO
+            .lock()
+            .unwrap()
+            .get(store_dir)
+            .and_then(|entry| entry.unlocked.clone())
+            .filter(|(_, exported)| *exported == file.cipher);
+        let cipher = match unlocked {
+            Some((cipher, _)) => cipher,
+            None => Arc::new(import_cipher(secret?, &file.cipher).ok()?),
+        };
+        cipher.decrypt_value(&file.payload).ok()
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, secret);
         None
     }
 }
@@ -166,6 +229,60 @@ pub(crate) fn remove_session_file(store_dir: &Path) {
     }
 }
 
+/// Account ids under `accounts_dir` that have a session file, sorted.
+pub(crate) fn stored_account_ids(accounts_dir: &Path) -> Vec<String> {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let mut ids: Vec<String> = std::fs::read_dir(accounts_dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| {
+                        e.file_name()
+                            .to_str()?
+                            .strip_suffix(".session.json")
+                            .map(str::to_owned)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        ids.sort();
+        ids
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = accounts_dir;
+        Vec::new()
+    }
+}
+
+/// Deletes `*.trash.*` dirs left behind by `trash_store_dir`.
+pub(crate) fn purge_trashed_stores(parent: &Path) {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let Ok(entries) = std::fs::read_dir(parent) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir()
+                && path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.contains(".trash."))
+            {
+                let _ = std::fs::remove_dir_all(&path);
+            }
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = parent;
+    }
+}
+
 pub(crate) async fn load_room_list_cache(store_dir: &Path) -> Vec<RoomListEntry> {
     #[cfg(not(target_family = "wasm"))]
     {
@@ -200,46 +317,488 @@ pub(crate) async fn write_room_list_cache(
     }
 }
 
+pub(crate) async fn load_room_list_sort(store_dir: &Path) -> RoomListSort {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        match tokio::fs::read_to_string(room_list_sort_file(store_dir)).await {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_default(),
+            Err(_) => RoomListSort::default(),
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = store_dir;
+        RoomListSort::default()
+    }
+}
+
+pub(crate) async fn write_room_list_sort(
+    store_dir: &Path,
+    sort: &RoomListSort,
+) -> std::io::Result<()> {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let payload = serde_json::to_string(sort).unwrap_or_default();
+        let path = room_list_sort_file(store_dir);
+        // A crash mid-write must not leave a truncated file that resets the
+        // user's choice.
+        tokio::task::spawn_blocking(move || write_replacing(&path, payload.as_bytes()))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, sort);
+        Ok(())
+    }
+}
+
+pub(crate) async fn load_image_upload_options(store_dir: &Path) -> ImageUploadOptions {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        match tokio::fs::read_to_string(image_upload_options_file(store_dir)).await {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_default(),
+            Err(_) => ImageUploadOptions::default(),
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = store_dir;
+        ImageUploadOptions::default()
+    }
+}
+
+pub(crate) async fn write_image_upload_options(
+    store_dir: &Path,
+    options: &ImageUploadOptions,
+) -> std::io::Result<()> {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let payload = serde_json::to_string(options).unwrap_or_default();
+        tokio::fs::write(image_upload_options_file(store_dir), payload).await
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, options);
+        Ok(())
+    }
+}
+
 pub(crate) struct SearchIndexConfig {
     pub(crate) dir: PathBuf,
     pub(crate) key: String,
 }
 
-pub(crate) fn search_index_config(store_dir: &Path) -> Option<SearchIndexConfig> {
+pub(crate) fn search_index_config(
+    store_dir: &Path,
+    passphrase: Option<&str>,
+) -> Result<Option<SearchIndexConfig>, FfiError> {
     #[cfg(not(target_family = "wasm"))]
     {
         let dir = store_dir.join("search_index");
         let _ = std::fs::create_dir_all(&dir);
 
         let key_file = store_dir.join("search_index_key.txt");
-        let key = match std::fs::read_to_string(&key_file) {
-            Ok(existing) => {
-                let trimmed = existing.trim().to_string();
-                if trimmed.is_empty() {
+        let plaintext_key = std::fs::read_to_string(&key_file)
+            .ok()
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty());
+
+        let Some(passphrase) = passphrase else {
+            let key = match plaintext_key {
+                Some(existing) => existing,
+                None => {
                     let generated = format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
                     let _ = std::fs::write(&key_file, &generated);
                     generated
-                } else {
-                    trimmed
                 }
+            };
+            return Ok(Some(SearchIndexConfig { dir, key }));
+        };
+
+        let sealed_file = sealed_index_key_file(store_dir);
+        if let Ok(txt) = std::fs::read_to_string(&sealed_file) {
+            let key: String = open_with_passphrase(passphrase, &txt)?;
+            return Ok(Some(SearchIndexConfig { dir, key }));
+        }
+
+        // First run with a passphrase: wrap the existing key (so the index
+        // stays readable) and drop the plaintext copy.
+        let key = plaintext_key
+            .unwrap_or_else(|| format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4()));
+        let sealed = seal_with_passphrase(passphrase, &key).map_err(FfiError::Msg)?;
+        std::fs::write(&sealed_file, sealed)?;
+        let _ = std::fs::remove_file(&key_file);
+
+        Ok(Some(SearchIndexConfig { dir, key }))
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, passphrase);
+        Ok(None)
+    }
+}
+
+/// SQLite databases `sqlite_store` creates, each holding its own wrapped
+/// `StoreCipher` under the `cipher` key of the `kv` table.
+#[cfg(not(target_family = "wasm"))]
+const SQLITE_STORE_FILES: &[&str] = &[
+    "matrix-sdk-state.sqlite3",
+    "matrix-sdk-crypto.sqlite3",
+    "matrix-sdk-event-cache.sqlite3",
+    "matrix-sdk-media.sqlite3",
+];
+
+/// Re-wraps every store cipher (and the search index key) under
+/// `new_passphrase`. Nothing is written unless `old_passphrase` unlocks all
+/// of them. The new wrappings are staged first and the old ones journalled,
+/// so a rekey cut short is rolled back (by `recover_rekey`) rather than
+/// leaving stores under different passphrases. Open clients keep working:
+/// they hold the unwrapped cipher.
+pub(crate) fn rekey_stores(
+    store_dir: &Path,
+    old_passphrase: &str,
+    new_passphrase: &str,
+) -> Result<(), FfiError> {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        recover_rekey(store_dir)?;
+
+        let mut staged = Vec::new();
+        for name in SQLITE_STORE_FILES {
+            let path = store_dir.join(name);
+            if !path.exists() {
+                continue;
             }
-            Err(_) => {
-                let generated = format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
-                let _ = std::fs::write(&key_file, &generated);
-                generated
+            let Some(old) = read_store_cipher(&path)? else {
+                return Err(FfiError::Msg(format!(
+                    "{name} was created without a passphrase and cannot be rekeyed"
+                )));
+            };
+            let cipher =
+                StoreCipher::import(old_passphrase, &old).map_err(|_| FfiError::WrongPassphrase)?;
+            let new = cipher
+                .export(new_passphrase)
+                .map_err(|e| FfiError::Msg(e.to_string()))?;
+            staged.push((name.to_string(), old, new));
+        }
+
+        let sealed_file = sealed_index_key_file(store_dir);
+        let old_index = std::fs::read_to_string(&sealed_file).ok();
+        let new_index = match &old_index {
+            Some(txt) => {
+                let key: String = open_with_passphrase(old_passphrase, txt)?;
+                Some(seal_with_passphrase(new_passphrase, &key).map_err(FfiError::Msg)?)
+            }
+            None => None,
+        };
+
+        let journal = RekeyJournal {
+            ciphers: staged
+                .iter()
+                .map(|(name, old, _)| (name.clone(), old.clone()))
+                .collect(),
+            index_key: old_index,
+        };
+        let journal = serde_json::to_vec(&journal).map_err(|e| FfiError::Msg(e.to_string()))?;
+        write_replacing(&rekey_journal_file(store_dir), &journal)?;
+
+        let commit = || -> Result<(), FfiError> {
+            for (name, _, new) in &staged {
+                write_store_cipher(&store_dir.join(name), new)?;
+            }
+            if let Some(sealed) = &new_index {
+                write_replacing(&sealed_file, sealed.as_bytes())?;
             }
+            Ok(())
         };
+        if let Err(e) = commit() {
+            warn!("Rekey of {:?} failed, rolling back: {e}", store_dir);
+            recover_rekey(store_dir)?;
+            return Err(e);
+        }
+        std::fs::remove_file(rekey_journal_file(store_dir))?;
+        info!("Rekeyed stores in {:?}", store_dir);
+        Ok(())
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, old_passphrase, new_passphrase);
+        Err(FfiError::Msg("rekey_stores: not supported on web".into()))
+    }
+}
 
-        Some(SearchIndexConfig { dir, key })
+/// Puts back what an interrupted `rekey_stores` journalled, so the old
+/// passphrase opens every store again. Must run before the stores are opened.
+pub(crate) fn recover_rekey(store_dir: &Path) -> Result<(), FfiError> {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let journal_file = rekey_journal_file(store_dir);
+        let Ok(txt) = std::fs::read(&journal_file) else {
+            return Ok(());
+        };
+        // A journal that never got fully written means nothing was changed yet.
+        if let Ok(journal) = serde_json::from_slice::<RekeyJournal>(&txt) {
+            for (name, old) in &journal.ciphers {
+                write_store_cipher(&store_dir.join(name), old)?;
+            }
+            if let Some(sealed) = &journal.index_key {
+                write_replacing(&sealed_index_key_file(store_dir), sealed.as_bytes())?;
+            }
+            warn!("Rolled back an interrupted rekey in {:?}", store_dir);
+        }
+        std::fs::remove_file(&journal_file)?;
+        Ok(())
     }
 
     #[cfg(target_family = "wasm")]
     {
         let _ = store_dir;
-        None
+        Ok(())
+    }
+}
+
+/// Whether `passphrase` unwraps the stores' cipher; false only when it
+/// definitely does not, so other store failures are not taken for it.
+pub(crate) fn store_passphrase_rejected(store_dir: &Path, passphrase: &str) -> bool {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        SQLITE_STORE_FILES.iter().any(|name| {
+            matches!(
+                read_store_cipher(&store_dir.join(name)),
+                Ok(Some(exported)) if StoreCipher::import(passphrase, &exported).is_err()
+            )
+        })
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, passphrase);
+        false
+    }
+}
+
+/// The stores' wrapped ciphers as they were before an unfinished rekey.
+#[cfg(not(target_family = "wasm"))]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RekeyJournal {
+    /// Store file name and the cipher it held.
+    ciphers: Vec<(String, Vec<u8>)>,
+    /// The sealed search index key file, if there was one.
+    index_key: Option<String>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn rekey_journal_file(store_dir: &Path) -> PathBuf {
+    store_dir.join("rekey_journal.json")
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn read_store_cipher(path: &Path) -> Result<Option<Vec<u8>>, FfiError> {
+    use rusqlite::OptionalExtension;
+
+    if !path.exists() {
+        return Ok(None);
+    }
+    let conn = rusqlite::Connection::open(path).map_err(|e| FfiError::Msg(e.to_string()))?;
+    conn.query_row("SELECT value FROM kv WHERE key = 'cipher'", [], |r| {
+        r.get(0)
+    })
+    .optional()
+    .map_err(|e| FfiError::Msg(e.to_string()))
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn write_store_cipher(path: &Path, exported: &[u8]) -> Result<(), FfiError> {
+    let conn = rusqlite::Connection::open(path).map_err(|e| FfiError::Msg(e.to_string()))?;
+    conn.execute(
+        "UPDATE kv SET value = ?1 WHERE key = 'cipher'",
+        rusqlite::params![exported],
+    )
+    .map_err(|e| FfiError::Msg(e.to_string()))?;
+    Ok(())
+}
+
+/// Writes through a temp file and a rename, so `path` is never half written.
+#[cfg(not(target_family = "wasm"))]
+fn write_replacing(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let tmp = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sealed_index_key_file(store_dir: &Path) -> PathBuf {
+    store_dir.join("search_index_key.enc")
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn seal_with_passphrase(passphrase: &str, value: &impl serde::Serialize) -> Result<String, String> {
+    let cipher = StoreCipher::new().map_err(|e| e.to_string())?;
+    let file = SealedFile {
+        version: 1,
+        scheme: "passphrase".to_string(),
+        cipher: cipher.export(passphrase).map_err(|e| e.to_string())?,
+        payload: cipher.encrypt_value(value).map_err(|e| e.to_string())?,
+    };
+    serde_json::to_string(&file).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn open_with_passphrase<T: serde::de::DeserializeOwned>(
+    passphrase: &str,
+    txt: &str,
+) -> Result<T, FfiError> {
+    let file: SealedFile = serde_json::from_str(txt).map_err(|e| FfiError::Msg(e.to_string()))?;
+    let cipher =
+        StoreCipher::import(passphrase, &file.cipher).map_err(|_| FfiError::WrongPassphrase)?;
+    cipher
+        .decrypt_value(&file.payload)
+        .map_err(|e| FfiError::Msg(e.to_string()))
+}
+
+/// Per-store session secret plus the cipher it unlocked, cached so token
+/// refreshes don't re-run the passphrase KDF on every write.
+#[cfg(not(target_family = "wasm"))]
+struct SessionCrypto {
+    secret: SessionSecret,
+    unlocked: Option<(Arc<StoreCipher>, Vec<u8>)>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+static SESSION_CRYPTO: Lazy<Mutex<HashMap<PathBuf, SessionCrypto>>> = Lazy::new(Default::default);
+
+/// On-disk form of an encrypted session or key file. `cipher` is the exported
+/// `StoreCipher`, wrapped with the passphrase or host key.
+#[cfg(not(target_family = "wasm"))]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SealedFile {
+    version: u8,
+    scheme: String,
+    cipher: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+pub(crate) fn set_session_secret(store_dir: &Path, secret: Option<SessionSecret>) {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let mut map = SESSION_CRYPTO.lock().unwrap();
+        match secret {
+            Some(secret) => {
+                map.insert(
+                    store_dir.to_path_buf(),
+                    SessionCrypto {
+                        secret,
+                        unlocked: None,
+                    },
+                );
+            }
+            None => {
+                map.remove(store_dir);
+            }
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        let _ = (store_dir, secret);
     }
 }
 
+#[cfg(not(target_family = "wasm"))]
+fn has_session_secret(store_dir: &Path) -> bool {
+    SESSION_CRYPTO.lock().unwrap().contains_key(store_dir)
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn host_key(key: &[u8]) -> [u8; 32] {
+    blake3::derive_key("mages_ffi session file key v1", key)
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn export_cipher(secret: &SessionSecret, cipher: &StoreCipher) -> Result<Vec<u8>, String> {
+    match secret {
+        SessionSecret::Passphrase { passphrase } => cipher.export(passphrase),
+        SessionSecret::Key { key } => cipher.export_with_key(&host_key(key)),
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn import_cipher(secret: &SessionSecret, exported: &[u8]) -> Result<StoreCipher, String> {
+    match secret {
+        SessionSecret::Passphrase { passphrase } => StoreCipher::import(passphrase, exported),
+        SessionSecret::Key { key } => StoreCipher::import_with_key(&host_key(key), exported),
+    }
+    .map_err(|_| "session secret does not match the encrypted session file".to_string())
+}
+
+/// Serialises `info` encrypted under the store's secret, or `None` when the
+/// store has no secret and the file stays plaintext.
+#[cfg(not(target_family = "wasm"))]
+fn seal_session(store_dir: &Path, info: &SessionInfo) -> Result<Option<String>, String> {
+    let mut map = SESSION_CRYPTO.lock().unwrap();
+    let Some(entry) = map.get_mut(store_dir) else {
+        return Ok(None);
+    };
+    if entry.unlocked.is_none() {
+        let cipher = StoreCipher::new().map_err(|e| e.to_string())?;
+        let exported = export_cipher(&entry.secret, &cipher)?;
+        entry.unlocked = Some((Arc::new(cipher), exported));
+    }
+    let (cipher, exported) = entry.unlocked.as_ref().unwrap();
+    let file = SealedFile {
+        version: 1,
+        scheme: match entry.secret {
+            SessionSecret::Passphrase { .. } => "passphrase",
+            SessionSecret::Key { .. } => "key",
+        }
+        .to_string(),
+        cipher: exported.clone(),
+        payload: cipher.encrypt_value(info).map_err(|e| e.to_string())?,
+    };
+    serde_json::to_string(&file)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Parses a session file, decrypting it if needed. The flag is `true` when
+/// the file was still plaintext and should be migrated.
+#[cfg(not(target_family = "wasm"))]
+fn open_session(store_dir: &Path, txt: &str) -> Result<(SessionInfo, bool), FfiError> {
+    if let Ok(info) = serde_json::from_str::<SessionInfo>(txt) {
+        return Ok((info, true));
+    }
+    let file: SealedFile = serde_json::from_str(txt).map_err(|e| FfiError::Msg(e.to_string()))?;
+
+    let mut map = SESSION_CRYPTO.lock().unwrap();
+    let entry = map.get_mut(store_dir).ok_or(FfiError::WrongPassphrase)?;
+    let cipher = match &entry.unlocked {
+        Some((cipher, exported)) if *exported == file.cipher => cipher.clone(),
+        _ => {
+            let cipher = import_cipher(&entry.secret, &file.cipher)
+                .map_err(|_| FfiError::WrongPassphrase)?;
+            let cipher = Arc::new(cipher);
+            entry.unlocked = Some((cipher.clone(), file.cipher.clone()));
+            cipher
+        }
+    };
+    let info = cipher
+        .decrypt_value(&file.payload)
+        .map_err(|e| FfiError::Msg(e.to_string()))?;
+    Ok((info, false))
+}
+
 fn session_file(store_dir: &Path) -> PathBuf {
     let mut name = store_dir
         .file_name()
@@ -258,6 +817,14 @@ fn room_list_cache_file(store_dir: &Path) -> PathBuf {
     store_dir.join("room_list_cache.json")
 }
 
+fn room_list_sort_file(store_dir: &Path) -> PathBuf {
+    store_dir.join("room_list_sort.json")
+}
+
+fn image_upload_options_file(store_dir: &Path) -> PathBuf {
+    store_dir.join("image_upload_options.json")
+}
+
 pub(crate) async fn persist_session(store_dir: &Path, info: &SessionInfo) -> std::io::Result<()> {
     #[cfg(not(target_family = "wasm"))]
     {
@@ -267,7 +834,11 @@ pub(crate) async fn persist_session(store_dir: &Path, info: &SessionInfo) -> std
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        let payload = serde_json::to_string(info).unwrap();
+        let payload = match seal_session(store_dir, info) {
+            Ok(Some(sealed)) => sealed,
+            Ok(None) => serde_json::to_string(info).unwrap(),
+            Err(e) => return Err(std::io::Error::other(e)),
+        };
         let tmp_path = path.with_extension("tmp");
         tokio::fs::write(&tmp_path, payload).await?;
         tokio::fs::rename(&tmp_path, &path).await
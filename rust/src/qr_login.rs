@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+
+use matrix_sdk::authentication::oauth::qrcode::{
+    CheckCodeSender, GeneratedQrProgress, GrantLoginProgress, LoginProgress, QrProgress,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::QrLoginListener;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "phase")]
+pub enum QrLoginEvent {
+    Starting,
+    /// New device: render `qr_data` (raw MSC4108 bytes) as a QR code.
+    QrReady {
+        qr_data: Vec<u8>,
+    },
+    /// New device: the QR was scanned; ask for the 2-digit code the other
+    /// device shows and pass it to `qr_login_submit_check_code`.
+    QrScanned,
+    /// Existing device: show this code for the user to type on the new device.
+    CheckCode {
+        code: u8,
+    },
+    WaitingForToken {
+        user_code: String,
+    },
+    /// Existing device: open this URL to approve the new device at the provider.
+    WaitingForAuth {
+        verification_uri: String,
+    },
+    SyncingSecrets,
+    Done,
+    Error {
+        message: String,
+    },
+}
+
+pub fn emit(listener: &dyn QrLoginListener, event: &QrLoginEvent) {
+    let json = serde_json::to_string(event).unwrap_or_default();
+    listener.on_event(json);
+}
+
+/// Maps progress of the "generate QR and wait" side. The check-code sender
+/// is parked in `check_code` until the host submits the code. `Done` is left
+/// to the caller, which emits it after the session is persisted.
+pub fn map_login_progress(
+    progress: LoginProgress<GeneratedQrProgress>,
+    check_code: &Mutex<Option<CheckCodeSender>>,
+) -> Option<QrLoginEvent> {
+    Some(match progress {
+        LoginProgress::Starting => QrLoginEvent::Starting,
+        LoginProgress::EstablishingSecureChannel(GeneratedQrProgress::QrReady(data)) => {
+            QrLoginEvent::QrReady {
+                qr_data: data.to_bytes(),
+            }
+        }
+        LoginProgress::EstablishingSecureChannel(GeneratedQrProgress::QrScanned(sender)) => {
+            *check_code.lock().unwrap() = Some(sender);
+            QrLoginEvent::QrScanned
+        }
+        LoginProgress::WaitingForToken { user_code } => QrLoginEvent::WaitingForToken { user_code },
+        LoginProgress::SyncingSecrets => QrLoginEvent::SyncingSecrets,
+        _ => return None,
+    })
+}
+
+/// Maps progress of the "scan and approve" side on the logged-in device.
+pub fn map_grant_progress(progress: GrantLoginProgress<QrProgress>) -> Option<QrLoginEvent> {
+    Some(match progress {
+        GrantLoginProgress::Starting => QrLoginEvent::Starting,
+        GrantLoginProgress::EstablishingSecureChannel(QrProgress { check_code }) => {
+            QrLoginEvent::CheckCode {
+                code: check_code.to_digit(),
+            }
+        }
+        GrantLoginProgress::WaitingForAuth { verification_uri } => QrLoginEvent::WaitingForAuth {
+            verification_uri: verification_uri.to_string(),
+        },
+        GrantLoginProgress::SyncingSecrets => QrLoginEvent::SyncingSecrets,
+        GrantLoginProgress::Done => QrLoginEvent::Done,
+        _ => return None,
+    })
+}
@@ -0,0 +1,75 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use matrix_sdk::{Client as SdkClient, crypto::KeyExportError, encryption::RoomKeyImportError};
+use tracing::info;
+
+use crate::{
+    FfiError, ProgressObserver, RoomKeyImportSummary,
+    errors::{IntoFfi, ffi_err},
+    safe_call,
+};
+
+/// Writes megolm sessions to `path` in the standard passphrase-encrypted key
+/// export format. `room_filter` limits the export to those rooms.
+pub(crate) async fn export(
+    sdk: &SdkClient,
+    path: String,
+    passphrase: String,
+    room_filter: Option<Vec<String>>,
+) -> Result<u64, FfiError> {
+    let exported = AtomicU64::new(0);
+    sdk.encryption()
+        .export_room_keys(PathBuf::from(&path), &passphrase, |key| {
+            let keep = room_filter
+                .as_ref()
+                .is_none_or(|rooms| rooms.iter().any(|r| r == key.room_id.as_str()));
+            if keep {
+                exported.fetch_add(1, Ordering::Relaxed);
+            }
+            keep
+        })
+        .await
+        .ffi()?;
+    let exported = exported.into_inner();
+    info!("Exported {exported} room key(s) to {path}");
+    Ok(exported)
+}
+
+/// Imports a key export file through the SDK, which decrypts and stores it
+/// in one go without reporting how far it got: `progress` sees the start
+/// (`total` unknown) and the end (`sent` = `total` = keys in the file).
+pub(crate) async fn import(
+    sdk: &SdkClient,
+    path: String,
+    passphrase: String,
+    progress: Option<&dyn ProgressObserver>,
+) -> Result<RoomKeyImportSummary, FfiError> {
+    if let Some(p) = progress {
+        safe_call(|| p.on_progress(0, None));
+    }
+    let res = sdk
+        .encryption()
+        .import_room_keys(PathBuf::from(&path), &passphrase)
+        .await
+        .map_err(|e| match e {
+            RoomKeyImportError::Export(KeyExportError::InvalidMac) => FfiError::WrongPassphrase,
+            RoomKeyImportError::Export(other) => ffi_err!("invalid key export: {}", other),
+            other => FfiError::Msg(other.to_string()),
+        })?;
+
+    let summary = RoomKeyImportSummary {
+        imported: res.imported_count as u64,
+        total: res.total_count as u64,
+    };
+    if let Some(p) = progress {
+        safe_call(|| p.on_progress(summary.total, Some(summary.total)));
+    }
+    info!(
+        "Imported {}/{} room key(s) from {path}",
+        summary.imported, summary.total
+    );
+    Ok(summary)
+}
@@ -0,0 +1,159 @@
+use std::cmp::Ordering;
+
+use crate::{RoomListDiff, RoomListEntry, RoomListOrder, RoomListSort};
+
+/// Keeps the room list in the user's chosen order on top of the SDK's
+/// recency-ordered diffs, and translates those diffs into positional diffs
+/// against the sorted view.
+pub(crate) struct SortedRoomList {
+    sort: RoomListSort,
+    /// Room ids in SDK order, so index-based SDK diffs can be resolved.
+    sdk_ids: Vec<String>,
+    view: Vec<RoomListEntry>,
+}
+
+impl SortedRoomList {
+    pub(crate) fn new(sort: RoomListSort) -> Self {
+        Self {
+            sort,
+            sdk_ids: Vec::new(),
+            view: Vec::new(),
+        }
+    }
+
+    pub(crate) fn entries(&self) -> &[RoomListEntry] {
+        &self.view
+    }
+
+    /// Re-sorts the whole view; observers get it as one reset.
+    pub(crate) fn set_sort(&mut self, sort: RoomListSort) -> RoomListDiff {
+        self.sort = sort;
+        let sort = &self.sort;
+        self.view.sort_by(|a, b| compare(sort, a, b));
+        RoomListDiff::Reset {
+            values: self.view.clone(),
+        }
+    }
+
+    pub(crate) fn apply(&mut self, diff: RoomListDiff) -> Vec<RoomListDiff> {
+        let mut out = Vec::new();
+        match diff {
+            RoomListDiff::Reset { values } => {
+                self.sdk_ids = values.iter().map(|e| e.room_id.clone()).collect();
+                self.view = values;
+                out.push(self.set_sort(self.sort.clone()));
+            }
+            RoomListDiff::Clear => {
+                self.sdk_ids.clear();
+                self.view.clear();
+                out.push(RoomListDiff::Clear);
+            }
+            RoomListDiff::Append { values } => {
+                for value in values {
+                    self.sdk_ids.push(value.room_id.clone());
+                    out.push(self.insert(value));
+                }
+            }
+            RoomListDiff::Insert { index, value } => {
+                let index = (index as usize).min(self.sdk_ids.len());
+                self.sdk_ids.insert(index, value.room_id.clone());
+                out.push(self.insert(value));
+            }
+            RoomListDiff::Set { index, value } => {
+                let Some(slot) = self.sdk_ids.get_mut(index as usize) else {
+                    return out;
+                };
+                let old_id = std::mem::replace(slot, value.room_id.clone());
+                let old_pos = self.remove(&old_id);
+                let new_pos = self.insertion_point(&value);
+                match old_pos {
+                    Some(old) if old == new_pos => {
+                        self.view.insert(new_pos, value.clone());
+                        out.push(RoomListDiff::Set {
+                            index: new_pos as u32,
+                            value,
+                        });
+                    }
+                    old => {
+                        if let Some(old) = old {
+                            out.push(RoomListDiff::Remove { index: old as u32 });
+                        }
+                        out.push(self.insert(value));
+                    }
+                }
+            }
+            RoomListDiff::Remove { index } => {
+                if (index as usize) < self.sdk_ids.len() {
+                    let id = self.sdk_ids.remove(index as usize);
+                    out.extend(self.remove_diff(&id));
+                }
+            }
+            RoomListDiff::Truncate { length } => {
+                let keep = (length as usize).min(self.sdk_ids.len());
+                let removed: Vec<String> = self.sdk_ids.drain(keep..).collect();
+                for id in removed {
+                    out.extend(self.remove_diff(&id));
+                }
+            }
+        }
+        out
+    }
+
+    fn insertion_point(&self, value: &RoomListEntry) -> usize {
+        self.view
+            .partition_point(|e| compare(&self.sort, e, value) == Ordering::Less)
+    }
+
+    fn insert(&mut self, value: RoomListEntry) -> RoomListDiff {
+        let index = self.insertion_point(&value);
+        self.view.insert(index, value.clone());
+        RoomListDiff::Insert {
+            index: index as u32,
+            value,
+        }
+    }
+
+    fn remove(&mut self, room_id: &str) -> Option<usize> {
+        let pos = self.view.iter().position(|e| e.room_id == room_id)?;
+        self.view.remove(pos);
+        Some(pos)
+    }
+
+    fn remove_diff(&mut self, room_id: &str) -> Option<RoomListDiff> {
+        self.remove(room_id).map(|index| RoomListDiff::Remove {
+            index: index as u32,
+        })
+    }
+}
+
+fn has_unread(e: &RoomListEntry) -> bool {
+    e.messages > 0 || e.notifications > 0 || e.mentions > 0 || e.marked_unread
+}
+
+/// Total order for `sort`; the room id breaks ties so positions are stable.
+fn compare(sort: &RoomListSort, a: &RoomListEntry, b: &RoomListEntry) -> Ordering {
+    let by_recency = || b.last_ts.cmp(&a.last_ts);
+    let pinned = if sort.favourites_first {
+        b.is_favourite.cmp(&a.is_favourite)
+    } else {
+        Ordering::Equal
+    };
+    let sunk = if sort.low_priority_last {
+        a.is_low_priority.cmp(&b.is_low_priority)
+    } else {
+        Ordering::Equal
+    };
+    let order = match sort.order {
+        RoomListOrder::Recency => by_recency(),
+        RoomListOrder::Name => a
+            .name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then_with(by_recency),
+        RoomListOrder::UnreadFirst => has_unread(b).cmp(&has_unread(a)).then_with(by_recency),
+    };
+    pinned
+        .then(sunk)
+        .then(order)
+        .then_with(|| a.room_id.cmp(&b.room_id))
+}
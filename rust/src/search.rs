@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use futures_util::StreamExt;
+use matrix_sdk::{
+    Client as SdkClient, Room,
+    ruma::{
+        OwnedEventId, RoomId,
+        events::{AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent},
+    },
+};
+use tracing::warn;
+
+use crate::{
+    FfiError, SearchFilter, SearchHit, SearchPage, SearchSnippet, TextRange,
+    errors::{IntoFfi, OptionFfi},
+};
+
+/// Index events read at most from one room when the filter rejects hits.
+const MAX_SCAN_PER_ROOM: usize = 1_000;
+/// Rooms searched at the same time by `search_all_rooms`.
+const ROOM_CONCURRENCY: usize = 8;
+/// Characters of context kept before the first match in a snippet.
+const SNIPPET_BEFORE: usize = 40;
+/// Characters kept from the first match onwards.
+const SNIPPET_AFTER: usize = 120;
+
+/// One room, in index order. Hits that can no longer be loaded (redacted,
+/// undecryptable, not a message) are dropped rather than returned empty.
+pub(crate) async fn search_room(
+    room: &Room,
+    query: &str,
+    limit: usize,
+    offset: Option<u32>,
+) -> Result<SearchPage, FfiError> {
+    let event_ids = room
+        .search(query, limit, offset.map(|o| o as usize))
+        .await
+        .ffi()?;
+    let terms = terms(query);
+    let mut hits = Vec::with_capacity(event_ids.len());
+    for eid in &event_ids {
+        if let Some(hit) = load_hit(room, eid, &terms).await {
+            hits.push(hit);
+        }
+    }
+    // Paginate on what the index returned, so dropped hits do not end it early.
+    let next_offset = (event_ids.len() == limit)
+        .then(|| offset.unwrap_or(0).saturating_add(event_ids.len() as u32));
+    Ok(SearchPage {
+        hits,
+        next_offset,
+        next_cursor: None,
+    })
+}
+
+/// All joined rooms (or `filter.room_ids`), searched `ROOM_CONCURRENCY` at a
+/// time and ranked together. Each page reads up to `limit` hits that pass
+/// `filter` from every room, starting where the room's earlier pages stopped,
+/// and merges them with [`merge`]. `cursor` records those per-room positions,
+/// so every hit is returned exactly once however deep in its room it sits.
+pub(crate) async fn search_all_rooms(
+    sdk: &SdkClient,
+    query: &str,
+    filter: &SearchFilter,
+    limit: usize,
+    cursor: Option<&str>,
+) -> Result<SearchPage, FfiError> {
+    let cursor = match cursor {
+        Some(c) => Some(Cursor::decode(c).or_ffi("invalid search cursor")?),
+        None => None,
+    };
+    let rooms: Vec<(Room, usize)> = match &cursor {
+        // Rooms joined since the first page are not searched; rooms the
+        // cursor no longer lists were exhausted.
+        Some(c) => c
+            .rooms
+            .iter()
+            .filter_map(|(id, &start)| {
+                let room = sdk.get_room(<&RoomId>::try_from(id.as_str()).ok()?)?;
+                Some((room, start))
+            })
+            .collect(),
+        None => match &filter.room_ids {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| <&RoomId>::try_from(id.as_str()).ok())
+                .filter_map(|rid| sdk.get_room(rid))
+                .map(|room| (room, 0))
+                .collect(),
+            None => sdk
+                .joined_rooms()
+                .into_iter()
+                .map(|room| (room, 0))
+                .collect(),
+        },
+    };
+    let terms = terms(query);
+    let now_ms = cursor.as_ref().map_or_else(current_ms, |c| c.now_ms);
+
+    let windows: Vec<Window> = futures_util::stream::iter(&rooms)
+        .map(|(room, start)| search_one(room, query, filter, &terms, limit, *start))
+        .buffer_unordered(ROOM_CONCURRENCY)
+        .collect()
+        .await;
+    let (hits, rooms) = merge(windows, &terms, query, now_ms, limit);
+    let next_cursor = (!rooms.is_empty()).then(|| Cursor { now_ms, rooms }.encode());
+    Ok(SearchPage {
+        hits,
+        next_offset: None,
+        next_cursor,
+    })
+}
+
+/// Hits from one room that pass the filter, in index order, each with its
+/// index position.
+pub(crate) struct Window {
+    pub room_id: String,
+    /// Index position the read started at.
+    pub start: usize,
+    pub hits: Vec<(usize, SearchHit)>,
+    /// Index position just past the last event read.
+    pub next_scan: usize,
+    /// Whether the room may have hits past `next_scan`.
+    pub more: bool,
+}
+
+/// Up to `cap` hits from one room that pass `filter`, read from index
+/// position `start` on. Rejected hits do not count towards `cap`; the index is
+/// read on, up to `MAX_SCAN_PER_ROOM` events per call.
+async fn search_one(
+    room: &Room,
+    query: &str,
+    filter: &SearchFilter,
+    terms: &[String],
+    cap: usize,
+    start: usize,
+) -> Window {
+    let mut window = Window {
+        room_id: room.room_id().to_string(),
+        start,
+        hits: Vec::new(),
+        next_scan: start,
+        more: true,
+    };
+    while window.next_scan - start < MAX_SCAN_PER_ROOM {
+        let event_ids = match room.search(query, cap, Some(window.next_scan)).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                warn!("search in {} failed: {e}", room.room_id());
+                window.more = false;
+                return window;
+            }
+        };
+        for eid in &event_ids {
+            let pos = window.next_scan;
+            window.next_scan += 1;
+            let Some(hit) = load_hit(room, eid, terms).await else {
+                continue;
+            };
+            if !matches_filter(filter, &hit) {
+                continue;
+            }
+            window.hits.push((pos, hit));
+            if window.hits.len() == cap {
+                return window;
+            }
+        }
+        if event_ids.len() < cap {
+            window.more = false;
+            return window;
+        }
+    }
+    window
+}
+
+/// Takes up to `limit` hits from `windows`, each time the best-scoring next
+/// hit of any room. A room's hits are taken in index order, so it resumes
+/// just past the last one taken and hits left over are read again on the
+/// next page rather than skipped. Returns the page and the resume position of
+/// every room that may still have hits.
+pub(crate) fn merge(
+    windows: Vec<Window>,
+    terms: &[String],
+    query: &str,
+    now_ms: u64,
+    limit: usize,
+) -> (Vec<SearchHit>, BTreeMap<String, usize>) {
+    let mut queues: Vec<_> = windows
+        .into_iter()
+        .map(|w| {
+            let hits: VecDeque<_> = w
+                .hits
+                .into_iter()
+                .map(|(pos, hit)| (pos, score(terms, query, &hit, pos, now_ms), hit))
+                .collect();
+            (w.room_id, w.start, hits, w.next_scan, w.more)
+        })
+        .collect();
+    let mut page = Vec::with_capacity(limit);
+    while page.len() < limit {
+        let best = queues
+            .iter_mut()
+            .filter_map(|(_, resume, hits, ..)| Some((resume, hits.front()?.1, hits)))
+            .min_by(|(_, a, ha), (_, b, hb)| {
+                order((*a, &ha[0].2.event_id), (*b, &hb[0].2.event_id))
+            });
+        let Some((resume, _, hits)) = best else {
+            break;
+        };
+        let (pos, _, hit) = hits.pop_front().expect("queue has a front");
+        *resume = pos + 1;
+        page.push(hit);
+    }
+    let rooms = queues
+        .into_iter()
+        .filter_map(|(room_id, resume, hits, next_scan, more)| {
+            if !hits.is_empty() {
+                Some((room_id, resume))
+            } else {
+                more.then_some((room_id, next_scan))
+            }
+        })
+        .collect();
+    (page, rooms)
+}
+
+/// Where the previous `search_all_rooms` page ended. `now_ms` is pinned for
+/// the whole search so recency, and with it the order, does not drift
+/// between pages; `rooms` holds the index position each room resumes at, and
+/// rooms missing from it have no hits left.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Cursor {
+    pub now_ms: u64,
+    pub rooms: BTreeMap<String, usize>,
+}
+
+impl Cursor {
+    pub(crate) fn encode(&self) -> String {
+        let rooms = serde_json::to_string(&self.rooms).unwrap_or_default();
+        format!("2:{}:{rooms}", self.now_ms)
+    }
+
+    pub(crate) fn decode(s: &str) -> Option<Self> {
+        // The room map goes last: room ids contain ':' themselves.
+        let mut parts = s.splitn(3, ':');
+        if parts.next()? != "2" {
+            return None;
+        }
+        Some(Self {
+            now_ms: parts.next()?.parse().ok()?,
+            rooms: serde_json::from_str(parts.next()?).ok()?,
+        })
+    }
+}
+
+/// Result order: best score first, event id breaking ties so the order is
+/// total and a cursor can point into it.
+pub(crate) fn order(a: (f64, &str), b: (f64, &str)) -> std::cmp::Ordering {
+    b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1))
+}
+
+fn current_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Loads the event through the room's event cache, falling back to the
+/// server for events the cache no longer holds.
+async fn load_hit(room: &Room, event_id: &OwnedEventId, terms: &[String]) -> Option<SearchHit> {
+    let event = room.load_or_fetch_event(event_id, None).await.ok()?;
+    let AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+        SyncMessageLikeEvent::Original(msg),
+    )) = event.raw().deserialize().ok()?
+    else {
+        return None;
+    };
+    let body = msg.content.body().to_owned();
+    let sender_display_name = match room.get_member_no_sync(&msg.sender).await {
+        Ok(Some(m)) => m.display_name().map(str::to_owned),
+        _ => None,
+    };
+    Some(SearchHit {
+        room_id: room.room_id().to_string(),
+        event_id: event_id.to_string(),
+        sender: msg.sender.to_string(),
+        snippet: snippet(&body, terms),
+        body,
+        timestamp_ms: msg.origin_server_ts.0.into(),
+        sender_display_name,
+    })
+}
+
+pub(crate) fn matches_filter(filter: &SearchFilter, hit: &SearchHit) -> bool {
+    if let Some(senders) = &filter.senders {
+        if !senders.iter().any(|s| *s == hit.sender) {
+            return false;
+        }
+    }
+    if filter.after_ms.is_some_and(|t| hit.timestamp_ms < t) {
+        return false;
+    }
+    if filter.before_ms.is_some_and(|t| hit.timestamp_ms >= t) {
+        return false;
+    }
+    true
+}
+
+pub(crate) fn terms(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .map(|t| {
+            t.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Term coverage and phrase matches weigh most; the index's own order and
+/// recency (relative to `now_ms`) break near-ties.
+pub(crate) fn score(
+    terms: &[String],
+    query: &str,
+    hit: &SearchHit,
+    rank: usize,
+    now_ms: u64,
+) -> f64 {
+    let body = hit.body.to_lowercase();
+    let coverage = if terms.is_empty() {
+        0.0
+    } else {
+        terms.iter().filter(|t| body.contains(t.as_str())).count() as f64 / terms.len() as f64
+    };
+    let phrase = if terms.len() > 1 && body.contains(&query.trim().to_lowercase()) {
+        1.0
+    } else {
+        0.0
+    };
+    let index_rank = 1.0 / (1.0 + rank as f64);
+    let age_days = now_ms.saturating_sub(hit.timestamp_ms) as f64 / 86_400_000.0;
+    let recency = (-age_days / 30.0).exp();
+    2.0 * coverage + phrase + 0.5 * index_rank + 0.5 * recency
+}
+
+/// Char-index ranges of every term occurrence in `chars`, case-insensitive.
+pub(crate) fn find_terms(chars: &[char], terms: &[String]) -> Vec<(usize, usize)> {
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    let mut found = Vec::new();
+    for term in terms {
+        let needle: Vec<char> = term.chars().collect();
+        if needle.is_empty() || needle.len() > lower.len() {
+            continue;
+        }
+        for start in 0..=lower.len() - needle.len() {
+            if lower[start..start + needle.len()] == needle[..] {
+                found.push((start, start + needle.len()));
+            }
+        }
+    }
+    found.sort_unstable();
+    // Merge overlaps so highlights never nest.
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(found.len());
+    for (s, e) in found {
+        match merged.last_mut() {
+            Some(last) if s <= last.1 => last.1 = last.1.max(e),
+            _ => merged.push((s, e)),
+        }
+    }
+    merged
+}
+
+pub(crate) fn snippet(body: &str, terms: &[String]) -> Option<SearchSnippet> {
+    let chars: Vec<char> = body.chars().collect();
+    let matches = find_terms(&chars, terms);
+    let &(first, _) = matches.first()?;
+    let from = first.saturating_sub(SNIPPET_BEFORE);
+    let to = (first + SNIPPET_AFTER).min(chars.len());
+
+    let mut text = String::new();
+    if from > 0 {
+        text.push('…');
+    }
+    // UTF-16 offset of each char in the window, relative to `text`.
+    let mut utf16_at = Vec::with_capacity(to - from + 1);
+    let mut pos = text.encode_utf16().count();
+    for c in &chars[from..to] {
+        utf16_at.push(pos);
+        pos += c.len_utf16();
+        text.push(*c);
+    }
+    utf16_at.push(pos);
+    if to < chars.len() {
+        text.push('…');
+    }
+
+    let highlights = matches
+        .into_iter()
+        .filter(|&(s, e)| s >= from && e <= to)
+        .map(|(s, e)| TextRange {
+            start: utf16_at[s - from] as u32,
+            end: utf16_at[e - from] as u32,
+        })
+        .collect();
+    Some(SearchSnippet { text, highlights })
+}
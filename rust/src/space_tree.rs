@@ -0,0 +1,220 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use matrix_sdk::{
+    Client as SdkClient, Room,
+    deserialized_responses::SyncOrStrippedState,
+    ruma::{
+        OwnedRoomId, RoomId,
+        events::{
+            SyncStateEvent,
+            space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
+        },
+    },
+};
+
+use crate::{SpaceTree, SpaceTreeNode, UnreadStats};
+
+/// Rooms `space` lists through `m.space.child`. Children removed by an empty
+/// event (no `via`) are skipped.
+pub(crate) async fn child_ids(space: &Room) -> HashSet<OwnedRoomId> {
+    let Ok(events) = space
+        .get_state_events_static::<SpaceChildEventContent>()
+        .await
+    else {
+        return HashSet::new();
+    };
+    events
+        .into_iter()
+        .filter_map(|raw| match raw.deserialize().ok()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(ev))
+                if !ev.content.via.is_empty() =>
+            {
+                Some(ev.state_key)
+            }
+            SyncOrStrippedState::Stripped(ev) if !ev.content.via.is_empty() => Some(ev.state_key),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Spaces `room` claims as parents through `m.space.parent`.
+async fn parent_ids(room: &Room) -> Vec<OwnedRoomId> {
+    let Ok(events) = room
+        .get_state_events_static::<SpaceParentEventContent>()
+        .await
+    else {
+        return Vec::new();
+    };
+    events
+        .into_iter()
+        .filter_map(|raw| match raw.deserialize().ok()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(ev))
+                if !ev.content.via.is_empty() =>
+            {
+                Some(ev.state_key)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Direct children of every joined space, from both sides of the link:
+/// the space's `m.space.child` events and the rooms' `m.space.parent` ones.
+async fn children_by_space(sdk: &SdkClient) -> HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>> {
+    let mut children: HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>> = HashMap::new();
+    for space in sdk.joined_space_rooms() {
+        let ids = child_ids(&space).await;
+        children
+            .entry(space.room_id().to_owned())
+            .or_default()
+            .extend(ids);
+    }
+    for room in sdk.joined_rooms() {
+        for parent in parent_ids(&room).await {
+            if let Some(siblings) = children.get_mut(&parent) {
+                siblings.insert(room.room_id().to_owned());
+            }
+        }
+    }
+    children
+}
+
+/// The links between joined spaces and their rooms, with every room below
+/// each space worked out. Only `m.space.child` / `m.space.parent` events and
+/// joining or leaving a space change it, so observers keep one around and
+/// reload it just then.
+pub(crate) struct SpaceGraph {
+    children: HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>>,
+    below: HashMap<OwnedRoomId, HashSet<OwnedRoomId>>,
+}
+
+impl SpaceGraph {
+    pub(crate) async fn load(sdk: &SdkClient) -> Self {
+        Self::new(children_by_space(sdk).await)
+    }
+
+    pub(crate) fn new(children: HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>>) -> Self {
+        let below = children
+            .keys()
+            .map(|space_id| {
+                let mut out = HashSet::new();
+                collect_rooms(&children, space_id, &mut HashSet::new(), &mut out);
+                (space_id.clone(), out)
+            })
+            .collect();
+        Self { children, below }
+    }
+
+    /// False once a space was joined or left, which sends no link event.
+    pub(crate) fn covers(&self, sdk: &SdkClient) -> bool {
+        let joined = joined_space_ids(sdk);
+        joined.len() == self.children.len()
+            && joined.iter().all(|id| self.children.contains_key(id))
+    }
+}
+
+/// The spaces the graph is built from; a change means it is out of date.
+pub(crate) fn joined_space_ids(sdk: &SdkClient) -> BTreeSet<OwnedRoomId> {
+    sdk.joined_space_rooms()
+        .iter()
+        .map(|s| s.room_id().to_owned())
+        .collect()
+}
+
+/// Every room under `space_id`, through nested spaces too, including ones
+/// not joined yet so they show up as soon as they are. Nested spaces
+/// themselves are not included.
+pub(crate) async fn descendant_rooms(sdk: &SdkClient, space_id: &RoomId) -> HashSet<OwnedRoomId> {
+    let mut graph = SpaceGraph::load(sdk).await;
+    graph.below.remove(space_id).unwrap_or_default()
+}
+
+pub(crate) fn collect_rooms(
+    children: &HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>>,
+    space_id: &RoomId,
+    visited: &mut HashSet<OwnedRoomId>,
+    out: &mut HashSet<OwnedRoomId>,
+) {
+    // Space graphs may contain cycles.
+    if !visited.insert(space_id.to_owned()) {
+        return;
+    }
+    for child in children.get(space_id).into_iter().flatten() {
+        if children.contains_key(child) {
+            collect_rooms(children, child, visited, out);
+        } else {
+            out.insert(child.clone());
+        }
+    }
+}
+
+/// Flattened tree of the joined spaces in `graph`. Unread counts are summed
+/// over all rooms below each space, counting a room once even if it is
+/// reachable through several subspaces; they, names and avatars are read
+/// fresh on every call.
+pub(crate) async fn build(sdk: &SdkClient, graph: &SpaceGraph) -> SpaceTree {
+    let children = &graph.children;
+    let nested: HashSet<&OwnedRoomId> = children
+        .values()
+        .flatten()
+        .filter(|rid| children.contains_key(*rid))
+        .collect();
+
+    let mut spaces = Vec::with_capacity(children.len());
+    let mut root_spaces = Vec::new();
+    for space_id in children.keys() {
+        let Some(space) = sdk.get_room(space_id) else {
+            continue;
+        };
+        if !nested.contains(space_id) {
+            root_spaces.push(space_id.to_string());
+        }
+
+        let mut child_spaces = Vec::new();
+        let mut child_rooms = Vec::new();
+        for child in children.get(space_id).into_iter().flatten() {
+            if children.contains_key(child) {
+                child_spaces.push(child.to_string());
+            } else if sdk.get_room(child).is_some() {
+                child_rooms.push(child.to_string());
+            }
+        }
+
+        let mut unread = UnreadStats {
+            messages: 0,
+            notifications: 0,
+            mentions: 0,
+        };
+        let mut rooms_with_unread = 0u32;
+        let below = graph.below.get(space_id).into_iter().flatten();
+        for room in below.filter_map(|rid| sdk.get_room(rid)) {
+            unread.messages += room.num_unread_messages();
+            unread.notifications += room.num_unread_notifications();
+            unread.mentions += room.num_unread_mentions();
+            if room.num_unread_messages() > 0 || room.is_marked_unread() {
+                rooms_with_unread += 1;
+            }
+        }
+
+        let name = space
+            .display_name()
+            .await
+            .map(|d| d.to_string())
+            .unwrap_or_else(|_| space_id.to_string());
+        spaces.push(SpaceTreeNode {
+            space_id: space_id.to_string(),
+            name,
+            avatar_url: space.avatar_url().map(|mxc| mxc.to_string()),
+            child_spaces,
+            child_rooms,
+            unread,
+            rooms_with_unread,
+        });
+    }
+    spaces.sort_by(|a, b| a.space_id.cmp(&b.space_id));
+    root_spaces.sort();
+    SpaceTree {
+        root_spaces,
+        spaces,
+    }
+}
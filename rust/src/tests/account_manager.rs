@@ -0,0 +1,118 @@
+use std::{path::Path, sync::Arc};
+
+use super::*;
+use crate::{AccountManager, Client, FfiError, SessionInfo, platform};
+
+fn manager(base: &Path, provider: Option<u8>) -> AccountManager {
+    AccountManager::new(
+        base.to_string_lossy().into_owned(),
+        None,
+        provider.map(|b| Box::new(FixedKey(b)) as Box<dyn SessionKeyProvider>),
+    )
+}
+
+fn logged_in(mgr: &AccountManager, hs: &MockHomeserver, account_id: &str) -> Arc<Client> {
+    let client = mgr
+        .add_account(account_id.into(), hs.url(), None)
+        .expect("account opens against mock homeserver");
+    client
+        .login("alice".into(), "hunter2".into(), None)
+        .expect("login against mock homeserver");
+    client
+}
+
+fn session_text(base: &Path, account_id: &str) -> String {
+    std::fs::read_to_string(
+        base.join("accounts")
+            .join(format!("{account_id}.session.json")),
+    )
+    .unwrap()
+}
+
+#[test]
+fn list_accounts_covers_open_and_closed_accounts() {
+    let hs = MockHomeserver::start();
+    hs.mock_login();
+    let dir = tempfile::tempdir().unwrap();
+    let mgr = manager(dir.path(), None);
+    logged_in(&mgr, &hs, "work");
+
+    let listed = mgr.list_accounts();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].account_id, "work");
+    assert_eq!(listed[0].user_id.as_deref(), Some(USER_ID));
+    assert!(listed[0].is_open);
+    assert!(listed[0].is_token_valid);
+
+    assert!(mgr.close_account("work".into()));
+    let listed = mgr.list_accounts();
+    assert!(!listed[0].is_open);
+    assert_eq!(listed[0].user_id.as_deref(), Some(USER_ID));
+}
+
+#[test]
+fn migrate_sessions_seals_plaintext_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let store_dir = dir.path().join("accounts").join("home");
+    let info = SessionInfo {
+        user_id: USER_ID.into(),
+        device_id: DEVICE_ID.into(),
+        access_token: ACCESS_TOKEN.into(),
+        refresh_token: None,
+        homeserver: "http://localhost".into(),
+        auth_api: "matrix".into(),
+        client_id: None,
+        is_token_valid: true,
+    };
+    RT.block_on(platform::persist_session(&store_dir, &info))
+        .unwrap();
+
+    let mgr = manager(dir.path(), Some(7));
+    // Listing reads the plaintext file without rewriting it.
+    assert_eq!(mgr.list_accounts()[0].user_id.as_deref(), Some(USER_ID));
+    assert!(session_text(dir.path(), "home").contains(ACCESS_TOKEN));
+
+    mgr.migrate_sessions();
+    assert!(!session_text(dir.path(), "home").contains(ACCESS_TOKEN));
+    assert_eq!(mgr.list_accounts()[0].user_id.as_deref(), Some(USER_ID));
+    let wrong_key = manager(dir.path(), Some(8));
+    assert!(wrong_key.list_accounts()[0].user_id.is_none());
+}
+
+#[test]
+fn remove_account_signs_out_a_closed_account() {
+    let hs = MockHomeserver::start();
+    hs.mock_login();
+    hs.mock_logout(true);
+    let dir = tempfile::tempdir().unwrap();
+    let mgr = manager(dir.path(), None);
+    logged_in(&mgr, &hs, "work");
+    assert!(mgr.close_account("work".into()));
+
+    mgr.remove_account("work".into(), true).unwrap();
+    assert_eq!(hs.requests_matching("POST", "/logout").len(), 1);
+    assert!(mgr.list_accounts().is_empty());
+    assert!(!dir.path().join("accounts").join("work").exists());
+}
+
+#[test]
+fn failed_logout_keeps_the_account() {
+    let hs = MockHomeserver::start();
+    hs.mock_login();
+    hs.mock_logout(false);
+    let dir = tempfile::tempdir().unwrap();
+    let mgr = manager(dir.path(), None);
+    logged_in(&mgr, &hs, "work");
+
+    assert!(matches!(
+        mgr.remove_account("work".into(), true),
+        Err(FfiError::Forbidden(_))
+    ));
+    let listed = mgr.list_accounts();
+    assert_eq!(listed.len(), 1);
+    assert!(listed[0].is_open);
+
+    // Without signing out the account goes regardless.
+    mgr.remove_account("work".into(), false).unwrap();
+    assert!(mgr.list_accounts().is_empty());
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use super::*;
+use crate::platform;
+
+const WAIT: Duration = Duration::from_secs(10);
+
+#[test]
+fn login_persists_session_in_account_dir() {
+    let hs = MockHomeserver::start();
+    let tc = TestClient::logged_in(&hs);
+
+    assert!(tc.client.is_logged_in());
+    assert_eq!(tc.client.whoami().as_deref(), Some(USER_ID));
+    assert_eq!(
+        hs.requests_matching("POST", "/_matrix/client/v3/login")
+            .len(),
+        1
+    );
+
+    let info = RT
+        .block_on(platform::load_session(&tc.store_dir()))
+        .unwrap()
+        .expect("session persisted after login");
+    assert_eq!(info.user_id, USER_ID);
+    assert_eq!(info.device_id, DEVICE_ID);
+    assert_eq!(info.access_token, ACCESS_TOKEN);
+    assert_eq!(info.auth_api, "matrix");
+}
+
+#[test]
+fn send_message_reaches_homeserver() {
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync(vec![text_event(
+        "$e1",
+        "@bob:localhost",
+        "hello",
+        1_700_000_001_000,
+    )]);
+    hs.mock_send("$sent");
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+
+    tc.client
+        .send_message(ROOM_ID.into(), "hi bob".into(), None)
+        .expect("send_message queues the event");
+
+    assert!(
+        wait_until(WAIT, || {
+            hs.requests_matching("PUT", "/send/m.room.message/")
+                .iter()
+                .any(|r| String::from_utf8_lossy(&r.body).contains("hi bob"))
+        }),
+        "no PUT /send reached the mock homeserver"
+    );
+}
+
+#[test]
+fn paginate_backwards_loads_older_events() {
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync(vec![text_event(
+        "$e2",
+        "@bob:localhost",
+        "newest",
+        1_700_000_002_000,
+    )]);
+    hs.mock_messages(vec![text_event(
+        "$e1",
+        "@bob:localhost",
+        "oldest",
+        1_700_000_001_000,
+    )]);
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+
+    tc.client
+        .paginate_backwards(ROOM_ID.into(), 20)
+        .expect("paginate_backwards");
+
+    assert!(
+        wait_until(WAIT, || {
+            tc.client
+                .recent_events(ROOM_ID.into(), 50)
+                .iter()
+                .any(|e| e.body == "oldest")
+        }),
+        "paginated event missing from the timeline"
+    );
+    assert!(!hs.requests_matching("GET", "/messages").is_empty());
+}
+
+#[test]
+fn observe_timeline_emits_reset_then_live_diff() {
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync(vec![text_event(
+        "$e1",
+        "@bob:localhost",
+        "first",
+        1_700_000_001_000,
+    )]);
+    hs.mock_incremental_sync(
+        "s1",
+        "s2",
+        vec![text_event(
+            "$e2",
+            "@bob:localhost",
+            "second",
+            1_700_000_002_000,
+        )],
+    );
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+
+    let obs = RecordingTimeline::default();
+    let sub = tc
+        .client
+        .observe_timeline(ROOM_ID.into(), Box::new(obs.clone()));
+    assert_ne!(sub, 0);
+
+    assert!(
+        wait_until(WAIT, || obs.bodies().iter().any(|b| b == "first")),
+        "initial timeline never reached the observer"
+    );
+    assert!(matches!(
+        obs.diffs.lock().unwrap().first(),
+        Some(TimelineDiffKind::Reset { .. })
+    ));
+
+    tc.sync_once();
+    assert!(
+        wait_until(WAIT, || obs.bodies().iter().any(|b| b == "second")),
+        "live event never reached the observer"
+    );
+    assert!(obs.errors.lock().unwrap().is_empty());
+    assert!(tc.client.unobserve_timeline(sub));
+}
+
+#[test]
+fn observe_room_list_snapshots_joined_room() {
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync(vec![text_event(
+        "$e1",
+        "@bob:localhost",
+        "hello",
+        1_700_000_001_000,
+    )]);
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+
+    let obs = RecordingRoomList::default();
+    let token = tc.client.observe_room_list(Box::new(obs.clone()));
+
+    assert!(
+        wait_until(WAIT, || {
+            obs.latest()
+                .is_some_and(|items| items.iter().any(|r| r.room_id == ROOM_ID))
+        }),
+        "room list snapshot never contained the joined room"
+    );
+    let entry = obs
+        .latest()
+        .unwrap()
+        .into_iter()
+        .find(|r| r.room_id == ROOM_ID)
+        .unwrap();
+    assert_eq!(entry.name, "Test Room");
+    assert!(tc.client.unobserve_room_list(token));
+}
+
+#[test]
+fn apply_room_list_diff_keeps_entries_in_step() {
+    use matrix_sdk::ruma::RoomId;
+    use matrix_sdk_ui::{
+        eyeball_im::{Vector, VectorDiff},
+        room_list_service::RoomListItem,
+    };
+
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync(vec![text_event(
+        "$e1",
+        "@bob:localhost",
+        "hello",
+        1_700_000_001_000,
+    )]);
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+
+    let core = &tc.client.core;
+    let room = core
+        .sdk
+        .get_room(<&RoomId>::try_from(ROOM_ID).unwrap())
+        .expect("room from initial sync");
+    let item = || RoomListItem::from(room.clone());
+    let mut entries = Vec::<RoomListEntry>::new();
+    let mut apply = |diff| RT.block_on(core.apply_room_list_diff(&mut entries, diff));
+
+    let Some(RoomListDiff::Reset { values }) = apply(VectorDiff::Reset {
+        values: Vector::from(vec![item()]),
+    }) else {
+        panic!("reset was not passed through");
+    };
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].name, "Test Room");
+
+    assert!(matches!(
+        apply(VectorDiff::PushBack { value: item() }),
+        Some(RoomListDiff::Insert { index: 1, .. })
+    ));
+    assert!(matches!(
+        apply(VectorDiff::Set {
+            index: 0,
+            value: item()
+        }),
+        Some(RoomListDiff::Set { index: 0, .. })
+    ));
+    // Out of range: nothing to emit, nothing touched.
+    assert!(apply(VectorDiff::Remove { index: 5 }).is_none());
+    assert!(
+        apply(VectorDiff::Insert {
+            index: 5,
+            value: item()
+        })
+        .is_none()
+    );
+    assert!(matches!(
+        apply(VectorDiff::PopBack),
+        Some(RoomListDiff::Remove { index: 1 })
+    ));
+    assert!(matches!(
+        apply(VectorDiff::Truncate { length: 0 }),
+        Some(RoomListDiff::Truncate { length: 0 })
+    ));
+    assert!(matches!(
+        apply(VectorDiff::Clear),
+        Some(RoomListDiff::Clear)
+    ));
+    assert!(entries.is_empty());
+}
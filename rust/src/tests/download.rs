@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use matrix_sdk::ruma::MxcUri;
+use wiremock::matchers::header;
+
+use super::*;
+use crate::download::part_path;
+
+const MXC: &str = "mxc://localhost/media";
+const V1_PATH: &str = "/_matrix/client/v1/media/download/localhost/media";
+const V3_PATH: &str = "/_matrix/media/v3/download/localhost/media";
+
+fn media() -> Vec<u8> {
+    (0..1_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn dest(tc: &TestClient) -> PathBuf {
+    tc.base_dir.path().join("downloads").join("media.bin")
+}
+
+/// Leaves `bytes` behind as if an earlier attempt had stopped there.
+fn leave_part(dest: &Path, bytes: &[u8]) -> PathBuf {
+    let part = part_path(dest, <&MxcUri>::from(MXC));
+    std::fs::create_dir_all(part.parent().unwrap()).unwrap();
+    std::fs::write(&part, bytes).unwrap();
+    part
+}
+
+fn full_response() -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("content-type", "application/octet-stream")
+        .set_body_bytes(media())
+}
+
+fn download(tc: &TestClient, dest: &Path) -> crate::DownloadResult {
+    tc.client
+        .download_media(MXC.into(), dest.to_string_lossy().into_owned(), None)
+        .expect("download from mock homeserver")
+}
+
+#[test]
+fn download_resumes_a_partial_file_with_a_range_request() {
+    let hs = MockHomeserver::start();
+    let media = media();
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V1_PATH))
+            .and(header("range", "bytes=400-"))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("content-range", "bytes 400-999/1000")
+                    .set_body_bytes(media[400..].to_vec()),
+            ),
+    );
+    let tc = TestClient::logged_in(&hs);
+    let dest = dest(&tc);
+    let part = leave_part(&dest, &media[..400]);
+
+    let result = download(&tc, &dest);
+
+    assert_eq!(result.bytes, 1_000);
+    assert_eq!(std::fs::read(&dest).unwrap(), media);
+    assert!(!part.exists());
+    assert_eq!(hs.requests_matching("GET", V1_PATH).len(), 1);
+}
+
+#[test]
+fn download_starts_over_when_the_range_is_not_satisfiable() {
+    let hs = MockHomeserver::start();
+    // Mounted first, so it wins for ranged requests.
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V1_PATH))
+            .and(header("range", "bytes=1200-"))
+            .respond_with(ResponseTemplate::new(416)),
+    );
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V1_PATH))
+            .respond_with(full_response()),
+    );
+    let tc = TestClient::logged_in(&hs);
+    let dest = dest(&tc);
+    leave_part(&dest, &[7; 1_200]);
+
+    let result = download(&tc, &dest);
+
+    assert_eq!(result.bytes, 1_000);
+    assert_eq!(std::fs::read(&dest).unwrap(), media());
+    let requests = hs.requests_matching("GET", V1_PATH);
+    assert_eq!(requests.len(), 2);
+    assert!(!requests[1].headers.contains_key("range"));
+}
+
+#[test]
+fn download_starts_over_when_the_server_sends_another_range() {
+    let hs = MockHomeserver::start();
+    let media = media();
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V1_PATH))
+            .and(header("range", "bytes=400-"))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("content-range", "bytes 0-999/1000")
+                    .set_body_bytes(media.clone()),
+            ),
+    );
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V1_PATH))
+            .respond_with(full_response()),
+    );
+    let tc = TestClient::logged_in(&hs);
+    let dest = dest(&tc);
+    leave_part(&dest, &[7; 400]);
+
+    let result = download(&tc, &dest);
+
+    assert_eq!(result.bytes, 1_000);
+    assert_eq!(std::fs::read(&dest).unwrap(), media);
+    assert_eq!(hs.requests_matching("GET", V1_PATH).len(), 2);
+}
+
+#[test]
+fn concurrent_downloads_of_the_same_media_share_one_request() {
+    let hs = MockHomeserver::start();
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V1_PATH))
+            .respond_with(full_response().set_delay(Duration::from_millis(500))),
+    );
+    let tc = TestClient::logged_in(&hs);
+    let dest = dest(&tc);
+
+    let (a, b) = std::thread::scope(|s| {
+        let a = s.spawn(|| download(&tc, &dest));
+        let b = s.spawn(|| download(&tc, &dest));
+        (a.join().unwrap(), b.join().unwrap())
+    });
+
+    assert_eq!((a.bytes, b.bytes), (1_000, 1_000));
+    assert_eq!(a.path, b.path);
+    assert_eq!(std::fs::read(&dest).unwrap(), media());
+    assert_eq!(hs.requests_matching("GET", V1_PATH).len(), 1);
+}
+
+#[test]
+fn download_falls_back_to_legacy_media_endpoints() {
+    let hs = MockHomeserver::start();
+    hs.mount(Mock::given(method("GET")).and(path(V1_PATH)).respond_with(
+        ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_UNRECOGNIZED",
+            "error": "Unrecognized request",
+        })),
+    ));
+    hs.mount(
+        Mock::given(method("GET"))
+            .and(path(V3_PATH))
+            .respond_with(full_response()),
+    );
+    let tc = TestClient::logged_in(&hs);
+    let dest = dest(&tc);
+
+    let result = download(&tc, &dest);
+
+    assert_eq!(result.bytes, 1_000);
+    assert_eq!(std::fs::read(&dest).unwrap(), media());
+    assert_eq!(hs.requests_matching("GET", V1_PATH).len(), 1);
+    assert_eq!(hs.requests_matching("GET", V3_PATH).len(), 1);
+}
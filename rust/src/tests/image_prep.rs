@@ -0,0 +1,233 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, RgbImage};
+
+use crate::{
+    ImageUploadOptions, ImageUploadQuality,
+    image_prep::{MAX_PREPARE_BYTES, prepare, strip_jpeg, strip_png, strip_webp},
+};
+
+const EXIF_TIFF: &[u8] = b"MM\0*\0\0\0\x08\0\0";
+const XMP: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+
+fn jpeg_segment(marker: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0xFF, marker];
+    out.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc32(&out[4..]).to_be_bytes());
+    out
+}
+
+fn riff_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut out = kind.to_vec();
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    out
+}
+
+fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let body: Vec<u8> = chunks.concat();
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&body);
+    out
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn strip_jpeg_drops_exif_xmp_and_comments_only() {
+    let jfif = jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    let icc = jpeg_segment(0xE2, b"ICC_PROFILE\0\x01\x01");
+    let scan = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9];
+    let mut exif_data = b"Exif\0\0".to_vec();
+    exif_data.extend_from_slice(EXIF_TIFF);
+    let mut xmp_data = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+    xmp_data.extend_from_slice(XMP);
+
+    let input = [
+        vec![0xFF, 0xD8],
+        jfif.clone(),
+        jpeg_segment(0xE1, &exif_data),
+        jpeg_segment(0xE1, &xmp_data),
+        jpeg_segment(0xED, b"Photoshop 3.0\0"),
+        jpeg_segment(0xFE, b"taken at home"),
+        icc.clone(),
+        scan.to_vec(),
+    ]
+    .concat();
+
+    let expected = [vec![0xFF, 0xD8], jfif, icc, scan.to_vec()].concat();
+    assert_eq!(strip_jpeg(&input).unwrap(), expected);
+}
+
+#[test]
+fn strip_jpeg_rejects_broken_segments() {
+    assert!(strip_jpeg(b"not a jpeg").is_none());
+    // APP1 claims more bytes than the file has.
+    assert!(strip_jpeg(&[0xFF, 0xD8, 0xFF, 0xE1, 0x40, 0x00, 0x00]).is_none());
+}
+
+#[test]
+fn strip_png_drops_exif_text_and_time() {
+    let sig = b"\x89PNG\r\n\x1a\n".to_vec();
+    let ihdr = png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+    let idat = png_chunk(b"IDAT", &[0x78, 0x9C]);
+    let iend = png_chunk(b"IEND", &[]);
+    let mut itxt = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+    itxt.extend_from_slice(XMP);
+
+    let input = [
+        sig.clone(),
+        ihdr.clone(),
+        png_chunk(b"eXIf", EXIF_TIFF),
+        png_chunk(b"iTXt", &itxt),
+        png_chunk(b"tEXt", b"Comment\0hello"),
+        png_chunk(b"tIME", &[0x07, 0xEA, 1, 1, 0, 0, 0]),
+        idat.clone(),
+        iend.clone(),
+    ]
+    .concat();
+
+    let expected = [sig, ihdr, idat, iend].concat();
+    assert_eq!(strip_png(&input).unwrap(), expected);
+    assert!(strip_png(&input[..input.len() - 3]).is_none());
+}
+
+#[test]
+fn strip_webp_drops_chunks_and_clears_vp8x_flags() {
+    // Alpha (0x10) stays, EXIF (0x08) and XMP (0x04) go.
+    let vp8x = riff_chunk(b"VP8X", &[0x1C, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    // Odd length, so the chunk carries a padding byte.
+    let image = riff_chunk(b"VP8 ", &[1, 2, 3]);
+    let input = webp(&[
+        vp8x,
+        image.clone(),
+        riff_chunk(b"EXIF", EXIF_TIFF),
+        riff_chunk(b"XMP ", XMP),
+    ]);
+
+    let out = strip_webp(&input).unwrap();
+    let expected = webp(&[
+        riff_chunk(b"VP8X", &[0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+        image,
+    ]);
+    assert_eq!(out, expected);
+    assert_eq!(
+        u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize,
+        out.len() - 8
+    );
+}
+
+#[test]
+fn strip_rejects_lengths_past_the_end_without_panicking() {
+    let sig = b"\x89PNG\r\n\x1a\n".to_vec();
+    let huge = [sig.clone(), vec![0xFF; 4], b"tEXt".to_vec()].concat();
+    assert!(strip_png(&huge).is_none());
+
+    let huge = webp(&[[b"EXIF".as_slice(), &[0xFF; 4]].concat()]);
+    assert!(strip_webp(&huge).is_none());
+    // A VP8X chunk without room for its flags byte.
+    let empty_vp8x = webp(&[riff_chunk(b"VP8X", &[])]);
+    assert!(strip_webp(&empty_vp8x).is_none());
+}
+
+#[test]
+fn prepare_goes_by_contents_not_the_file_name() {
+    let mut png = Vec::new();
+    RgbImage::from_pixel(4, 4, image::Rgb([200, 10, 10]))
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    // Right after the signature and IHDR.
+    let at = 8 + 25;
+    let with_exif = [&png[..at], &png_chunk(b"eXIf", EXIF_TIFF), &png[at..]].concat();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("photo.jpg");
+    std::fs::write(&path, &with_exif).unwrap();
+
+    let prepared = prepare(&path, &ImageUploadOptions::default())
+        .unwrap()
+        .expect("metadata to strip");
+    assert_eq!(prepared.mime, mime::IMAGE_PNG);
+    assert!(!contains(&prepared.bytes, b"eXIf"));
+    assert_eq!(
+        prepared.saved_bytes,
+        (with_exif.len() - prepared.bytes.len()) as u64
+    );
+}
+
+#[test]
+fn prepare_fails_rather_than_send_metadata() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("broken.jpg");
+    // Sniffs as JPEG, but neither strips nor decodes.
+    let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x40, 0x00];
+    bytes.extend_from_slice(b"Exif\0\0");
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(prepare(&path, &ImageUploadOptions::default()).is_err());
+
+    let keep = ImageUploadOptions {
+        strip_metadata: false,
+        quality: ImageUploadQuality::Optimised,
+        ..ImageUploadOptions::default()
+    };
+    assert!(prepare(&path, &keep).unwrap().is_none());
+
+    std::fs::write(&path, b"plain text").unwrap();
+    assert!(
+        prepare(&path, &ImageUploadOptions::default())
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[test]
+fn prepare_refuses_oversized_images() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("huge.png");
+    std::fs::write(&path, b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR").unwrap();
+    // Sparse, so the test does not write the whole file.
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(MAX_PREPARE_BYTES + 1)
+        .unwrap();
+
+    assert!(prepare(&path, &ImageUploadOptions::default()).is_err());
+    let keep = ImageUploadOptions {
+        strip_metadata: false,
+        quality: ImageUploadQuality::Optimised,
+        ..ImageUploadOptions::default()
+    };
+    assert!(prepare(&path, &keep).unwrap().is_none());
+}
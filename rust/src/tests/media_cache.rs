@@ -0,0 +1,144 @@
+use std::{
+    fs::File,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use tempfile::TempDir;
+
+use crate::{MediaCachePolicy, MediaClearScope, download::InFlight, media_cache::MediaCache};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// A `bytes`-long file last modified `age` ago, which is the access time the
+/// cache adopts for files it finds on disk.
+fn file(dir: &TempDir, name: &str, bytes: usize, age: Duration) -> PathBuf {
+    let path = dir.path().join(name);
+    std::fs::write(&path, vec![0u8; bytes]).unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() - age)
+        .unwrap();
+    path
+}
+
+fn unbounded() -> MediaCachePolicy {
+    MediaCachePolicy {
+        max_bytes: None,
+        max_age_ms: None,
+    }
+}
+
+#[test]
+fn reconcile_skips_the_index_and_its_temp_file() {
+    let dir = tempfile::tempdir().unwrap();
+    file(&dir, "dl_a.bin", 10, HOUR);
+    let cache = MediaCache::open(dir.path().to_owned());
+    cache.flush();
+    // Left behind by a save that died before the rename.
+    file(&dir, "index.tmp", 1_000, HOUR);
+
+    let overview = cache.overview();
+    assert!(dir.path().join("index.json").exists());
+    assert_eq!(overview.file_count, 1);
+    assert_eq!(overview.total_bytes, 10);
+}
+
+#[test]
+fn maintain_evicts_by_age() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = file(&dir, "dl_old.bin", 10, 10 * 24 * HOUR);
+    let fresh = file(&dir, "dl_fresh.bin", 10, HOUR);
+    let cache = MediaCache::open(dir.path().to_owned());
+
+    cache.set_policy(MediaCachePolicy {
+        max_bytes: None,
+        max_age_ms: Some(24 * 60 * 60 * 1000),
+    });
+    assert!(!old.exists());
+    assert!(fresh.exists());
+    assert_eq!(cache.overview().total_bytes, 10);
+}
+
+#[test]
+fn maintain_evicts_least_recently_used_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = file(&dir, "dl_a.bin", 100, 3 * HOUR);
+    let b = file(&dir, "dl_b.bin", 100, 2 * HOUR);
+    let c = file(&dir, "dl_c.bin", 100, HOUR);
+    let cache = MediaCache::open(dir.path().to_owned());
+    cache.set_policy(unbounded());
+    // Opening `a` makes it the most recent.
+    assert_eq!(cache.get(&a), Some(100));
+
+    cache.set_policy(MediaCachePolicy {
+        max_bytes: Some(200),
+        max_age_ms: None,
+    });
+    assert!(a.exists());
+    assert!(!b.exists());
+    assert!(c.exists());
+}
+
+#[test]
+fn pinned_files_survive_clear_and_eviction() {
+    let dir = tempfile::tempdir().unwrap();
+    let keep = file(&dir, "dl_keep.bin", 100, 10 * 24 * HOUR);
+    let evicted = file(&dir, "dl_evicted.bin", 100, HOUR);
+    let cache = MediaCache::open(dir.path().to_owned());
+    cache.set_policy(unbounded());
+
+    assert!(cache.set_pinned(&keep, true));
+    assert!(!cache.set_pinned(&dir.path().join("missing.bin"), true));
+    cache.set_policy(MediaCachePolicy {
+        max_bytes: Some(0),
+        max_age_ms: Some(1),
+    });
+    assert!(keep.exists());
+    assert!(!evicted.exists());
+
+    cache.clear(MediaClearScope::All);
+    assert!(keep.exists());
+    assert_eq!(cache.overview().pinned_bytes, 100);
+
+    assert!(cache.set_pinned(&keep, false));
+    assert_eq!(cache.clear(MediaClearScope::All), 100);
+    assert!(!keep.exists());
+}
+
+#[test]
+fn temporary_clear_keeps_thumbnails() {
+    let dir = tempfile::tempdir().unwrap();
+    let thumb = file(&dir, "thumb_a_64x64_scale.jpg", 10, HOUR);
+    let download = file(&dir, "dl_a.bin", 20, HOUR);
+    let partial = file(&dir, "dl_b.bin.0123abcd.part", 30, HOUR);
+    let cache = MediaCache::open(dir.path().to_owned());
+    cache.set_policy(unbounded());
+
+    assert_eq!(cache.clear(MediaClearScope::Temporary), 50);
+    assert!(thumb.exists());
+    assert!(!download.exists());
+    assert!(!partial.exists());
+}
+
+#[test]
+fn running_downloads_keep_their_partial_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let partial = file(&dir, "dl_a.bin.0123abcd.part", 30, 10 * 24 * HOUR);
+    let cache = MediaCache::open(dir.path().to_owned());
+    cache.set_policy(unbounded());
+
+    let running = InFlight::join(&partial);
+    assert_eq!(cache.clear(MediaClearScope::Temporary), 0);
+    cache.set_policy(MediaCachePolicy {
+        max_bytes: Some(0),
+        max_age_ms: Some(1),
+    });
+    assert!(partial.exists());
+
+    drop(running);
+    assert_eq!(cache.clear(MediaClearScope::All), 30);
+    assert!(!partial.exists());
+}
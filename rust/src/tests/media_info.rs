@@ -0,0 +1,192 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use image::{ImageFormat, RgbImage};
+use tempfile::TempDir;
+
+use crate::media_info::{boxes, probe_image, probe_mp4, probe_poster, read_mvhd, read_tkhd_size};
+
+const IDENTITY: [i32; 9] = [0x1_0000, 0, 0, 0, 0x1_0000, 0, 0, 0, 0x4000_0000];
+const ROTATE_90: [i32; 9] = [0, 0x1_0000, 0, -0x1_0000, 0, 0, 0, 0, 0x4000_0000];
+const ROTATE_180: [i32; 9] = [-0x1_0000, 0, 0, 0, -0x1_0000, 0, 0, 0, 0x4000_0000];
+const ROTATE_270: [i32; 9] = [0, -0x1_0000, 0, 0x1_0000, 0, 0, 0, 0, 0x4000_0000];
+
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+    out
+}
+
+fn mvhd_v0(timescale: u32, duration: u32) -> Vec<u8> {
+    let mut p = vec![0u8; 12];
+    p.extend_from_slice(&timescale.to_be_bytes());
+    p.extend_from_slice(&duration.to_be_bytes());
+    p.extend_from_slice(&[0u8; 80]);
+    mp4_box(b"mvhd", &p)
+}
+
+fn mvhd_v1(timescale: u32, duration: u64) -> Vec<u8> {
+    let mut p = vec![1u8, 0, 0, 0];
+    p.extend_from_slice(&[0u8; 16]);
+    p.extend_from_slice(&timescale.to_be_bytes());
+    p.extend_from_slice(&duration.to_be_bytes());
+    p.extend_from_slice(&[0u8; 80]);
+    mp4_box(b"mvhd", &p)
+}
+
+fn tkhd(matrix: [i32; 9], width: u32, height: u32) -> Vec<u8> {
+    let mut p = vec![0u8; 40];
+    for entry in matrix {
+        p.extend_from_slice(&entry.to_be_bytes());
+    }
+    p.extend_from_slice(&(width << 16).to_be_bytes());
+    p.extend_from_slice(&(height << 16).to_be_bytes());
+    mp4_box(b"tkhd", &p)
+}
+
+fn write(dir: &TempDir, name: &str, bytes: &[u8]) -> PathBuf {
+    let path = dir.path().join(name);
+    File::create(&path).unwrap().write_all(bytes).unwrap();
+    path
+}
+
+fn video(matrix: [i32; 9]) -> Vec<u8> {
+    let audio = mp4_box(b"trak", &tkhd(IDENTITY, 0, 0));
+    let picture = mp4_box(b"trak", &tkhd(matrix, 1920, 1080));
+    let moov = [mvhd_v0(600, 1_500), audio, picture].concat();
+    [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"moov", &moov)].concat()
+}
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]))
+        .write_to(&mut std::io::Cursor::new(&mut out), ImageFormat::Png)
+        .unwrap();
+    out
+}
+
+#[test]
+fn boxes_walks_siblings_and_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    // size 1: 64-bit size follows the type; size 0: runs to the end.
+    let mut large = 1u32.to_be_bytes().to_vec();
+    large.extend_from_slice(b"wide");
+    large.extend_from_slice(&20u64.to_be_bytes());
+    large.extend_from_slice(&[7u8; 4]);
+    let mut rest = 0u32.to_be_bytes().to_vec();
+    rest.extend_from_slice(b"mdat");
+    rest.extend_from_slice(&[9u8; 10]);
+    let bytes = [mp4_box(b"ftyp", b"isom"), large, rest].concat();
+    let path = write(&dir, "boxes.mp4", &bytes);
+
+    let mut file = File::open(&path).unwrap();
+    let found = boxes(&mut file, 0, bytes.len() as u64);
+    let kinds: Vec<_> = found.iter().map(|b| b.kind).collect();
+    assert_eq!(kinds, vec![*b"ftyp", *b"wide", *b"mdat"]);
+    let ranges: Vec<_> = found.iter().map(|b| (b.start, b.end)).collect();
+    assert_eq!(ranges, vec![(8, 12), (28, 32), (40, 50)]);
+}
+
+#[test]
+fn boxes_stops_at_sizes_that_do_not_fit() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut huge = 1u32.to_be_bytes().to_vec();
+    huge.extend_from_slice(b"huge");
+    huge.extend_from_slice(&u64::MAX.to_be_bytes());
+    let mut short = 4u32.to_be_bytes().to_vec();
+    short.extend_from_slice(b"tiny");
+    let mut past_end = 100u32.to_be_bytes().to_vec();
+    past_end.extend_from_slice(b"cut!");
+
+    for (name, tail) in [("huge", huge), ("short", short), ("past_end", past_end)] {
+        let bytes = [mp4_box(b"ftyp", b"isom"), tail].concat();
+        let path = write(&dir, name, &bytes);
+        let mut file = File::open(&path).unwrap();
+        let found = boxes(&mut file, 0, bytes.len() as u64);
+        assert_eq!(found.len(), 1, "{name}");
+        assert_eq!(&found[0].kind, b"ftyp");
+    }
+}
+
+#[test]
+fn read_mvhd_handles_both_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, bytes, ms) in [
+        ("v0", mvhd_v0(1_000, 5_000), Some(5_000)),
+        ("v1", mvhd_v1(90_000, 90_000 * 125), Some(125_000)),
+        ("no_timescale", mvhd_v0(0, 5_000), None),
+    ] {
+        let path = write(&dir, name, &bytes);
+        let mut file = File::open(&path).unwrap();
+        let mvhd = boxes(&mut file, 0, bytes.len() as u64).remove(0);
+        assert_eq!(read_mvhd(&mut file, &mvhd), ms, "{name}");
+    }
+}
+
+#[test]
+fn read_tkhd_size_swaps_for_quarter_turns() {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, matrix, size) in [
+        ("identity", IDENTITY, (640, 480)),
+        ("rotate_90", ROTATE_90, (480, 640)),
+        ("rotate_180", ROTATE_180, (640, 480)),
+        ("rotate_270", ROTATE_270, (480, 640)),
+    ] {
+        let bytes = tkhd(matrix, 640, 480);
+        let path = write(&dir, name, &bytes);
+        let mut file = File::open(&path).unwrap();
+        let tkhd = boxes(&mut file, 0, bytes.len() as u64).remove(0);
+        assert_eq!(read_tkhd_size(&mut file, &tkhd), Some(size), "{name}");
+    }
+}
+
+#[test]
+fn probe_mp4_reads_the_picture_track() {
+    let dir = tempfile::tempdir().unwrap();
+    let meta = probe_mp4(&write(&dir, "landscape.mp4", &video(IDENTITY))).unwrap();
+    assert_eq!((meta.width, meta.height), (Some(1920), Some(1080)));
+    assert_eq!(meta.duration_ms, Some(2_500));
+
+    let meta = probe_mp4(&write(&dir, "portrait.mp4", &video(ROTATE_90))).unwrap();
+    assert_eq!((meta.width, meta.height), (Some(1080), Some(1920)));
+
+    assert!(probe_mp4(&write(&dir, "empty.mp4", &mp4_box(b"ftyp", b"isom"))).is_none());
+}
+
+#[test]
+fn probe_image_thumbnails_only_large_images() {
+    let dir = tempfile::tempdir().unwrap();
+    let meta = probe_image(&write(&dir, "wide.png", &png(1_600, 300))).unwrap();
+    assert_eq!((meta.width, meta.height), (1_600, 300));
+    assert!(meta.blurhash.is_some());
+    let thumb = meta.thumbnail.unwrap();
+    assert_eq!((thumb.width, thumb.height), (800, 150));
+    assert_eq!(
+        image::guess_format(&thumb.bytes).unwrap(),
+        ImageFormat::Jpeg
+    );
+
+    let meta = probe_image(&write(&dir, "small.png", &png(40, 30))).unwrap();
+    assert_eq!((meta.width, meta.height), (40, 30));
+    assert!(meta.blurhash.is_some());
+    assert!(meta.thumbnail.is_none());
+
+    assert!(probe_image(&write(&dir, "text.png", b"not an image")).is_none());
+}
+
+#[test]
+fn probe_poster_always_gives_a_jpeg_thumbnail() {
+    let dir = tempfile::tempdir().unwrap();
+    let (thumb, blurhash) = probe_poster(&write(&dir, "poster.png", &png(320, 180))).unwrap();
+    assert_eq!((thumb.width, thumb.height), (320, 180));
+    assert_eq!(
+        image::guess_format(&thumb.bytes).unwrap(),
+        ImageFormat::Jpeg
+    );
+    assert!(blurhash.is_some());
+
+    let (thumb, _) = probe_poster(&write(&dir, "big.png", &png(1_920, 1_080))).unwrap();
+    assert_eq!((thumb.width, thumb.height), (800, 450));
+
+    assert!(probe_poster(&write(&dir, "text.png", b"not an image")).is_none());
+}
@@ -0,0 +1,436 @@
+//! In-process test harness: a wiremock stand-in for the homeserver plus a real
+//! `Client` built against it in a temp store dir. Everything runs on loopback,
+//! so the suite needs no network access.
+
+mod account_manager;
+mod client;
+mod download;
+mod image_prep;
+mod media_cache;
+mod media_info;
+mod rekey;
+mod room_list_sort;
+mod search;
+mod session_file;
+mod space_tree;
+mod upload;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::config::SyncSettings;
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use wiremock::{
+    Mock, MockServer, Request, ResponseTemplate,
+    matchers::{method, path, path_regex, query_param, query_param_is_missing},
+};
+
+use crate::{
+    Client, RT, RoomListDiff, RoomListEntry, RoomListObserver, SessionKeyProvider, SessionSecret,
+    TimelineDiffKind, TimelineObserver,
+};
+
+pub(crate) const USER_ID: &str = "@alice:localhost";
+pub(crate) const DEVICE_ID: &str = "MAGESTEST";
+pub(crate) const ROOM_ID: &str = "!room:localhost";
+pub(crate) const ACCESS_TOKEN: &str = "syt_test_token";
+
+pub(crate) struct MockHomeserver {
+    pub server: MockServer,
+}
+
+impl MockHomeserver {
+    /// Starts the server with the endpoints every logged-in client touches
+    /// (versions, key upload/query, sliding sync) already mounted.
+    pub fn start() -> Self {
+        let server = RT.block_on(MockServer::start());
+        let this = Self { server };
+        this.mount_baseline();
+        this
+    }
+
+    pub fn url(&self) -> String {
+        self.server.uri()
+    }
+
+    fn mount(&self, mock: Mock) {
+        RT.block_on(mock.mount(&self.server));
+    }
+
+    fn mount_baseline(&self) {
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path("/_matrix/client/versions"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "versions": ["v1.1", "v1.5", "v1.11"],
+                    "unstable_features": { "org.matrix.simplified_msc3575": true },
+                }))),
+        );
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/_matrix/client/v3/keys/upload"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "one_time_key_counts": { "signed_curve25519": 50 },
+                }))),
+        );
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/_matrix/client/v3/keys/query"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "device_keys": {},
+                    "failures": {},
+                }))),
+        );
+        // The sync service is started by the send paths; keep it idle instead
+        // of letting it spin on 404s.
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path_regex(
+                    r"^/_matrix/client/unstable/org\.matrix\.simplified_msc3575/sync$",
+                ))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(
+                            json!({ "pos": "0", "lists": {}, "rooms": {}, "extensions": {} }),
+                        )
+                        .set_delay(Duration::from_millis(500)),
+                ),
+        );
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path_regex(
+                    r"^/_matrix/client/v3/rooms/[^/]+/state/m\.room\.encryption/?$",
+                ))
+                .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                    "errcode": "M_NOT_FOUND",
+                    "error": "Event not found.",
+                }))),
+        );
+    }
+
+    pub fn mock_login(&self) {
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/_matrix/client/v3/login"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "user_id": USER_ID,
+                    "access_token": ACCESS_TOKEN,
+                    "device_id": DEVICE_ID,
+                    "home_server": "localhost",
+                }))),
+        );
+    }
+
+    /// `POST /logout` succeeds, or fails with a non-retryable `M_FORBIDDEN`.
+    pub fn mock_logout(&self, ok: bool) {
+        let response = if ok {
+            ResponseTemplate::new(200).set_body_json(json!({}))
+        } else {
+            ResponseTemplate::new(403).set_body_json(json!({
+                "errcode": "M_FORBIDDEN",
+                "error": "Logout refused.",
+            }))
+        };
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/_matrix/client/v3/logout"))
+                .respond_with(response),
+        );
+    }
+
+    /// First `/sync` (no `since`) joins [`ROOM_ID`] with the given timeline.
+    pub fn mock_initial_sync(&self, timeline: Vec<Value>) {
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path("/_matrix/client/v3/sync"))
+                .and(query_param_is_missing("since"))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(joined_room_sync("s1", timeline, true)),
+                ),
+        );
+    }
+
+    /// First `/sync` (no `since`) returns `body` as is, for room layouts
+    /// beyond the single [`ROOM_ID`].
+    pub fn mock_initial_sync_body(&self, body: Value) {
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path("/_matrix/client/v3/sync"))
+                .and(query_param_is_missing("since"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body)),
+        );
+    }
+
+    /// `/sync?since=<since>` returns `timeline` as new events and `next` as the token.
+    pub fn mock_incremental_sync(&self, since: &str, next: &str, timeline: Vec<Value>) {
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path("/_matrix/client/v3/sync"))
+                .and(query_param("since", since))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(joined_room_sync(next, timeline, false)),
+                ),
+        );
+    }
+
+    pub fn mock_send(&self, event_id: &str) {
+        self.mount(
+            Mock::given(method("PUT"))
+                .and(path_regex(
+                    r"^/_matrix/client/v3/rooms/[^/]+/send/m\.room\.message/[^/]+$",
+                ))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(json!({ "event_id": event_id })),
+                ),
+        );
+    }
+
+    /// `POST /_matrix/media/v3/upload` answers with `content_uri`.
+    pub fn mock_upload(&self, content_uri: &str) {
+        self.mount(
+            Mock::given(method("POST"))
+                .and(path("/_matrix/media/v3/upload"))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(json!({ "content_uri": content_uri })),
+                ),
+        );
+    }
+
+    /// `/messages` returns `chunk` (newest first) and no `end`, i.e. start of room.
+    pub fn mock_messages(&self, chunk: Vec<Value>) {
+        self.mount(
+            Mock::given(method("GET"))
+                .and(path_regex(r"^/_matrix/client/v3/rooms/[^/]+/messages$"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "start": "p0",
+                    "chunk": chunk,
+                    "state": [],
+                }))),
+        );
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        RT.block_on(self.server.received_requests())
+            .unwrap_or_default()
+    }
+
+    pub fn requests_matching(&self, verb: &str, path_fragment: &str) -> Vec<Request> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method.as_str() == verb && r.url.path().contains(path_fragment))
+            .collect()
+    }
+}
+
+pub(crate) struct TestClient {
+    pub client: Client,
+    pub base_dir: TempDir,
+}
+
+impl TestClient {
+    pub fn new(hs: &MockHomeserver) -> Self {
+        let base_dir = tempfile::tempdir().expect("tempdir");
+        let client = Client::new(
+            hs.url(),
+            base_dir.path().to_string_lossy().to_string(),
+            Some("alice".into()),
+            None,
+            None,
+        )
+        .expect("client builds against mock homeserver");
+        Self { client, base_dir }
+    }
+
+    pub fn logged_in(hs: &MockHomeserver) -> Self {
+        hs.mock_login();
+        let this = Self::new(hs);
+        this.client
+            .login("alice".into(), "hunter2".into(), Some("Mages tests".into()))
+            .expect("login against mock homeserver");
+        this
+    }
+
+    pub fn store_dir(&self) -> std::path::PathBuf {
+        self.base_dir.path().join("accounts").join("alice")
+    }
+
+    pub fn sync_once(&self) {
+        RT.block_on(
+            self.client
+                .core
+                .sdk
+                .sync_once(SyncSettings::default().timeout(Duration::ZERO)),
+        )
+        .expect("sync_once against mock homeserver");
+    }
+}
+
+pub(crate) fn wait_until(timeout: Duration, mut cond: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if cond() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    cond()
+}
+
+pub(crate) fn text_event(event_id: &str, sender: &str, body: &str, ts: u64) -> Value {
+    json!({
+        "type": "m.room.message",
+        "event_id": event_id,
+        "sender": sender,
+        "origin_server_ts": ts,
+        "content": { "msgtype": "m.text", "body": body },
+    })
+}
+
+fn state_event(ty: &str, state_key: &str, sender: &str, content: Value) -> Value {
+    json!({
+        "type": ty,
+        "state_key": state_key,
+        "event_id": format!("$state-{ty}-{state_key}"),
+        "sender": sender,
+        "origin_server_ts": 1_700_000_000_000u64,
+        "content": content,
+    })
+}
+
+fn joined_room_sync(next_batch: &str, timeline: Vec<Value>, initial: bool) -> Value {
+    let state = if initial {
+        vec![
+            state_event(
+                "m.room.create",
+                "",
+                USER_ID,
+                json!({ "room_version": "10" }),
+            ),
+            state_event(
+                "m.room.member",
+                USER_ID,
+                USER_ID,
+                json!({ "membership": "join" }),
+            ),
+            state_event(
+                "m.room.member",
+                "@bob:localhost",
+                "@bob:localhost",
+                json!({ "membership": "join", "displayname": "Bob" }),
+            ),
+            state_event("m.room.name", "", USER_ID, json!({ "name": "Test Room" })),
+        ]
+    } else {
+        Vec::new()
+    };
+    json!({
+        "next_batch": next_batch,
+        "rooms": {
+            "join": {
+                ROOM_ID: {
+                    "state": { "events": state },
+                    "timeline": { "events": timeline, "limited": false, "prev_batch": "p1" },
+                    "ephemeral": { "events": [] },
+                    "account_data": { "events": [] },
+                    "summary": { "m.joined_member_count": 2, "m.invited_member_count": 0 },
+                    "unread_notifications": { "notification_count": 0, "highlight_count": 0 },
+                }
+            }
+        },
+    })
+}
+
+/// A 32-byte host key made of `byte`.
+pub(crate) fn key(byte: u8) -> Option<SessionSecret> {
+    Some(SessionSecret::Key {
+        key: vec![byte; 32],
+    })
+}
+
+/// Hands every account the same [`key`].
+pub(crate) struct FixedKey(pub u8);
+
+impl SessionKeyProvider for FixedKey {
+    fn session_secret(&self, _account_key: String) -> Option<SessionSecret> {
+        key(self.0)
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct RecordingTimeline {
+    pub diffs: Arc<Mutex<Vec<TimelineDiffKind>>>,
+    pub errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl RecordingTimeline {
+    /// Every body the observer has been shown, in arrival order.
+    pub fn bodies(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        for diff in self.diffs.lock().unwrap().iter() {
+            match diff {
+                TimelineDiffKind::Append { values } | TimelineDiffKind::Reset { values } => {
+                    out.extend(values.iter().map(|v| v.body.clone()))
+                }
+                TimelineDiffKind::PushBack { value }
+                | TimelineDiffKind::PushFront { value }
+                | TimelineDiffKind::UpdateByItemId { value, .. }
+                | TimelineDiffKind::UpsertByItemId { value, .. } => out.push(value.body.clone()),
+                _ => {}
+            }
+        }
+        out
+    }
+}
+
+impl TimelineObserver for RecordingTimeline {
+    fn on_diff(&self, diff: TimelineDiffKind) {
+        self.diffs.lock().unwrap().push(diff);
+    }
+    fn on_error(&self, message: String) {
+        self.errors.lock().unwrap().push(message);
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct RecordingRoomList {
+    pub resets: Arc<Mutex<Vec<Vec<RoomListEntry>>>>,
+    pub updates: Arc<Mutex<Vec<RoomListEntry>>>,
+    /// The last reset with every later diff applied.
+    pub current: Arc<Mutex<Option<Vec<RoomListEntry>>>>,
+}
+
+impl RecordingRoomList {
+    pub fn latest(&self) -> Option<Vec<RoomListEntry>> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+impl RoomListObserver for RecordingRoomList {
+    fn on_reset(&self, items: Vec<RoomListEntry>) {
+        *self.current.lock().unwrap() = Some(items.clone());
+        self.resets.lock().unwrap().push(items);
+    }
+    fn on_update(&self, item: RoomListEntry) {
+        self.updates.lock().unwrap().push(item);
+    }
+    fn on_diff(&self, diff: RoomListDiff) {
+        let mut current = self.current.lock().unwrap();
+        let items = current.get_or_insert_with(Vec::new);
+        match diff {
+            RoomListDiff::Append { values } => items.extend(values),
+            RoomListDiff::Insert { index, value } => items.insert(index as usize, value),
+            RoomListDiff::Set { index, value } => items[index as usize] = value,
+            RoomListDiff::Remove { index } => {
+                items.remove(index as usize);
+            }
+            RoomListDiff::Truncate { length } => items.truncate(length as usize),
+            RoomListDiff::Clear => items.clear(),
+            RoomListDiff::Reset { values } => *items = values,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use matrix_sdk_store_encryption::StoreCipher;
+
+use crate::{FfiError, platform};
+
+const STORES: &[&str] = &["matrix-sdk-state.sqlite3", "matrix-sdk-crypto.sqlite3"];
+
+fn make_store(path: &Path, cipher: &StoreCipher, passphrase: &str) {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute("CREATE TABLE kv (key TEXT PRIMARY KEY, value BLOB)", [])
+        .unwrap();
+    conn.execute(
+        "INSERT INTO kv (key, value) VALUES ('cipher', ?1)",
+        rusqlite::params![cipher.export(passphrase).unwrap()],
+    )
+    .unwrap();
+}
+
+fn cipher_blob(path: &Path) -> Vec<u8> {
+    rusqlite::Connection::open(path)
+        .unwrap()
+        .query_row("SELECT value FROM kv WHERE key = 'cipher'", [], |r| {
+            r.get(0)
+        })
+        .unwrap()
+}
+
+fn opens_with(dir: &Path, passphrase: &str) -> bool {
+    STORES
+        .iter()
+        .all(|name| StoreCipher::import(passphrase, &cipher_blob(&dir.join(name))).is_ok())
+}
+
+fn stores() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let cipher = StoreCipher::new().unwrap();
+    for name in STORES {
+        make_store(&dir.path().join(name), &cipher, "old");
+    }
+    dir
+}
+
+#[test]
+fn rekey_moves_every_store_to_the_new_passphrase() {
+    let dir = stores();
+    platform::rekey_stores(dir.path(), "old", "new").unwrap();
+    assert!(opens_with(dir.path(), "new"));
+    assert!(platform::store_passphrase_rejected(dir.path(), "old"));
+    assert!(!platform::store_passphrase_rejected(dir.path(), "new"));
+    assert!(!dir.path().join("rekey_journal.json").exists());
+}
+
+#[test]
+fn rekey_with_wrong_passphrase_changes_nothing() {
+    let dir = stores();
+    let before = cipher_blob(&dir.path().join(STORES[0]));
+    assert!(matches!(
+        platform::rekey_stores(dir.path(), "wrong", "new"),
+        Err(FfiError::WrongPassphrase)
+    ));
+    assert_eq!(cipher_blob(&dir.path().join(STORES[0])), before);
+    assert!(opens_with(dir.path(), "old"));
+}
+
+#[test]
+fn interrupted_rekey_is_rolled_back() {
+    let dir = stores();
+    let old_blobs: Vec<(String, Vec<u8>)> = STORES
+        .iter()
+        .map(|name| (name.to_string(), cipher_blob(&dir.path().join(name))))
+        .collect();
+    platform::rekey_stores(dir.path(), "old", "new").unwrap();
+    // As if the process died after the first store was rewritten.
+    let conn = rusqlite::Connection::open(dir.path().join(STORES[1])).unwrap();
+    conn.execute(
+        "UPDATE kv SET value = ?1 WHERE key = 'cipher'",
+        rusqlite::params![old_blobs[1].1],
+    )
+    .unwrap();
+    let journal = serde_json::json!({ "ciphers": old_blobs, "index_key": null });
+    std::fs::write(dir.path().join("rekey_journal.json"), journal.to_string()).unwrap();
+
+    platform::recover_rekey(dir.path()).unwrap();
+    assert!(opens_with(dir.path(), "old"));
+    assert!(!dir.path().join("rekey_journal.json").exists());
+}
@@ -0,0 +1,168 @@
+use crate::{
+    RoomListDiff, RoomListEntry, RoomListMembership, RoomListOrder, RoomListSort,
+    room_list_sort::SortedRoomList,
+};
+
+fn entry(room_id: &str, name: &str, last_ts: u64) -> RoomListEntry {
+    RoomListEntry {
+        room_id: room_id.into(),
+        name: name.into(),
+        last_ts,
+        notifications: 0,
+        messages: 0,
+        mentions: 0,
+        marked_unread: false,
+        is_favourite: false,
+        is_low_priority: false,
+        is_invited: false,
+        membership: RoomListMembership::Joined,
+        avatar_url: None,
+        is_dm: false,
+        is_encrypted: false,
+        member_count: 2,
+        topic: None,
+        latest_event: None,
+    }
+}
+
+fn by_name() -> RoomListSort {
+    RoomListSort {
+        order: RoomListOrder::Name,
+        ..RoomListSort::default()
+    }
+}
+
+fn ids(list: &SortedRoomList) -> Vec<&str> {
+    list.entries().iter().map(|e| e.room_id.as_str()).collect()
+}
+
+/// Applies `out` to `mirror` the way a host does, so the test checks that
+/// the emitted indices reproduce the sorted view.
+fn replay(mirror: &mut Vec<RoomListEntry>, out: Vec<RoomListDiff>) {
+    for diff in out {
+        match diff {
+            RoomListDiff::Append { values } => mirror.extend(values),
+            RoomListDiff::Insert { index, value } => mirror.insert(index as usize, value),
+            RoomListDiff::Set { index, value } => mirror[index as usize] = value,
+            RoomListDiff::Remove { index } => {
+                mirror.remove(index as usize);
+            }
+            RoomListDiff::Truncate { length } => mirror.truncate(length as usize),
+            RoomListDiff::Clear => mirror.clear(),
+            RoomListDiff::Reset { values } => *mirror = values,
+        }
+    }
+}
+
+fn mirror_ids(mirror: &[RoomListEntry]) -> Vec<&str> {
+    mirror.iter().map(|e| e.room_id.as_str()).collect()
+}
+
+/// SDK order (recency) is c, b, a; by name the view is a, b, c.
+fn seeded() -> (SortedRoomList, Vec<RoomListEntry>) {
+    let mut list = SortedRoomList::new(by_name());
+    let mut mirror = Vec::new();
+    let out = list.apply(RoomListDiff::Reset {
+        values: vec![
+            entry("!c", "Charlie", 3),
+            entry("!b", "Bravo", 2),
+            entry("!a", "Alpha", 1),
+        ],
+    });
+    replay(&mut mirror, out);
+    (list, mirror)
+}
+
+#[test]
+fn reset_is_sorted() {
+    let (list, mirror) = seeded();
+    assert_eq!(ids(&list), vec!["!a", "!b", "!c"]);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+}
+
+#[test]
+fn insert_lands_at_its_sorted_position() {
+    let (mut list, mut mirror) = seeded();
+    // First in SDK order, but between a and b by name.
+    let out = list.apply(RoomListDiff::Insert {
+        index: 0,
+        value: entry("!ab", "Alphabet", 4),
+    });
+    assert!(matches!(
+        out.as_slice(),
+        [RoomListDiff::Insert { index: 1, .. }]
+    ));
+    replay(&mut mirror, out);
+    assert_eq!(ids(&list), vec!["!a", "!ab", "!b", "!c"]);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+}
+
+#[test]
+fn remove_resolves_the_sdk_index() {
+    let (mut list, mut mirror) = seeded();
+    // SDK index 0 is c, which is last in the view.
+    let out = list.apply(RoomListDiff::Remove { index: 0 });
+    assert!(matches!(
+        out.as_slice(),
+        [RoomListDiff::Remove { index: 2 }]
+    ));
+    replay(&mut mirror, out);
+    assert_eq!(ids(&list), vec!["!a", "!b"]);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+
+    assert!(list.apply(RoomListDiff::Remove { index: 9 }).is_empty());
+}
+
+#[test]
+fn set_in_place_stays_a_set() {
+    let (mut list, mut mirror) = seeded();
+    let mut b = entry("!b", "Bravo", 5);
+    b.messages = 1;
+    let out = list.apply(RoomListDiff::Set { index: 1, value: b });
+    assert!(matches!(
+        out.as_slice(),
+        [RoomListDiff::Set { index: 1, .. }]
+    ));
+    replay(&mut mirror, out);
+    assert_eq!(mirror[1].messages, 1);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+}
+
+#[test]
+fn set_that_reorders_is_a_move() {
+    let (mut list, mut mirror) = seeded();
+    // Renaming a (SDK index 2) sends it to the end.
+    let out = list.apply(RoomListDiff::Set {
+        index: 2,
+        value: entry("!a", "Zulu", 1),
+    });
+    assert!(matches!(
+        out.as_slice(),
+        [
+            RoomListDiff::Remove { index: 0 },
+            RoomListDiff::Insert { index: 2, .. }
+        ]
+    ));
+    replay(&mut mirror, out);
+    assert_eq!(ids(&list), vec!["!b", "!c", "!a"]);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+}
+
+#[test]
+fn truncate_removes_the_dropped_sdk_tail() {
+    let (mut list, mut mirror) = seeded();
+    // Keeps c only: b and a go.
+    let out = list.apply(RoomListDiff::Truncate { length: 1 });
+    replay(&mut mirror, out);
+    assert_eq!(ids(&list), vec!["!c"]);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+}
+
+#[test]
+fn set_sort_resets_in_the_new_order() {
+    let (mut list, mut mirror) = seeded();
+    let out = list.set_sort(RoomListSort::default());
+    replay(&mut mirror, vec![out]);
+    assert_eq!(ids(&list), vec!["!c", "!b", "!a"]);
+    assert_eq!(mirror_ids(&mirror), ids(&list));
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    SearchFilter, SearchHit,
+    search::{Cursor, Window, find_terms, matches_filter, merge, order, score, snippet, terms},
+};
+
+const NOW_MS: u64 = 1_700_000_000_000;
+const DAY_MS: u64 = 86_400_000;
+
+fn hit(sender: &str, body: &str, timestamp_ms: u64) -> SearchHit {
+    SearchHit {
+        room_id: "!room:localhost".into(),
+        event_id: "$event".into(),
+        sender: sender.into(),
+        body: body.into(),
+        timestamp_ms,
+        sender_display_name: None,
+        snippet: None,
+    }
+}
+
+fn no_filter() -> SearchFilter {
+    SearchFilter {
+        room_ids: None,
+        senders: None,
+        after_ms: None,
+        before_ms: None,
+    }
+}
+
+fn chars(s: &str) -> Vec<char> {
+    s.chars().collect()
+}
+
+fn utf16_slice(text: &str, start: u32, end: u32) -> String {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    String::from_utf16(&units[start as usize..end as usize]).unwrap()
+}
+
+#[test]
+fn terms_lowercase_and_drop_punctuation() {
+    assert_eq!(terms("  Hello, WORLD! -- "), vec!["hello", "world"]);
+}
+
+#[test]
+fn find_terms_is_case_insensitive() {
+    let found = find_terms(&chars("Cat cat CAT"), &terms("cat"));
+    assert_eq!(found, vec![(0, 3), (4, 7), (8, 11)]);
+}
+
+#[test]
+fn find_terms_merges_overlapping_and_nested_matches() {
+    // "aaa" matches at 0 and 1; "aa" at 0, 1 and 2: all one range.
+    assert_eq!(find_terms(&chars("aaaa"), &terms("aaa aa")), vec![(0, 4)]);
+    // "rain" sits inside "rainbow".
+    let found = find_terms(&chars("a rainbow and rain"), &terms("rainbow rain"));
+    assert_eq!(found, vec![(2, 9), (14, 18)]);
+    // Touching ranges are merged too.
+    assert_eq!(
+        find_terms(&chars("foobar"), &terms("foo bar")),
+        vec![(0, 6)]
+    );
+}
+
+#[test]
+fn find_terms_ignores_terms_longer_than_the_body() {
+    assert!(find_terms(&chars("hi"), &terms("hello")).is_empty());
+}
+
+#[test]
+fn snippet_without_a_match_is_none() {
+    assert!(snippet("nothing here", &terms("absent")).is_none());
+}
+
+#[test]
+fn snippet_highlights_are_utf16_offsets() {
+    // 😀 is one char but two UTF-16 units.
+    let s = snippet("😀 hello 😀 hello", &terms("hello")).unwrap();
+    assert_eq!(s.text, "😀 hello 😀 hello");
+    let ranges: Vec<_> = s.highlights.iter().map(|r| (r.start, r.end)).collect();
+    assert_eq!(ranges, vec![(3, 8), (12, 17)]);
+    for r in &s.highlights {
+        assert_eq!(utf16_slice(&s.text, r.start, r.end), "hello");
+    }
+}
+
+#[test]
+fn snippet_trims_long_bodies_and_shifts_offsets() {
+    let body = format!("{}needle{}", "😀".repeat(60), "x".repeat(200));
+    let s = snippet(&body, &terms("needle")).unwrap();
+    assert!(s.text.starts_with('…'));
+    assert!(s.text.ends_with('…'));
+    assert_eq!(s.highlights.len(), 1);
+    let r = &s.highlights[0];
+    assert_eq!(utf16_slice(&s.text, r.start, r.end), "needle");
+}
+
+#[test]
+fn snippet_drops_matches_outside_the_window() {
+    let body = format!("needle{}needle", "x".repeat(300));
+    let s = snippet(&body, &terms("needle")).unwrap();
+    assert_eq!(s.highlights.len(), 1);
+    assert_eq!((s.highlights[0].start, s.highlights[0].end), (0, 6));
+}
+
+#[test]
+fn matches_filter_checks_sender_and_time_bounds() {
+    let h = hit("@bob:localhost", "hi", 1_000);
+    assert!(matches_filter(&no_filter(), &h));
+
+    let mut f = no_filter();
+    f.senders = Some(vec!["@alice:localhost".into()]);
+    assert!(!matches_filter(&f, &h));
+    f.senders = Some(vec!["@alice:localhost".into(), "@bob:localhost".into()]);
+    assert!(matches_filter(&f, &h));
+
+    // `after_ms` is inclusive, `before_ms` exclusive.
+    let mut f = no_filter();
+    f.after_ms = Some(1_000);
+    assert!(matches_filter(&f, &h));
+    f.after_ms = Some(1_001);
+    assert!(!matches_filter(&f, &h));
+    let mut f = no_filter();
+    f.before_ms = Some(1_000);
+    assert!(!matches_filter(&f, &h));
+    f.before_ms = Some(1_001);
+    assert!(matches_filter(&f, &h));
+}
+
+#[test]
+fn score_prefers_coverage_then_phrase() {
+    let q = "red fox";
+    let t = terms(q);
+    let both = score(&t, q, &hit("@a:x", "a red fox", NOW_MS), 0, NOW_MS);
+    let apart = score(&t, q, &hit("@a:x", "red and a fox", NOW_MS), 0, NOW_MS);
+    let one = score(&t, q, &hit("@a:x", "a red hen", NOW_MS), 0, NOW_MS);
+    assert!(both > apart);
+    assert!(apart > one);
+}
+
+#[test]
+fn score_breaks_ties_on_rank_and_recency() {
+    let q = "fox";
+    let t = terms(q);
+    let fresh = hit("@a:x", "fox", NOW_MS);
+    let old = hit("@a:x", "fox", NOW_MS - 90 * DAY_MS);
+    assert!(score(&t, q, &fresh, 0, NOW_MS) > score(&t, q, &fresh, 5, NOW_MS));
+    assert!(score(&t, q, &fresh, 0, NOW_MS) > score(&t, q, &old, 0, NOW_MS));
+    // Same inputs, same score: nothing depends on the wall clock.
+    assert_eq!(
+        score(&t, q, &old, 3, NOW_MS).to_bits(),
+        score(&t, q, &old, 3, NOW_MS).to_bits()
+    );
+}
+
+#[test]
+fn order_is_score_descending_then_event_id() {
+    assert!(order((2.0, "$b"), (1.0, "$a")).is_lt());
+    assert!(order((1.0, "$a"), (1.0, "$b")).is_lt());
+    assert!(order((1.0, "$a"), (1.0, "$a")).is_eq());
+}
+
+#[test]
+fn cursor_round_trips_room_ids_with_colons() {
+    let c = Cursor {
+        now_ms: NOW_MS,
+        rooms: BTreeMap::from([
+            ("!a:example.org".to_owned(), 40),
+            ("!b:example.org:8448".to_owned(), 0),
+        ]),
+    };
+    assert_eq!(Cursor::decode(&c.encode()), Some(c));
+    assert_eq!(Cursor::decode("garbage"), None);
+    assert_eq!(Cursor::decode("1:0:0:0:$x"), None);
+}
+
+/// A room's index as `search_one` reads it: hits at every position, up to
+/// `cap` of them from `start`.
+fn window(room_id: &str, hits: &[SearchHit], start: usize, cap: usize) -> Window {
+    let end = (start + cap).min(hits.len());
+    Window {
+        room_id: room_id.into(),
+        start,
+        hits: (start..end).map(|pos| (pos, hits[pos].clone())).collect(),
+        next_scan: end,
+        more: end < hits.len(),
+    }
+}
+
+#[test]
+fn merge_pages_through_every_hit_across_rooms() {
+    let q = "fox";
+    let t = terms(q);
+    // Later hits in room a are fresher, so they outscore what page one
+    // returned from room b: they must still come back on a later page.
+    let room = |room_id: &str, n: usize, fresh_from: usize| -> Vec<SearchHit> {
+        (0..n)
+            .map(|i| {
+                let age = if i >= fresh_from { 0 } else { 60 * DAY_MS };
+                SearchHit {
+                    room_id: room_id.into(),
+                    event_id: format!("${room_id}{i}"),
+                    ..hit("@a:x", "fox", NOW_MS - age)
+                }
+            })
+            .collect()
+    };
+    let a = room("!a:x", 9, 4);
+    let b = room("!b:x", 5, 5);
+    let limit = 3;
+
+    let mut rooms = BTreeMap::from([("!a:x".to_owned(), 0), ("!b:x".to_owned(), 0)]);
+    let mut seen = Vec::new();
+    let mut pages = 0;
+    while !rooms.is_empty() {
+        let windows = rooms
+            .iter()
+            .map(|(id, &start)| window(id, if id == "!a:x" { &a } else { &b }, start, limit))
+            .collect();
+        let (page, next) = merge(windows, &t, q, NOW_MS, limit);
+        assert!(page.len() <= limit);
+        assert!(
+            !page.is_empty(),
+            "a page with rooms left must make progress"
+        );
+        seen.extend(page.into_iter().map(|h| h.event_id));
+        rooms = next;
+        pages += 1;
+    }
+
+    assert_eq!(seen.len(), a.len() + b.len());
+    let unique: BTreeSet<_> = seen.iter().collect();
+    assert_eq!(unique.len(), seen.len());
+    assert_eq!(pages, (a.len() + b.len()).div_ceil(limit));
+}
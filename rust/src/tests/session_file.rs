@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+use super::*;
+use crate::{FfiError, SessionInfo, platform};
+
+fn session() -> SessionInfo {
+    SessionInfo {
+        user_id: USER_ID.into(),
+        device_id: DEVICE_ID.into(),
+        access_token: ACCESS_TOKEN.into(),
+        refresh_token: Some("refresh".into()),
+        homeserver: "http://localhost".into(),
+        auth_api: "matrix".into(),
+        client_id: None,
+        is_token_valid: true,
+    }
+}
+
+fn store(dir: &TempDir) -> PathBuf {
+    dir.path().join("accounts").join("alice")
+}
+
+fn session_text(store_dir: &Path) -> String {
+    std::fs::read_to_string(store_dir.parent().unwrap().join("alice.session.json")).unwrap()
+}
+
+#[test]
+fn sealed_session_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let store_dir = store(&dir);
+    platform::set_session_secret(&store_dir, key(1));
+    RT.block_on(platform::persist_session(&store_dir, &session()))
+        .unwrap();
+    assert!(!session_text(&store_dir).contains(ACCESS_TOKEN));
+
+    // A fresh secret registration, as after a restart.
+    platform::set_session_secret(&store_dir, key(1));
+    let info = RT
+        .block_on(platform::load_session(&store_dir))
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.access_token, ACCESS_TOKEN);
+    assert_eq!(info.refresh_token.as_deref(), Some("refresh"));
+}
+
+#[test]
+fn plaintext_session_is_sealed_on_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let store_dir = store(&dir);
+    RT.block_on(platform::persist_session(&store_dir, &session()))
+        .unwrap();
+    assert!(session_text(&store_dir).contains(ACCESS_TOKEN));
+
+    platform::set_session_secret(&store_dir, key(1));
+    let info = RT
+        .block_on(platform::load_session(&store_dir))
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.user_id, USER_ID);
+    assert!(!session_text(&store_dir).contains(ACCESS_TOKEN));
+
+    platform::set_session_secret(&store_dir, key(1));
+    let peeked = platform::peek_session(&store_dir, key(1).as_ref()).unwrap();
+    assert_eq!(peeked.access_token, ACCESS_TOKEN);
+}
+
+#[test]
+fn wrong_or_missing_secret_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let store_dir = store(&dir);
+    platform::set_session_secret(&store_dir, key(1));
+    RT.block_on(platform::persist_session(&store_dir, &session()))
+        .unwrap();
+    let sealed = session_text(&store_dir);
+
+    platform::set_session_secret(&store_dir, key(2));
+    assert!(matches!(
+        RT.block_on(platform::load_session(&store_dir)),
+        Err(FfiError::WrongPassphrase)
+    ));
+    platform::set_session_secret(&store_dir, None);
+    assert!(matches!(
+        RT.block_on(platform::load_session(&store_dir)),
+        Err(FfiError::WrongPassphrase)
+    ));
+    // The sealed file is left alone for the right secret.
+    assert_eq!(session_text(&store_dir), sealed);
+    assert!(platform::peek_session(&store_dir, key(2).as_ref()).is_none());
+}
+
+#[test]
+fn client_with_wrong_session_key_fails() {
+    let hs = MockHomeserver::start();
+    hs.mock_login();
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().to_string_lossy().to_string();
+    let open = |byte| {
+        Client::new_with_session_key(
+            hs.url(),
+            base.clone(),
+            Some("alice".into()),
+            None,
+            None,
+            Some(Box::new(FixedKey(byte))),
+        )
+    };
+
+    let client = open(1).unwrap();
+    client
+        .login("alice".into(), "hunter2".into(), None)
+        .unwrap();
+    drop(client);
+
+    assert!(matches!(open(2), Err(FfiError::WrongPassphrase)));
+    assert!(!session_text(&store(&dir)).contains(ACCESS_TOKEN));
+}
@@ -0,0 +1,151 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use serde_json::{Value, json};
+
+use super::*;
+use crate::space_tree::{self, SpaceGraph, build, collect_rooms, descendant_rooms};
+
+const SPACE_A: &str = "!a:localhost";
+const SPACE_B: &str = "!b:localhost";
+const ROOM_1: &str = "!r1:localhost";
+const ROOM_2: &str = "!r2:localhost";
+const ROOM_3: &str = "!r3:localhost";
+
+fn rid(id: &str) -> OwnedRoomId {
+    RoomId::parse(id).unwrap()
+}
+
+fn graph(links: &[(&str, &[&str])]) -> HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>> {
+    links
+        .iter()
+        .map(|(space, children)| (rid(space), children.iter().map(|c| rid(c)).collect()))
+        .collect()
+}
+
+fn below(children: &HashMap<OwnedRoomId, BTreeSet<OwnedRoomId>>, space: &str) -> Vec<String> {
+    let mut out = HashSet::new();
+    collect_rooms(children, &rid(space), &mut HashSet::new(), &mut out);
+    let mut out: Vec<_> = out.into_iter().map(|r| r.to_string()).collect();
+    out.sort();
+    out
+}
+
+#[test]
+fn collect_rooms_walks_nested_spaces_but_leaves_them_out() {
+    let children = graph(&[(SPACE_A, &[SPACE_B, ROOM_1]), (SPACE_B, &[ROOM_2, ROOM_1])]);
+    assert_eq!(below(&children, SPACE_A), vec![ROOM_1, ROOM_2]);
+    assert_eq!(below(&children, SPACE_B), vec![ROOM_1, ROOM_2]);
+    assert!(below(&children, "!unknown:localhost").is_empty());
+}
+
+#[test]
+fn collect_rooms_survives_cycles() {
+    let children = graph(&[(SPACE_A, &[SPACE_B, ROOM_1]), (SPACE_B, &[SPACE_A, ROOM_2])]);
+    assert_eq!(below(&children, SPACE_A), vec![ROOM_1, ROOM_2]);
+    assert_eq!(below(&children, SPACE_B), vec![ROOM_1, ROOM_2]);
+}
+
+fn state(ty: &str, state_key: &str, content: Value) -> Value {
+    json!({
+        "type": ty,
+        "state_key": state_key,
+        "event_id": format!("$state-{ty}-{state_key}"),
+        "sender": USER_ID,
+        "origin_server_ts": 1_700_000_000_000u64,
+        "content": content,
+    })
+}
+
+fn joined(name: &str, is_space: bool, mut extra: Vec<Value>) -> Value {
+    let mut create = json!({ "room_version": "10" });
+    if is_space {
+        create["type"] = json!("m.space");
+    }
+    let mut events = vec![
+        state("m.room.create", "", create),
+        state("m.room.member", USER_ID, json!({ "membership": "join" })),
+        state("m.room.name", "", json!({ "name": name })),
+    ];
+    events.append(&mut extra);
+    json!({
+        "state": { "events": events },
+        "timeline": { "events": [], "limited": false, "prev_batch": "p1" },
+        "ephemeral": { "events": [] },
+        "account_data": { "events": [] },
+        "summary": { "m.joined_member_count": 1, "m.invited_member_count": 0 },
+        "unread_notifications": { "notification_count": 0, "highlight_count": 0 },
+    })
+}
+
+fn child(id: &str) -> Value {
+    state("m.space.child", id, json!({ "via": ["localhost"] }))
+}
+
+#[test]
+fn build_flattens_spaces_from_both_sides_of_the_link() {
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync_body(json!({
+        "next_batch": "s1",
+        "rooms": { "join": {
+            // A lists B and room 1; room 3 only names A as its parent.
+            SPACE_A: joined("Space A", true, vec![child(SPACE_B), child(ROOM_1)]),
+            SPACE_B: joined("Space B", true, vec![
+                child(ROOM_2),
+                // Removed: no `via`.
+                state("m.space.child", ROOM_1, json!({})),
+            ]),
+            ROOM_1: joined("Room 1", false, vec![]),
+            ROOM_2: joined("Room 2", false, vec![]),
+            ROOM_3: joined("Room 3", false, vec![
+                state("m.space.parent", SPACE_A, json!({ "via": ["localhost"] })),
+            ]),
+        }},
+    }));
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+    let sdk = &tc.client.core.sdk;
+
+    let graph = RT.block_on(SpaceGraph::load(sdk));
+    assert!(graph.covers(sdk));
+    let tree = RT.block_on(build(sdk, &graph));
+
+    assert_eq!(tree.root_spaces, vec![SPACE_A]);
+    let ids: Vec<_> = tree.spaces.iter().map(|s| s.space_id.as_str()).collect();
+    assert_eq!(ids, vec![SPACE_A, SPACE_B]);
+    let a = &tree.spaces[0];
+    assert_eq!(a.name, "Space A");
+    assert_eq!(a.child_spaces, vec![SPACE_B]);
+    assert_eq!(a.child_rooms, vec![ROOM_1, ROOM_3]);
+    let b = &tree.spaces[1];
+    assert!(b.child_spaces.is_empty());
+    assert_eq!(b.child_rooms, vec![ROOM_2]);
+    assert_eq!(a.rooms_with_unread, 0);
+
+    // A graph from before the spaces were joined no longer covers them.
+    assert!(!SpaceGraph::new(HashMap::new()).covers(sdk));
+}
+
+#[test]
+fn descendant_rooms_include_rooms_not_joined_yet() {
+    const LATER: &str = "!later:localhost";
+    let hs = MockHomeserver::start();
+    hs.mock_initial_sync_body(json!({
+        "next_batch": "s1",
+        "rooms": { "join": {
+            SPACE_A: joined("Space A", true, vec![child(SPACE_B), child(ROOM_1)]),
+            SPACE_B: joined("Space B", true, vec![child(LATER)]),
+            ROOM_1: joined("Room 1", false, vec![]),
+        }},
+    }));
+    let tc = TestClient::logged_in(&hs);
+    tc.sync_once();
+    let sdk = &tc.client.core.sdk;
+
+    let rooms = RT.block_on(descendant_rooms(sdk, &rid(SPACE_A)));
+    assert_eq!(rooms, HashSet::from([rid(ROOM_1), rid(LATER)]));
+    assert_eq!(
+        space_tree::joined_space_ids(sdk),
+        BTreeSet::from([rid(SPACE_A), rid(SPACE_B)])
+    );
+}
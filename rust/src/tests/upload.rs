@@ -0,0 +1,267 @@
+use std::time::Duration;
+
+use wiremock::matchers::header;
+
+use super::*;
+use crate::{AttachmentUploadObserver, AttachmentUploadResult, ProgressObserver};
+
+const WAIT: Duration = Duration::from_secs(10);
+const REFRESHED_TOKEN: &str = "syt_refreshed_token";
+
+#[derive(Default, Clone)]
+struct RecordingUpload {
+    progress: Arc<Mutex<Vec<(u64, Option<u64>)>>>,
+    outcome: Arc<Mutex<Option<String>>>,
+}
+
+impl RecordingUpload {
+    fn outcome(&self) -> Option<String> {
+        self.outcome.lock().unwrap().clone()
+    }
+}
+
+impl ProgressObserver for RecordingUpload {
+    fn on_progress(&self, sent: u64, total: Option<u64>) {
+        self.progress.lock().unwrap().push((sent, total));
+    }
+}
+
+impl AttachmentUploadObserver for RecordingUpload {
+    fn on_progress(&self, sent: u64, total: Option<u64>) {
+        self.progress.lock().unwrap().push((sent, total));
+    }
+    fn on_complete(&self, result: AttachmentUploadResult) {
+        *self.outcome.lock().unwrap() = Some(format!("complete {}", result.event_id));
+    }
+    fn on_error(&self, message: String) {
+        *self.outcome.lock().unwrap() = Some(format!("error {message}"));
+    }
+    fn on_cancelled(&self) {
+        *self.outcome.lock().unwrap() = Some("cancelled".into());
+    }
+}
+
+/// A file spanning several read chunks, with a last partial one.
+fn attachment(tc: &TestClient) -> (String, u64) {
+    let path = tc.base_dir.path().join("notes.txt");
+    let bytes: Vec<u8> = (0..600_123u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, &bytes).unwrap();
+    (path.to_string_lossy().into_owned(), bytes.len() as u64)
+}
+
+fn joined_client(hs: &MockHomeserver) -> TestClient {
+    hs.mock_initial_sync(vec![]);
+    let tc = TestClient::logged_in(hs);
+    tc.sync_once();
+    tc
+}
+
+#[test]
+fn upload_progress_is_monotonic_up_to_the_file_size() {
+    let hs = MockHomeserver::start();
+    hs.mock_upload("mxc://localhost/notes");
+    hs.mock_send("$sent");
+    let tc = joined_client(&hs);
+    let (path, size) = attachment(&tc);
+    let progress = RecordingUpload::default();
+
+    let result = tc
+        .client
+        .send_attachment_from_path(
+            ROOM_ID.into(),
+            path,
+            "text/plain".into(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(Box::new(progress.clone())),
+        )
+        .unwrap();
+
+    assert_eq!(result.event_id, "$sent");
+    assert_eq!(result.attachment.mxc_uri, "mxc://localhost/notes");
+    let seen = progress.progress.lock().unwrap().clone();
+    assert!(seen.len() > 2, "one report per chunk, got {seen:?}");
+    assert!(seen.iter().all(|&(_, total)| total == Some(size)));
+    assert!(seen.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert_eq!(seen.last().unwrap().0, size);
+
+    let uploads = hs.requests_matching("POST", "/_matrix/media/v3/upload");
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].body.len() as u64, size);
+}
+
+#[test]
+fn upload_retries_once_with_a_refreshed_token() {
+    let hs = MockHomeserver::start();
+    hs.mount(
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user_id": USER_ID,
+                "access_token": ACCESS_TOKEN,
+                "refresh_token": "refresh",
+                "expires_in_ms": 3_600_000,
+                "device_id": DEVICE_ID,
+            }))),
+    );
+    hs.mount(
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/v3/refresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": REFRESHED_TOKEN,
+                "refresh_token": "refresh2",
+                "expires_in_ms": 3_600_000,
+            }))),
+    );
+    hs.mount(
+        Mock::given(method("POST"))
+            .and(path("/_matrix/media/v3/upload"))
+            .and(header("authorization", format!("Bearer {ACCESS_TOKEN}")))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "errcode": "M_UNKNOWN_TOKEN",
+                "error": "Access token has expired",
+                "soft_logout": true,
+            }))),
+    );
+    hs.mount(
+        Mock::given(method("POST"))
+            .and(path("/_matrix/media/v3/upload"))
+            .and(header("authorization", format!("Bearer {REFRESHED_TOKEN}")))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "content_uri": "mxc://localhost/notes" })),
+            ),
+    );
+    hs.mock_send("$sent");
+    hs.mock_initial_sync(vec![]);
+    let tc = TestClient::new(&hs);
+    tc.client
+        .login("alice".into(), "hunter2".into(), None)
+        .unwrap();
+    tc.sync_once();
+    let (path, size) = attachment(&tc);
+
+    let result = tc
+        .client
+        .send_attachment_from_path(
+            ROOM_ID.into(),
+            path,
+            "text/plain".into(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+    assert_eq!(result.attachment.mxc_uri, "mxc://localhost/notes");
+    assert_eq!(
+        hs.requests_matching("POST", "/_matrix/client/v3/refresh")
+            .len(),
+        1
+    );
+    let uploads = hs.requests_matching("POST", "/_matrix/media/v3/upload");
+    assert_eq!(uploads.len(), 2);
+    // The retry streams the whole file again rather than a spent body.
+    assert_eq!(uploads[1].body.len() as u64, size);
+}
+
+#[test]
+fn cancelled_upload_reports_cancelled_and_sends_nothing() {
+    let hs = MockHomeserver::start();
+    hs.mount(
+        Mock::given(method("POST"))
+            .and(path("/_matrix/media/v3/upload"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "content_uri": "mxc://localhost/notes" }))
+                    .set_delay(Duration::from_secs(30)),
+            ),
+    );
+    hs.mock_send("$sent");
+    let tc = joined_client(&hs);
+    let (path, _) = attachment(&tc);
+    let observer = RecordingUpload::default();
+
+    let id = tc.client.start_attachment_upload(
+        ROOM_ID.into(),
+        path,
+        "text/plain".into(),
+        None,
+        None,
+        None,
+        None,
+        None,
+        Box::new(observer.clone()),
+    );
+    assert!(wait_until(WAIT, || {
+        !observer.progress.lock().unwrap().is_empty()
+    }));
+    assert!(tc.client.cancel_attachment_upload(id));
+
+    assert!(wait_until(WAIT, || observer.outcome().is_some()));
+    assert_eq!(observer.outcome().as_deref(), Some("cancelled"));
+    // Nothing left to cancel, and no event goes out later.
+    assert!(!tc.client.cancel_attachment_upload(id));
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(
+        hs.requests_matching("PUT", "/send/m.room.message/")
+            .is_empty()
+    );
+    assert_eq!(observer.outcome().as_deref(), Some("cancelled"));
+}
+
+#[test]
+fn video_goes_up_with_the_host_thumbnail_and_its_blurhash() {
+    let hs = MockHomeserver::start();
+    hs.mock_upload("mxc://localhost/media");
+    hs.mock_send("$sent");
+    let tc = joined_client(&hs);
+    // Not a parseable MP4: the header fields are simply left out.
+    let video = tc.base_dir.path().join("clip.mp4");
+    std::fs::write(&video, [0u8; 64]).unwrap();
+    let poster = tc.base_dir.path().join("poster.png");
+    image::RgbImage::from_pixel(320, 180, image::Rgb([10, 120, 200]))
+        .save(&poster)
+        .unwrap();
+
+    let result = tc
+        .client
+        .send_attachment_from_path(
+            ROOM_ID.into(),
+            video.to_string_lossy().into_owned(),
+            "video/mp4".into(),
+            None,
+            None,
+            None,
+            None,
+            Some(poster.to_string_lossy().into_owned()),
+            None,
+        )
+        .unwrap();
+
+    let blurhash = result
+        .attachment
+        .blurhash
+        .clone()
+        .expect("blurhash from the poster");
+    assert_eq!(
+        result.attachment.thumbnail_mxc_uri.as_deref(),
+        Some("mxc://localhost/media")
+    );
+    assert_eq!(
+        hs.requests_matching("POST", "/_matrix/media/v3/upload")
+            .len(),
+        2
+    );
+    let sent = hs.requests_matching("PUT", "/send/m.room.message/");
+    let content: Value = serde_json::from_slice(&sent[0].body).unwrap();
+    assert_eq!(content["msgtype"], "m.video");
+    assert_eq!(content["info"]["thumbnail_info"]["mimetype"], "image/jpeg");
+    assert!(content["info"].to_string().contains(&blurhash));
+}
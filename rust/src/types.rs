@@ -16,11 +16,43 @@ pub enum FfiError {
     NotLive,
     #[error("Existing beacon information not found")]
     BeaconNotFound,
+    #[error("room not found")]
+    RoomNotFound,
+    #[error("timeline not available")]
+    TimelineUnavailable,
+    /// Access token missing, revoked or rejected; the session must be replaced.
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    /// Token was invalidated with `soft_logout: true`; re-login keeps the device.
+    #[error("soft logout")]
+    SoftLogout,
+    #[error("rate limited")]
+    RateLimited { retry_after_ms: Option<u64> },
+    #[error("network unreachable: {0}")]
+    NetworkUnreachable(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    InvalidId(String),
+    #[error("crypto error: {0}")]
+    CryptoError(String),
+    /// The store passphrase does not unlock the existing encrypted stores.
+    #[error("store passphrase is incorrect")]
+    WrongPassphrase,
+    #[error("cancelled")]
+    Cancelled,
 }
 
 impl From<matrix_sdk::Error> for FfiError {
     fn from(e: matrix_sdk::Error) -> Self {
-        FfiError::Msg(format!("matrix_sdk error: {e:?}"))
+        crate::errors::classify_sdk(&e)
+            .unwrap_or_else(|| FfiError::Msg(format!("matrix_sdk error: {e:?}")))
+    }
+}
+impl From<matrix_sdk::HttpError> for FfiError {
+    fn from(e: matrix_sdk::HttpError) -> Self {
+        crate::errors::classify_http(&e)
+            .unwrap_or_else(|| FfiError::Msg(format!("http error: {e:?}")))
     }
 }
 impl From<matrix_sdk_ui::notification_client::Error> for FfiError {
@@ -77,6 +109,8 @@ pub struct MessageEvent {
     pub state_event_type: Option<String>,
     pub live_location: Option<LiveLocationEvent>,
     pub raw_json: Option<String>,
+    #[uniffi(default = None)]
+    pub utd: Option<UtdInfo>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Record)]
@@ -106,6 +140,22 @@ pub struct AttachmentInfo {
     pub thumbnail_mxc_uri: Option<String>,
     pub encrypted: Option<EncFile>,
     pub thumbnail_encrypted: Option<EncFile>,
+    /// Room the event was seen in; used to attribute cached copies.
+    #[uniffi(default = None)]
+    pub room_id: Option<String>,
+    /// Placeholder to show while the image or video loads.
+    #[uniffi(default = None)]
+    pub blurhash: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct AttachmentUploadResult {
+    pub event_id: String,
+    /// What was sent, e.g. to forward it or seed the media cache.
+    pub attachment: AttachmentInfo,
+    /// How much smaller the image got through `ImageUploadOptions`.
+    #[uniffi(default = 0)]
+    pub saved_bytes: u64,
 }
 
 #[derive(Clone, Serialize, Deserialize, Record)]
@@ -127,6 +177,29 @@ pub struct DeviceSummary {
     pub ed25519: String,
     pub is_own: bool,
     pub verified: bool,
+    #[uniffi(default = None)]
+    pub last_seen_ip: Option<String>,
+    #[uniffi(default = None)]
+    pub last_seen_ts: Option<u64>,
+    /// MSC3814 offline device kept by `set_dehydrated_device_enabled`.
+    #[uniffi(default = false)]
+    pub is_dehydrated: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct RoomKeyImportSummary {
+    /// Sessions that were new or better than the ones we had.
+    pub imported: u64,
+    pub total: u64,
+}
+
+/// Outcome of `delete_devices` / `sign_out_other_sessions`.
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct DeviceDeletion {
+    pub deleted: Vec<String>,
+    /// OAuth accounts: sessions are ended at the provider, so each requested
+    /// device maps to the account-management URL that ends it.
+    pub end_session_urls: HashMap<String, String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Record)]
@@ -168,7 +241,7 @@ pub struct RenderedNotification {
     pub room_avatar_url: Option<String>,
 }
 
-#[derive(Clone, Serialize, Deserialize, Record)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Record)]
 pub struct UnreadStats {
     pub messages: u64,
     pub notifications: u64,
@@ -317,9 +390,98 @@ pub enum MediaClearScope {
     Temporary,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Enum)]
+pub enum MediaCacheKind {
+    Thumbnail,
+    Image,
+    Video,
+    Audio,
+    File,
+    Sticker,
+    /// Interrupted download, kept for resuming.
+    Partial,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct MediaCacheRoomUsage {
+    /// None for files not tied to a known room.
+    pub room_id: Option<String>,
+    pub bytes: u64,
+    pub files: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct MediaCacheKindUsage {
+    pub kind: MediaCacheKind,
+    pub bytes: u64,
+    pub files: u32,
+}
+
 #[derive(Clone, Serialize, Deserialize, Record)]
 pub struct MediaCacheOverview {
     pub total_bytes: u64,
+    #[uniffi(default = 0)]
+    pub pinned_bytes: u64,
+    #[uniffi(default = 0)]
+    pub file_count: u32,
+    /// Largest first.
+    #[uniffi(default = [])]
+    pub by_room: Vec<MediaCacheRoomUsage>,
+    #[uniffi(default = [])]
+    pub by_kind: Vec<MediaCacheKindUsage>,
+}
+
+/// Limits applied to unpinned media cache files; `None` means unbounded.
+#[derive(Clone, Debug, Serialize, Deserialize, Record)]
+pub struct MediaCachePolicy {
+    pub max_bytes: Option<u64>,
+    /// Evicts files not opened for this long.
+    pub max_age_ms: Option<u64>,
+}
+
+impl Default for MediaCachePolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(512 * 1024 * 1024),
+            max_age_ms: Some(30 * 24 * 60 * 60 * 1000),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum ImageUploadQuality {
+    /// Full resolution, as taken.
+    #[default]
+    Original,
+    /// Downscaled to `max_dimension` and re-encoded.
+    Optimised,
+}
+
+/// What happens to JPEG, PNG and WebP images before they are uploaded.
+/// Other images and files go up unchanged.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Record)]
+#[serde(default)]
+pub struct ImageUploadOptions {
+    /// Drops EXIF (including GPS location), XMP and text metadata. The
+    /// camera's rotation is applied to the pixels first. An image whose
+    /// metadata cannot be removed is not sent.
+    pub strip_metadata: bool,
+    pub quality: ImageUploadQuality,
+    /// Longest edge in pixels for `Optimised`.
+    pub max_dimension: u32,
+    /// JPEG quality (1-100) for `Optimised`.
+    pub jpeg_quality: u8,
+}
+
+impl Default for ImageUploadOptions {
+    fn default() -> Self {
+        Self {
+            strip_metadata: true,
+            quality: ImageUploadQuality::Original,
+            max_dimension: 2048,
+            jpeg_quality: 80,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, uniffi::Record)]
@@ -355,6 +517,73 @@ pub struct RoomListEntry {
     pub latest_event: Option<LatestRoomEvent>,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum RoomListOrder {
+    /// Most recent activity first, as the SDK orders rooms.
+    #[default]
+    Recency,
+    Name,
+    /// Rooms with unread messages, mentions or a manual unread mark first,
+    /// each group by recency.
+    UnreadFirst,
+}
+
+/// How `observe_room_list` orders rooms. Sorting covers the rooms loaded so
+/// far; more are paged in through `room_list_update_visible_range`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Record)]
+#[serde(default)]
+pub struct RoomListSort {
+    pub order: RoomListOrder,
+    #[uniffi(default = false)]
+    pub favourites_first: bool,
+    #[uniffi(default = false)]
+    pub low_priority_last: bool,
+}
+
+/// Positional change to the list last sent through `on_reset`. Indices refer
+/// to the list as it is after all previous diffs were applied in order.
+#[derive(Clone, Debug, Serialize, Deserialize, Enum)]
+pub enum RoomListDiff {
+    Append { values: Vec<RoomListEntry> },
+    Insert { index: u32, value: RoomListEntry },
+    Set { index: u32, value: RoomListEntry },
+    Remove { index: u32 },
+    Truncate { length: u32 },
+    Clear,
+    Reset { values: Vec<RoomListEntry> },
+}
+
+/// Which rooms `observe_room_list` shows. All set conditions must hold;
+/// the default shows every joined or invited room.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Record)]
+#[serde(default)]
+pub struct RoomListFilter {
+    #[uniffi(default = false)]
+    pub unread_only: bool,
+    #[uniffi(default = false)]
+    pub dms_only: bool,
+    #[uniffi(default = false)]
+    pub groups_only: bool,
+    #[uniffi(default = false)]
+    pub favourites: bool,
+    #[uniffi(default = false)]
+    pub low_priority: bool,
+    #[uniffi(default = false)]
+    pub invites: bool,
+    #[uniffi(default = false)]
+    pub mentions_only: bool,
+    /// Only rooms in this space. The room list keeps this up to date as
+    /// rooms are added to or removed from the space (or its subspaces).
+    #[uniffi(default = None)]
+    pub space_id: Option<String>,
+    /// With `space_id`, also rooms in its subspaces, at any depth.
+    #[uniffi(default = false)]
+    pub include_subspaces: bool,
+    /// Fuzzy match against the room's display name.
+    #[uniffi(default = None)]
+    pub name_query: Option<String>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Record)]
 pub struct DirectoryUser {
     pub user_id: String,
@@ -407,6 +636,27 @@ pub struct SpaceInfo {
     pub avatar_url: Option<String>,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Record)]
+pub struct SpaceTreeNode {
+    pub space_id: String,
+    pub name: String,
+    pub avatar_url: Option<String>,
+    /// Joined spaces directly below this one; look them up in `SpaceTree::spaces`.
+    pub child_spaces: Vec<String>,
+    /// Joined rooms directly below this space.
+    pub child_rooms: Vec<String>,
+    /// Summed over every room below this space, nested spaces included.
+    pub unread: UnreadStats,
+    pub rooms_with_unread: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Record)]
+pub struct SpaceTree {
+    /// Joined spaces that are not a child of another joined space.
+    pub root_spaces: Vec<String>,
+    pub spaces: Vec<SpaceTreeNode>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Record)]
 pub struct SpaceChildInfo {
     pub room_id: String,
@@ -456,6 +706,20 @@ pub struct PollDefinition {
     pub max_selections: u32,
 }
 
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct TextRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct SearchSnippet {
+    /// Excerpt of the body around the first match.
+    pub text: String,
+    /// Matched terms in `text`, as UTF-16 offsets (end exclusive).
+    pub highlights: Vec<TextRange>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Record)]
 pub struct SearchHit {
     pub room_id: String,
@@ -463,12 +727,37 @@ pub struct SearchHit {
     pub sender: String,
     pub body: String,
     pub timestamp_ms: u64,
+    #[uniffi(default = None)]
+    pub sender_display_name: Option<String>,
+    #[uniffi(default = None)]
+    pub snippet: Option<SearchSnippet>,
+}
+
+/// Narrows `search_all_rooms`. Unset fields do not filter.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Record)]
+#[serde(default)]
+pub struct SearchFilter {
+    #[uniffi(default = None)]
+    pub room_ids: Option<Vec<String>>,
+    #[uniffi(default = None)]
+    pub senders: Option<Vec<String>>,
+    /// Only events sent at or after this time (ms since epoch).
+    #[uniffi(default = None)]
+    pub after_ms: Option<u64>,
+    /// Only events sent before this time (ms since epoch).
+    #[uniffi(default = None)]
+    pub before_ms: Option<u64>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Record)]
 pub struct SearchPage {
     pub hits: Vec<SearchHit>,
+    /// `search_room` paging.
     pub next_offset: Option<u32>,
+    /// `search_all_rooms` paging: opaque, pass it back as `cursor`.
+    #[serde(default)]
+    #[uniffi(default = None)]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Record)]
@@ -612,6 +901,47 @@ pub enum EventType {
     Sticker,
     LiveLocation,
     Location,
+    Undecryptable,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Enum)]
+pub enum UtdReason {
+    /// We never received the room key; it may still arrive.
+    MissingSession,
+    /// The sender chose not to share the key with us.
+    Withheld,
+    /// Keys were withheld from, or not backed up for, our unverified device.
+    UnverifiedDevice,
+    /// The sending device is not signed by its owner.
+    UnverifiedSender,
+    /// The sender's identity changed after we verified it.
+    SenderIdentityChanged,
+    SentBeforeWeJoined,
+    /// Historical message whose key only key backup could provide.
+    KeyBackupDisabled,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct UtdInfo {
+    pub reason: UtdReason,
+    /// Megolm session to pass to `retry_decryption`.
+    pub session_id: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct UtdReasonCount {
+    pub reason: UtdReason,
+    pub count: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct UtdRoomStats {
+    pub room_id: String,
+    /// Events reported as undecryptable this session.
+    pub total: u64,
+    /// Of those, how many decrypted later once the key arrived.
+    pub late_decrypted: u64,
+    pub by_reason: Vec<UtdReasonCount>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Enum)]
@@ -638,6 +968,36 @@ pub enum RecoveryState {
     Unknown,
 }
 
+#[derive(Clone, Serialize, Deserialize, Enum)]
+pub enum IdentityResetStep {
+    Starting,
+    /// UIAA stages follow through the `UiaaHandler`.
+    AwaitingAuthentication,
+    /// Open `url` so the user can approve the reset at the OAuth provider;
+    /// the reset completes on its own once approved.
+    OAuthApproval {
+        url: String,
+    },
+    Done,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Enum)]
+pub enum IdentityState {
+    Verified,
+    Pinned,
+    /// Identity changed since we pinned it; pin the new one to acknowledge.
+    PinViolation,
+    /// Identity changed after we verified it; re-verify or withdraw.
+    VerificationViolation,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct IdentityChange {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    pub state: IdentityState,
+}
+
 #[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Enum)]
 pub enum BackupState {
     Unknown,
@@ -819,6 +1179,21 @@ pub trait RecoveryObserver: Send + Sync {
     fn on_error(&self, message: String);
 }
 
+#[export(callback_interface)]
+pub trait IdentityResetListener: Send + Sync {
+    fn on_step(&self, step: IdentityResetStep);
+}
+
+#[export(callback_interface)]
+pub trait IdentityChangeObserver: Send + Sync {
+    fn on_changes(&self, changes: Vec<IdentityChange>);
+}
+
+#[export(callback_interface)]
+pub trait UtdReportObserver: Send + Sync {
+    fn on_update(&self, rooms: Vec<UtdRoomStats>);
+}
+
 #[export(callback_interface)]
 pub trait RecoveryStateObserver: Send + Sync {
     fn on_update(&self, state: RecoveryState);
@@ -834,10 +1209,35 @@ pub trait ProgressObserver: Send + Sync {
     fn on_progress(&self, sent: u64, total: Option<u64>);
 }
 
+#[export(callback_interface)]
+pub trait AttachmentUploadObserver: Send + Sync {
+    fn on_progress(&self, sent: u64, total: Option<u64>);
+    fn on_complete(&self, result: AttachmentUploadResult);
+    fn on_error(&self, message: String);
+    fn on_cancelled(&self);
+}
+
+#[export(callback_interface)]
+pub trait MediaDownloadObserver: Send + Sync {
+    fn on_progress(&self, received: u64, total: Option<u64>);
+    fn on_complete(&self, result: DownloadResult);
+    fn on_error(&self, message: String);
+    /// The partial file is kept; downloading the same media to the same
+    /// path again resumes it.
+    fn on_cancelled(&self);
+}
+
+#[export(callback_interface)]
+pub trait SpaceTreeObserver: Send + Sync {
+    fn on_update(&self, tree: SpaceTree);
+}
+
 #[export(callback_interface)]
 pub trait RoomListObserver: Send + Sync {
     fn on_reset(&self, items: Vec<RoomListEntry>);
     fn on_update(&self, item: RoomListEntry);
+    /// Everything after the first `on_reset`, later full resets included.
+    fn on_diff(&self, diff: RoomListDiff);
 }
 
 #[uniffi::export(callback_interface)]
@@ -857,6 +1257,12 @@ pub trait VerifEventListener: Send + Sync {
     fn on_event(&self, event_json: String);
 }
 
+/// Progress of a QR code login, as JSON-encoded `QrLoginEvent`s.
+#[export(callback_interface)]
+pub trait QrLoginListener: Send + Sync {
+    fn on_event(&self, event_json: String);
+}
+
 #[export(callback_interface)]
 pub trait SendObserver: Send + Sync {
     fn on_update(&self, update: SendUpdate);
@@ -931,6 +1337,8 @@ impl From<PredecessorRoom> for PredecessorRoomInfo {
 
 pub(crate) enum RoomListCmd {
     SetUnreadOnly(bool),
+    SetFilter(RoomListFilter),
+    SetSort(RoomListSort),
     UpdateVisibleRange((Vec<u64>, usize)),
 }
 
@@ -938,6 +1346,143 @@ fn default_auth_api() -> String {
     "matrix".to_owned()
 }
 
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct UiaaPolicy {
+    pub id: String,
+    pub version: String,
+    pub name: String,
+    pub url: String,
+}
+
+/// A user-interactive auth stage the server wants completed next.
+#[derive(Clone, Serialize, Deserialize, Enum)]
+pub enum UiaaStage {
+    Dummy,
+    Password,
+    Terms {
+        policies: Vec<UiaaPolicy>,
+    },
+    /// `sent_to` is `None` until an address has been submitted; afterwards
+    /// the host should answer `Continue` once the user clicked the link.
+    EmailIdentity {
+        sent_to: Option<String>,
+    },
+    RegistrationToken,
+    /// The host renders the captcha for `public_key` and returns its response.
+    Recaptcha {
+        public_key: Option<String>,
+    },
+    /// A validation code was texted to `sent_to`; answer with `Code`.
+    MsisdnCode {
+        sent_to: String,
+    },
+    Unsupported {
+        kind: String,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize, Enum)]
+pub enum UiaaResponse {
+    /// Dummy stage, terms accepted, or email link clicked.
+    Continue,
+    Password {
+        password: String,
+    },
+    Email {
+        address: String,
+    },
+    RegistrationToken {
+        token: String,
+    },
+    Recaptcha {
+        response: String,
+    },
+    Code {
+        code: String,
+    },
+    Cancel,
+}
+
+#[export(callback_interface)]
+pub trait UiaaHandler: Send + Sync {
+    /// Called once per stage; `error` is set when the previous attempt at the
+    /// same stage was rejected. May block until the user answers: native
+    /// builds call it via `block_in_place`, off the async runtime's workers.
+    /// On the web it runs on the only thread, so answer without waiting.
+    fn on_stage(&self, stage: UiaaStage, error: Option<String>) -> UiaaResponse;
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Enum)]
+pub enum ThreePidMedium {
+    Email,
+    Msisdn,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct ThreePid {
+    pub medium: ThreePidMedium,
+    pub address: String,
+    pub validated_at_ms: u64,
+    pub added_at_ms: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct StoredAccount {
+    pub account_id: String,
+    /// `None` when the session file is encrypted and no secret unlocks it.
+    pub user_id: Option<String>,
+    pub homeserver: Option<String>,
+    pub is_token_valid: bool,
+    pub is_open: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Record)]
+pub struct AccountUnread {
+    pub account_id: String,
+    pub user_id: Option<String>,
+    pub unread_rooms: u64,
+    pub messages: u64,
+    pub notifications: u64,
+    pub mentions: u64,
+}
+
+#[export(callback_interface)]
+pub trait AccountUnreadObserver: Send + Sync {
+    /// Full snapshot across every open account, re-sent on any room update.
+    fn on_update(&self, accounts: Vec<AccountUnread>);
+}
+
+#[derive(Clone, Record)]
+pub struct ClientConfig {
+    pub homeserver_url: String,
+    pub base_store_dir: String,
+    #[uniffi(default = None)]
+    pub account_id: Option<String>,
+    #[uniffi(default = None)]
+    pub proxy: Option<String>,
+    #[uniffi(default = None)]
+    pub enable_share_history_on_invite: Option<bool>,
+    /// Encrypts the state, crypto, event-cache and search-index stores.
+    /// Must be the same on every launch; change it with `rekey_stores`.
+    #[uniffi(default = None)]
+    pub store_passphrase: Option<String>,
+}
+
+/// Secret protecting the persisted session file. `Key` bytes come from the
+/// platform keystore and may be any length; they are hashed down to 32 bytes.
+#[derive(Clone, Enum)]
+pub enum SessionSecret {
+    Passphrase { passphrase: String },
+    Key { key: Vec<u8> },
+}
+
+#[export(callback_interface)]
+pub trait SessionKeyProvider: Send + Sync {
+    /// Secret for the account whose store dir is named `account_key`, or `None`
+    /// to keep the session file in plaintext.
+    fn session_secret(&self, account_key: String) -> Option<SessionSecret>;
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct SessionInfo {
     pub user_id: String,
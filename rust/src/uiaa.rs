@@ -0,0 +1,282 @@
+use matrix_sdk::{
+    Client as SdkClient, HttpError,
+    ruma::{
+        ClientSecret, OwnedClientSecret, OwnedSessionId, UInt,
+        api::client::{
+            account::request_registration_token_via_email,
+            uiaa::{self, AuthData, AuthType, MatrixUserIdentifier, UiaaInfo, UserIdentifier},
+        },
+    },
+};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{FfiError, UiaaHandler, UiaaPolicy, UiaaResponse, UiaaStage};
+
+/// What the UIAA-protected request is for; decides how email stages are
+/// bootstrapped.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UiaaPurpose {
+    Registration,
+    Account,
+}
+
+/// Email validation in flight: the token was requested and the user has to
+/// click the link before `m.login.email.identity` can be submitted.
+struct PendingEmail {
+    address: String,
+    client_secret: OwnedClientSecret,
+    sid: OwnedSessionId,
+    send_attempt: u32,
+}
+
+/// Errors that may carry a UIAA 401 response.
+pub(crate) trait UiaaError: Into<FfiError> {
+    fn uiaa_info(&self) -> Option<&UiaaInfo>;
+}
+
+impl UiaaError for HttpError {
+    fn uiaa_info(&self) -> Option<&UiaaInfo> {
+        self.as_uiaa_response()
+    }
+}
+
+impl UiaaError for matrix_sdk::Error {
+    fn uiaa_info(&self) -> Option<&UiaaInfo> {
+        self.as_uiaa_response()
+    }
+}
+
+/// Re-sends `send` with auth data for each stage the server asks for until it
+/// succeeds, fails with a non-UIAA error, or the host cancels.
+pub(crate) async fn drive<T, E, F, Fut>(
+    sdk: &SdkClient,
+    purpose: UiaaPurpose,
+    handler: &dyn UiaaHandler,
+    mut send: F,
+) -> Result<T, FfiError>
+where
+    F: FnMut(Option<AuthData>) -> Fut,
+    E: UiaaError,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut auth: Option<AuthData> = None;
+    let mut pending_email: Option<PendingEmail> = None;
+    let mut last_stage: Option<AuthType> = None;
+
+    loop {
+        let err = match send(auth.take()).await {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+        let Some(info) = err.uiaa_info() else {
+            return Err(err.into());
+        };
+
+        let next = next_stage(info).ok_or_else(|| {
+            FfiError::Msg("server offered no completable authentication flow".into())
+        })?;
+        let error = (last_stage.as_ref() == Some(&next))
+            .then(|| info.auth_error.as_ref().map(|e| e.message.clone()))
+            .flatten();
+        last_stage = Some(next.clone());
+        let session = info.session.clone();
+        let params = info
+            .params
+            .as_ref()
+            .and_then(|p| serde_json::from_str::<Value>(p.get()).ok())
+            .unwrap_or(Value::Null);
+
+        let data = match next {
+            AuthType::Dummy => {
+                expect_continue(ask(handler, UiaaStage::Dummy, error))?;
+                AuthData::Dummy(uiaa::Dummy::new())
+            }
+            AuthType::Terms => {
+                let policies = terms_policies(&params);
+                expect_continue(ask(handler, UiaaStage::Terms { policies }, error))?;
+                AuthData::Terms(uiaa::Terms::new())
+            }
+            AuthType::RegistrationToken => {
+                match ask(handler, UiaaStage::RegistrationToken, error) {
+                    UiaaResponse::RegistrationToken { token } => {
+                        AuthData::RegistrationToken(uiaa::RegistrationToken::new(token))
+                    }
+                    other => return Err(unexpected(other)),
+                }
+            }
+            AuthType::ReCaptcha => {
+                let public_key = params
+                    .pointer("/m.login.recaptcha/public_key")
+                    .and_then(Value::as_str)
+                    .map(str::to_owned);
+                match ask(handler, UiaaStage::Recaptcha { public_key }, error) {
+                    UiaaResponse::Recaptcha { response } => {
+                        AuthData::ReCaptcha(uiaa::ReCaptcha::new(response))
+                    }
+                    other => return Err(unexpected(other)),
+                }
+            }
+            AuthType::Password => match ask(handler, UiaaStage::Password, error) {
+                UiaaResponse::Password { password } => {
+                    let user_id = sdk
+                        .user_id()
+                        .ok_or_else(|| FfiError::Unauthorized("no logged-in user".into()))?;
+                    AuthData::Password(uiaa::Password::new(
+                        UserIdentifier::Matrix(MatrixUserIdentifier::new(user_id.to_string())),
+                        password,
+                    ))
+                }
+                other => return Err(unexpected(other)),
+            },
+            AuthType::EmailIdentity if purpose == UiaaPurpose::Registration => {
+                let mut error = error;
+                loop {
+                    let sent_to = pending_email.as_ref().map(|p| p.address.clone());
+                    match ask(handler, UiaaStage::EmailIdentity { sent_to }, error.take()) {
+                        UiaaResponse::Email { address } => {
+                            // Same address: resend under the same secret with a bumped attempt.
+                            let (secret, attempt) =
+                                match pending_email.as_ref().filter(|p| p.address == address) {
+                                    Some(p) => (p.client_secret.clone(), p.send_attempt + 1),
+                                    None => (ClientSecret::new(), 1),
+                                };
+                            pending_email = Some(
+                                request_registration_email(sdk, address, secret, attempt).await?,
+                            );
+                        }
+                        UiaaResponse::Continue => match &pending_email {
+                            Some(p) => {
+                                break AuthData::EmailIdentity(uiaa::EmailIdentity::new(
+                                    uiaa::ThirdpartyIdCredentials::new(
+                                        p.sid.clone(),
+                                        p.client_secret.clone(),
+                                    ),
+                                ));
+                            }
+                            None => error = Some("an email address is required".into()),
+                        },
+                        other => return Err(unexpected(other)),
+                    }
+                }
+            }
+            other => {
+                let kind = other.as_str().to_owned();
+                let _ = ask(
+                    handler,
+                    UiaaStage::Unsupported { kind: kind.clone() },
+                    error,
+                );
+                return Err(FfiError::Msg(format!(
+                    "authentication stage {kind} is not supported"
+                )));
+            }
+        };
+
+        auth = Some(with_session(data, session));
+    }
+}
+
+/// First stage not yet completed in the first flow consistent with what the
+/// server says is already done.
+fn next_stage(info: &UiaaInfo) -> Option<AuthType> {
+    info.flows
+        .iter()
+        .find(|f| f.stages.starts_with(&info.completed))
+        .and_then(|f| f.stages.get(info.completed.len()).cloned())
+}
+
+fn with_session(mut data: AuthData, session: Option<String>) -> AuthData {
+    match &mut data {
+        AuthData::Dummy(d) => d.session = session,
+        AuthData::Terms(d) => d.session = session,
+        AuthData::RegistrationToken(d) => d.session = session,
+        AuthData::ReCaptcha(d) => d.session = session,
+        AuthData::Password(d) => d.session = session,
+        AuthData::EmailIdentity(d) => d.session = session,
+        _ => {}
+    }
+    data
+}
+
+async fn request_registration_email(
+    sdk: &SdkClient,
+    address: String,
+    client_secret: OwnedClientSecret,
+    send_attempt: u32,
+) -> Result<PendingEmail, FfiError> {
+    let req = request_registration_token_via_email::v3::Request::new(
+        client_secret.clone(),
+        address.clone(),
+        UInt::from(send_attempt),
+    );
+    let resp = sdk.send(req).await?;
+    info!("Registration email sent to {address}");
+    Ok(PendingEmail {
+        address,
+        client_secret,
+        sid: resp.sid,
+        send_attempt,
+    })
+}
+
+fn terms_policies(params: &Value) -> Vec<UiaaPolicy> {
+    let Some(policies) = params
+        .pointer("/m.login.terms/policies")
+        .and_then(Value::as_object)
+    else {
+        return Vec::new();
+    };
+    policies
+        .iter()
+        .map(|(id, p)| {
+            let version = p.get("version").and_then(Value::as_str).unwrap_or_default();
+            let localized = p.get("en").or_else(|| {
+                p.as_object()
+                    .and_then(|o| o.values().find(|v| v.is_object()))
+            });
+            let field = |k: &str| {
+                localized
+                    .and_then(|l| l.get(k))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            UiaaPolicy {
+                id: id.clone(),
+                version: version.to_owned(),
+                name: field("name"),
+                url: field("url"),
+            }
+        })
+        .collect()
+}
+
+/// Asks the host about a stage. The answer may wait on the user (an email
+/// link can take minutes), so native builds run the callback through
+/// `block_in_place` rather than stalling a runtime worker.
+fn ask(handler: &dyn UiaaHandler, stage: UiaaStage, error: Option<String>) -> UiaaResponse {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        tokio::task::block_in_place(|| handler.on_stage(stage, error))
+    }
+
+    #[cfg(target_family = "wasm")]
+    {
+        handler.on_stage(stage, error)
+    }
+}
+
+fn expect_continue(resp: UiaaResponse) -> Result<(), FfiError> {
+    match resp {
+        UiaaResponse::Continue => Ok(()),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn unexpected(resp: UiaaResponse) -> FfiError {
+    match resp {
+        UiaaResponse::Cancel => FfiError::Cancelled,
+        _ => FfiError::Msg("unexpected response for authentication stage".into()),
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use matrix_sdk::{
+    Client as SdkClient, EncryptionState, Room,
+    crypto::{AttachmentEncryptor, MediaEncryptionInfo},
+    reqwest::{StatusCode, header},
+    room::reply::{EnforceThread, Reply},
+    ruma::{
+        OwnedEventId, OwnedMxcUri, UInt,
+        events::room::{
+            EncryptedFile, EncryptedFileInit, ImageInfo, MediaSource, ThumbnailInfo,
+            message::{
+                AddMentions, AudioInfo, AudioMessageEventContent, FileInfo,
+                FileMessageEventContent, FormattedBody, ImageMessageEventContent, MessageType,
+                RoomMessageEventContentWithoutRelation, VideoInfo, VideoMessageEventContent,
+            },
+        },
+    },
+};
+use mime::Mime;
+use tracing::{info, warn};
+
+use crate::{
+    AttachmentInfo, AttachmentKind, AttachmentUploadObserver, AttachmentUploadResult, EncFile,
+    FfiError, ImageUploadOptions, ProgressObserver,
+    errors::{IntoFfi, OptionFfi, classify_reqwest, classify_status},
+    image_prep, media_info, safe_call,
+};
+
+/// Bytes read from disk per chunk. With `CHUNKS_IN_FLIGHT` this bounds the
+/// memory an upload holds, whatever the file size.
+const CHUNK: usize = 256 * 1024;
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+pub(crate) struct AttachmentUpload {
+    pub path: PathBuf,
+    pub mime: Mime,
+    pub filename: String,
+    pub caption: Option<String>,
+    pub formatted_caption: Option<String>,
+    pub reply_to: Option<OwnedEventId>,
+    pub image_options: ImageUploadOptions,
+    /// Host-supplied poster frame for a video.
+    pub thumbnail: Option<PathBuf>,
+}
+
+impl AttachmentUpload {
+    /// Unknown MIME types go up as `application/octet-stream`; the file name
+    /// defaults to the last path component.
+    pub(crate) fn new(
+        path: String,
+        mime: String,
+        filename: Option<String>,
+        caption: Option<String>,
+        formatted_caption: Option<String>,
+        reply_to_event_id: Option<String>,
+    ) -> Result<Self, FfiError> {
+        let path = PathBuf::from(path);
+        let filename = filename.unwrap_or_else(|| {
+            path.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "file".into())
+        });
+        let reply_to = reply_to_event_id
+            .map(|id| OwnedEventId::try_from(id).ffi())
+            .transpose()?;
+        Ok(Self {
+            mime: mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            path,
+            filename,
+            caption,
+            formatted_caption,
+            reply_to,
+            image_options: ImageUploadOptions::default(),
+            thumbnail: None,
+        })
+    }
+}
+
+/// Routes upload progress to an [`AttachmentUploadObserver`].
+pub(crate) struct ObserverProgress(pub Arc<dyn AttachmentUploadObserver>);
+
+impl ProgressObserver for ObserverProgress {
+    fn on_progress(&self, sent: u64, total: Option<u64>) {
+        self.0.on_progress(sent, total);
+    }
+}
+
+/// Streams the file to the media repository (encrypting it on the fly in
+/// encrypted rooms), then sends the message. `progress` sees the bytes as
+/// they are handed to the connection. Images are first put through
+/// `upload.image_options`, in memory; the file itself is never changed.
+pub(crate) async fn send_attachment(
+    sdk: &SdkClient,
+    room: &Room,
+    mut upload: AttachmentUpload,
+    progress: Option<Arc<dyn ProgressObserver>>,
+) -> Result<AttachmentUploadResult, FfiError> {
+    let encrypted = matches!(room.encryption_state(), EncryptionState::Encrypted);
+    let kind = kind_for(&upload.mime);
+    let (prepared, mut meta) = {
+        let (path, kind) = (upload.path.clone(), kind.clone());
+        let options = upload.image_options.clone();
+        let poster = upload.thumbnail.clone();
+        tokio::task::spawn_blocking(move || {
+            let prepared = match &kind {
+                AttachmentKind::Image => image_prep::prepare(&path, &options)?,
+                _ => None,
+            };
+            let mut meta = match &prepared {
+                Some(p) => image_meta(media_info::probe_image_bytes(&p.bytes)),
+                None => probe(&path, &kind),
+            };
+            if let Some(poster) = poster {
+                add_poster(&mut meta, &kind, &poster);
+            }
+            Ok::<_, FfiError>((prepared, meta))
+        })
+        .await
+        .ffi()??
+    };
+    let mut saved_bytes = 0;
+    let (prepared, total) = match prepared {
+        Some(p) => {
+            if p.mime != upload.mime {
+                upload.filename = rename_for(&upload.filename, &p.mime);
+                upload.mime = p.mime;
+            }
+            saved_bytes = p.saved_bytes;
+            let total = p.bytes.len() as u64;
+            (Some(Arc::<[u8]>::from(p.bytes)), total)
+        }
+        None => (None, tokio::fs::metadata(&upload.path).await.ffi()?.len()),
+    };
+
+    // Ciphertext has the plaintext's length (AES-CTR), so both are `total`.
+    let content_type = if encrypted {
+        mime::APPLICATION_OCTET_STREAM.to_string()
+    } else {
+        upload.mime.to_string()
+    };
+    let (mxc, reader) = upload_stream(sdk, &upload.filename, &content_type, total, || {
+        stream_file(
+            &upload.path,
+            prepared.clone(),
+            encrypted,
+            total,
+            progress.clone(),
+        )
+    })
+    .await?;
+    let encryption_info = reader.await.ffi()?.ffi()?;
+    let (source, enc) = media_source(&mxc, encryption_info)?;
+
+    // A missing thumbnail is no reason to fail the send.
+    let thumbnail = match meta.thumbnail.take() {
+        Some(thumb) => upload_thumbnail(sdk, encrypted, thumb)
+            .await
+            .inspect_err(|e| warn!("thumbnail upload failed: {e}"))
+            .ok(),
+        None => None,
+    };
+
+    let attachment = AttachmentInfo {
+        kind: kind.clone(),
+        mxc_uri: mxc.to_string(),
+        file_name: Some(upload.filename.clone()),
+        mime: Some(upload.mime.to_string()),
+        size_bytes: Some(total),
+        width: meta.width,
+        height: meta.height,
+        duration_ms: meta.duration_ms,
+        thumbnail_mxc_uri: thumbnail.as_ref().map(|t| t.mxc.to_string()),
+        encrypted: enc,
+        thumbnail_encrypted: thumbnail.as_ref().and_then(|t| t.enc.clone()),
+        room_id: Some(room.room_id().to_string()),
+        blurhash: meta.blurhash.clone(),
+    };
+    let content = message_content(&upload, kind, source, total, &meta, thumbnail);
+    let content = match upload.reply_to.clone() {
+        Some(event_id) => room
+            .make_reply_event(
+                content,
+                Reply {
+                    event_id,
+                    enforce_thread: EnforceThread::Unthreaded,
+                    add_mentions: AddMentions::Yes,
+                },
+            )
+            .await
+            .ffi()?,
+        None => content.into(),
+    };
+    let response = room.send(content).await.ffi()?;
+    info!(
+        "Uploaded {} ({total} bytes) to {}",
+        upload.filename,
+        room.room_id()
+    );
+
+    Ok(AttachmentUploadResult {
+        event_id: response.event_id.to_string(),
+        attachment,
+        saved_bytes,
+    })
+}
+
+/// What [`media_info`] could read from the file, for the event's `info`.
+#[derive(Default)]
+struct MediaMeta {
+    width: Option<u32>,
+    height: Option<u32>,
+    duration_ms: Option<u64>,
+    blurhash: Option<String>,
+    thumbnail: Option<media_info::Thumbnail>,
+}
+
+fn probe(path: &Path, kind: &AttachmentKind) -> MediaMeta {
+    match kind {
+        AttachmentKind::Image => image_meta(media_info::probe_image(path)),
+        // Header fields only; the thumbnail comes from the host.
+        AttachmentKind::Video => media_info::probe_mp4(path)
+            .map(|m| MediaMeta {
+                width: m.width,
+                height: m.height,
+                duration_ms: m.duration_ms,
+                ..MediaMeta::default()
+            })
+            .unwrap_or_default(),
+        _ => MediaMeta::default(),
+    }
+}
+
+/// Uses the host's poster frame as a video's thumbnail and blurhash. Other
+/// kinds get theirs from the file itself.
+fn add_poster(meta: &mut MediaMeta, kind: &AttachmentKind, poster: &Path) {
+    if !matches!(kind, AttachmentKind::Video) {
+        warn!("ignoring thumbnail {} for a non-video", poster.display());
+        return;
+    }
+    match media_info::probe_poster(poster) {
+        Some((thumbnail, blurhash)) => {
+            meta.thumbnail = Some(thumbnail);
+            meta.blurhash = blurhash;
+        }
+        None => warn!(
+            "video thumbnail {} could not be read, sending without it",
+            poster.display()
+        ),
+    }
+}
+
+fn image_meta(meta: Option<media_info::ImageMeta>) -> MediaMeta {
+    meta.map(|m| MediaMeta {
+        width: Some(m.width),
+        height: Some(m.height),
+        blurhash: m.blurhash,
+        thumbnail: m.thumbnail,
+        ..MediaMeta::default()
+    })
+    .unwrap_or_default()
+}
+
+/// Swaps the extension when preparing the image changed its format.
+fn rename_for(filename: &str, mime: &Mime) -> String {
+    let ext = match mime.subtype().as_str() {
+        "jpeg" => "jpg",
+        other => other,
+    };
+    Path::new(filename)
+        .with_extension(ext)
+        .to_string_lossy()
+        .into_owned()
+}
+
+struct UploadedThumbnail {
+    mxc: OwnedMxcUri,
+    source: MediaSource,
+    enc: Option<EncFile>,
+    info: ThumbnailInfo,
+}
+
+/// Thumbnails are small enough to encrypt and send from memory.
+async fn upload_thumbnail(
+    sdk: &SdkClient,
+    encrypted: bool,
+    thumb: media_info::Thumbnail,
+) -> Result<UploadedThumbnail, FfiError> {
+    let size = thumb.bytes.len() as u64;
+    let (body, keys, content_type) = if encrypted {
+        let mut plaintext = std::io::Cursor::new(thumb.bytes);
+        let mut encryptor = AttachmentEncryptor::new(&mut plaintext);
+        let mut ciphertext = Vec::with_capacity(size as usize);
+        encryptor.read_to_end(&mut ciphertext).ffi()?;
+        (
+            ciphertext,
+            Some(encryptor.finish()),
+            mime::APPLICATION_OCTET_STREAM,
+        )
+    } else {
+        (thumb.bytes, None, mime::IMAGE_JPEG)
+    };
+    let (mxc, ()) = upload_stream(sdk, "thumbnail.jpg", content_type.as_ref(), size, || {
+        (matrix_sdk::reqwest::Body::from(body.clone()), ())
+    })
+    .await?;
+    let (source, enc) = media_source(&mxc, keys)?;
+
+    let mut info = ThumbnailInfo::new();
+    info.width = UInt::new(thumb.width.into());
+    info.height = UInt::new(thumb.height.into());
+    info.mimetype = Some(mime::IMAGE_JPEG.to_string());
+    info.size = UInt::new(size);
+    Ok(UploadedThumbnail {
+        mxc,
+        source,
+        enc,
+        info,
+    })
+}
+
+fn media_source(
+    mxc: &OwnedMxcUri,
+    keys: Option<MediaEncryptionInfo>,
+) -> Result<(MediaSource, Option<EncFile>), FfiError> {
+    let Some(keys) = keys else {
+        return Ok((MediaSource::Plain(mxc.clone()), None));
+    };
+    let file: EncryptedFile = EncryptedFileInit {
+        url: mxc.clone(),
+        key: keys.key,
+        iv: keys.iv,
+        hashes: keys.hashes,
+        v: keys.version,
+    }
+    .into();
+    let enc = EncFile {
+        url: mxc.to_string(),
+        json: serde_json::to_string(&file).ffi()?,
+    };
+    Ok((MediaSource::Encrypted(Box::new(file)), Some(enc)))
+}
+
+/// Reads (and, in encrypted rooms, encrypts) the file on a blocking thread
+/// and hands it over as a request body. The reader's result carries the
+/// encryption keys once the whole file went through.
+fn stream_file(
+    path: &Path,
+    prepared: Option<Arc<[u8]>>,
+    encrypted: bool,
+    total: u64,
+    progress: Option<Arc<dyn ProgressObserver>>,
+) -> (
+    matrix_sdk::reqwest::Body,
+    tokio::task::JoinHandle<std::io::Result<Option<MediaEncryptionInfo>>>,
+) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<std::io::Result<Vec<u8>>>(CHUNKS_IN_FLIGHT);
+    let path = path.to_owned();
+    let reader = tokio::task::spawn_blocking(move || {
+        let mut file: Box<dyn Read + Send> = match prepared {
+            Some(bytes) => Box::new(Cursor::new(bytes)),
+            None => Box::new(std::io::BufReader::new(std::fs::File::open(&path)?)),
+        };
+        if encrypted {
+            let mut encryptor = AttachmentEncryptor::new(&mut file);
+            pump(&mut encryptor, &tx)?;
+            Ok(Some(encryptor.finish()))
+        } else {
+            pump(&mut file, &tx)?;
+            Ok(None)
+        }
+    });
+
+    if let Some(p) = progress.as_ref() {
+        safe_call(|| p.on_progress(0, Some(total)));
+    }
+    let body = async_stream::stream! {
+        let mut sent = 0u64;
+        while let Some(chunk) = rx.recv().await {
+            if let Ok(bytes) = &chunk {
+                sent += bytes.len() as u64;
+                if let Some(p) = progress.as_ref() {
+                    safe_call(|| p.on_progress(sent, Some(total)));
+                }
+            }
+            yield chunk;
+        }
+    };
+    (matrix_sdk::reqwest::Body::wrap_stream(body), reader)
+}
+
+/// Feeds `reader` into the channel chunk by chunk. Fails once the receiving
+/// side is gone, which is how a cancelled upload stops reading the file.
+fn pump(
+    reader: &mut impl Read,
+    tx: &tokio::sync::mpsc::Sender<std::io::Result<Vec<u8>>>,
+) -> std::io::Result<()> {
+    loop {
+        let mut buf = vec![0u8; CHUNK];
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(std::io::Error::new(e.kind(), e.to_string())));
+                return Err(e);
+            }
+        };
+        buf.truncate(n);
+        if tx.blocking_send(Ok(buf)).is_err() {
+            return Err(std::io::Error::other("upload cancelled"));
+        }
+    }
+}
+
+/// `POST /_matrix/media/v3/upload` with a streamed body, through the SDK's
+/// HTTP client so proxy and TLS settings apply. A streamed body cannot be
+/// replayed, so `body` is asked for a fresh one (along with whatever it set
+/// up to produce it) when the access token has to be refreshed first.
+async fn upload_stream<T>(
+    sdk: &SdkClient,
+    filename: &str,
+    content_type: &str,
+    length: u64,
+    mut body: impl FnMut() -> (matrix_sdk::reqwest::Body, T),
+) -> Result<(OwnedMxcUri, T), FfiError> {
+    let mut url = sdk.homeserver().join("_matrix/media/v3/upload").ffi()?;
+    url.query_pairs_mut().append_pair("filename", filename);
+
+    let mut refreshed = false;
+    loop {
+        let token = sdk
+            .access_token()
+            .ok_or_else(|| FfiError::Unauthorized("not logged in".into()))?;
+        let (body, extra) = body();
+        let resp = sdk
+            .http_client()
+            .post(url.clone())
+            .bearer_auth(token)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, length)
+            .body(body)
+            .send()
+            .await
+            .map_err(classify_reqwest)?;
+
+        let status = resp.status();
+        if status == StatusCode::UNAUTHORIZED && !refreshed && refresh_token(sdk).await {
+            refreshed = true;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(classify_status(
+                status,
+                resp.text().await.unwrap_or_default(),
+            ));
+        }
+
+        let json: serde_json::Value = resp.json().await.ffi()?;
+        let uri = json
+            .get("content_uri")
+            .and_then(|v| v.as_str())
+            .or_ffi("upload response has no content_uri")?;
+        return Ok((uri.into(), extra));
+    }
+}
+
+/// Refreshes the access token after the homeserver rejected it, as the SDK
+/// does for its own requests. False when there is nothing to refresh with.
+pub(crate) async fn refresh_token(sdk: &SdkClient) -> bool {
+    sdk.refresh_access_token()
+        .await
+        .inspect_err(|e| warn!("access token refresh failed: {e}"))
+        .is_ok()
+}
+
+fn kind_for(mime: &Mime) -> AttachmentKind {
+    match mime.type_() {
+        mime::IMAGE => AttachmentKind::Image,
+        mime::VIDEO => AttachmentKind::Video,
+        mime::AUDIO => AttachmentKind::Audio,
+        _ => AttachmentKind::File,
+    }
+}
+
+fn message_content(
+    upload: &AttachmentUpload,
+    kind: AttachmentKind,
+    source: MediaSource,
+    size: u64,
+    meta: &MediaMeta,
+    thumbnail: Option<UploadedThumbnail>,
+) -> RoomMessageEventContentWithoutRelation {
+    // With a caption the body carries it and `filename` the name.
+    let (body, filename) = match upload.caption.clone() {
+        Some(caption) => (caption, Some(upload.filename.clone())),
+        None => (upload.filename.clone(), None),
+    };
+    let formatted = upload
+        .formatted_caption
+        .clone()
+        .filter(|_| upload.caption.is_some())
+        .map(FormattedBody::html);
+    let mimetype = Some(upload.mime.to_string());
+    let size = UInt::new(size);
+    let width = meta.width.map(UInt::from);
+    let height = meta.height.map(UInt::from);
+    let (thumbnail_source, thumbnail_info) = match thumbnail {
+        Some(t) => (Some(t.source), Some(Box::new(t.info))),
+        None => (None, None),
+    };
+
+    let msgtype = match kind {
+        AttachmentKind::Image => {
+            let mut info = ImageInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            info.width = width;
+            info.height = height;
+            info.blurhash = meta.blurhash.clone();
+            info.thumbnail_source = thumbnail_source;
+            info.thumbnail_info = thumbnail_info;
+            let mut img = ImageMessageEventContent::new(body, source);
+            img.info = Some(Box::new(info));
+            img.filename = filename;
+            img.formatted = formatted;
+            MessageType::Image(img)
+        }
+        AttachmentKind::Video => {
+            let mut info = VideoInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            info.width = width;
+            info.height = height;
+            info.duration = meta.duration_ms.map(Duration::from_millis);
+            info.blurhash = meta.blurhash.clone();
+            info.thumbnail_source = thumbnail_source;
+            info.thumbnail_info = thumbnail_info;
+            let mut vid = VideoMessageEventContent::new(body, source);
+            vid.info = Some(Box::new(info));
+            vid.filename = filename;
+            vid.formatted = formatted;
+            MessageType::Video(vid)
+        }
+        AttachmentKind::Audio => {
+            let mut info = AudioInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            info.duration = meta.duration_ms.map(Duration::from_millis);
+            let mut audio = AudioMessageEventContent::new(body, source);
+            audio.info = Some(Box::new(info));
+            audio.filename = filename;
+            audio.formatted = formatted;
+            MessageType::Audio(audio)
+        }
+        AttachmentKind::File => {
+            let mut info = FileInfo::new();
+            info.mimetype = mimetype;
+            info.size = size;
+            info.thumbnail_source = thumbnail_source;
+            info.thumbnail_info = thumbnail_info;
+            let mut file = FileMessageEventContent::new(body, source);
+            file.info = Some(Box::new(info));
+            file.filename = filename;
+            file.formatted = formatted;
+            MessageType::File(file)
+        }
+    };
+    RoomMessageEventContentWithoutRelation::new(msgtype)
+}
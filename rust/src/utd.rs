@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+};
+
+use matrix_sdk::{crypto::types::events::UtdCause, ruma::OwnedRoomId};
+use matrix_sdk_ui::{
+    timeline::{EncryptedMessage, Timeline},
+    unable_to_decrypt_hook::{UnableToDecryptHook, UnableToDecryptInfo},
+};
+
+use tracing::debug;
+
+use crate::{UtdInfo, UtdReason, UtdReasonCount, UtdRoomStats, spawn_detached};
+
+pub(crate) type OpenTimelines = Mutex<HashMap<OwnedRoomId, Arc<Timeline>>>;
+
+pub(crate) fn reason_from_cause(cause: UtdCause) -> UtdReason {
+    match cause {
+        UtdCause::SentBeforeWeJoined => UtdReason::SentBeforeWeJoined,
+        UtdCause::WithheldBySender => UtdReason::Withheld,
+        UtdCause::WithheldForUnverifiedOrInsecureDevice
+        | UtdCause::HistoricalMessageAndDeviceIsUnverified => UtdReason::UnverifiedDevice,
+        UtdCause::UnsignedDevice | UtdCause::UnknownDevice => UtdReason::UnverifiedSender,
+        UtdCause::VerificationViolation => UtdReason::SenderIdentityChanged,
+        UtdCause::HistoricalMessageAndBackupIsDisabled => UtdReason::KeyBackupDisabled,
+        _ => UtdReason::MissingSession,
+    }
+}
+
+pub(crate) fn utd_info(msg: &EncryptedMessage) -> UtdInfo {
+    match msg {
+        EncryptedMessage::MegolmV1AesSha2 {
+            session_id, cause, ..
+        } => UtdInfo {
+            reason: reason_from_cause(*cause),
+            session_id: Some(session_id.clone()),
+        },
+        _ => UtdInfo {
+            reason: UtdReason::MissingSession,
+            session_id: None,
+        },
+    }
+}
+
+#[derive(Default)]
+struct RoomCounts {
+    total: u64,
+    late_decrypted: u64,
+    by_reason: HashMap<UtdReason, u64>,
+}
+
+/// Per-room tallies of UTDs reported by the client's hook. Reports whose
+/// event decrypted after all (`time_to_decrypt` set) also count as
+/// `late_decrypted`; the rest are definite failures.
+pub(crate) struct UtdReporter {
+    rooms: Mutex<HashMap<OwnedRoomId, RoomCounts>>,
+    /// Bumped on every report so observers can re-read the snapshot.
+    generation: tokio::sync::watch::Sender<u64>,
+}
+
+impl UtdReporter {
+    pub(crate) fn new() -> Self {
+        Self {
+            rooms: Mutex::new(HashMap::new()),
+            generation: tokio::sync::watch::channel(0).0,
+        }
+    }
+
+    /// The parent hook for the client's one `UtdHookManager`. The SDK
+    /// reports UTDs without their room, so each is looked up in the open
+    /// timelines, which are the only ones the manager is attached to.
+    pub(crate) fn client_hook(
+        self: &Arc<Self>,
+        timelines: Weak<OpenTimelines>,
+    ) -> Arc<dyn UnableToDecryptHook> {
+        Arc::new(ClientUtdHook {
+            timelines,
+            reporter: self.clone(),
+        })
+    }
+
+    pub(crate) fn subscribe(&self) -> tokio::sync::watch::Receiver<u64> {
+        self.generation.subscribe()
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<UtdRoomStats> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut out: Vec<UtdRoomStats> = rooms
+            .iter()
+            .map(|(room_id, c)| UtdRoomStats {
+                room_id: room_id.to_string(),
+                total: c.total,
+                late_decrypted: c.late_decrypted,
+                by_reason: c
+                    .by_reason
+                    .iter()
+                    .map(|(reason, count)| UtdReasonCount {
+                        reason: *reason,
+                        count: *count,
+                    })
+                    .collect(),
+            })
+            .collect();
+        out.sort_by(|a, b| b.total.cmp(&a.total));
+        out
+    }
+
+    fn record(&self, room_id: &OwnedRoomId, info: &UnableToDecryptInfo) {
+        {
+            let mut rooms = self.rooms.lock().unwrap();
+            let counts = rooms.entry(room_id.clone()).or_default();
+            counts.total += 1;
+            if info.time_to_decrypt.is_some() {
+                counts.late_decrypted += 1;
+            }
+            *counts
+                .by_reason
+                .entry(reason_from_cause(info.cause))
+                .or_default() += 1;
+        }
+        self.generation.send_modify(|g| *g += 1);
+    }
+}
+
+struct ClientUtdHook {
+    /// Weak: the timelines hold the manager, which holds this hook.
+    timelines: Weak<OpenTimelines>,
+    reporter: Arc<UtdReporter>,
+}
+
+impl std::fmt::Debug for ClientUtdHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientUtdHook").finish_non_exhaustive()
+    }
+}
+
+impl UnableToDecryptHook for ClientUtdHook {
+    fn on_utd(&self, info: UnableToDecryptInfo) {
+        let Some(timelines) = self.timelines.upgrade() else {
+            return;
+        };
+        let open: Vec<_> = timelines
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(room_id, tl)| (room_id.clone(), tl.clone()))
+            .collect();
+        let reporter = self.reporter.clone();
+        spawn_detached!(async move {
+            for (room_id, tl) in open {
+                if tl.item_by_event_id(&info.event_id).await.is_some() {
+                    reporter.record(&room_id, &info);
+                    return;
+                }
+            }
+            debug!("UTD {} is in no open timeline", info.event_id);
+        });
+    }
+}
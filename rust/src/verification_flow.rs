@@ -1,8 +1,14 @@
-use futures_util::StreamExt;
+use std::{collections::HashMap, sync::Mutex};
+
+use futures_util::{Stream, StreamExt, stream::BoxStream};
 use matrix_sdk::encryption::verification::{
-    SasState as SdkSasState, Verification, VerificationRequest, VerificationRequestState,
+    QrVerification, QrVerificationState, SasState as SdkSasState, SasVerification, Verification,
+    VerificationRequest, VerificationRequestState,
 };
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::VerifEventListener;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "phase")]
@@ -17,6 +23,18 @@ pub enum VerifEvent {
         other_user: String,
         other_device: String,
     },
+    /// Our QR code; render `qr_data` for the other device to scan.
+    QrReady {
+        qr_data: Vec<u8>,
+    },
+    /// The other device scanned our code. Once it shows success, call
+    /// `confirm_qr_verification`.
+    QrScanned {
+        other_user: String,
+        other_device: String,
+    },
+    /// We scanned the other device's code and wait for it to confirm.
+    QrReciprocated,
     Confirmed,
     Done,
     Cancelled {
@@ -27,23 +45,54 @@ pub enum VerifEvent {
     },
 }
 
+impl VerifEvent {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            VerifEvent::Done | VerifEvent::Cancelled { .. } | VerifEvent::Error { .. }
+        )
+    }
+}
+
+/// Hands each event to `lis` as JSON until the flow ends, then drops the
+/// flow's entry `id` from `tasks`.
+pub(crate) async fn forward_verification(
+    events: impl Stream<Item = VerifEvent>,
+    lis: &dyn VerifEventListener,
+    id: u64,
+    tasks: &Mutex<HashMap<u64, JoinHandle<()>>>,
+) {
+    futures_util::pin_mut!(events);
+    while let Some(event) = events.next().await {
+        let terminal = event.is_terminal();
+        lis.on_event(serde_json::to_string(&event).unwrap_or_default());
+        if terminal {
+            break;
+        }
+    }
+    tasks.lock().unwrap().remove(&id);
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmojiEntry {
     pub symbol: String,
     pub description: String,
 }
 
-/// Outgoing: we created the request, we start SAS after Ready.
+/// Outgoing: we created the request. After Ready we show a QR code if
+/// `show_qr` and the other side can scan, otherwise start SAS if
+/// `we_start_sas`.
 pub async fn drive_verification_request(
     request: VerificationRequest,
     we_start_sas: bool,
-) -> impl futures_util::Stream<Item = VerifEvent> {
+    show_qr: bool,
+) -> impl Stream<Item = VerifEvent> {
     let flow_id = request.flow_id().to_owned();
     async_stream::stream! {
         yield VerifEvent::Requested { flow_id };
 
         let mut req_changes = request.changes();
-        let sas = loop {
+        let first = loop {
             let Some(state) = req_changes.next().await else {
                 yield VerifEvent::Error { message: "Request stream ended".into() };
                 return;
@@ -51,9 +100,22 @@ pub async fn drive_verification_request(
             match state {
                 VerificationRequestState::Ready { .. } => {
                     yield VerifEvent::Ready;
+                    if show_qr {
+                        match generate_qr(&request).await {
+                            Ok(Some((qr, qr_data))) => {
+                                yield VerifEvent::QrReady { qr_data };
+                                break Verification::QrV1(qr);
+                            }
+                            Ok(None) => {}
+                            Err(message) => {
+                                yield VerifEvent::Error { message };
+                                return;
+                            }
+                        }
+                    }
                     if we_start_sas {
                         match request.start_sas().await {
-                            Ok(Some(sas)) => break sas,
+                            Ok(Some(sas)) => break Verification::SasV1(sas),
                             Ok(None) => {
                                 yield VerifEvent::Error { message: "start_sas returned None".into() };
                                 return;
@@ -65,11 +127,7 @@ pub async fn drive_verification_request(
                         }
                     }
                 }
-                VerificationRequestState::Transitioned { verification } => {
-                    if let Verification::SasV1(sas) = verification { break sas; }
-                    yield VerifEvent::Error { message: "Non-SAS method".into() };
-                    return;
-                }
+                VerificationRequestState::Transitioned { verification } => break verification,
                 VerificationRequestState::Cancelled(info) => {
                     yield VerifEvent::Cancelled { reason: info.reason().to_owned() };
                     return;
@@ -79,37 +137,16 @@ pub async fn drive_verification_request(
             }
         };
 
-        yield VerifEvent::SasStarted;
-
-        // Outgoing side initiated SAS, no need to accept it ourselves.
-        let mut sas_changes = sas.changes();
-        while let Some(state) = sas_changes.next().await {
-            match state {
-                SdkSasState::KeysExchanged { emojis, .. } => {
-                    yield VerifEvent::KeysExchanged {
-                        emojis: emojis.map(|e| e.emojis.iter().map(|em| EmojiEntry {
-                            symbol: em.symbol.to_string(),
-                            description: em.description.to_string(),
-                        }).collect()).unwrap_or_default(),
-                        other_user: sas.other_user_id().to_string(),
-                        other_device: sas.other_device().device_id().to_string(),
-                    };
-                }
-                SdkSasState::Confirmed => { yield VerifEvent::Confirmed; }
-                SdkSasState::Done { .. } => { yield VerifEvent::Done; return; }
-                SdkSasState::Cancelled(info) => {
-                    yield VerifEvent::Cancelled { reason: info.reason().to_owned() };
-                    return;
-                }
-                _ => {}
-            }
+        for await event in follow(req_changes, first) {
+            yield event;
         }
     }
 }
 
 pub async fn drive_incoming_verification(
     request: VerificationRequest,
-) -> impl futures_util::Stream<Item = VerifEvent> {
+    show_qr: bool,
+) -> impl Stream<Item = VerifEvent> {
     async_stream::stream! {
         // Subscribe BEFORE accept so we never miss the Transitioned event
         let mut req_changes = request.changes();
@@ -120,34 +157,147 @@ pub async fn drive_incoming_verification(
         }
         yield VerifEvent::Ready;
 
-        // Wait for other side to start SAS -> Transitioned
-        let sas = loop {
-            let Some(state) = req_changes.next().await else {
-                yield VerifEvent::Error { message: "Stream ended waiting for SAS".into() };
-                return;
-            };
-            match state {
-                VerificationRequestState::Ready { .. } => { /* already emitted */ }
-                VerificationRequestState::Transitioned { verification } => {
-                    if let Verification::SasV1(sas) = verification { break sas; }
-                    yield VerifEvent::Error { message: "Non-SAS method".into() };
+        let mut shown = None;
+        if show_qr {
+            match generate_qr(&request).await {
+                Ok(Some((qr, qr_data))) => {
+                    yield VerifEvent::QrReady { qr_data };
+                    shown = Some(Verification::QrV1(qr));
+                }
+                Ok(None) => {}
+                Err(message) => {
+                    yield VerifEvent::Error { message };
                     return;
                 }
-                VerificationRequestState::Cancelled(info) => {
+            }
+        }
+
+        // Wait for the other side to start SAS or for either side to scan.
+        let first = match shown {
+            Some(qr) => qr,
+            None => loop {
+                let Some(state) = req_changes.next().await else {
+                    yield VerifEvent::Error { message: "Stream ended waiting for SAS".into() };
+                    return;
+                };
+                match state {
+                    VerificationRequestState::Ready { .. } => { /* already emitted */ }
+                    VerificationRequestState::Transitioned { verification } => break verification,
+                    VerificationRequestState::Cancelled(info) => {
+                        yield VerifEvent::Cancelled { reason: info.reason().to_owned() };
+                        return;
+                    }
+                    VerificationRequestState::Done => { yield VerifEvent::Done; return; }
+                    _ => {}
+                }
+            },
+        };
+
+        for await event in follow(req_changes, first) {
+            yield event;
+        }
+    }
+}
+
+async fn generate_qr(
+    request: &VerificationRequest,
+) -> Result<Option<(QrVerification, Vec<u8>)>, String> {
+    match request.generate_qr_code().await {
+        Ok(Some(qr)) => match qr.to_bytes() {
+            Ok(bytes) => Ok(Some((qr, bytes))),
+            Err(e) => Err(format!("Encoding QR code failed: {e}")),
+        },
+        Ok(None) => Ok(None),
+        Err(e) => Err(format!("Generating QR code failed: {e}")),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Sas,
+    QrShown,
+    QrScanned,
+}
+
+fn method(v: &Verification) -> Option<Method> {
+    match v {
+        Verification::SasV1(_) => Some(Method::Sas),
+        Verification::QrV1(qr) if qr.reciprocated() => Some(Method::QrScanned),
+        Verification::QrV1(_) => Some(Method::QrShown),
+        _ => None,
+    }
+}
+
+fn method_events(v: Verification) -> BoxStream<'static, VerifEvent> {
+    match v {
+        Verification::SasV1(sas) => sas_events(sas).boxed(),
+        Verification::QrV1(qr) => qr_events(qr).boxed(),
+        _ => futures_util::stream::once(async {
+            VerifEvent::Error {
+                message: "Unsupported verification method".into(),
+            }
+        })
+        .boxed(),
+    }
+}
+
+enum Step {
+    Event(Option<VerifEvent>),
+    Request(Option<VerificationRequestState>),
+}
+
+/// Follows the verification the request transitioned into, switching when
+/// the request transitions again (e.g. our QR was shown but the other side
+/// started SAS, or we scanned theirs instead).
+fn follow(
+    mut req_changes: impl Stream<Item = VerificationRequestState> + Unpin + Send + 'static,
+    first: Verification,
+) -> impl Stream<Item = VerifEvent> {
+    async_stream::stream! {
+        let mut current = method(&first);
+        let mut events = method_events(first);
+        let mut req_open = true;
+        loop {
+            let step = tokio::select! {
+                ev = events.next() => Step::Event(ev),
+                state = req_changes.next(), if req_open => Step::Request(state),
+            };
+            match step {
+                Step::Event(Some(event)) => {
+                    let terminal = event.is_terminal();
+                    yield event;
+                    if terminal { return; }
+                }
+                Step::Event(None) => return,
+                Step::Request(None) => req_open = false,
+                Step::Request(Some(VerificationRequestState::Transitioned { verification })) => {
+                    let next = method(&verification);
+                    if next.is_some() && next != current {
+                        current = next;
+                        events = method_events(verification);
+                    }
+                }
+                Step::Request(Some(VerificationRequestState::Cancelled(info))) => {
                     yield VerifEvent::Cancelled { reason: info.reason().to_owned() };
                     return;
                 }
-                VerificationRequestState::Done => { yield VerifEvent::Done; return; }
-                _ => {}
+                Step::Request(Some(_)) => {}
             }
-        };
+        }
+    }
+}
 
+fn sas_events(sas: SasVerification) -> impl Stream<Item = VerifEvent> {
+    async_stream::stream! {
         yield VerifEvent::SasStarted;
         let mut sas_changes = sas.changes();
 
-        if let Err(e) = sas.accept().await {
-            yield VerifEvent::Error { message: format!("SAS accept failed: {e}") };
-            return;
+        // The side that did not start SAS has to accept it.
+        if !sas.we_started() {
+            if let Err(e) = sas.accept().await {
+                yield VerifEvent::Error { message: format!("SAS accept failed: {e}") };
+                return;
+            }
         }
 
         while let Some(state) = sas_changes.next().await {
@@ -173,3 +323,37 @@ pub async fn drive_incoming_verification(
         }
     }
 }
+
+fn qr_events(qr: QrVerification) -> impl Stream<Item = VerifEvent> {
+    async_stream::stream! {
+        let mut qr_changes = qr.changes();
+
+        // Scanning reciprocates before we get to subscribe.
+        let mut reciprocated = qr.reciprocated();
+        if reciprocated {
+            yield VerifEvent::QrReciprocated;
+        }
+
+        while let Some(state) = qr_changes.next().await {
+            match state {
+                QrVerificationState::Scanned => {
+                    yield VerifEvent::QrScanned {
+                        other_user: qr.other_user_id().to_string(),
+                        other_device: qr.other_device().device_id().to_string(),
+                    };
+                }
+                QrVerificationState::Reciprocated if !reciprocated => {
+                    reciprocated = true;
+                    yield VerifEvent::QrReciprocated;
+                }
+                QrVerificationState::Confirmed => { yield VerifEvent::Confirmed; }
+                QrVerificationState::Done { .. } => { yield VerifEvent::Done; return; }
+                QrVerificationState::Cancelled(info) => {
+                    yield VerifEvent::Cancelled { reason: info.reason().to_owned() };
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::core::{CoreClient, TimelineManager, map_send_queue_update, room_list_membership};
 use crate::js_observer_json;
 use crate::js_observer_noargs;
+use crate::room_list_sort::SortedRoomList;
 use crate::types::*;
 use crate::verification_flow::{
     VerifEvent, drive_incoming_verification, drive_verification_request,
@@ -57,9 +58,8 @@ use matrix_sdk::{
 use mime::Mime;
 
 use matrix_sdk_ui::{
-    eyeball_im::{Vector, VectorDiff},
+    eyeball_im::VectorDiff,
     notification_client::{NotificationClient, NotificationProcessSetup, NotificationStatus},
-    room_list_service::filters,
     sync_service::{State, SyncService},
     timeline::RoomExt,
 };
@@ -151,6 +151,9 @@ impl RoomListObserver for JsRoomListObserver {
     fn on_update(&self, item: RoomListEntry) {
         call_js(&self.1, to_json(&item));
     }
+    fn on_diff(&self, diff: RoomListDiff) {
+        call_js(&self.1, to_json(&diff));
+    }
 }
 unsafe impl Send for JsRoomListObserver {}
 unsafe impl Sync for JsRoomListObserver {}
@@ -414,6 +417,7 @@ wasm_delegate_json! {
     "ownLastRead"       => own_last_read(room_id: String)                         or OwnReceipt { event_id: None, ts_ms: None };
     "reactionsForEvent" => reactions_for_event(room_id: String, event_id: String)  or Vec::<ReactionSummary>::new();
     "mySpaces"          => my_spaces()                                             or Vec::<SpaceInfo>::new();
+    "spaceTree"         => space_tree()                                            or SpaceTree { root_spaces: Vec::new(), spaces: Vec::new() };
 }
 
 wasm_delegate_result_json! {
@@ -1236,7 +1240,7 @@ impl WasmClient {
     }
 
     #[wasm_bindgen(js_name = observeRoomList)]
-    pub fn observe_room_list(&self, on_reset: Function, _on_update: Function) -> f64 {
+    pub fn observe_room_list(&self, on_reset: Function, on_diff: Function) -> f64 {
         let Some(state) = self.state() else {
             return 0.0;
         };
@@ -1253,28 +1257,37 @@ impl WasmClient {
                 let rls = svc.room_list_service();
                 let Ok(all) = rls.all_rooms().await else { return; };
                 let (stream, controller) = all.entries_with_dynamic_adapters(50);
-                controller.set_filter(Box::new(filters::new_filter_non_left()));
-                use matrix_sdk_ui::room_list_service::RoomListItem;
+                let mut filter = RoomListFilter::default();
+                controller.set_filter(s.core.room_list_filter(&filter).await);
                 tokio::pin!(stream);
-                let mut items = Vector::<RoomListItem>::new();
+                let mut entries = Vec::<RoomListEntry>::new();
+                let mut sorted = SortedRoomList::new(RoomListSort::default());
+                let mut initial = true;
+                let mut emit = move |diff: RoomListDiff| match diff {
+                    RoomListDiff::Reset { values } if initial => {
+                        initial = false;
+                        call_js(&on_reset, to_json(&values))
+                    }
+                    diff => call_js(&on_diff, to_json(&diff)),
+                };
                 loop {
                     tokio::select! {
                         Some(cmd) = cmd_rx.recv() => {
                             match cmd {
                                 RoomListCmd::SetUnreadOnly(u) => {
-                                    if u {
-                                        let unread_filter: filters::BoxedFilterFn = Box::new(
-                                            |room: &RoomListItem| -> bool {
-                                                let receipts = room.read_receipts();
-                                                receipts.num_unread > 0 || room.is_marked_unread()
-                                            },
-                                        );
-                                        controller.set_filter(Box::new(filters::new_filter_all(vec![Box::new(filters::new_filter_non_left()), unread_filter])));
-                                    }
-                                    else { controller.set_filter(Box::new(filters::new_filter_non_left())); }
+                                    filter.unread_only = u;
+                                    controller.set_filter(s.core.room_list_filter(&filter).await);
+                                }
+                                RoomListCmd::SetFilter(next) => {
+                                    filter = next;
+                                    controller.set_filter(s.core.room_list_filter(&filter).await);
+                                }
+                                RoomListCmd::SetSort(sort) => {
+                                    emit(sorted.set_sort(sort));
+                                    s.room_list_cache.replace(sorted.entries().to_vec());
                                 }
                                 RoomListCmd::UpdateVisibleRange((range, threshold)) => {
-                                    let total_items = items.len();
+                                    let total_items = entries.len();
                                     if let Some(&position) = range.last() {
                                         let threshold_idx = total_items.saturating_sub(threshold);
                                         if total_items > 0 && position as usize >= threshold_idx {
@@ -1285,11 +1298,14 @@ impl WasmClient {
                             }
                         }
                         Some(diffs) = stream.next() => {
-                            if CoreClient::apply_vector_diff(&mut items, diffs) {
-                                let snapshot = s.core.build_room_list_snapshot(&items).await;
-                                s.room_list_cache.replace(snapshot.clone());
-                                call_js(&on_reset, to_json(&snapshot));
+                            for diff in diffs {
+                                let Some(diff) = s.core.apply_room_list_diff(&mut entries, diff).await
+                                else {
+                                    continue;
+                                };
+                                sorted.apply(diff).into_iter().for_each(&mut emit);
                             }
+                            s.room_list_cache.replace(sorted.entries().to_vec());
                         }
                         else => break,
                     }
@@ -1978,6 +1994,36 @@ impl WasmClient {
         }
     }
 
+    #[wasm_bindgen(js_name = roomListSetFilter)]
+    pub fn room_list_set_filter(&self, token: f64, filter: JsValue) -> bool {
+        let Some(state) = self.state() else {
+            return false;
+        };
+        let Ok(filter) = serde_wasm_bindgen::from_value::<RoomListFilter>(filter) else {
+            return false;
+        };
+        if let Some(tx) = state.room_list_cmds.borrow().get(&(token as u64)).cloned() {
+            tx.send(RoomListCmd::SetFilter(filter)).is_ok()
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen(js_name = roomListSetSort)]
+    pub fn room_list_set_sort(&self, token: f64, sort: JsValue) -> bool {
+        let Some(state) = self.state() else {
+            return false;
+        };
+        let Ok(sort) = serde_wasm_bindgen::from_value::<RoomListSort>(sort) else {
+            return false;
+        };
+        if let Some(tx) = state.room_list_cmds.borrow().get(&(token as u64)).cloned() {
+            tx.send(RoomListCmd::SetSort(sort)).is_ok()
+        } else {
+            false
+        }
+    }
+
     #[wasm_bindgen(js_name = roomListUpdateVisibleRange)]
     pub fn room_list_update_visible_range(
         &self,
@@ -2321,6 +2367,23 @@ impl WasmClient {
         to_json(&SearchPage {
             hits: vec![],
             next_offset: None,
+            next_cursor: None,
+        })
+    }
+
+    #[wasm_bindgen(js_name = searchAllRooms)]
+    pub async fn search_all_rooms(
+        &self,
+        _query: String,
+        _filter: JsValue,
+        _limit: u32,
+        _cursor: Option<String>,
+    ) -> JsValue {
+        // no search index on wasm
+        to_json(&SearchPage {
+            hits: vec![],
+            next_offset: None,
+            next_cursor: None,
         })
     }
 
@@ -2518,6 +2581,9 @@ impl WasmClient {
                     .map(|my| my == d.device_id())
                     .unwrap_or(false),
                 verified: d.is_verified(),
+                last_seen_ip: None,
+                last_seen_ts: None,
+                is_dehydrated: d.is_dehydrated(),
             })
             .collect();
         to_json(&items)
@@ -2651,7 +2717,7 @@ impl WasmClient {
         let client = state.client().clone();
         let fid = flow_id.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            let stream = drive_verification_request(request, true).await;
+            let stream = drive_verification_request(request, true, false).await;
             futures_util::pin_mut!(stream);
             while let Some(event) = stream.next().await {
                 let json = serde_json::to_string(&event).unwrap_or_default();
@@ -2718,7 +2784,7 @@ impl WasmClient {
         let client = state.client().clone();
         let fid = flow_id.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            let stream = drive_verification_request(request, true).await;
+            let stream = drive_verification_request(request, true, false).await;
             futures_util::pin_mut!(stream);
             while let Some(event) = stream.next().await {
                 let json = serde_json::to_string(&event).unwrap_or_default();
@@ -2927,7 +2993,7 @@ impl WasmClient {
         };
 
         wasm_bindgen_futures::spawn_local(async move {
-            let stream = drive_incoming_verification(request).await;
+            let stream = drive_incoming_verification(request, false).await;
             futures_util::pin_mut!(stream);
             while let Some(event) = stream.next().await {
                 let json = serde_json::to_string(&event).unwrap_or_default();
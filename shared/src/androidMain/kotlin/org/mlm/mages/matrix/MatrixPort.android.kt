@@ -1,11 +1,15 @@
 package org.mlm.mages.matrix
 import co.touchlab.kermit.Logger
 
+import kotlinx.coroutines.CoroutineScope
 import kotlinx.coroutines.Dispatchers
+import kotlinx.coroutines.SupervisorJob
 import kotlinx.coroutines.channels.awaitClose
 import kotlinx.coroutines.channels.trySendBlocking
+import kotlinx.coroutines.delay
 import kotlinx.coroutines.flow.Flow
 import kotlinx.coroutines.flow.callbackFlow
+import kotlinx.coroutines.launch
 import kotlinx.coroutines.withContext
 import kotlinx.coroutines.withTimeoutOrNull
 import kotlinx.serialization.json.Json
@@ -24,6 +28,7 @@ import org.mlm.mages.*
 import org.mlm.mages.platform.AndroidBrowserAuthCoordinator
 import org.mlm.mages.platform.MagesPaths
 import org.mlm.mages.platform.platformNeedsControlledAudioDevices
+import java.util.concurrent.atomic.AtomicBoolean
 
 private inline fun <T> runWithFfiResult(block: () -> T): Result<T> =
     runCatching(block).recoverCatching { e ->
@@ -40,6 +45,10 @@ private inline fun <T> runWithFfiResult(block: () -> T): Result<T> =
 private val matrixDispatcher = Dispatchers.IO.limitedParallelism(4)
 private val mediaDispatcher = Dispatchers.IO.limitedParallelism(2)
 
+// How long room list diffs settle before the host gets a fresh list.
+private const val ROOM_LIST_PUBLISH_DELAY_MS = 50L
+private val roomListScope = CoroutineScope(SupervisorJob() + matrixDispatcher)
+
 class RustMatrixPort : MatrixPort, VerificationService {
     @Volatile
     private var client: FfiClient? = null
@@ -596,7 +605,8 @@ class RustMatrixPort : MatrixPort, VerificationService {
         caption: String?,
         formattedCaption: String?,
         replyToEventId: String?,
-        onProgress: ((Long, Long?) -> Unit)?
+        onProgress: ((Long, Long?) -> Unit)?,
+        thumbnailPath: String?
     ): Boolean {
         val cb = if (onProgress != null) object : mages.ProgressObserver {
             override fun onProgress(sent: ULong, total: ULong?) {
@@ -604,7 +614,7 @@ class RustMatrixPort : MatrixPort, VerificationService {
             }
         } else null
         return withContext(Dispatchers.IO) {
-            runWithFfiResult { withClient { it.sendAttachmentFromPath(roomId, path, mime, filename, caption, formattedCaption, replyToEventId, cb) } }.isSuccess
+            runWithFfiResult { withClient { it.sendAttachmentFromPath(roomId, path, mime, filename, caption, formattedCaption, replyToEventId, thumbnailPath, cb) } }.isSuccess
         }
     }
 
@@ -761,30 +771,96 @@ class RustMatrixPort : MatrixPort, VerificationService {
 
     override suspend fun observeRoomList(observer: MatrixPort.RoomListObserver): ULong =
         withContext(matrixDispatcher) {
+            // Mirror of the Rust list, mapped once per changed row, so
+            // positional diffs can be applied. Guarded by itself: diffs arrive
+            // on the Rust thread, publishes run on roomListScope.
+            val rows = mutableListOf<RoomListEntry>()
             val currentVisible = linkedMapOf<String, RoomListEntry>()
+            val publishPending = AtomicBoolean(false)
 
-            val cb = object : mages.RoomListObserver {
-                override fun onReset(items: List<mages.RoomListEntry>) {
+            fun publishAll() {
+                val visible = synchronized(rows) {
                     currentVisible.clear()
-                    items.asSequence()
-                        .map { it.toKotlinRoomListEntry() }
+                    rows.asSequence()
                         .filter { it.isVisibleInMainRoomList() }
                         .forEach { currentVisible[it.roomId] = it }
+                    currentVisible.values.toList()
+                }
+                observer.onReset(visible)
+            }
 
-                    observer.onReset(currentVisible.values.toList())
+            // A sync batch arrives as one diff per call; publish once the
+            // burst has been applied instead of once per diff.
+            fun schedulePublish() {
+                if (publishPending.compareAndSet(false, true)) {
+                    roomListScope.launch {
+                        delay(ROOM_LIST_PUBLISH_DELAY_MS)
+                        publishPending.set(false)
+                        publishAll()
+                    }
+                }
+            }
+
+            val cb = object : mages.RoomListObserver {
+                override fun onReset(items: List<mages.RoomListEntry>) {
+                    val mapped = items.map { it.toKotlinRoomListEntry() }
+                    synchronized(rows) {
+                        rows.clear()
+                        rows.addAll(mapped)
+                    }
+                    publishAll()
+                }
+
+                override fun onDiff(diff: mages.RoomListDiff) {
+                    synchronized(rows) {
+                        when (diff) {
+                            is mages.RoomListDiff.Set -> {
+                                val index = diff.index.toInt()
+                                if (index !in rows.indices) return
+                                rows[index] = diff.value.toKotlinRoomListEntry()
+                            }
+                            is mages.RoomListDiff.Insert -> {
+                                val index = diff.index.toInt()
+                                if (index !in 0..rows.size) return
+                                rows.add(index, diff.value.toKotlinRoomListEntry())
+                            }
+                            is mages.RoomListDiff.Append ->
+                                rows.addAll(diff.values.map { it.toKotlinRoomListEntry() })
+                            is mages.RoomListDiff.Remove -> {
+                                val index = diff.index.toInt()
+                                if (index !in rows.indices) return
+                                rows.removeAt(index)
+                            }
+                            is mages.RoomListDiff.Truncate -> {
+                                val length = diff.length.toInt()
+                                while (rows.size > length) rows.removeAt(rows.lastIndex)
+                            }
+                            is mages.RoomListDiff.Clear -> rows.clear()
+                            is mages.RoomListDiff.Reset -> {
+                                rows.clear()
+                                rows.addAll(diff.values.map { it.toKotlinRoomListEntry() })
+                            }
+                        }
+                    }
+                    if (diff is mages.RoomListDiff.Set) {
+                        // Same position, so only the entry itself changed.
+                        publishEntry(diff.value.toKotlinRoomListEntry())
+                    } else {
+                        schedulePublish()
+                    }
                 }
 
                 override fun onUpdate(item: mages.RoomListEntry) {
-                    val mapped = item.toKotlinRoomListEntry()
+                    publishEntry(item.toKotlinRoomListEntry())
+                }
 
+                private fun publishEntry(mapped: RoomListEntry) {
                     if (mapped.isVisibleInMainRoomList()) {
-                        currentVisible[mapped.roomId] = mapped
+                        synchronized(rows) { currentVisible[mapped.roomId] = mapped }
                         observer.onUpdate(mapped)
                     } else {
-                        val removed = currentVisible.remove(mapped.roomId)
-                        if (removed != null) {
-                            observer.onReset(currentVisible.values.toList())
-                        }
+                        val removed = synchronized(rows) { currentVisible.remove(mapped.roomId) }
+                        if (removed != null) schedulePublish()
                     }
                 }
             }
@@ -715,6 +715,7 @@ interface MatrixPort {
         formattedCaption: String? = null,
         replyToEventId: String? = null,
         onProgress: ((Long, Long?) -> Unit)? = null,
+        thumbnailPath: String? = null,
     ): Boolean
 
     suspend fun sendStickerFromPath(
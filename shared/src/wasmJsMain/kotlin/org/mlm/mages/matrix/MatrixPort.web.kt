@@ -719,7 +719,8 @@ class WebStubMatrixPort : MatrixPort, VerificationService {
         caption: String?,
         formattedCaption: String?,
         replyToEventId: String?,
-        onProgress: ((Long, Long?) -> Unit)?
+        onProgress: ((Long, Long?) -> Unit)?,
+        thumbnailPath: String?
     ): Boolean {
         val bytes = retrieveWebBlob(path) ?: throw Exception("Blob not found for path: $path")
         clearWebBlob(path)
@@ -822,6 +823,8 @@ class WebStubMatrixPort : MatrixPort, VerificationService {
         requireClient().observeOwnReceipt(roomId, jsCallback0 { observer.onChanged() }).toULong()
 
     override suspend fun observeRoomList(observer: MatrixPort.RoomListObserver): ULong {
+        // Mirror of the Rust list, so positional diffs can be applied.
+        val rows = mutableListOf<RoomListEntry>()
         val token = requireClient().observeRoomList(
             jsCallback1 { itemsValue: JsAny? ->
                 val raw = runCatching { itemsValue.toJsonArray() }.getOrElse {
@@ -830,20 +833,81 @@ class WebStubMatrixPort : MatrixPort, VerificationService {
                 val items = runCatching {
                     wasmJson.decodeFromJsonElement<List<RoomListEntry>>(raw)
                 }.getOrDefault(emptyList())
+                rows.clear()
+                rows.addAll(items)
                 observer.onReset(items)
             },
-            jsCallback1 { itemValue: JsAny? ->
-                val item = runCatching {
-                    wasmJson.decodeFromJsonElement<RoomListEntry>(itemValue.toJsonElement())
-                }.getOrNull()
-                if (item != null) {
-                    observer.onUpdate(item)
+            jsCallback1 { diffValue: JsAny? ->
+                when (val changed = applyRoomListDiff(rows, diffValue)) {
+                    null -> Unit
+                    is RoomListChange.Updated -> observer.onUpdate(changed.item)
+                    RoomListChange.Reordered -> observer.onReset(rows.toList())
                 }
             }
         )
         return token.toULong()
     }
 
+    private sealed interface RoomListChange {
+        data class Updated(val item: RoomListEntry) : RoomListChange
+        data object Reordered : RoomListChange
+    }
+
+    /**
+     * Applies a `RoomListDiff` (serde's externally tagged JSON) to [rows].
+     * Returns null when the diff could not be decoded or is out of range.
+     */
+    private fun applyRoomListDiff(rows: MutableList<RoomListEntry>, diffValue: JsAny?): RoomListChange? {
+        val element = runCatching { diffValue.toJsonElement() }.getOrNull() ?: return null
+        // Unit variants serialise as a bare string.
+        if (element is JsonPrimitive) {
+            if (element.contentOrNull != "Clear") return null
+            rows.clear()
+            return RoomListChange.Reordered
+        }
+        val obj = element as? JsonObject ?: return null
+        val entry = obj.entries.singleOrNull() ?: return null
+        val value = entry.value as? JsonObject ?: return null
+        val index = (value["index"] as? JsonPrimitive)?.contentOrNull?.toIntOrNull()
+        fun item(): RoomListEntry? = value["value"]?.let {
+            runCatching { wasmJson.decodeFromJsonElement<RoomListEntry>(it) }.getOrNull()
+        }
+        fun items(): List<RoomListEntry>? = value["values"]?.let {
+            runCatching { wasmJson.decodeFromJsonElement<List<RoomListEntry>>(it) }.getOrNull()
+        }
+
+        when (entry.key) {
+            "Set" -> {
+                val item = item() ?: return null
+                if (index == null || index !in rows.indices) return null
+                rows[index] = item
+                return RoomListChange.Updated(item)
+            }
+            "Insert" -> {
+                val item = item() ?: return null
+                if (index == null || index !in 0..rows.size) return null
+                rows.add(index, item)
+            }
+            "Append" -> rows.addAll(items() ?: return null)
+            "Remove" -> {
+                if (index == null || index !in rows.indices) return null
+                rows.removeAt(index)
+            }
+            "Truncate" -> {
+                val length = (value["length"] as? JsonPrimitive)?.contentOrNull?.toIntOrNull()
+                    ?: return null
+                while (rows.size > length) rows.removeAt(rows.lastIndex)
+            }
+            "Reset" -> {
+                val values = items() ?: return null
+                rows.clear()
+                rows.addAll(values)
+            }
+            else -> return null
+        }
+        return RoomListChange.Reordered
+    }
+
     override fun unobserveRoomList(token: ULong) {
         requireClient().unobserveRoomList(token.toDouble())
     }